@@ -1,6 +1,6 @@
 
-use std::{io, error, iter};
-use dbus::arg::ArgType;
+use std::{io, error};
+use dbus::arg::{ArgType, SignatureType};
 use xml;
 
 fn find_attr<'a>(a: &'a Vec<xml::attribute::OwnedAttribute>, n: &str) -> Result<&'a str, Box<dyn error::Error>> {
@@ -207,67 +207,46 @@ struct GenVars {
     gen: Vec<String>,
 }
 
-fn xml_to_rust_type<I: Iterator<Item=char>>(i: &mut iter::Peekable<I>, out: bool, genvars: &mut Option<GenVars>) -> Result<String, Box<dyn error::Error>> {
-
-    let c = i.next().ok_or_else(|| "unexpected end of signature")?;
-    let atype = ArgType::from_i32(c as i32);
-    let result = match (atype, c) {
-        (Err(_), '(') => {
+fn xml_to_rust_type(t: &SignatureType, out: bool, genvars: &mut Option<GenVars>) -> String {
+    match (t, out) {
+        (SignatureType::Struct(fields), _) => {
             let mut s: Vec<String> = vec!();
-            while i.peek() != Some(&')') {
-                let n = xml_to_rust_type(i, out, genvars)?;
-                s.push(n);
-            };
-            i.next().unwrap();
+            for f in fields { s.push(xml_to_rust_type(f, out, genvars)); }
             format!("({})", s.join(", "))
         },
-        (Err(_), a @ _) => return Err(format!("Unknown character in signature {:?}", a).into()),
-        (Ok(a @ _), _) => match (a, out) {
-            (ArgType::Byte, _) => "u8".into(),
-            (ArgType::Boolean, _) => "bool".into(),
-            (ArgType::Int16, _) => "i16".into(),
-            (ArgType::UInt16, _) => "u16".into(),
-            (ArgType::Int32, _) => "i32".into(),
-            (ArgType::UInt32, _) => "u32".into(),
-            (ArgType::Int64, _) => "i64".into(),
-            (ArgType::UInt64, _) => "u64".into(),
-            (ArgType::Double, _) => "f64".into(),
-            (ArgType::UnixFd, _) => "arg::OwnedFd".into(),
-            (ArgType::String, false) => "&str".into(),
-            (ArgType::String, true) => "String".into(),
-            (ArgType::ObjectPath, false) => "dbus::Path".into(),
-            (ArgType::ObjectPath, true) => "dbus::Path<'static>".into(),
-            (ArgType::Signature, false) => "dbus::Signature".into(),
-            (ArgType::Signature, true) => "dbus::Signature<'static>".into(),
-            (ArgType::Variant, _) => if let &mut Some(ref mut g) = genvars {
-                let t = format!("arg::Variant<{}>", g.prefix);
-                g.gen.push(g.prefix.clone());
-                g.prefix = format!("{}X", g.prefix);
-                t
-            } else if out { "arg::Variant<Box<dyn arg::RefArg + 'static>>".into() }
-            else { "arg::Variant<Box<dyn arg::RefArg>>".into() }
-            (ArgType::Array, _) => if i.peek() == Some(&'{') {
-                i.next();
-                let n1 = xml_to_rust_type(i, out, &mut None)?;
-                let n2 = xml_to_rust_type(i, out, &mut None)?;
-                if i.next() != Some('}') { return Err("No end of dict".into()); }
-                format!("::std::collections::HashMap<{}, {}>", n1, n2)
-            } else {
-                format!("Vec<{}>", xml_to_rust_type(i, out, &mut None)?)
-            },
-            (ArgType::Invalid, _) |
-            (ArgType::Struct, _) |
-            (ArgType::DictEntry, _) => return Err(format!("Unexpected character in signature {:?}", a).into())
-        }
-    };
-    Ok(result)
+        (SignatureType::Basic(ArgType::Byte), _) => "u8".into(),
+        (SignatureType::Basic(ArgType::Boolean), _) => "bool".into(),
+        (SignatureType::Basic(ArgType::Int16), _) => "i16".into(),
+        (SignatureType::Basic(ArgType::UInt16), _) => "u16".into(),
+        (SignatureType::Basic(ArgType::Int32), _) => "i32".into(),
+        (SignatureType::Basic(ArgType::UInt32), _) => "u32".into(),
+        (SignatureType::Basic(ArgType::Int64), _) => "i64".into(),
+        (SignatureType::Basic(ArgType::UInt64), _) => "u64".into(),
+        (SignatureType::Basic(ArgType::Double), _) => "f64".into(),
+        (SignatureType::Basic(ArgType::UnixFd), _) => "arg::OwnedFd".into(),
+        (SignatureType::Basic(ArgType::String), false) => "&str".into(),
+        (SignatureType::Basic(ArgType::String), true) => "String".into(),
+        (SignatureType::Basic(ArgType::ObjectPath), false) => "dbus::Path".into(),
+        (SignatureType::Basic(ArgType::ObjectPath), true) => "dbus::Path<'static>".into(),
+        (SignatureType::Basic(ArgType::Signature), false) => "dbus::Signature".into(),
+        (SignatureType::Basic(ArgType::Signature), true) => "dbus::Signature<'static>".into(),
+        (SignatureType::Basic(a), _) => unreachable!("{:?} is not a basic type", a),
+        (SignatureType::Variant, _) => if let &mut Some(ref mut g) = genvars {
+            let t = format!("arg::Variant<{}>", g.prefix);
+            g.gen.push(g.prefix.clone());
+            g.prefix = format!("{}X", g.prefix);
+            t
+        } else if out { "arg::Variant<Box<dyn arg::RefArg + 'static>>".into() }
+        else { "arg::Variant<Box<dyn arg::RefArg>>".into() }
+        (SignatureType::Dict(k, v), _) => format!("::std::collections::HashMap<{}, {}>",
+            xml_to_rust_type(k, out, &mut None), xml_to_rust_type(v, out, &mut None)),
+        (SignatureType::Array(e), _) => format!("Vec<{}>", xml_to_rust_type(e, out, &mut None)),
+    }
 }
 
 fn make_type(s: &str, out: bool, genvars: &mut Option<GenVars>) -> Result<String, Box<dyn error::Error>> {
-    let mut i = s.chars().peekable();
-    let r = xml_to_rust_type(&mut i, out, genvars)?;
-    if i.next().is_some() { Err("Expected type to end".into()) }
-    else { Ok(r) }
+    let t = SignatureType::parse(s)?;
+    Ok(xml_to_rust_type(&t, out, genvars))
 }
 
 impl Arg {
@@ -0,0 +1,455 @@
+//! Derive macros for the `Arg`, `Append`, `Get` and `RefArg` traits of the dbus crate.
+//!
+//! These can be used directly from this crate, or through `dbus::arg` with the `derive`
+//! feature of the dbus crate enabled.
+//!
+//! # Structs
+//!
+//! A struct with fields (named or unnamed) becomes a D-Bus struct, with the fields in order:
+//!
+//! ```
+//! #[derive(dbus_derive::Arg, dbus_derive::Append, dbus_derive::Get, Debug, PartialEq)]
+//! struct Point { x: i32, y: i32, label: String }
+//!
+//! use dbus::arg::Arg;
+//! assert_eq!(&*Point::signature(), "(iis)");
+//! ```
+//!
+//! # Dicts
+//!
+//! A struct with named fields and the `#[dbus(dict)]` attribute becomes a dict of variants
+//! (`a{sv}`), with the field names as keys. A key can be changed with `#[dbus(rename = "Key")]`.
+//! Fields of type `Option` are left out of the dict when they are `None`, and are `None`
+//! when missing from the dict. Other fields are required, and unknown keys are ignored.
+//!
+//! ```
+//! #[derive(dbus_derive::Arg, dbus_derive::Append, dbus_derive::Get, Debug, PartialEq)]
+//! #[dbus(dict)]
+//! struct Settings {
+//!     #[dbus(rename = "Name")]
+//!     name: String,
+//!     volume: Option<u8>,
+//! }
+//!
+//! use dbus::arg::Arg;
+//! assert_eq!(&*Settings::signature(), "a{sv}");
+//! ```
+//!
+//! # Enums
+//!
+//! An enum without fields becomes a `u`, with the discriminant of the variant as the value.
+//! With the `#[dbus(string)]` attribute, it instead becomes an `s` with the variant name
+//! (which can be changed with `#[dbus(rename = "name")]`).
+//!
+//! ```
+//! #[derive(dbus_derive::Arg, dbus_derive::Append, dbus_derive::Get, Debug, PartialEq)]
+//! #[dbus(string)]
+//! enum State { #[dbus(rename = "on")] On, #[dbus(rename = "off")] Off }
+//!
+//! use dbus::arg::Arg;
+//! assert_eq!(&*State::signature(), "s");
+//! ```
+//!
+//! # RefArg
+//!
+//! Deriving `RefArg` requires the type to also implement `Clone` and `Debug`, and not
+//! to contain any non-static references.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{TokenStream as TokenStream2, Span};
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, DeriveInput, Data, Fields, Lit, Meta, NestedMeta, Error,
+    Ident, Type, Generics, GenericParam, Lifetime, LifetimeDef, Member, Attribute, PathArguments, GenericArgument};
+
+type Result<T> = std::result::Result<T, Error>;
+
+struct Field {
+    member: Member,
+    ty: Type,
+    /// The key of the field, for dicts.
+    key: String,
+    /// The inner type, if this is an optional field of a dict.
+    optional: Option<Type>,
+}
+
+struct Variant {
+    ident: Ident,
+    /// The string value, for enums represented as strings.
+    name: String,
+    /// The integer value, for enums represented as integers.
+    value: TokenStream2,
+}
+
+enum Kind {
+    Struct(Vec<Field>),
+    Dict(Vec<Field>),
+    Enum { variants: Vec<Variant>, string: bool },
+}
+
+struct Input {
+    name: Ident,
+    generics: Generics,
+    kind: Kind,
+}
+
+/// Gets the contents of all `#[dbus(...)]` attributes.
+fn dbus_attrs(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut r = vec!();
+    for a in attrs.iter().filter(|a| a.path.is_ident("dbus")) {
+        match a.parse_meta()? {
+            Meta::List(l) => for n in l.nested {
+                match n {
+                    NestedMeta::Meta(m) => r.push(m),
+                    NestedMeta::Lit(l) => return Err(Error::new_spanned(l, "Unexpected literal in dbus attribute")),
+                }
+            },
+            m => return Err(Error::new_spanned(m, "Expected #[dbus(...)]")),
+        }
+    }
+    Ok(r)
+}
+
+/// Parses the attributes of a field or variant, which can only be `rename`.
+fn rename_attr(attrs: &[Attribute], allowed: bool) -> Result<Option<String>> {
+    let mut r = None;
+    for m in dbus_attrs(attrs)? {
+        match &m {
+            Meta::NameValue(nv) if allowed && nv.path.is_ident("rename") => match &nv.lit {
+                Lit::Str(s) => r = Some(s.value()),
+                l => return Err(Error::new_spanned(l, "Expected a string")),
+            },
+            _ => return Err(Error::new_spanned(m, "Unknown dbus attribute")),
+        }
+    }
+    Ok(r)
+}
+
+/// Returns T, if the type is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<Type> {
+    let p = match ty { Type::Path(p) if p.qself.is_none() => &p.path, _ => return None };
+    let seg = p.segments.last()?;
+    if seg.ident != "Option" { return None }
+    match &seg.arguments {
+        PathArguments::AngleBracketed(a) if a.args.len() == 1 => match a.args.first()? {
+            GenericArgument::Type(t) => Some(t.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_input(input: DeriveInput) -> Result<Input> {
+    let (mut dict, mut string) = (false, false);
+    for m in dbus_attrs(&input.attrs)? {
+        match &m {
+            Meta::Path(p) if p.is_ident("dict") => dict = true,
+            Meta::Path(p) if p.is_ident("string") => string = true,
+            _ => return Err(Error::new_spanned(m, "Unknown dbus attribute, expected 'dict' or 'string'")),
+        }
+    }
+    let kind = match input.data {
+        Data::Struct(s) => {
+            if string { return Err(Error::new_spanned(&input.ident, "#[dbus(string)] is only supported on enums")) }
+            if dict && !matches!(s.fields, Fields::Named(_)) {
+                return Err(Error::new_spanned(&input.ident, "#[dbus(dict)] requires a struct with named fields"))
+            }
+            if s.fields.is_empty() {
+                return Err(Error::new_spanned(&input.ident, "Structs without fields cannot be represented in D-Bus"))
+            }
+            let fields = s.fields.into_iter().enumerate().map(|(idx, f)| {
+                let key = rename_attr(&f.attrs, dict)?;
+                let member = match f.ident {
+                    Some(id) => Member::Named(id),
+                    None => Member::Unnamed(idx.into()),
+                };
+                let key = key.unwrap_or_else(|| match &member {
+                    Member::Named(id) => id.to_string(),
+                    Member::Unnamed(i) => i.index.to_string(),
+                });
+                let optional = if dict { option_inner(&f.ty) } else { None };
+                Ok(Field { member, ty: f.ty, key, optional })
+            }).collect::<Result<Vec<_>>>()?;
+            if dict { Kind::Dict(fields) } else { Kind::Struct(fields) }
+        }
+        Data::Enum(e) => {
+            if dict { return Err(Error::new_spanned(&input.ident, "#[dbus(dict)] is only supported on structs")) }
+            if e.variants.is_empty() {
+                return Err(Error::new_spanned(&input.ident, "Enums without variants cannot be represented in D-Bus"))
+            }
+            let mut prev: Option<TokenStream2> = None;
+            let variants = e.variants.into_iter().map(|v| {
+                if !v.fields.is_empty() {
+                    return Err(Error::new_spanned(&v.ident, "Only enums without fields are supported"))
+                }
+                let name = rename_attr(&v.attrs, string)?.unwrap_or_else(|| v.ident.to_string());
+                let value = match (&v.discriminant, &prev) {
+                    (Some((_, expr)), _) => quote!((#expr) as u32),
+                    (None, Some(p)) => quote!((#p) + 1),
+                    (None, None) => quote!(0u32),
+                };
+                prev = Some(value.clone());
+                Ok(Variant { ident: v.ident, name, value })
+            }).collect::<Result<Vec<_>>>()?;
+            Kind::Enum { variants, string }
+        }
+        Data::Union(u) => return Err(Error::new_spanned(u.union_token, "Unions are not supported")),
+    };
+    Ok(Input { name: input.ident, generics: input.generics, kind })
+}
+
+/// Adds bounds to every type parameter.
+fn with_bounds(generics: &Generics, bounds: &[TokenStream2]) -> Generics {
+    let mut g = generics.clone();
+    for p in g.params.iter_mut() {
+        if let GenericParam::Type(t) = p {
+            for b in bounds { t.bounds.push(parse_quote!(#b)); }
+        }
+    }
+    g
+}
+
+fn arg_type(kind: &Kind) -> TokenStream2 {
+    match kind {
+        Kind::Struct(_) => quote!(::dbus::arg::ArgType::Struct),
+        Kind::Dict(_) => quote!(::dbus::arg::ArgType::Array),
+        Kind::Enum { string: false, .. } => quote!(::dbus::arg::ArgType::UInt32),
+        Kind::Enum { string: true, .. } => quote!(::dbus::arg::ArgType::String),
+    }
+}
+
+/// The signature, where field_sig returns the signature of a field.
+fn signature<F: Fn(&Field) -> TokenStream2>(kind: &Kind, field_sig: F) -> TokenStream2 {
+    match kind {
+        Kind::Struct(fields) => {
+            let sigs = fields.iter().map(field_sig);
+            quote! {
+                let mut s = String::from("(");
+                #( s.push_str(&#sigs); )*
+                s.push_str(")");
+                ::dbus::Signature::from(s)
+            }
+        }
+        Kind::Dict(_) => quote!(::dbus::Signature::from("a{sv}")),
+        Kind::Enum { string: false, .. } => quote!(::dbus::Signature::from("u")),
+        Kind::Enum { string: true, .. } => quote!(::dbus::Signature::from("s")),
+    }
+}
+
+/// The body of an append method, appending to `i`.
+///
+/// append_field returns code that appends the expression to `s`,
+/// field_sig returns the signature of a field given the expression.
+fn append_body<A, S>(input: &Input, append_field: A, field_sig: S) -> TokenStream2
+where A: Fn(&TokenStream2) -> TokenStream2, S: Fn(&Field, &TokenStream2) -> TokenStream2 {
+    let name = &input.name;
+    match &input.kind {
+        Kind::Struct(fields) => {
+            let appends = fields.iter().map(|f| { let m = &f.member; append_field(&quote!(&self.#m)) });
+            quote! { i.append_struct(|s| { #( #appends )* }); }
+        }
+        Kind::Dict(fields) => {
+            let entries = fields.iter().map(|f| {
+                let (m, key) = (&f.member, &f.key);
+                let (x, outer) = if f.optional.is_some() { (quote!(x), Some(quote!(&self.#m))) } else { (quote!(&self.#m), None) };
+                let (sig, append) = (field_sig(f, &x), append_field(&x));
+                let entry = quote! {
+                    d.append_dict_entry(|e| {
+                        e.append(#key);
+                        e.append_variant(&#sig, |s| { #append });
+                    });
+                };
+                match outer {
+                    Some(o) => quote! { if let Some(x) = #o { #entry } },
+                    None => entry,
+                }
+            });
+            quote! {
+                i.append_dict(&::dbus::Signature::from("s"), &::dbus::Signature::from("v"), |d| { #( #entries )* });
+            }
+        }
+        Kind::Enum { variants, string: false } => {
+            let (ids, values) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.value));
+            quote! {
+                let v: u32 = match self { #( #name::#ids => #values, )* };
+                i.append(v);
+            }
+        }
+        Kind::Enum { variants, string: true } => {
+            let (ids, names) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.name));
+            quote! { i.append(match self { #( #name::#ids => #names, )* }); }
+        }
+    }
+}
+
+fn derive_arg(input: &Input) -> TokenStream2 {
+    let name = &input.name;
+    let g = with_bounds(&input.generics, &[quote!(::dbus::arg::Arg)]);
+    let (impl_g, ty_g, where_c) = g.split_for_impl();
+    let at = arg_type(&input.kind);
+    let sig = signature(&input.kind, |f| { let ty = &f.ty; quote!(<#ty as ::dbus::arg::Arg>::signature()) });
+    quote! {
+        impl #impl_g ::dbus::arg::Arg for #name #ty_g #where_c {
+            const ARG_TYPE: ::dbus::arg::ArgType = #at;
+            fn signature() -> ::dbus::Signature<'static> { #sig }
+        }
+    }
+}
+
+fn derive_append(input: &Input) -> TokenStream2 {
+    let name = &input.name;
+    let g = with_bounds(&input.generics, &[quote!(::dbus::arg::Arg), quote!(::dbus::arg::Append)]);
+    let (impl_g, ty_g, where_c) = g.split_for_impl();
+    let body = append_body(input,
+        |x| quote!(::dbus::arg::Append::append_by_ref(#x, s);),
+        |f, _| { let ty = f.optional.as_ref().unwrap_or(&f.ty); quote!(<#ty as ::dbus::arg::Arg>::signature()) });
+    quote! {
+        impl #impl_g ::dbus::arg::Append for #name #ty_g #where_c {
+            fn append_by_ref(&self, i: &mut ::dbus::arg::IterAppend) { #body }
+        }
+    }
+}
+
+fn derive_get(input: &Input) -> TokenStream2 {
+    let name = &input.name;
+    // Reuse the lifetime of the type if it has exactly one, so that e g &'a str fields can be borrowed
+    let lifetimes: Vec<_> = input.generics.lifetimes().map(|l| l.lifetime.clone()).collect();
+    let lt = if lifetimes.len() == 1 { lifetimes[0].clone() } else { Lifetime::new("'dbus_get", Span::call_site()) };
+    let mut g = with_bounds(&input.generics, &[quote!(::dbus::arg::Get<#lt>)]);
+    if lifetimes.len() != 1 { g.params.insert(0, GenericParam::Lifetime(LifetimeDef::new(lt.clone()))); }
+    let (impl_g, _, where_c) = g.split_for_impl();
+    let (_, ty_g, _) = input.generics.split_for_impl();
+
+    let body = match &input.kind {
+        Kind::Struct(fields) => {
+            let vars: Vec<_> = (0..fields.len()).map(|i| Ident::new(&format!("f{}", i), Span::call_site())).collect();
+            let tys = fields.iter().map(|f| &f.ty);
+            let members = fields.iter().map(|f| &f.member);
+            quote! {
+                let mut s = i.recurse(::dbus::arg::ArgType::Struct)?;
+                #( let #vars: #tys = s.get()?; s.next(); )*
+                Some(#name { #( #members: #vars, )* })
+            }
+        }
+        Kind::Dict(fields) => {
+            let vars: Vec<_> = (0..fields.len()).map(|i| Ident::new(&format!("f{}", i), Span::call_site())).collect();
+            let tys = fields.iter().map(|f| f.optional.as_ref().unwrap_or(&f.ty));
+            let keys = fields.iter().map(|f| &f.key);
+            let members = fields.iter().map(|f| &f.member);
+            let values = fields.iter().zip(&vars).map(|(f, v)| if f.optional.is_some() { quote!(#v) } else { quote!(#v?) });
+            quote! {
+                if &*i.signature() != "a{sv}" { return None }
+                let mut a = i.recurse(::dbus::arg::ArgType::Array)?;
+                #( let mut #vars: Option<#tys> = None; )*
+                while let Some(mut e) = a.recurse(::dbus::arg::ArgType::DictEntry) {
+                    let k: &str = e.get()?;
+                    e.next();
+                    let mut v = e.recurse(::dbus::arg::ArgType::Variant)?;
+                    match k {
+                        #( #keys => #vars = Some(v.get()?), )*
+                        _ => {},
+                    }
+                    a.next();
+                }
+                Some(#name { #( #members: #values, )* })
+            }
+        }
+        Kind::Enum { variants, string: false } => {
+            let (ids, values) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.value));
+            quote! {
+                let v: u32 = i.get()?;
+                #( if v == #values { return Some(#name::#ids) } )*
+                None
+            }
+        }
+        Kind::Enum { variants, string: true } => {
+            let (ids, names) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.name));
+            quote! {
+                let v: &str = i.get()?;
+                match v {
+                    #( #names => Some(#name::#ids), )*
+                    _ => None,
+                }
+            }
+        }
+    };
+    quote! {
+        impl #impl_g ::dbus::arg::Get<#lt> for #name #ty_g #where_c {
+            fn get(i: &mut ::dbus::arg::Iter<#lt>) -> Option<Self> { #body }
+        }
+    }
+}
+
+fn derive_refarg(input: &Input) -> TokenStream2 {
+    let name = &input.name;
+    let mut g = with_bounds(&input.generics, &[quote!(::dbus::arg::RefArg)]);
+    let (_, ty_g, _) = input.generics.split_for_impl();
+    g.make_where_clause().predicates.push(parse_quote!(#name #ty_g: Clone + 'static));
+    let (impl_g, _, where_c) = g.split_for_impl();
+    let at = arg_type(&input.kind);
+    let sig = signature(&input.kind, |f| { let m = &f.member; quote!(::dbus::arg::RefArg::signature(&self.#m)) });
+    let body = append_body(input,
+        |x| quote!(::dbus::arg::RefArg::append(#x, s);),
+        |_, x| quote!(::dbus::arg::RefArg::signature(#x)));
+    let extra = match &input.kind {
+        Kind::Struct(fields) => {
+            let members = fields.iter().map(|f| &f.member);
+            quote! {
+                fn as_iter<'dbus_iter>(&'dbus_iter self) -> Option<Box<dyn Iterator<Item=&'dbus_iter dyn ::dbus::arg::RefArg> + 'dbus_iter>> {
+                    let v: Vec<&dyn ::dbus::arg::RefArg> = vec!( #( &self.#members, )* );
+                    Some(Box::new(v.into_iter()))
+                }
+            }
+        }
+        Kind::Dict(_) => quote!(),
+        Kind::Enum { variants, string: false } => {
+            let (ids, values) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.value));
+            quote! {
+                fn as_u64(&self) -> Option<u64> { Some(match self { #( #name::#ids => #values, )* }.into()) }
+                fn as_i64(&self) -> Option<i64> { self.as_u64().map(|v| v as i64) }
+            }
+        }
+        Kind::Enum { variants, string: true } => {
+            let (ids, names) = (variants.iter().map(|v| &v.ident), variants.iter().map(|v| &v.name));
+            quote! {
+                fn as_str(&self) -> Option<&str> { Some(match self { #( #name::#ids => #names, )* }) }
+            }
+        }
+    };
+    quote! {
+        impl #impl_g ::dbus::arg::RefArg for #name #ty_g #where_c {
+            fn arg_type(&self) -> ::dbus::arg::ArgType { #at }
+            fn signature(&self) -> ::dbus::Signature<'static> { #sig }
+            fn append(&self, i: &mut ::dbus::arg::IterAppend) { #body }
+            fn as_any(&self) -> &dyn ::std::any::Any where Self: 'static { self }
+            fn as_any_mut(&mut self) -> &mut dyn ::std::any::Any where Self: 'static { self }
+            fn box_clone(&self) -> Box<dyn ::dbus::arg::RefArg + 'static> { Box::new(self.clone()) }
+            #extra
+        }
+    }
+}
+
+fn derive(input: TokenStream, f: fn(&Input) -> TokenStream2) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match parse_input(input) {
+        Ok(i) => f(&i).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derives `dbus::arg::Arg`, i e the D-Bus type and signature.
+#[proc_macro_derive(Arg, attributes(dbus))]
+pub fn arg(input: TokenStream) -> TokenStream { derive(input, derive_arg) }
+
+/// Derives `dbus::arg::Append`. The type must also implement `Arg`.
+#[proc_macro_derive(Append, attributes(dbus))]
+pub fn append(input: TokenStream) -> TokenStream { derive(input, derive_append) }
+
+/// Derives `dbus::arg::Get`.
+#[proc_macro_derive(Get, attributes(dbus))]
+pub fn get(input: TokenStream) -> TokenStream { derive(input, derive_get) }
+
+/// Derives `dbus::arg::RefArg`. The type must also implement `Clone` and `Debug`.
+#[proc_macro_derive(RefArg, attributes(dbus))]
+pub fn refarg(input: TokenStream) -> TokenStream { derive(input, derive_refarg) }
@@ -0,0 +1,123 @@
+use dbus::arg::{Arg, RefArg, Variant, IterAppend};
+use dbus::Message;
+use dbus_derive as d;
+use std::collections::HashMap;
+
+#[derive(d::Arg, d::Append, d::Get, d::RefArg, Debug, PartialEq, Clone)]
+struct Point { x: i32, y: i32, label: String }
+
+#[derive(d::Arg, d::Append, d::Get, Debug, PartialEq)]
+struct Borrowed<'a>(&'a str, Vec<Point>);
+
+#[derive(d::Arg, d::Append, d::Get, Debug, PartialEq)]
+struct Generic<T> { value: T, count: u8 }
+
+#[derive(d::Arg, d::Append, d::Get, d::RefArg, Debug, PartialEq, Clone)]
+#[dbus(dict)]
+struct Settings {
+    #[dbus(rename = "Name")]
+    name: String,
+    volume: Option<u8>,
+    origin: Point,
+}
+
+#[derive(d::Arg, d::Append, d::Get, d::RefArg, Debug, PartialEq, Clone, Copy)]
+enum Level { Low, Medium = 5, High }
+
+#[derive(d::Arg, d::Append, d::Get, d::RefArg, Debug, PartialEq, Clone, Copy)]
+#[dbus(string)]
+enum State { #[dbus(rename = "on")] On, Off }
+
+fn msg() -> Message { Message::new_method_call("a.b", "/", "a.b", "c").unwrap() }
+
+/// Appends the value, checks that the signature agrees with what was appended, and reads it back.
+fn roundtrip<T: Arg + dbus::arg::Append + for<'a> dbus::arg::Get<'a> + PartialEq + std::fmt::Debug>(v: T) -> Message {
+    let m = msg().append1(&v);
+    assert_eq!(m.iter_init().signature(), <T as Arg>::signature());
+    assert_eq!(m.read1::<T>().unwrap(), v);
+    m
+}
+
+#[test]
+fn structs() {
+    let p = Point { x: 1, y: -2, label: "p".into() };
+    assert_eq!(&*<Point as Arg>::signature(), "(iis)");
+    let m = roundtrip(p.clone());
+    assert_eq!(m.read1::<(i32, i32, &str)>().unwrap(), (1, -2, "p"));
+
+    assert_eq!(&*<Generic<Vec<String>> as Arg>::signature(), "(asy)");
+    roundtrip(Generic { value: vec!("a".to_string()), count: 3 });
+
+    let b = Borrowed("x", vec!(p.clone(), p));
+    let m = msg().append1(&b);
+    assert_eq!(m.iter_init().signature(), <Borrowed as Arg>::signature());
+    assert_eq!(&*<Borrowed as Arg>::signature(), "(sa(iis))");
+    assert_eq!(m.read1::<Borrowed>().unwrap(), b);
+}
+
+#[test]
+fn dicts() {
+    let p = Point { x: 1, y: 2, label: "o".into() };
+    let s = Settings { name: "n".into(), volume: Some(7), origin: p.clone() };
+    let m = roundtrip(s.clone());
+    let h: HashMap<String, Variant<Box<dyn RefArg>>> = m.read1().unwrap();
+    assert_eq!(h.len(), 3);
+    assert_eq!(h["Name"].0.as_str(), Some("n"));
+    assert_eq!(h["volume"].0.as_u64(), Some(7));
+
+    let s2 = Settings { volume: None, ..s };
+    let m = roundtrip(s2.clone());
+    let h: HashMap<String, Variant<Box<dyn RefArg>>> = m.read1().unwrap();
+    assert!(!h.contains_key("volume"));
+
+    // Unknown keys are ignored, missing required keys fail
+    let mut h: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+    h.insert("Name", Variant(Box::new("n".to_string())));
+    h.insert("extra", Variant(Box::new(5u32)));
+    assert!(msg().append1(&h).read1::<Settings>().is_err());
+    h.insert("origin", Variant(Box::new((1i32, 2i32, "o".to_string()))));
+    assert_eq!(msg().append1(&h).read1::<Settings>().unwrap(), s2);
+}
+
+#[test]
+fn enums() {
+    assert_eq!(&*<Level as Arg>::signature(), "u");
+    assert_eq!(&*<State as Arg>::signature(), "s");
+    roundtrip(Level::Low);
+    roundtrip(Level::High);
+    roundtrip(State::On);
+    assert_eq!(msg().append1(Level::High).read1::<u32>().unwrap(), 6);
+    assert_eq!(msg().append1(Level::Medium).read1::<u32>().unwrap(), 5);
+    assert_eq!(msg().append1(State::On).read1::<&str>().unwrap(), "on");
+    assert_eq!(msg().append1(State::Off).read1::<&str>().unwrap(), "Off");
+    assert!(msg().append1(3u32).read1::<Level>().is_err());
+    assert!(msg().append1("Other").read1::<State>().is_err());
+}
+
+#[test]
+fn refargs() {
+    let v: Vec<Box<dyn RefArg>> = vec!(
+        Box::new(Point { x: 1, y: 2, label: "a".into() }),
+        Box::new(Settings { name: "n".into(), volume: None, origin: Point { x: 0, y: 0, label: "".into() } }),
+        Box::new(Level::Medium),
+        Box::new(State::Off),
+    );
+    let mut m = msg();
+    {
+        let mut ia = IterAppend::new(&mut m);
+        for x in &v {
+            assert_eq!(x.box_clone().signature(), x.signature());
+            x.append(&mut ia);
+        }
+    }
+    let (mut i, mut sig) = (m.iter_init(), String::new());
+    loop { sig.push_str(&i.signature()); if !i.next() { break } }
+    assert_eq!(sig, "(iis)a{sv}us");
+    assert_eq!(v[2].as_u64(), Some(5));
+    assert_eq!(v[3].as_str(), Some("Off"));
+    assert_eq!(v[0].as_iter().unwrap().count(), 3);
+    let (p, s, l, st): (Point, Settings, Level, State) = m.read4().unwrap();
+    assert_eq!(&p.label, "a");
+    assert_eq!(&s.name, "n");
+    assert_eq!((l, st), (Level::Medium, State::Off));
+}
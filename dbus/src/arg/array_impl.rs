@@ -1,12 +1,11 @@
 use super::*;
-use crate::{Message, ffi};
+use crate::Message;
 use crate::strings::{Signature, Path};
 use std::marker::PhantomData;
-use std::{ptr, mem, any, fmt};
-use super::check;
+use std::{mem, any, fmt};
 use std::ffi::{CString};
-use std::os::raw::{c_void, c_int};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet, VecDeque};
+use std::convert::TryFrom;
 use std::hash::{Hash, BuildHasher};
 
 // Map DBus-Type -> Alignment. Copied from _dbus_marshal_write_fixed_multi in
@@ -31,7 +30,6 @@ impl<'a, T: Arg> Arg for &'a [T] {
 }
 
 fn array_append<T: Arg, F: FnMut(&T, &mut IterAppend)>(z: &[T], i: &mut IterAppend, mut f: F) {
-    let zptr = z.as_ptr();
     let zlen = z.len() as i32;
 
     // Can we do append_fixed_array?
@@ -39,8 +37,7 @@ fn array_append<T: Arg, F: FnMut(&T, &mut IterAppend)>(z: &[T], i: &mut IterAppe
     let can_fixed_array = (zlen > 1) && (z.len() == zlen as usize) && FIXED_ARRAY_ALIGNMENTS.iter().any(|&v| v == a);
 
     i.append_container(ArgType::Array, Some(T::signature().as_cstr()), |s|
-        if can_fixed_array { unsafe { check("dbus_message_iter_append_fixed_array",
-            ffi::dbus_message_iter_append_fixed_array(&mut s.0, a.0 as c_int, &zptr as *const _ as *const c_void, zlen)) }}
+        if can_fixed_array { s.append_fixed_array(a.0, z) }
         else { for arg in z { f(arg, s); }}
     );
 }
@@ -63,6 +60,9 @@ impl<'a, T: Arg + RefArg> RefArg for &'a [T] {
     fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
     #[inline]
     fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_iter<'b>(&'b self) -> Option<Box<dyn Iterator<Item=&'b dyn RefArg> + 'b>> {
+        Some(Box::new(self.iter().map(|b| b as &dyn RefArg)))
+    }
 
     fn box_clone(&self) -> Box<dyn RefArg + 'static> {
         Box::new(InternalArray {
@@ -93,21 +93,7 @@ impl<T: Arg + RefArg> RefArg for Vec<T> {
 impl<'a, T: FixedArray> Get<'a> for &'a [T] {
     fn get(i: &mut Iter<'a>) -> Option<&'a [T]> {
         debug_assert!(FIXED_ARRAY_ALIGNMENTS.iter().any(|&v| v == (T::ARG_TYPE, mem::size_of::<T>())));
-        i.recurse(Self::ARG_TYPE).and_then(|mut si| unsafe {
-            let etype = ffi::dbus_message_iter_get_element_type(&mut i.0);
-
-            if etype != T::ARG_TYPE as c_int { return None };
-
-            let mut v: *mut T = ptr::null_mut();
-            let mut i = 0;
-            ffi::dbus_message_iter_get_fixed_array(&mut si.0, &mut v as *mut _ as *mut c_void, &mut i);
-            if v.is_null() {
-                assert_eq!(i, 0);
-                Some(&[][..])
-            } else {
-                Some(::std::slice::from_raw_parts(v, i as usize))
-            }
-        })
+        i.get_fixed_array(T::ARG_TYPE)
     }
 }
 
@@ -191,15 +177,55 @@ impl<'a, K: DictKey + Get<'a> + Eq + Hash, V: Arg + Get<'a>, S: BuildHasher + De
 impl<K: DictKey + RefArg + Eq + Hash, V: RefArg + Arg, S: BuildHasher> RefArg for HashMap<K, V, S> {
     fn arg_type(&self) -> ArgType { ArgType::Array }
     fn signature(&self) -> Signature<'static> { format!("a{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature()).into() }
-    fn append(&self, i: &mut IterAppend) {
-        let sig = CString::new(format!("{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature())).unwrap();
-        i.append_container(ArgType::Array, Some(&sig), |s| for (k, v) in self {
-            s.append_container(ArgType::DictEntry, None, |ss| {
-                k.append(ss);
-                v.append(ss);
-            })
-        });
+    fn append(&self, i: &mut IterAppend) { dict_refarg_append(self.iter(), i) }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_iter<'b>(&'b self) -> Option<Box<dyn Iterator<Item=&'b dyn RefArg> + 'b>> {
+        Some(Box::new(self.iter().flat_map(|(k, v)| vec![k as &dyn RefArg, v as &dyn RefArg].into_iter())))
+    }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> {
+        Box::new(InternalDict {
+            outer_sig: self.signature(),
+            data: self.iter().map(|(k, v)| (k.box_clone(), v.box_clone())).collect(),
+        })
+    }
+}
+
+fn dict_refarg_append<'b, K: 'b + Arg + RefArg, V: 'b + Arg + RefArg, I: Iterator<Item=(&'b K, &'b V)>>(z: I, i: &mut IterAppend) {
+    let sig = CString::new(format!("{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature())).unwrap();
+    i.append_container(ArgType::Array, Some(&sig), |s| for (k, v) in z {
+        s.append_container(ArgType::DictEntry, None, |ss| {
+            k.append(ss);
+            v.append(ss);
+        })
+    });
+}
+
+impl<K: DictKey, V: Arg> Arg for BTreeMap<K, V> {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> {
+        Signature::from(format!("a{{{}{}}}", K::signature(), V::signature())) }
+}
+
+impl<K: DictKey + Append + Ord, V: Arg + Append> Append for BTreeMap<K, V> {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        Dict::new(self.iter()).append_by_ref(i);
     }
+}
+
+impl<'a, K: DictKey + Get<'a> + Ord, V: Arg + Get<'a>> Get<'a> for BTreeMap<K, V> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        Dict::get(i).map(|d| d.collect())
+    }
+}
+
+impl<K: DictKey + RefArg + Ord, V: RefArg + Arg> RefArg for BTreeMap<K, V> {
+    fn arg_type(&self) -> ArgType { ArgType::Array }
+    fn signature(&self) -> Signature<'static> { format!("a{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature()).into() }
+    fn append(&self, i: &mut IterAppend) { dict_refarg_append(self.iter(), i) }
     #[inline]
     fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
     #[inline]
@@ -234,6 +260,128 @@ impl<'a, T: Arg + Get<'a>> Get<'a> for Vec<T> {
 }
 
 
+/// Represents a D-Bus array.
+impl<T: Arg> Arg for Box<[T]> {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> { Signature::from(format!("a{}", T::signature())) }
+}
+
+impl<T: Arg + Append> Append for Box<[T]> {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        Array::new(self.iter()).append_by_ref(i);
+    }
+}
+
+impl<'a, T: Arg + Get<'a>> Get<'a> for Box<[T]> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        Vec::get(i).map(Vec::into_boxed_slice)
+    }
+}
+
+impl<T: Arg + RefArg> RefArg for Box<[T]> {
+    fn arg_type(&self) -> ArgType { ArgType::Array }
+    fn signature(&self) -> Signature<'static> { Signature::from(format!("a{}", <T as Arg>::signature())) }
+    fn append(&self, i: &mut IterAppend) {
+        array_append(self, i, |arg, s| RefArg::append(arg,s));
+    }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_iter<'a>(&'a self) -> Option<Box<dyn Iterator<Item=&'a dyn RefArg> + 'a>> {
+        Some(Box::new(self.iter().map(|b| b as &dyn RefArg)))
+    }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { (&**self).box_clone() }
+}
+
+/// Represents a D-Bus array. Reading fails if the array does not have exactly N elements.
+impl<T: Arg, const N: usize> Arg for [T; N] {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> { Signature::from(format!("a{}", T::signature())) }
+}
+
+impl<T: Arg + Append, const N: usize> Append for [T; N] {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        Array::new(self.iter()).append_by_ref(i);
+    }
+}
+
+impl<'a, T: Arg + Get<'a>, const N: usize> Get<'a> for [T; N] {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        Vec::get(i).and_then(|v| <[T; N]>::try_from(v).ok())
+    }
+}
+
+impl<T: Arg + RefArg, const N: usize> RefArg for [T; N] {
+    fn arg_type(&self) -> ArgType { ArgType::Array }
+    fn signature(&self) -> Signature<'static> { Signature::from(format!("a{}", <T as Arg>::signature())) }
+    fn append(&self, i: &mut IterAppend) {
+        array_append(self, i, |arg, s| RefArg::append(arg,s));
+    }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_iter<'a>(&'a self) -> Option<Box<dyn Iterator<Item=&'a dyn RefArg> + 'a>> {
+        Some(Box::new(self.iter().map(|b| b as &dyn RefArg)))
+    }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { (&self[..]).box_clone() }
+}
+
+macro_rules! seq_impl {
+    ($t: ident <T $(, $s: ident)*>, [$($tb: path),*], [$($w: tt)*]) => {
+
+/// Represents a D-Bus array.
+impl<T: Arg $(, $s)*> Arg for $t<T $(, $s)*> {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> { Signature::from(format!("a{}", T::signature())) }
+}
+
+impl<T: Arg + Append $(, $s)*> Append for $t<T $(, $s)*> {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        Array::new(self.iter()).append_by_ref(i);
+    }
+}
+
+impl<'a, T: Arg + Get<'a> $(+ $tb)* $(, $s)*> Get<'a> for $t<T $(, $s)*> where $($w)* {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        <Array<T, Iter<'a>>>::get(i).map(|a| a.collect())
+    }
+}
+
+impl<T: Arg + RefArg $(, $s)*> RefArg for $t<T $(, $s)*> {
+    fn arg_type(&self) -> ArgType { ArgType::Array }
+    fn signature(&self) -> Signature<'static> { Signature::from(format!("a{}", <T as Arg>::signature())) }
+    fn append(&self, i: &mut IterAppend) {
+        i.append_container(ArgType::Array, Some(<T as Arg>::signature().as_cstr()), |s|
+            for arg in self { RefArg::append(arg,s) }
+        );
+    }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_iter<'b>(&'b self) -> Option<Box<dyn Iterator<Item=&'b dyn RefArg> + 'b>> {
+        Some(Box::new(self.iter().map(|b| b as &dyn RefArg)))
+    }
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> {
+        Box::new(InternalArray {
+            inner_sig: <T as Arg>::signature(),
+            data: self.iter().map(|x| x.box_clone()).collect(),
+        })
+    }
+}
+
+    }
+}
+
+seq_impl!(VecDeque<T>, [], []);
+seq_impl!(BTreeSet<T>, [Ord], []);
+seq_impl!(HashSet<T, S>, [Eq, Hash], [S: BuildHasher + Default]);
+
+
 #[derive(Copy, Clone, Debug)]
 /// Represents a D-Bus Array. Maximum flexibility (wraps an iterator of items to append). 
 ///
@@ -436,7 +584,7 @@ impl RefArg for InternalArray {
 
 pub fn get_array_refarg(i: &mut Iter) -> Box<dyn RefArg> {
     debug_assert!(i.arg_type() == ArgType::Array);
-    let etype = ArgType::from_i32(unsafe { ffi::dbus_message_iter_get_element_type(&mut i.0) } as i32).unwrap();
+    let etype = i.element_type();
 
     let x = match etype {
         ArgType::Byte => get_fixed_array_refarg::<u8>(i),
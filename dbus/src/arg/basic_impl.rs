@@ -1,44 +1,11 @@
-use crate::ffi;
 use super::*;
-use super::check;
 use crate::strings::{Signature, Path};
-use std::{ptr, any, mem};
+use std::any;
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::ffi::CStr;
-use std::os::raw::{c_void, c_char, c_int};
-
-
-fn arg_append_basic<T>(i: *mut ffi::DBusMessageIter, arg_type: ArgType, v: T) {
-    let p = &v as *const _ as *const c_void;
-    unsafe {
-        check("dbus_message_iter_append_basic", ffi::dbus_message_iter_append_basic(i, arg_type as c_int, p));
-    };
-}
-
-fn arg_get_basic<T>(i: *mut ffi::DBusMessageIter, arg_type: ArgType) -> Option<T> {
-    unsafe {
-        let mut c: T = mem::zeroed();
-        if ffi::dbus_message_iter_get_arg_type(i) != arg_type as c_int { return None };
-        ffi::dbus_message_iter_get_basic(i, &mut c as *mut _ as *mut c_void);
-        Some(c)
-    }
-}
-
-fn arg_append_str(i: *mut ffi::DBusMessageIter, arg_type: ArgType, v: &CStr) {
-    let p = v.as_ptr();
-    let q = &p as *const _ as *const c_void;
-    unsafe {
-        check("dbus_message_iter_append_basic", ffi::dbus_message_iter_append_basic(i, arg_type as c_int, q));
-    };
-}
-
-unsafe fn arg_get_str<'a>(i: *mut ffi::DBusMessageIter, arg_type: ArgType) -> Option<&'a CStr> {
-    if ffi::dbus_message_iter_get_arg_type(i) != arg_type as c_int { return None };
-    let mut p = ptr::null_mut();
-    ffi::dbus_message_iter_get_basic(i, &mut p as *mut _ as *mut c_void);
-    Some(CStr::from_ptr(p as *const c_char))
-}
-
-
+use std::os::raw::c_char;
 
 
 // Implementation for basic types.
@@ -53,11 +20,11 @@ impl Arg for $t {
 }
 
 impl Append for $t {
-    fn append_by_ref(&self, i: &mut IterAppend) { arg_append_basic(&mut i.0, ArgType::$s, *self) }
+    fn append_by_ref(&self, i: &mut IterAppend) { i.append_basic(ArgType::$s, *self) }
 }
 
 impl<'a> Get<'a> for $t {
-    fn get(i: &mut Iter) -> Option<Self> { arg_get_basic(&mut i.0, ArgType::$s) }
+    fn get(i: &mut Iter) -> Option<Self> { i.get_basic(ArgType::$s) }
 }
 
 impl RefArg for $t {
@@ -66,7 +33,7 @@ impl RefArg for $t {
     #[inline]
     fn signature(&self) -> Signature<'static> { unsafe { Signature::from_slice_unchecked($f) } }
     #[inline]
-    fn append(&self, i: &mut IterAppend) { arg_append_basic(&mut i.0, ArgType::$s, *self) }
+    fn append(&self, i: &mut IterAppend) { i.append_basic(ArgType::$s, *self) }
     #[inline]
     fn as_any(&self) -> &dyn any::Any { self }
     #[inline]
@@ -130,11 +97,11 @@ impl Arg for bool {
     fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"b\0") } }
 }
 impl Append for bool {
-    fn append_by_ref(&self, i: &mut IterAppend) { arg_append_basic(&mut i.0, ArgType::Boolean, if *self {1} else {0}) }
+    fn append_by_ref(&self, i: &mut IterAppend) { i.append_basic(ArgType::Boolean, if *self {1} else {0}) }
 }
 impl DictKey for bool {}
 impl<'a> Get<'a> for bool {
-    fn get(i: &mut Iter) -> Option<Self> { arg_get_basic::<u32>(&mut i.0, ArgType::Boolean).map(|q| q != 0) }
+    fn get(i: &mut Iter) -> Option<Self> { i.get_basic::<u32>( ArgType::Boolean).map(|q| q != 0) }
 }
 
 refarg_impl!(bool, _i, Some(if *_i { 1 } else { 0 }), None, Some(if *_i { 1 as u64 } else { 0 as u64 }), Some(if *_i { 1 as f64 } else { 0 as f64 }));
@@ -144,11 +111,11 @@ impl Arg for f64 {
     fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"d\0") } }
 }
 impl Append for f64 {
-    fn append_by_ref(&self, i: &mut IterAppend) { arg_append_basic(&mut i.0, ArgType::Double, *self) }
+    fn append_by_ref(&self, i: &mut IterAppend) { i.append_basic(ArgType::Double, *self) }
 }
 impl DictKey for f64 {}
 impl<'a> Get<'a> for f64 {
-    fn get(i: &mut Iter) -> Option<Self> { arg_get_basic(&mut i.0, ArgType::Double) }
+    fn get(i: &mut Iter) -> Option<Self> { i.get_basic(ArgType::Double) }
 }
 unsafe impl FixedArray for f64 {}
 
@@ -162,7 +129,6 @@ impl<'a> Arg for &'a str {
 
 impl<'a> Append for &'a str {
     fn append_by_ref(&self, i: &mut IterAppend) {
-        use std::borrow::Cow;
         let b: &[u8] = self.as_bytes();
         let v: Cow<[u8]> = if !b.is_empty() && b[b.len()-1] == 0 { Cow::Borrowed(b) }
         else {
@@ -171,12 +137,12 @@ impl<'a> Append for &'a str {
             Cow::Owned(bb)
         };
         let z = unsafe { CStr::from_ptr(v.as_ptr() as *const c_char) };
-        arg_append_str(&mut i.0, ArgType::String, &z)
+        i.append_str(ArgType::String, z)
     }
 }
 impl<'a> DictKey for &'a str {}
 impl<'a> Get<'a> for &'a str {
-    fn get(i: &mut Iter<'a>) -> Option<&'a str> { unsafe { arg_get_str(&mut i.0, ArgType::String) }
+    fn get(i: &mut Iter<'a>) -> Option<&'a str> { i.get_str(ArgType::String)
         .and_then(|s| s.to_str().ok()) }
 }
 
@@ -201,6 +167,48 @@ impl<'a> Get<'a> for String {
 
 refarg_impl!(String, _i, None, Some(&_i), None, None);
 
+macro_rules! str_ptr_impl {
+    ($t: ident) => {
+
+/// Represents a D-Bus string.
+impl Arg for $t<str> {
+    const ARG_TYPE: ArgType = ArgType::String;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"s\0") } }
+}
+impl Append for $t<str> {
+    fn append_by_ref(&self, i: &mut IterAppend) { (&**self).append_by_ref(i) }
+}
+impl DictKey for $t<str> {}
+impl<'a> Get<'a> for $t<str> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> { <&str>::get(i).map($t::from) }
+}
+
+refarg_impl!($t<str>, _i, None, Some(&_i), None, None);
+
+    }
+}
+
+str_ptr_impl!(Box);
+str_ptr_impl!(Rc);
+str_ptr_impl!(Arc);
+
+/// Represents a D-Bus string.
+impl<'a> Arg for Cow<'a, str> {
+    const ARG_TYPE: ArgType = ArgType::String;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"s\0") } }
+}
+impl<'a> Append for Cow<'a, str> {
+    fn append_by_ref(&self, i: &mut IterAppend) { (&**self).append_by_ref(i) }
+}
+impl<'a> DictKey for Cow<'a, str> {}
+
+// Like Path and Signature, only the owned version can be read, so that it works with ReadAll.
+impl<'a> Get<'a> for Cow<'static, str> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> { <&str>::get(i).map(|s| Cow::Owned(s.into())) }
+}
+
+refarg_impl!(Cow<'static, str>, _i, None, Some(_i), None, None);
+
 /// Represents a D-Bus string.
 impl<'a> Arg for &'a CStr {
     const ARG_TYPE: ArgType = ArgType::String;
@@ -211,14 +219,14 @@ impl<'a> Arg for &'a CStr {
 /// Note: Will give D-Bus errors in case the CStr is not valid UTF-8.
 impl<'a> Append for &'a CStr {
     fn append(self, i: &mut IterAppend) {
-        arg_append_str(&mut i.0, Self::arg_type(), &self)
+        i.append_str(Self::arg_type(), &self)
     }
 }
 */
 
 impl<'a> DictKey for &'a CStr {}
 impl<'a> Get<'a> for &'a CStr {
-    fn get(i: &mut Iter<'a>) -> Option<&'a CStr> { unsafe { arg_get_str(&mut i.0, Self::ARG_TYPE) }}
+    fn get(i: &mut Iter<'a>) -> Option<&'a CStr> { i.get_str(Self::ARG_TYPE)}
 }
 
 impl Arg for OwnedFd {
@@ -227,13 +235,13 @@ impl Arg for OwnedFd {
 }
 impl Append for OwnedFd {
     fn append_by_ref(&self, i: &mut IterAppend) {
-        arg_append_basic(&mut i.0, ArgType::UnixFd, self.as_raw_fd())
+        i.append_fd(self.as_raw_fd())
     }
 }
 impl DictKey for OwnedFd {}
 impl<'a> Get<'a> for OwnedFd {
     fn get(i: &mut Iter) -> Option<Self> {
-        arg_get_basic(&mut i.0, ArgType::UnixFd).map(OwnedFd::new)
+        i.get_fd()
     }
 }
 
@@ -250,7 +258,7 @@ impl<'a> Arg for $t<'a> {
 impl RefArg for $t<'static> {
     fn arg_type(&self) -> ArgType { ArgType::$s }
     fn signature(&self) -> Signature<'static> { unsafe { Signature::from_slice_unchecked($f) } }
-    fn append(&self, i: &mut IterAppend) { arg_append_str(&mut i.0, ArgType::$s, self.as_cstr()) }
+    fn append(&self, i: &mut IterAppend) { i.append_str(ArgType::$s, self.as_cstr()) }
     #[inline]
     fn as_any(&self) -> &dyn any::Any { self }
     #[inline]
@@ -265,7 +273,7 @@ impl<'a> DictKey for $t<'a> {}
 
 impl<'a> Append for $t<'a> {
     fn append_by_ref(&self, i: &mut IterAppend) {
-        arg_append_str(&mut i.0, ArgType::$s, self.as_cstr())
+        i.append_str(ArgType::$s, self.as_cstr())
     }
 }
 
@@ -280,9 +288,9 @@ impl<'a> Get<'a> for $t<'a> {
 */
 
 impl<'a> Get<'a> for $t<'static> {
-    fn get(i: &mut Iter<'a>) -> Option<$t<'static>> { unsafe {
-        arg_get_str(&mut i.0, ArgType::$s).map(|s| $t::from_slice_unchecked(s.to_bytes_with_nul()).into_static())
-    }}
+    fn get(i: &mut Iter<'a>) -> Option<$t<'static>> { 
+        i.get_str(ArgType::$s).map(|s| unsafe { $t::from_slice_unchecked(s.to_bytes_with_nul()) }.into_static())
+    }
 }
 
 
@@ -0,0 +1,141 @@
+//! Appending and reading arguments through libdbus.
+
+use super::{ArgType, OwnedFd};
+use crate::{ffi, Message, Signature};
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::{c_void, c_char, c_int};
+use std::os::unix::io::RawFd;
+use std::{mem, ptr, slice};
+
+fn check(f: &str, i: u32) { if i == 0 { panic!("D-Bus error: '{}' failed", f) }}
+
+fn ffi_iter() -> ffi::DBusMessageIter { unsafe { mem::zeroed() }}
+
+#[derive(Clone, Copy)]
+pub struct AppendState(ffi::DBusMessageIter);
+
+impl AppendState {
+    pub fn new(m: &mut Message) -> Self {
+        let mut i = ffi_iter();
+        unsafe { ffi::dbus_message_iter_init_append(m.ptr(), &mut i) };
+        AppendState(i)
+    }
+
+    pub fn append_basic<T: Copy>(&mut self, _: &Message, arg_type: ArgType, v: T) {
+        let p = &v as *const _ as *const c_void;
+        unsafe {
+            check("dbus_message_iter_append_basic", ffi::dbus_message_iter_append_basic(&mut self.0, arg_type as c_int, p));
+        };
+    }
+
+    pub fn append_str(&mut self, _: &Message, arg_type: ArgType, v: &CStr) {
+        let p = v.as_ptr();
+        let q = &p as *const _ as *const c_void;
+        unsafe {
+            check("dbus_message_iter_append_basic", ffi::dbus_message_iter_append_basic(&mut self.0, arg_type as c_int, q));
+        };
+    }
+
+    pub fn append_fd(&mut self, m: &Message, fd: RawFd) { self.append_basic(m, ArgType::UnixFd, fd) }
+
+    pub fn append_fixed_array<T>(&mut self, _: &Message, arg_type: ArgType, v: &[T]) {
+        let (zptr, zlen) = (v.as_ptr(), v.len() as c_int);
+        unsafe { check("dbus_message_iter_append_fixed_array",
+            ffi::dbus_message_iter_append_fixed_array(&mut self.0, arg_type as c_int, &zptr as *const _ as *const c_void, zlen)) }
+    }
+
+    pub fn open(&mut self, _: &Message, arg_type: ArgType, sig: Option<&CStr>) -> Self {
+        let mut s = ffi_iter();
+        let p = sig.map(|s| s.as_ptr()).unwrap_or(ptr::null());
+        check("dbus_message_iter_open_container",
+            unsafe { ffi::dbus_message_iter_open_container(&mut self.0, arg_type as c_int, p, &mut s) });
+        AppendState(s)
+    }
+
+    pub fn close(&mut self, _: &Message, mut s: Self) {
+        check("dbus_message_iter_close_container",
+            unsafe { ffi::dbus_message_iter_close_container(&mut self.0, &mut s.0) });
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct IterState<'a>(ffi::DBusMessageIter, PhantomData<&'a Message>);
+
+impl<'a> IterState<'a> {
+    pub fn new(m: &'a Message) -> Self {
+        let mut i = ffi_iter();
+        unsafe { ffi::dbus_message_iter_init(m.ptr(), &mut i) };
+        IterState(i, PhantomData)
+    }
+
+    pub fn signature(&mut self) -> Signature<'static> {
+        unsafe {
+            let c = ffi::dbus_message_iter_get_signature(&mut self.0);
+            assert!(!c.is_null());
+            let cc = CStr::from_ptr(c);
+            let r = Signature::new(cc.to_bytes());
+            ffi::dbus_free(c as *mut c_void);
+            r.unwrap()
+        }
+    }
+
+    pub fn arg_type(&mut self) -> ArgType {
+        let s = unsafe { ffi::dbus_message_iter_get_arg_type(&mut self.0) };
+        ArgType::from_i32(s as i32).unwrap()
+    }
+
+    pub fn element_type(&mut self) -> ArgType {
+        let s = unsafe { ffi::dbus_message_iter_get_element_type(&mut self.0) };
+        ArgType::from_i32(s as i32).unwrap()
+    }
+
+    pub fn next(&mut self) -> bool {
+        unsafe { ffi::dbus_message_iter_next(&mut self.0) != 0 }
+    }
+
+    pub fn recurse(&mut self, arg_type: ArgType) -> Option<Self> {
+        let mut subiter = ffi_iter();
+        unsafe {
+            if ffi::dbus_message_iter_get_arg_type(&mut self.0) != arg_type as c_int { return None };
+            ffi::dbus_message_iter_recurse(&mut self.0, &mut subiter)
+        }
+        Some(IterState(subiter, PhantomData))
+    }
+
+    pub fn get_basic<T: Copy>(&mut self, arg_type: ArgType) -> Option<T> {
+        unsafe {
+            let mut c: T = mem::zeroed();
+            if ffi::dbus_message_iter_get_arg_type(&mut self.0) != arg_type as c_int { return None };
+            ffi::dbus_message_iter_get_basic(&mut self.0, &mut c as *mut _ as *mut c_void);
+            Some(c)
+        }
+    }
+
+    pub fn get_str(&mut self, arg_type: ArgType) -> Option<&'a CStr> {
+        unsafe {
+            if ffi::dbus_message_iter_get_arg_type(&mut self.0) != arg_type as c_int { return None };
+            let mut p = ptr::null_mut();
+            ffi::dbus_message_iter_get_basic(&mut self.0, &mut p as *mut _ as *mut c_void);
+            Some(CStr::from_ptr(p as *const c_char))
+        }
+    }
+
+    pub fn get_fd(&mut self, _: &'a Message) -> Option<OwnedFd> {
+        self.get_basic(ArgType::UnixFd).map(OwnedFd::new)
+    }
+
+    pub fn get_fixed_array<T: Copy>(&mut self, arg_type: ArgType) -> Option<&'a [T]> {
+        let mut si = self.recurse(ArgType::Array)?;
+        if self.element_type() != arg_type { return None };
+        let mut v: *mut T = ptr::null_mut();
+        let mut i = 0;
+        unsafe { ffi::dbus_message_iter_get_fixed_array(&mut si.0, &mut v as *mut _ as *mut c_void, &mut i) };
+        if v.is_null() {
+            assert_eq!(i, 0);
+            Some(&[][..])
+        } else {
+            Some(unsafe { slice::from_raw_parts(v, i as usize) })
+        }
+    }
+}
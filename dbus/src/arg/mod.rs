@@ -57,30 +57,56 @@
 //!
 //! `OwnedFd` - a file descriptor sent from the remote side.
 //!
+//! With the `derive` feature, `Arg`, `Append`, `Get` and `RefArg` can be derived for your own
+//! structs and enums, see the `dbus-derive` crate for details.
+//!
 
 mod msgarg;
 mod basic_impl;
 mod variantstruct_impl;
 mod array_impl;
+mod sigtype;
+mod value;
+mod propmap;
 
 pub mod messageitem;
+#[cfg(feature = "serde")]
+pub mod serde;
 
 pub use self::msgarg::{Arg, FixedArray, Get, DictKey, Append, RefArg, AppendAll, ReadAll, ArgAll, cast, cast_mut};
 pub use self::array_impl::{Array, Dict};
+pub use self::sigtype::{SignatureType, SignatureTypes, MAX_SIGNATURE_LEN, MAX_ARRAY_DEPTH, MAX_STRUCT_DEPTH, MAX_TOTAL_DEPTH};
 pub use self::variantstruct_impl::Variant;
-
-use std::{fmt, mem, ptr, error};
+pub use self::value::{Value, ValueArray, ValueDict};
+pub use self::propmap::PropMap;
+pub (crate) use self::propmap::convert;
+#[cfg(feature = "derive")]
+pub use dbus_derive::{Arg, Append, Get, RefArg};
+
+#[cfg(not(feature = "native"))]
+mod ffi_iter;
+#[cfg(not(feature = "native"))]
+use self::ffi_iter::{AppendState, IterState};
+#[cfg(feature = "native")]
+mod native_iter;
+#[cfg(feature = "native")]
+use self::native_iter::{AppendState, IterState};
+
+use std::{fmt, mem, error};
 use crate::{ffi, Message, Signature, Path};
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_void, c_int};
-use std::os::unix::io::{RawFd, AsRawFd};
+use std::os::unix::io::{RawFd, AsRawFd, IntoRawFd, FromRawFd};
+use std::os::unix::net::{UnixStream, UnixListener};
+use std::net::TcpStream;
+use std::fs::File;
 
-fn check(f: &str, i: u32) { if i == 0 { panic!("D-Bus error: '{}' failed", f) }} 
-
-fn ffi_iter() -> ffi::DBusMessageIter { unsafe { mem::zeroed() }} 
 
 /// An RAII wrapper around Fd to ensure that file descriptor is closed
 /// when the scope ends.
+///
+/// Converts to and from `File`, `UnixStream`, `UnixListener` and `TcpStream`, so that e g a pipe
+/// or a socket can be appended to a message. Check `Channel::can_send_type(ArgType::UnixFd)`
+/// to see whether a connection can pass file descriptors.
 #[derive(Debug, PartialEq, PartialOrd)]
 pub struct OwnedFd {
     fd: RawFd
@@ -120,30 +146,62 @@ impl AsRawFd for OwnedFd {
     }
 }
 
+impl IntoRawFd for OwnedFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.into_fd()
+    }
+}
+
+impl FromRawFd for OwnedFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> OwnedFd {
+        OwnedFd::new(fd)
+    }
+}
+
+macro_rules! owned_fd_from {
+    ($t: ty) => {
+        impl From<$t> for OwnedFd {
+            fn from(x: $t) -> OwnedFd { OwnedFd::new(x.into_raw_fd()) }
+        }
+
+        impl From<OwnedFd> for $t {
+            fn from(x: OwnedFd) -> $t { unsafe { <$t>::from_raw_fd(x.into_fd()) } }
+        }
+    }
+}
+
+owned_fd_from!(File);
+owned_fd_from!(UnixStream);
+owned_fd_from!(UnixListener);
+owned_fd_from!(TcpStream);
+
 
 #[derive(Clone, Copy)]
 /// Helper struct for appending one or more arguments to a Message. 
-pub struct IterAppend<'a>(ffi::DBusMessageIter, &'a Message);
+pub struct IterAppend<'a>(AppendState, &'a Message);
 
 impl<'a> IterAppend<'a> {
     /// Creates a new IterAppend struct.
     pub fn new(m: &'a mut Message) -> IterAppend<'a> { 
-        let mut i = ffi_iter();
-        unsafe { ffi::dbus_message_iter_init_append(m.ptr(), &mut i) };
+        let i = AppendState::new(m);
         IterAppend(i, m)
     }
 
     /// Appends the argument.
     pub fn append<T: Append>(&mut self, a: T) { a.append(self) }
 
+    fn append_basic<T: Copy>(&mut self, arg_type: ArgType, v: T) { self.0.append_basic(self.1, arg_type, v) }
+
+    fn append_str(&mut self, arg_type: ArgType, v: &CStr) { self.0.append_str(self.1, arg_type, v) }
+
+    fn append_fd(&mut self, fd: RawFd) { self.0.append_fd(self.1, fd) }
+
+    fn append_fixed_array<T>(&mut self, arg_type: ArgType, v: &[T]) { self.0.append_fixed_array(self.1, arg_type, v) }
+
     fn append_container<F: FnOnce(&mut IterAppend<'a>)>(&mut self, arg_type: ArgType, sig: Option<&CStr>, f: F) {
-        let mut s = IterAppend(ffi_iter(), self.1);
-        let p = sig.map(|s| s.as_ptr()).unwrap_or(ptr::null());
-        check("dbus_message_iter_open_container",
-            unsafe { ffi::dbus_message_iter_open_container(&mut self.0, arg_type as c_int, p, &mut s.0) });
+        let mut s = IterAppend(self.0.open(self.1, arg_type, sig), self.1);
         f(&mut s);
-        check("dbus_message_iter_close_container",
-            unsafe { ffi::dbus_message_iter_close_container(&mut self.0, &mut s.0) });
+        self.0.close(self.1, s.0);
     }
 
     /// Low-level function to append a variant.
@@ -206,16 +264,28 @@ impl<'a> IterAppend<'a> {
 
 #[derive(Clone, Copy)]
 /// Helper struct for retrieve one or more arguments from a Message.
-pub struct Iter<'a>(ffi::DBusMessageIter, &'a Message, u32);
+pub struct Iter<'a>(IterState<'a>, &'a Message, u32);
 
 impl<'a> Iter<'a> {
     /// Creates a new struct for iterating over the arguments of a message, starting with the first argument. 
     pub fn new(m: &'a Message) -> Iter<'a> { 
-        let mut i = ffi_iter();
-        unsafe { ffi::dbus_message_iter_init(m.ptr(), &mut i) };
-        Iter(i, m, 0)
+        Iter(IterState::new(m), m, 0)
     }
 
+    /// The message this iterator reads from.
+    pub (crate) fn message(&self) -> &'a Message { self.1 }
+
+    fn get_basic<T: Copy>(&mut self, arg_type: ArgType) -> Option<T> { self.0.get_basic(arg_type) }
+
+    fn get_str(&mut self, arg_type: ArgType) -> Option<&'a CStr> { self.0.get_str(arg_type) }
+
+    fn get_fd(&mut self) -> Option<OwnedFd> { self.0.get_fd(self.1) }
+
+    fn get_fixed_array<T: Copy>(&mut self, arg_type: ArgType) -> Option<&'a [T]> { self.0.get_fixed_array(arg_type) }
+
+    /// The element type of the current argument, which must be an array.
+    fn element_type(&mut self) -> ArgType { self.0.element_type() }
+
     /// Returns the current argument, if T is the argument type. Otherwise returns None.
     pub fn get<T: Get<'a>>(&mut self) -> Option<T> {
         T::get(self)
@@ -253,31 +323,19 @@ impl<'a> Iter<'a> {
     }
 
     /// Returns the type signature for the current argument.
-    pub fn signature(&mut self) -> Signature<'static> {
-        unsafe {
-            let c = ffi::dbus_message_iter_get_signature(&mut self.0);
-            assert!(c != ptr::null_mut());
-            let cc = CStr::from_ptr(c);
-            let r = Signature::new(cc.to_bytes());
-            ffi::dbus_free(c as *mut c_void);
-            r.unwrap()
-        }
-    }
+    pub fn signature(&mut self) -> Signature<'static> { self.0.signature() }
 
     /// The raw arg_type for the current item.
     ///
     /// Unlike Arg::arg_type, this requires access to self and is not a static method.
     /// You can match this against Arg::arg_type for different types to understand what type the current item is.
     /// In case you're past the last argument, this function will return 0.
-    pub fn arg_type(&mut self) -> ArgType {
-        let s = unsafe { ffi::dbus_message_iter_get_arg_type(&mut self.0) };
-        ArgType::from_i32(s as i32).unwrap()
-    }
+    pub fn arg_type(&mut self) -> ArgType { self.0.arg_type() }
 
     /// Returns false if there are no more items.
     pub fn next(&mut self) -> bool {
         self.2 += 1;
-        unsafe { ffi::dbus_message_iter_next(&mut self.0) != 0 } 
+        self.0.next()
     }
 
     /// Wrapper around `get` and `next`. Calls `get`, and then `next` if `get` succeeded. 
@@ -323,12 +381,7 @@ impl<'a> Iter<'a> {
         let containers = [ArgType::Array, ArgType::DictEntry, ArgType::Struct, ArgType::Variant];
         if !containers.iter().any(|&t| t == arg_type) { return None; }
 
-        let mut subiter = ffi_iter();
-        unsafe {
-            if ffi::dbus_message_iter_get_arg_type(&mut self.0) != arg_type as c_int { return None };
-            ffi::dbus_message_iter_recurse(&mut self.0, &mut subiter)
-        }
-        Some(Iter(subiter, self.1, 0))
+        self.0.recurse(arg_type).map(|s| Iter(s, self.1, 0))
     }
 }
 
@@ -482,3 +535,26 @@ fn test_compile() {
     q.append(Variant((6u8, 7u8)));
 }
 
+#[test]
+fn owned_fd_conversions() {
+    use std::io::{Read, Write};
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let raw = a.as_raw_fd();
+    let fd = OwnedFd::from(a);
+    assert_eq!(fd.as_raw_fd(), raw);
+    // A clone is a new file descriptor for the same socket
+    let mut a: UnixStream = fd.clone().into();
+    assert_ne!(a.as_raw_fd(), raw);
+    drop(fd);
+    a.write_all(b"hello").unwrap();
+    drop(a);
+    let mut s = String::new();
+    b.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "hello");
+
+    let dir = tempfile::tempdir().unwrap();
+    let l = UnixListener::bind(dir.path().join("sock")).unwrap();
+    let raw = l.as_raw_fd();
+    let l: UnixListener = OwnedFd::from(l).into();
+    assert_eq!(l.as_raw_fd(), raw);
+}
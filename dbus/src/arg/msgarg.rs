@@ -280,6 +280,12 @@ argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str,);
 argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str,);
 argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str,);
 argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str, l L str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str, l L str, m M str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str, l L str, m M str, n N str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str, l L str, m M str, n N str, o O str,);
+argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H str, i I str, j J str, k K str, l L str, m M str, n N str, o O str, p P str,);
 
 
 
@@ -412,4 +418,45 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn std_containers() {
+        use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+        use std::borrow::Cow;
+        use std::sync::Arc;
+
+        let mut bm = BTreeMap::new();
+        bm.insert(2u32, "b".to_string());
+        bm.insert(1u32, "a".to_string());
+        let bs: BTreeSet<i16> = vec!(3, 1, 2).into_iter().collect();
+        let hs: HashSet<String> = vec!("x".to_string()).into_iter().collect();
+        let vd: VecDeque<u8> = vec!(1, 2, 3).into_iter().collect();
+        let bx: Box<[Path<'static>]> = vec!(Path::from("/a")).into_boxed_slice();
+        let arr = [1.5f64, 2.5];
+        let s: (Arc<str>, Cow<'static, str>) = ("arc".into(), "cow".into());
+
+        let m = Message::new_signal("/", "com.example", "Sig").unwrap()
+            .append3(&bm, &bs, &hs).append3(&vd, &bx, &arr).append1(&s);
+        let mut sig = String::new();
+        let mut i = m.iter_init();
+        loop { sig.push_str(&i.signature()); if !i.next() { break } }
+        assert_eq!(sig, "a{us}anasayaoad(ss)");
+
+        let r: (BTreeMap<u32, String>, BTreeSet<i16>, HashSet<String>, VecDeque<u8>, Box<[Path]>, [f64; 2], (Arc<str>, Cow<str>))
+            = m.read_all().unwrap();
+        assert_eq!(r, (bm.clone(), bs.clone(), hs, vd, bx, arr, s));
+        assert_eq!(bm.keys().collect::<Vec<_>>(), vec!(&1, &2));
+        assert!(m.read1::<[f64; 3]>().is_err());
+
+        let refs: Vec<Box<dyn RefArg>> = vec!(Box::new(bm), Box::new(bs), Box::new(arr), Box::new(Arc::<str>::from("z")));
+        let m = Message::new_signal("/", "com.example", "Sig").unwrap().append_ref(&refs);
+        let r: (BTreeMap<u32, String>, Vec<i16>, Vec<f64>, String) = m.read4().unwrap();
+        assert_eq!(r.1, vec!(1, 2, 3));
+        assert_eq!(&*r.3, "z");
+        assert_eq!(refs[2].box_clone().signature(), Signature::from("ad"));
+
+        let t = (1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8, 13u8, 14u8, 15u8, "16");
+        let m = Message::new_signal("/", "com.example", "Sig").unwrap().append1(t);
+        assert_eq!(m.read1::<(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, String)>().unwrap().15, "16");
+    }
 }
@@ -0,0 +1,107 @@
+//! Appending and reading arguments with the native marshaller.
+
+use super::{ArgType, OwnedFd};
+use crate::{Message, Signature};
+use crate::native::Cursor;
+use std::ffi::CStr;
+use std::os::unix::io::RawFd;
+use std::{mem, ptr, slice};
+
+/// Struct and dict entry arg types are 'r' and 'e', but signatures use parentheses and braces.
+fn code(t: ArgType) -> u8 {
+    match t {
+        ArgType::Struct => b'(',
+        ArgType::DictEntry => b'{',
+        t => t as u8,
+    }
+}
+
+fn arg_type(c: u8) -> ArgType {
+    match c {
+        b'(' => ArgType::Struct,
+        b'{' => ArgType::DictEntry,
+        c => ArgType::from_i32(c as i32).unwrap(),
+    }
+}
+
+fn as_bytes<T>(v: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(v.as_ptr() as *const u8, mem::size_of_val(v)) }
+}
+
+#[derive(Clone, Copy)]
+pub struct AppendState;
+
+impl AppendState {
+    pub fn new(_: &mut Message) -> Self { AppendState }
+
+    pub fn append_basic<T: Copy>(&mut self, m: &Message, arg_type: ArgType, v: T) {
+        m.with_native_body_mut(|b| b.append_fixed(code(arg_type), as_bytes(&[v])))
+    }
+
+    pub fn append_str(&mut self, m: &Message, arg_type: ArgType, v: &CStr) {
+        m.with_native_body_mut(|b| b.append_str(code(arg_type), v.to_bytes()))
+    }
+
+    pub fn append_fd(&mut self, m: &Message, fd: RawFd) {
+        m.with_native_body_mut(|b| b.append_fd(fd))
+    }
+
+    pub fn append_fixed_array<T>(&mut self, m: &Message, arg_type: ArgType, v: &[T]) {
+        m.with_native_body_mut(|b| b.append_fixed_array(code(arg_type), as_bytes(v)))
+    }
+
+    pub fn open(&mut self, m: &Message, arg_type: ArgType, sig: Option<&CStr>) -> Self {
+        m.with_native_body_mut(|b| b.open(code(arg_type), sig.map(|s| s.to_bytes()).unwrap_or(&[])));
+        AppendState
+    }
+
+    pub fn close(&mut self, m: &Message, _: Self) {
+        m.with_native_body_mut(|b| b.close())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct IterState<'a>(Cursor<'a>);
+
+impl<'a> IterState<'a> {
+    pub fn new(m: &'a Message) -> Self { IterState(Cursor::new(m.native_body())) }
+
+    pub fn signature(&mut self) -> Signature<'static> {
+        let s = [self.0.signature(), b"\0"].concat();
+        unsafe { Signature::from_slice_unchecked(&s) }.into_static()
+    }
+
+    pub fn arg_type(&mut self) -> ArgType { arg_type(self.0.type_code()) }
+
+    pub fn element_type(&mut self) -> ArgType {
+        match self.0.signature() {
+            [b'a', c, ..] => arg_type(*c),
+            _ => ArgType::Invalid,
+        }
+    }
+
+    pub fn next(&mut self) -> bool { self.0.next() }
+
+    pub fn recurse(&mut self, arg_type: ArgType) -> Option<Self> { self.0.recurse(code(arg_type)).map(IterState) }
+
+    pub fn get_basic<T: Copy>(&mut self, arg_type: ArgType) -> Option<T> {
+        let b = self.0.fixed(code(arg_type))?;
+        assert_eq!(b.len(), mem::size_of::<T>());
+        Some(unsafe { ptr::read_unaligned(b.as_ptr() as *const T) })
+    }
+
+    pub fn get_str(&mut self, arg_type: ArgType) -> Option<&'a CStr> { self.0.str(code(arg_type)) }
+
+    pub fn get_fd(&mut self, m: &'a Message) -> Option<OwnedFd> {
+        let idx: u32 = self.get_basic(ArgType::UnixFd)?;
+        m.native_body().fds.get(idx as usize).cloned()
+    }
+
+    pub fn get_fixed_array<T: Copy>(&mut self, arg_type: ArgType) -> Option<&'a [T]> {
+        let b = self.0.fixed_array(code(arg_type))?;
+        let size = mem::size_of::<T>();
+        assert_eq!(b.len() % size, 0);
+        // Elements are aligned to their size within the body, which is 8 byte aligned
+        Some(unsafe { slice::from_raw_parts(b.as_ptr() as *const T, b.len() / size) })
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Arg, Append, Get, RefArg, Iter, IterAppend, ArgType, Variant, TypeMismatchError};
+use crate::{Message, Signature};
+use std::collections::{HashMap, hash_map};
+use std::{fmt, ops};
+
+/// A map of property names to values, i e an `a{sv}`.
+///
+/// This is what you get from e g `Properties.GetAll`, `Properties.PropertiesChanged`
+/// and `ObjectManager.GetManagedObjects`. Values can be read as concrete Rust types with `get`,
+/// and maps can be built from typed values with `with`.
+///
+/// # Example
+///
+/// ```
+/// use dbus::arg::PropMap;
+/// use dbus::Path;
+/// let props = PropMap::new()
+///     .with("Name", "Hello".to_string())
+///     .with("Volume", 5u32)
+///     .with("Devices", vec!(Path::from("/dev/a"), Path::from("/dev/b")));
+///
+/// assert_eq!(props.get::<String>("Name").unwrap(), Some("Hello".into()));
+/// assert_eq!(props.get::<u32>("Volume").unwrap(), Some(5));
+/// assert_eq!(props.get::<Vec<Path>>("Devices").unwrap().unwrap()[1], Path::from("/dev/b"));
+/// assert_eq!(props.get::<u32>("Missing").unwrap(), None);
+/// assert!(props.get::<u32>("Name").is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct PropMap(HashMap<String, Variant<Box<dyn RefArg>>>);
+
+impl PropMap {
+    /// Creates a new, empty map.
+    pub fn new() -> Self { Default::default() }
+
+    /// Adds a property to the map, replacing any previous value with the same name.
+    ///
+    /// Use this to build a map, e g `PropMap::new().with("Name", "x".to_string()).with("Size", 5u64)`.
+    pub fn with<K: Into<String>, V: RefArg + 'static>(mut self, key: K, value: V) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    /// Sets a property, returning the previous value (if any).
+    pub fn set<K: Into<String>, V: RefArg + 'static>(&mut self, key: K, value: V) -> Option<Variant<Box<dyn RefArg>>> {
+        self.0.insert(key.into(), Variant(Box::new(value)))
+    }
+
+    /// Reads a property as a concrete type.
+    ///
+    /// Returns `Ok(None)` if there is no property with that name, and a `TypeMismatchError`
+    /// if the property is not of type T. Nested arrays, dicts, structs and variants are
+    /// converted the same way as when reading them from a message, so e g an `a{sas}` can be read
+    /// as a `HashMap<String, Vec<String>>` and an `(ou)` can be read as a `(Path, u32)`.
+    ///
+    /// If T is a variant type (e g `Variant<u32>` or `arg::Value`), the value is read including its variant wrapper.
+    pub fn get<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<Option<T>, TypeMismatchError> {
+        self.0.get(key).map(|v| convert(&v.0.signature(), |i| v.0.append(i))).transpose()
+    }
+
+    /// Returns the inner value of a property, without converting it.
+    pub fn get_refarg(&self, key: &str) -> Option<&(dyn RefArg + 'static)> {
+        self.0.get(key).map(|v| &*v.0)
+    }
+
+    /// Consumes the map, returning the underlying HashMap.
+    pub fn into_inner(self) -> HashMap<String, Variant<Box<dyn RefArg>>> { self.0 }
+}
+
+/// Converts a value, which is appended by f and has signature sig, to T.
+///
+/// If T is a variant type, the value is wrapped in a variant first.
+/// Going through a message means that all types that can be read from a message can be read here, too.
+pub (crate) fn convert<T: Arg + for<'b> Get<'b>, F: FnOnce(&mut IterAppend)>(sig: &Signature, f: F) -> Result<T, TypeMismatchError> {
+    let mut m = Message::new_signal("/", "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap();
+    {
+        let mut i = IterAppend::new(&mut m);
+        if T::ARG_TYPE == ArgType::Variant { i.append_variant(sig, f) } else { f(&mut i) }
+    }
+    m.read1()
+}
+
+impl Clone for PropMap {
+    fn clone(&self) -> Self {
+        PropMap(self.0.iter().map(|(k, v)| (k.clone(), Variant(v.0.box_clone()))).collect())
+    }
+}
+
+impl ops::Deref for PropMap {
+    type Target = HashMap<String, Variant<Box<dyn RefArg>>>;
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl ops::DerefMut for PropMap {
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
+}
+
+impl From<HashMap<String, Variant<Box<dyn RefArg>>>> for PropMap {
+    fn from(x: HashMap<String, Variant<Box<dyn RefArg>>>) -> Self { PropMap(x) }
+}
+
+impl From<PropMap> for HashMap<String, Variant<Box<dyn RefArg>>> {
+    fn from(x: PropMap) -> Self { x.0 }
+}
+
+impl IntoIterator for PropMap {
+    type Item = (String, Variant<Box<dyn RefArg>>);
+    type IntoIter = hash_map::IntoIter<String, Variant<Box<dyn RefArg>>>;
+    fn into_iter(self) -> Self::IntoIter { self.0.into_iter() }
+}
+
+impl<'a> IntoIterator for &'a PropMap {
+    type Item = (&'a String, &'a Variant<Box<dyn RefArg>>);
+    type IntoIter = hash_map::Iter<'a, String, Variant<Box<dyn RefArg>>>;
+    fn into_iter(self) -> Self::IntoIter { self.0.iter() }
+}
+
+impl Arg for PropMap {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"a{sv}\0") } }
+}
+
+impl Append for PropMap {
+    fn append_by_ref(&self, i: &mut IterAppend) { self.0.append_by_ref(i) }
+}
+
+impl<'a> Get<'a> for PropMap {
+    fn get(i: &mut Iter<'a>) -> Option<Self> { i.get().map(PropMap) }
+}
+
+impl fmt::Display for PropMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut keys: Vec<_> = self.0.keys().collect();
+        keys.sort();
+        f.write_str("{")?;
+        for (idx, k) in keys.into_iter().enumerate() {
+            if idx > 0 { f.write_str(", ")? }
+            write!(f, "{:?}: {:?}", k, self.0[k].0)?;
+        }
+        f.write_str("}")
+    }
+}
+
+#[test]
+fn propmap_nested() {
+    use crate::Path;
+    use super::Value;
+    let mut inner = HashMap::new();
+    inner.insert("x".to_string(), vec!("a".to_string(), "b".into()));
+    let p = PropMap::new()
+        .with("Dict", inner.clone())
+        .with("Struct", (Path::from("/a"), 7u32))
+        .with("Nested", Variant(5i16))
+        .with("Bytes", vec!(1u8, 2, 3));
+
+    let m = Message::new_signal("/", "a.b", "C").unwrap().append1(&p);
+    assert_eq!(&*m.iter_init().signature(), "a{sv}");
+    let p: PropMap = m.read1().unwrap();
+
+    assert_eq!(p.get::<HashMap<String, Vec<String>>>("Dict").unwrap(), Some(inner));
+    assert_eq!(p.get::<(Path, u32)>("Struct").unwrap(), Some((Path::from("/a"), 7)));
+    assert_eq!(p.get::<Variant<Variant<i16>>>("Nested").unwrap(), Some(Variant(Variant(5))));
+    assert_eq!(p.get::<Variant<u32>>("Struct").unwrap_err().found_arg_type(), ArgType::Variant);
+    assert_eq!(p.get::<Value>("Nested").unwrap(), Some(Value::Variant(Box::new(Value::Int16(5)))));
+    assert_eq!(p.get::<Vec<u8>>("Bytes").unwrap(), Some(vec!(1, 2, 3)));
+    assert_eq!(p.get_refarg("Bytes").unwrap().signature(), Signature::from("ay"));
+
+    let e = p.get::<Vec<u32>>("Struct").unwrap_err();
+    assert_eq!(e.expected_arg_type(), ArgType::Array);
+    assert_eq!(e.found_arg_type(), ArgType::Struct);
+    assert_eq!(p.get::<i16>("Nested").unwrap_err().found_arg_type(), ArgType::Variant);
+    assert_eq!(p.clone().into_inner().len(), 4);
+}
@@ -0,0 +1,964 @@
+//! Serde support: serializes Rust values into D-Bus arguments, and deserializes them back.
+//!
+//! The mapping between the serde data model and D-Bus types is:
+//!
+//! * bool, u8, i16, u16, i32, u32, i64, u64 and f64 map to the corresponding D-Bus types.
+//!   i8 is sent as i16, f32 as f64, and char as a string.
+//! * Strings map to strings. Object paths and signatures can be deserialized into strings too.
+//! * Sequences map to arrays, and byte buffers to `ay`.
+//! * Maps map to dicts (`a{..}`), the key must be a basic type.
+//! * Structs, tuples and tuple structs map to D-Bus structs. Newtype structs are transparent.
+//!   Structs can also be deserialized from dicts with string keys, such as `a{sv}`.
+//! * `Option<T>` maps to an array of zero or one element (`aT`).
+//! * Enums map to variants: a unit variant is a variant containing its name as a string,
+//!   other variants are a variant containing a dict with a single entry (`a{sv}`),
+//!   from the variant name to the content.
+//! * The unit type and unit structs cannot be represented, since D-Bus has no empty structs.
+//!
+//! When deserializing, a variant is looked through transparently.
+//!
+//! # Example
+//!
+//! ```
+//! use dbus::arg::serde::{to_append, from_iter, signature_of};
+//! use serde_derive::{Serialize, Deserialize};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Config { name: String, retries: u32, tags: Vec<String> }
+//!
+//! assert_eq!(&*signature_of::<Config>()?, "(suas)");
+//!
+//! let c = Config { name: "test".into(), retries: 3, tags: vec!() };
+//! let mut m = dbus::Message::new_method_call("com.example.Service", "/", "com.example.Config", "Set").unwrap();
+//! // The signature is needed here, because the type of an empty array cannot be inferred from the value
+//! to_append(&c, Some(&signature_of::<Config>()?), &mut dbus::arg::IterAppend::new(&mut m))?;
+//!
+//! let c2: Config = from_iter(&mut m.iter_init())?;
+//! assert_eq!(c, c2);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use super::{Iter, IterAppend, ArgType, TypeMismatchError};
+use crate::Signature;
+use ::serde::{ser, de, Serialize, Deserialize};
+use ::serde::de::IntoDeserializer;
+use std::{fmt, error};
+
+/// An error that occured while serializing or deserializing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl Error {
+    fn new<S: Into<String>>(s: S) -> Self { Error(s.into()) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(&self.0) }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self { Error(msg.to_string()) }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self { Error(msg.to_string()) }
+}
+
+impl From<TypeMismatchError> for Error {
+    fn from(t: TypeMismatchError) -> Self { Error(t.to_string()) }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> Self { crate::Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.0) }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Splits off the first complete type of a signature.
+fn split_type(s: &str) -> Result<(&str, &str)> {
+    let b = s.as_bytes();
+    let mut depth = 0;
+    for (i, c) in b.iter().enumerate() {
+        match c {
+            b'a' => continue,
+            b'(' | b'{' => depth += 1,
+            b')' | b'}' => depth -= 1,
+            _ => {},
+        }
+        if depth == 0 { return Ok(s.split_at(i + 1)) }
+        if depth < 0 { break }
+    }
+    Err(Error::new(format!("Invalid signature '{}'", s)))
+}
+
+/// Whether a single complete type is a basic type, which is what dict keys must be.
+fn is_basic_type(t: &str) -> bool { t.len() == 1 && !"avr({".contains(t) }
+
+/// Checks a signature computed during serialization.
+fn sig(s: &str) -> Result<Signature<'static>> {
+    Signature::new(s).map_err(Error::new)
+}
+
+/// A value that has been serialized, but not yet appended to a message.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Bool(bool),
+    Byte(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+    /// The element signature is None until resolved.
+    Array(Option<Signature<'static>>, Vec<Node>),
+    /// Key and value signatures are None until resolved.
+    Dict(Option<(Signature<'static>, Signature<'static>)>, Vec<(Node, Node)>),
+    Struct(Vec<Node>),
+    /// The signature of the contents is None until resolved.
+    Variant(Option<Signature<'static>>, Box<Node>),
+}
+
+impl Node {
+    fn basic_sig(&self) -> Option<&'static str> {
+        Some(match self {
+            Node::Bool(_) => "b",
+            Node::Byte(_) => "y",
+            Node::I16(_) => "n",
+            Node::U16(_) => "q",
+            Node::I32(_) => "i",
+            Node::U32(_) => "u",
+            Node::I64(_) => "x",
+            Node::U64(_) => "t",
+            Node::F64(_) => "d",
+            Node::Str(_) => "s",
+            _ => return None,
+        })
+    }
+
+    /// The signature of this value, if it can be determined without a hint.
+    fn infer(&self) -> Option<String> {
+        if let Some(s) = self.basic_sig() { return Some(s.into()) }
+        match self {
+            Node::Array(Some(e), _) => Some(format!("a{}", &**e)),
+            Node::Array(None, items) => items.iter().find_map(|i| i.infer()).map(|e| format!("a{}", e)),
+            Node::Dict(Some((k, v)), _) => Some(format!("a{{{}{}}}", &**k, &**v)),
+            Node::Dict(None, items) => {
+                let k = items.iter().find_map(|i| i.0.infer()).filter(|k| is_basic_type(k))?;
+                let v = items.iter().find_map(|i| i.1.infer())?;
+                Some(format!("a{{{}{}}}", k, v))
+            }
+            Node::Struct(f) => {
+                let f = f.iter().map(|x| x.infer()).collect::<Option<Vec<_>>>()?;
+                Some(format!("({})", f.concat()))
+            }
+            Node::Variant(_, _) => Some("v".into()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Determines the signature of every container, using the expected signature where the
+    /// value itself is not enough (i e, for empty arrays). Returns the signature of this value.
+    fn resolve(&mut self, expected: Option<&str>) -> Result<String> {
+        let expected = match expected { Some(e) => Some(e.to_string()), None => self.infer() };
+        let e = expected.ok_or_else(|| Error::new("Cannot determine the type of an empty array, supply a signature"))?;
+        let mismatch = |found: &str| Error::new(format!("Expected a value of type '{}', found '{}'", e, found));
+        if let Some(s) = self.basic_sig() {
+            return if s == e { Ok(e) } else { Err(mismatch(s)) }
+        }
+        match self {
+            Node::Array(es, items) => {
+                if !e.starts_with('a') || e.starts_with("a{") { return Err(mismatch("an array")) }
+                for i in items.iter_mut() { i.resolve(Some(&e[1..]))?; }
+                *es = Some(sig(&e[1..])?);
+            }
+            Node::Dict(kv, items) => {
+                if !e.starts_with("a{") { return Err(mismatch("a dict")) }
+                let (k, v) = split_type(&e[2..e.len()-1])?;
+                if !is_basic_type(k) { return Err(Error::new(format!("Dict key type '{}' is not a basic type", k))) }
+                for (ik, iv) in items.iter_mut() {
+                    ik.resolve(Some(k))?;
+                    iv.resolve(Some(v))?;
+                }
+                *kv = Some((sig(k)?, sig(v)?));
+            }
+            Node::Struct(fields) => {
+                if !e.starts_with('(') { return Err(mismatch("a struct")) }
+                let mut rest = &e[1..e.len()-1];
+                for f in fields.iter_mut() {
+                    if rest.is_empty() { return Err(mismatch("a struct with more fields")) }
+                    let (t, r) = split_type(rest)?;
+                    f.resolve(Some(t))?;
+                    rest = r;
+                }
+                if !rest.is_empty() { return Err(mismatch("a struct with fewer fields")) }
+            }
+            Node::Variant(s, inner) => {
+                if e != "v" { return Err(mismatch("v")) }
+                *s = Some(sig(&inner.resolve(None)?)?);
+            }
+            _ => unreachable!(),
+        }
+        Ok(e)
+    }
+
+    /// Appends a resolved value.
+    fn write(&self, ia: &mut IterAppend) {
+        match self {
+            Node::Bool(x) => ia.append(*x),
+            Node::Byte(x) => ia.append(*x),
+            Node::I16(x) => ia.append(*x),
+            Node::U16(x) => ia.append(*x),
+            Node::I32(x) => ia.append(*x),
+            Node::U32(x) => ia.append(*x),
+            Node::I64(x) => ia.append(*x),
+            Node::U64(x) => ia.append(*x),
+            Node::F64(x) => ia.append(*x),
+            Node::Str(x) => ia.append(&**x),
+            Node::Array(Some(es), items) => ia.append_array(es, |s| for i in items { i.write(s) }),
+            Node::Dict(Some((k, v)), items) => ia.append_dict(k, v, |s| for (ik, iv) in items {
+                s.append_dict_entry(|e| { ik.write(e); iv.write(e) })
+            }),
+            Node::Struct(fields) => ia.append_struct(|s| for f in fields { f.write(s) }),
+            Node::Variant(Some(sig), inner) => ia.append_variant(sig, |s| inner.write(s)),
+            _ => unreachable!("Node::write called before Node::resolve"),
+        }
+    }
+}
+
+/// A serialized value, which can be appended to a message.
+///
+/// Created by `to_encoded` or by using `Serializer` directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoded(Node);
+
+impl Encoded {
+    /// The signature of the value, if it can be determined.
+    ///
+    /// This is None if the value contains an empty array and no signature has been supplied.
+    pub fn signature(&self) -> Option<Signature<'static>> {
+        self.0.infer().and_then(|s| Signature::new(s).ok())
+    }
+
+    /// Checks that the value matches the signature, and determines the types of empty arrays from it.
+    pub fn set_signature(&mut self, sig: &Signature) -> Result<()> {
+        let (t, rest) = split_type(sig)?;
+        if !rest.is_empty() { return Err(Error::new(format!("Signature '{}' must be a single complete type", &**sig))) }
+        let mut n = self.0.clone();
+        n.resolve(Some(t))?;
+        self.0 = n;
+        Ok(())
+    }
+
+    /// Appends the value to a message.
+    ///
+    /// Fails if the signature cannot be determined, or is not a valid D-Bus signature
+    /// (e g, it is nested too deeply or too long).
+    pub fn append_to(&self, ia: &mut IterAppend) -> Result<()> {
+        let mut n = self.0.clone();
+        sig(&n.resolve(None)?)?;
+        n.write(ia);
+        Ok(())
+    }
+}
+
+/// Serializes a value into an `Encoded`, ready to be appended to a message.
+pub fn to_encoded<T: Serialize + ?Sized>(value: &T) -> Result<Encoded> {
+    value.serialize(Serializer)
+}
+
+/// Serializes a value and appends it to a message as one argument.
+///
+/// If the value contains empty arrays, their type cannot be inferred from the value itself,
+/// so then a signature must be supplied (e g, one returned from `signature_of`).
+pub fn to_append<T: Serialize + ?Sized>(value: &T, sig: Option<&Signature>, ia: &mut IterAppend) -> Result<()> {
+    let mut e = to_encoded(value)?;
+    if let Some(s) = sig { e.set_signature(s)? }
+    e.append_to(ia)
+}
+
+/// Deserializes the current argument of the iterator, and moves to the next one.
+pub fn from_iter<'a, T: Deserialize<'a>>(i: &mut Iter<'a>) -> Result<T> {
+    let r = T::deserialize(Deserializer::new(*i))?;
+    i.next();
+    Ok(r)
+}
+
+/// A serializer that creates an `Encoded` value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Serializer;
+
+/// Helper struct for serializing compound values (arrays, dicts, structs and enum variants).
+#[derive(Debug)]
+pub struct Compound {
+    items: Vec<Node>,
+    keys: Vec<Node>,
+    variant: Option<&'static str>,
+    is_struct: bool,
+    is_map: bool,
+}
+
+impl Compound {
+    fn new(variant: Option<&'static str>, is_struct: bool, len: Option<usize>) -> Self {
+        Compound { items: Vec::with_capacity(len.unwrap_or(0)), keys: vec!(), variant, is_struct, is_map: false }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(value.serialize(Serializer)?.0);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Encoded> {
+        let n = if self.is_struct {
+            if self.items.is_empty() { return Err(Error::new("Empty structs cannot be represented in D-Bus")) }
+            Node::Struct(self.items)
+        } else if self.is_map {
+            Node::Dict(None, self.keys.into_iter().zip(self.items).collect())
+        } else { Node::Array(None, self.items) };
+        Ok(Encoded(match self.variant {
+            Some(v) => variant_node(v, n),
+            None => n,
+        }))
+    }
+}
+
+fn variant_node(name: &str, content: Node) -> Node {
+    let d = Node::Dict(None, vec!((Node::Str(name.into()), Node::Variant(None, Box::new(content)))));
+    Node::Variant(None, Box::new(d))
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Encoded;
+    type Error = Error;
+    type SerializeSeq = Compound;
+    type SerializeTuple = Compound;
+    type SerializeTupleStruct = Compound;
+    type SerializeTupleVariant = Compound;
+    type SerializeMap = Compound;
+    type SerializeStruct = Compound;
+    type SerializeStructVariant = Compound;
+
+    fn serialize_bool(self, v: bool) -> Result<Encoded> { Ok(Encoded(Node::Bool(v))) }
+    fn serialize_i8(self, v: i8) -> Result<Encoded> { Ok(Encoded(Node::I16(v.into()))) }
+    fn serialize_i16(self, v: i16) -> Result<Encoded> { Ok(Encoded(Node::I16(v))) }
+    fn serialize_i32(self, v: i32) -> Result<Encoded> { Ok(Encoded(Node::I32(v))) }
+    fn serialize_i64(self, v: i64) -> Result<Encoded> { Ok(Encoded(Node::I64(v))) }
+    fn serialize_u8(self, v: u8) -> Result<Encoded> { Ok(Encoded(Node::Byte(v))) }
+    fn serialize_u16(self, v: u16) -> Result<Encoded> { Ok(Encoded(Node::U16(v))) }
+    fn serialize_u32(self, v: u32) -> Result<Encoded> { Ok(Encoded(Node::U32(v))) }
+    fn serialize_u64(self, v: u64) -> Result<Encoded> { Ok(Encoded(Node::U64(v))) }
+    fn serialize_f32(self, v: f32) -> Result<Encoded> { Ok(Encoded(Node::F64(v.into()))) }
+    fn serialize_f64(self, v: f64) -> Result<Encoded> { Ok(Encoded(Node::F64(v))) }
+    fn serialize_char(self, v: char) -> Result<Encoded> { Ok(Encoded(Node::Str(v.to_string()))) }
+    fn serialize_str(self, v: &str) -> Result<Encoded> { Ok(Encoded(Node::Str(v.into()))) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Encoded> {
+        Ok(Encoded(Node::Array(Some("y".into()), v.iter().map(|b| Node::Byte(*b)).collect())))
+    }
+    fn serialize_none(self) -> Result<Encoded> { Ok(Encoded(Node::Array(None, vec!()))) }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Encoded> {
+        Ok(Encoded(Node::Array(None, vec!(value.serialize(self)?.0))))
+    }
+    fn serialize_unit(self) -> Result<Encoded> { Err(Error::new("The unit type cannot be represented in D-Bus")) }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Encoded> {
+        Err(Error::new(format!("Unit struct {} cannot be represented in D-Bus", name)))
+    }
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Encoded> {
+        Ok(Encoded(Node::Variant(None, Box::new(Node::Str(variant.into())))))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<Encoded> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, variant: &'static str, value: &T) -> Result<Encoded> {
+        Ok(Encoded(variant_node(variant, value.serialize(self)?.0)))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound> { Ok(Compound::new(None, false, len)) }
+    fn serialize_tuple(self, len: usize) -> Result<Compound> { Ok(Compound::new(None, true, Some(len))) }
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<Compound> { Ok(Compound::new(None, true, Some(len))) }
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, variant: &'static str, len: usize) -> Result<Compound> {
+        Ok(Compound::new(Some(variant), true, Some(len)))
+    }
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound> {
+        let mut c = Compound::new(None, false, len);
+        c.is_map = true;
+        Ok(c)
+    }
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<Compound> { Ok(Compound::new(None, true, Some(len))) }
+    fn serialize_struct_variant(self, _: &'static str, _: u32, variant: &'static str, len: usize) -> Result<Compound> {
+        Ok(Compound::new(Some(variant), true, Some(len)))
+    }
+}
+
+impl ser::SerializeSeq for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeTuple for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeTupleStruct for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeTupleVariant for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeMap for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.keys.push(key.serialize(Serializer)?.0);
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeStruct for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+impl ser::SerializeStructVariant for Compound {
+    type Ok = Encoded;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, value: &T) -> Result<()> { self.push(value) }
+    fn end(self) -> Result<Encoded> { self.finish() }
+}
+
+/// A deserializer that reads the current argument of an `Iter`.
+#[derive(Debug, Clone, Copy)]
+pub struct Deserializer<'a> {
+    iter: Iter<'a>,
+}
+
+impl<'a> Deserializer<'a> {
+    /// Creates a deserializer for the current argument of the iterator.
+    pub fn new(iter: Iter<'a>) -> Self { Deserializer { iter } }
+
+    fn mismatch(&mut self, expected: &str) -> Error {
+        let t = self.iter.arg_type();
+        if t == ArgType::Invalid { return Error::new(format!("Expected {}, but there are no more arguments", expected)) }
+        Error::new(format!("Expected {}, found D-Bus type '{}'", expected, &*self.iter.signature()))
+    }
+
+    /// Looks through variants.
+    fn unwrap_variant(mut self) -> Self {
+        while self.iter.arg_type() == ArgType::Variant {
+            self.iter = self.iter.recurse(ArgType::Variant).unwrap();
+        }
+        self
+    }
+
+    fn is_dict(&mut self) -> bool {
+        self.iter.arg_type() == ArgType::Array && self.iter.signature().starts_with("a{")
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut d = self.unwrap_variant();
+        let i = &mut d.iter;
+        match i.arg_type() {
+            ArgType::Boolean => visitor.visit_bool(i.get().unwrap()),
+            ArgType::Byte => visitor.visit_u8(i.get().unwrap()),
+            ArgType::Int16 => visitor.visit_i16(i.get().unwrap()),
+            ArgType::UInt16 => visitor.visit_u16(i.get().unwrap()),
+            ArgType::Int32 => visitor.visit_i32(i.get().unwrap()),
+            ArgType::UInt32 => visitor.visit_u32(i.get().unwrap()),
+            ArgType::Int64 => visitor.visit_i64(i.get().unwrap()),
+            ArgType::UInt64 => visitor.visit_u64(i.get().unwrap()),
+            ArgType::Double => visitor.visit_f64(i.get().unwrap()),
+            ArgType::String => match i.get::<&'de str>() {
+                Some(s) => visitor.visit_borrowed_str(s),
+                None => Err(Error::new("String is not valid UTF-8")),
+            },
+            ArgType::ObjectPath => visitor.visit_str(&i.get::<crate::Path>().unwrap()),
+            ArgType::Signature => visitor.visit_str(&i.get::<Signature>().unwrap()),
+            ArgType::Array => {
+                let is_dict = d.is_dict();
+                let sub = d.iter.recurse(ArgType::Array).unwrap();
+                if is_dict { visitor.visit_map(MapAccess { iter: sub, entry: None }) }
+                else { visitor.visit_seq(SeqAccess { iter: sub }) }
+            }
+            ArgType::Struct => visitor.visit_seq(SeqAccess { iter: i.recurse(ArgType::Struct).unwrap() }),
+            _ => Err(d.mismatch("a value that can be deserialized")),
+        }
+    }
+
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut d = self.unwrap_variant();
+        let s: &str = d.iter.get().ok_or_else(|| d.mismatch("a string"))?;
+        let mut c = s.chars();
+        match (c.next(), c.next()) {
+            (Some(ch), None) => visitor.visit_char(ch),
+            _ => Err(Error::new(format!("Expected a single character, found '{}'", s))),
+        }
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut d = self.unwrap_variant();
+        if d.iter.arg_type() == ArgType::Array && &*d.iter.signature() == "ay" {
+            let b: &'de [u8] = d.iter.get().unwrap();
+            return visitor.visit_borrowed_bytes(b)
+        }
+        d.deserialize_any(visitor)
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        match self.iter.arg_type() {
+            // A value in a dict of variants, such as a{sv}, is there or not
+            ArgType::Variant => visitor.visit_some(self.unwrap_variant()),
+            ArgType::Array if !self.is_dict() => {
+                let mut sub = self.iter.recurse(ArgType::Array).unwrap();
+                if sub.arg_type() == ArgType::Invalid { return visitor.visit_none() }
+                let d = Deserializer::new(sub);
+                if sub.next() { return Err(Error::new("Expected an array of at most one element for an Option")) }
+                visitor.visit_some(d)
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(Error::new("The unit type cannot be represented in D-Bus"))
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(self, name: &'static str, _: V) -> Result<V::Value> {
+        Err(Error::new(format!("Unit struct {} cannot be represented in D-Bus", name)))
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(self, _: &'static str, _: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let mut d = self.unwrap_variant();
+        if let Some(s) = d.iter.get::<&str>() {
+            return visitor.visit_enum(EnumAccess { name: s.into(), content: None })
+        }
+        if d.is_dict() {
+            let mut sub = d.iter.recurse(ArgType::Array).unwrap();
+            if let Some(mut e) = sub.recurse(ArgType::DictEntry) {
+                if let Some(name) = e.get::<&str>() {
+                    let name = name.to_string();
+                    e.next();
+                    if !sub.next() { return visitor.visit_enum(EnumAccess { name, content: Some(Deserializer::new(e)) }) }
+                }
+            }
+        }
+        Err(d.mismatch("an enum (a variant containing a string or a dict with one entry)"))
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 str string
+        seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct SeqAccess<'a> {
+    iter: Iter<'a>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.iter.arg_type() == ArgType::Invalid { return Ok(None) }
+        let r = seed.deserialize(Deserializer::new(self.iter))?;
+        self.iter.next();
+        Ok(Some(r))
+    }
+}
+
+struct MapAccess<'a> {
+    iter: Iter<'a>,
+    entry: Option<Iter<'a>>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = Error;
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let mut e = match self.iter.recurse(ArgType::DictEntry) { Some(e) => e, None => return Ok(None) };
+        let r = seed.deserialize(Deserializer::new(e))?;
+        e.next();
+        self.entry = Some(e);
+        Ok(Some(r))
+    }
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let e = self.entry.take().ok_or_else(|| Error::new("Dict value requested before key"))?;
+        let r = seed.deserialize(Deserializer::new(e))?;
+        self.iter.next();
+        Ok(r)
+    }
+}
+
+struct EnumAccess<'a> {
+    name: String,
+    content: Option<Deserializer<'a>>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = Self;
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let d: ::serde::de::value::StringDeserializer<Error> = self.name.clone().into_deserializer();
+        Ok((seed.deserialize(d)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    fn unit_variant(self) -> Result<()> {
+        match self.content {
+            None => Ok(()),
+            Some(_) => Err(Error::new(format!("Expected unit variant {} to have no content", self.name))),
+        }
+    }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let c = self.content.ok_or_else(|| Error::new(format!("Variant {} has no content", self.name)))?;
+        seed.deserialize(c)
+    }
+    fn tuple_variant<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        let c = self.content.ok_or_else(|| Error::new(format!("Variant {} has no content", self.name)))?;
+        de::Deserializer::deserialize_any(c, visitor)
+    }
+    fn struct_variant<V: de::Visitor<'de>>(self, _: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let c = self.content.ok_or_else(|| Error::new(format!("Variant {} has no content", self.name)))?;
+        de::Deserializer::deserialize_any(c, visitor)
+    }
+}
+
+/// Maximum nesting depth when computing a signature, which also stops recursive types.
+const MAX_DEPTH: usize = 64;
+
+/// Computes the D-Bus signature of a type.
+///
+/// This works by pretending to deserialize a value of the type, so it fails for types which
+/// need to look at the data to know what to deserialize (e g untagged enums), and for
+/// recursive types.
+pub fn signature_of<'de, T: Deserialize<'de>>() -> Result<Signature<'static>> {
+    let mut s = String::new();
+    T::deserialize(SigTracer { sig: &mut s, depth: 0 })?;
+    Signature::new(s).map_err(Error::new)
+}
+
+struct SigTracer<'s> {
+    sig: &'s mut String,
+    depth: usize,
+}
+
+impl<'s> SigTracer<'s> {
+    fn push(&mut self, s: &str) -> Result<()> {
+        if self.depth > MAX_DEPTH { return Err(Error::new("Type is nested too deeply (or is recursive)")) }
+        self.sig.push_str(s);
+        Ok(())
+    }
+    fn child(&mut self) -> SigTracer<'_> { SigTracer { sig: self.sig, depth: self.depth + 1 } }
+}
+
+/// Gives out a fixed number of elements (or entries) to trace.
+struct SigSeq<'s, 't> {
+    tracer: &'t mut SigTracer<'s>,
+    left: usize,
+}
+
+impl<'de, 's, 't> de::SeqAccess<'de> for SigSeq<'s, 't> {
+    type Error = Error;
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.left == 0 { return Ok(None) }
+        self.left -= 1;
+        seed.deserialize(self.tracer.child()).map(Some)
+    }
+}
+
+impl<'de, 's, 't> de::MapAccess<'de> for SigSeq<'s, 't> {
+    type Error = Error;
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.left == 0 { return Ok(None) }
+        self.left -= 1;
+        seed.deserialize(self.tracer.child()).map(Some)
+    }
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(self.tracer.child())
+    }
+}
+
+impl<'de, 's, 't> de::EnumAccess<'de> for SigSeq<'s, 't> {
+    type Error = Error;
+    type Variant = Self;
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let d: ::serde::de::value::U32Deserializer<Error> = 0u32.into_deserializer();
+        Ok((seed.deserialize(d)?, self))
+    }
+}
+
+impl<'de, 's, 't> de::VariantAccess<'de> for SigSeq<'s, 't> {
+    type Error = Error;
+    fn unit_variant(self) -> Result<()> { Ok(()) }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let mut s = String::new();
+        seed.deserialize(SigTracer { sig: &mut s, depth: self.tracer.depth + 1 })
+    }
+    fn tuple_variant<V: de::Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        let mut s = String::new();
+        de::Deserializer::deserialize_tuple(SigTracer { sig: &mut s, depth: self.tracer.depth + 1 }, len, visitor)
+    }
+    fn struct_variant<V: de::Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let mut s = String::new();
+        de::Deserializer::deserialize_struct(SigTracer { sig: &mut s, depth: self.tracer.depth + 1 }, "", fields, visitor)
+    }
+}
+
+macro_rules! trace_basic {
+    ($($method: ident, $sig: expr, $visit: ident, $val: expr;)*) => {$(
+        fn $method<V: de::Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+            self.push($sig)?;
+            visitor.$visit($val)
+        }
+    )*}
+}
+
+impl<'de, 's> de::Deserializer<'de> for SigTracer<'s> {
+    type Error = Error;
+
+    trace_basic! {
+        deserialize_bool, "b", visit_bool, false;
+        deserialize_i8, "n", visit_i8, 0;
+        deserialize_i16, "n", visit_i16, 0;
+        deserialize_i32, "i", visit_i32, 0;
+        deserialize_i64, "x", visit_i64, 0;
+        deserialize_u8, "y", visit_u8, 0;
+        deserialize_u16, "q", visit_u16, 0;
+        deserialize_u32, "u", visit_u32, 0;
+        deserialize_u64, "t", visit_u64, 0;
+        deserialize_f32, "d", visit_f32, 0.0;
+        deserialize_f64, "d", visit_f64, 0.0;
+        deserialize_char, "s", visit_char, 'x';
+        deserialize_str, "s", visit_str, "";
+        deserialize_string, "s", visit_str, "";
+        deserialize_bytes, "ay", visit_bytes, &[];
+        deserialize_byte_buf, "ay", visit_bytes, &[];
+    }
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(Error::new("Cannot compute the signature of a type that is deserialized as 'any'"))
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        self.push("a")?;
+        visitor.visit_some(self.child())
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(Error::new("The unit type cannot be represented in D-Bus"))
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(self, name: &'static str, _: V) -> Result<V::Value> {
+        Err(Error::new(format!("Unit struct {} cannot be represented in D-Bus", name)))
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        self.push("a")?;
+        visitor.visit_seq(SigSeq { tracer: &mut self, left: 1 })
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(mut self, len: usize, visitor: V) -> Result<V::Value> {
+        if len == 0 { return Err(Error::new("Empty structs cannot be represented in D-Bus")) }
+        self.push("(")?;
+        let r = visitor.visit_seq(SigSeq { tracer: &mut self, left: len })?;
+        self.push(")")?;
+        Ok(r)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(self, _: &'static str, len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        self.push("a{")?;
+        let r = visitor.visit_map(SigSeq { tracer: &mut self, left: 1 })?;
+        self.push("}")?;
+        Ok(r)
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(self, _: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(mut self, _: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        if variants.is_empty() { return Err(Error::new("Enums without variants cannot be represented in D-Bus")) }
+        self.push("v")?;
+        visitor.visit_enum(SigSeq { tracer: &mut self, left: 0 })
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(0)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Message;
+    use serde_derive::{Serialize, Deserialize};
+    use std::collections::{HashMap, BTreeMap};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    enum Shape { Empty, Circle(f64), Rect(u32, u32), Named { name: String, sides: Vec<u8> } }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Inner { a: i8, b: char, c: Option<String> }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Wrapper(u64);
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Outer {
+        flag: bool,
+        inner: Inner,
+        list: Vec<Inner>,
+        map: BTreeMap<String, i32>,
+        shapes: Vec<Shape>,
+        w: Wrapper,
+        t: (u16, f32),
+    }
+
+    fn roundtrip<T: Serialize + for<'a> Deserialize<'a> + PartialEq + fmt::Debug>(v: &T) -> Message {
+        let mut m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap();
+        let sig = signature_of::<T>().unwrap();
+        to_append(v, Some(&sig), &mut IterAppend::new(&mut m)).unwrap();
+        assert_eq!(m.iter_init().signature(), sig);
+        let v2: T = from_iter(&mut m.iter_init()).unwrap();
+        assert_eq!(*v, v2);
+        m
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(&*signature_of::<Outer>().unwrap(), "(b(nsas)a(nsas)a{si}avt(qd))");
+        assert_eq!(&*signature_of::<HashMap<u8, Vec<Shape>>>().unwrap(), "a{yav}");
+        assert_eq!(&*signature_of::<Vec<u8>>().unwrap(), "ay");
+        assert!(signature_of::<()>().is_err());
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Rec { next: Option<Box<Rec>> }
+        assert!(signature_of::<Rec>().is_err());
+    }
+
+    #[test]
+    fn roundtrips() {
+        let inner = Inner { a: -5, b: 'ö', c: Some("x".into()) };
+        let o = Outer {
+            flag: true,
+            inner: inner.clone(),
+            list: vec!(inner.clone(), Inner { a: 1, b: 'a', c: None }),
+            map: vec!(("one".to_string(), 1), ("two".to_string(), 2)).into_iter().collect(),
+            shapes: vec!(Shape::Empty, Shape::Circle(2.5), Shape::Rect(3, 4), Shape::Named { name: "tri".into(), sides: vec!(1, 2, 3) }),
+            w: Wrapper(u64::max_value()),
+            t: (7, 0.5),
+        };
+        roundtrip(&o);
+        roundtrip(&Outer { list: vec!(), map: BTreeMap::new(), shapes: vec!(), ..o.clone() });
+        roundtrip(&vec!(vec!(), vec!(1u32)));
+        roundtrip(&Shape::Empty);
+
+        // Appending without a signature works as long as there are no empty arrays
+        let mut m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap();
+        to_append(&o, None, &mut IterAppend::new(&mut m)).unwrap();
+        assert!(to_append(&vec!(Vec::<u8>::new()), None, &mut IterAppend::new(&mut m)).is_err());
+        assert_eq!(from_iter::<Outer>(&mut m.iter_init()).unwrap(), o);
+
+        // Enum encoding as seen from the other side
+        let m = roundtrip(&Shape::Circle(1.0));
+        let v: crate::arg::Variant<HashMap<String, crate::arg::Variant<f64>>> = m.read1().unwrap();
+        assert_eq!((v.0)["Circle"].0, 1.0);
+    }
+
+    #[test]
+    fn from_dict() {
+        // Structs can be read from a{sv}, e g property maps
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Props<'a> { #[serde(borrow)] name: &'a str, count: u32, missing: Option<u8> }
+
+        let mut map: HashMap<&str, crate::arg::Variant<Box<dyn crate::arg::RefArg>>> = HashMap::new();
+        map.insert("name", crate::arg::Variant(Box::new("hello".to_string())));
+        map.insert("count", crate::arg::Variant(Box::new(5u32)));
+        map.insert("ignored", crate::arg::Variant(Box::new(5i64)));
+        let m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap().append1(map);
+        let p: Props = from_iter(&mut m.iter_init()).unwrap();
+        assert_eq!(p, Props { name: "hello", count: 5, missing: None });
+
+        let e = from_iter::<Inner>(&mut m.iter_init()).unwrap_err();
+        assert!(e.to_string().contains("missing field"));
+        let e = from_iter::<u32>(&mut m.iter_init().clone()).unwrap_err();
+        assert!(e.to_string().contains("invalid type"));
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut e = to_encoded(&(1u32, "a")).unwrap();
+        assert_eq!(&*e.signature().unwrap(), "(us)");
+        assert!(e.set_signature(&Signature::new("(ss)").unwrap()).is_err());
+        assert!(e.set_signature(&Signature::new("(us)").unwrap()).is_ok());
+        assert!(to_encoded(&()).is_err());
+        let mut hm = HashMap::new();
+        hm.insert((1u8, 2u8), 3u8);
+        let mut m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap();
+        assert!(to_encoded(&hm).unwrap().signature().is_none());
+        assert!(to_append(&hm, None, &mut IterAppend::new(&mut m)).is_err());
+        assert!(m.iter_init().arg_type() == ArgType::Invalid);
+        let empty: HashMap<u8, u8> = HashMap::new();
+        assert!(to_encoded(&empty).unwrap().set_signature(&Signature::new("ay").unwrap()).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-string-validation"))]
+    fn invalid_signature() {
+        let mut m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap();
+        let deep = (0..40).fold(Node::Byte(1), |n, _| Node::Array(None, vec!(n)));
+        assert!(Encoded(deep.clone()).signature().is_none());
+        assert!(Encoded(deep).append_to(&mut IterAppend::new(&mut m)).is_err());
+        let long = Node::Struct(vec!(Node::Byte(1); 300));
+        assert!(Encoded(long).append_to(&mut IterAppend::new(&mut m)).is_err());
+        let in_variant = Node::Variant(None, Box::new((0..40).fold(Node::Byte(1), |n, _| Node::Struct(vec!(n)))));
+        assert!(Encoded(in_variant).append_to(&mut IterAppend::new(&mut m)).is_err());
+        assert!(m.iter_init().arg_type() == ArgType::Invalid);
+    }
+}
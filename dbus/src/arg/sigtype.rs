@@ -0,0 +1,328 @@
+use super::{ArgType, Arg, RefArg};
+use crate::Signature;
+use std::{fmt, str};
+use std::convert::TryFrom;
+
+/// A parsed D-Bus type signature, i e a single complete type.
+///
+/// Use this to walk through a signature, instead of looking at its characters.
+///
+/// # Example
+///
+/// ```
+/// use dbus::arg::{SignatureType, ArgType};
+/// let t: SignatureType = "a{s(iv)}".parse().unwrap();
+/// if let SignatureType::Dict(k, v) = &t {
+///     assert_eq!(**k, SignatureType::Basic(ArgType::String));
+///     assert_eq!(v.to_string(), "(iv)");
+/// }
+/// assert_eq!(t.alignment(), 4);
+///
+/// let all: Vec<_> = SignatureType::iter("sa{sv}as").collect::<Result<_, _>>().unwrap();
+/// assert_eq!(all.len(), 3);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SignatureType {
+    /// A basic type, i e neither a container nor a variant. The ArgType is never
+    /// `Array`, `Struct`, `DictEntry`, `Variant` or `Invalid`.
+    Basic(ArgType),
+    /// An array of the inner type (but not a dict).
+    Array(Box<SignatureType>),
+    /// A dict, i e an array of dict entries, with key and value types.
+    /// The key is always a basic type.
+    Dict(Box<SignatureType>, Box<SignatureType>),
+    /// A struct, which has at least one field.
+    Struct(Vec<SignatureType>),
+    /// A variant.
+    Variant,
+}
+
+/// Maximum length of a signature, in bytes.
+pub const MAX_SIGNATURE_LEN: usize = 255;
+/// Maximum nesting of arrays (including dicts) inside each other.
+pub const MAX_ARRAY_DEPTH: usize = 32;
+/// Maximum nesting of structs (including dict entries) inside each other.
+pub const MAX_STRUCT_DEPTH: usize = 32;
+/// Maximum nesting of all containers, including variants, in a value.
+pub const MAX_TOTAL_DEPTH: usize = 64;
+
+fn basic_type(c: u8) -> Option<ArgType> {
+    let a = ArgType::from_i32(c as i32).ok()?;
+    match a {
+        ArgType::Array | ArgType::Struct | ArgType::DictEntry | ArgType::Variant | ArgType::Invalid => None,
+        a => Some(a),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    arrays: usize,
+    structs: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self, msg: &str) -> String {
+        format!("{} at position {} in signature '{}'", msg, self.pos, String::from_utf8_lossy(self.s))
+    }
+
+    fn parse(&mut self) -> Result<SignatureType, String> {
+        let c = *self.s.get(self.pos).ok_or_else(|| self.err("Unexpected end"))?;
+        if let Some(a) = basic_type(c) {
+            self.pos += 1;
+            return Ok(SignatureType::Basic(a))
+        }
+        match c {
+            b'v' => { self.pos += 1; Ok(SignatureType::Variant) },
+            b'a' => {
+                if self.arrays >= MAX_ARRAY_DEPTH { return Err(self.err("Arrays nested too deeply")) }
+                self.pos += 1;
+                self.arrays += 1;
+                let r = if self.s.get(self.pos) == Some(&b'{') { self.parse_dict_entry() }
+                    else { self.parse().map(|t| SignatureType::Array(Box::new(t))) };
+                self.arrays -= 1;
+                r
+            }
+            b'(' => {
+                if self.structs >= MAX_STRUCT_DEPTH { return Err(self.err("Structs nested too deeply")) }
+                self.pos += 1;
+                self.structs += 1;
+                let mut v = vec!();
+                while self.s.get(self.pos) != Some(&b')') { v.push(self.parse()?); }
+                if v.is_empty() { return Err(self.err("Empty struct")) }
+                self.pos += 1;
+                self.structs -= 1;
+                Ok(SignatureType::Struct(v))
+            }
+            b'{' => Err(self.err("Dict entry outside of array")),
+            b')' | b'}' => Err(self.err("Unexpected end of container")),
+            _ => Err(self.err(&format!("Invalid character '{}'", c as char))),
+        }
+    }
+
+    fn parse_dict_entry(&mut self) -> Result<SignatureType, String> {
+        if self.structs >= MAX_STRUCT_DEPTH { return Err(self.err("Structs nested too deeply")) }
+        self.pos += 1;
+        self.structs += 1;
+        let k = self.parse()?;
+        if !k.is_basic() { return Err(self.err("Dict key is not a basic type")) }
+        let v = self.parse()?;
+        if self.s.get(self.pos) != Some(&b'}') { return Err(self.err("Dict entry must have exactly two types")) }
+        self.pos += 1;
+        self.structs -= 1;
+        Ok(SignatureType::Dict(Box::new(k), Box::new(v)))
+    }
+}
+
+/// Iterator over the single complete types in a signature string, created by `SignatureType::iter`.
+///
+/// Iteration stops after the first error.
+#[derive(Clone, Debug)]
+pub struct SignatureTypes<'a> {
+    p: Parser<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for SignatureTypes<'a> {
+    type Item = Result<SignatureType, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.p.pos >= self.p.s.len() { return None }
+        if self.p.s.len() > MAX_SIGNATURE_LEN {
+            self.done = true;
+            return Some(Err(too_long()))
+        }
+        let r = self.p.parse();
+        self.done = r.is_err();
+        Some(r)
+    }
+}
+
+fn too_long() -> String { format!("Signature is longer than {} bytes", MAX_SIGNATURE_LEN) }
+
+impl SignatureType {
+    /// Parses a string containing a single complete type.
+    pub fn parse(s: &str) -> Result<SignatureType, String> {
+        if s.len() > MAX_SIGNATURE_LEN { return Err(too_long()) }
+        let mut p = Parser { s: s.as_bytes(), pos: 0, arrays: 0, structs: 0 };
+        let r = p.parse()?;
+        if p.pos != s.len() { return Err(p.err("Expected end of signature (more than one complete type)")) }
+        Ok(r)
+    }
+
+    /// Iterates over the single complete types in a string containing zero or more complete types,
+    /// such as the signature of a message body.
+    pub fn iter(s: &str) -> SignatureTypes<'_> {
+        SignatureTypes { p: Parser { s: s.as_bytes(), pos: 0, arrays: 0, structs: 0 }, done: false }
+    }
+
+    /// Parses a Signature.
+    pub fn from_signature(s: &Signature) -> Result<SignatureType, String> { SignatureType::parse(s) }
+
+    /// The type of the Rust type A.
+    ///
+    /// # Panics
+    ///
+    /// If the signature of A is not a single complete type. This never happens for the
+    /// `Arg` implementations in this crate.
+    pub fn of<A: Arg>() -> SignatureType {
+        SignatureType::from_signature(&A::signature()).unwrap()
+    }
+
+    /// The type of a RefArg.
+    ///
+    /// # Panics
+    ///
+    /// If the signature of the RefArg is not a single complete type. This never happens for the
+    /// `RefArg` implementations in this crate.
+    pub fn of_refarg(r: &dyn RefArg) -> SignatureType {
+        SignatureType::from_signature(&r.signature()).unwrap()
+    }
+
+    /// Creates the type for an ArgType, if it is a basic type or a variant.
+    ///
+    /// Returns None for containers, which also need to know the types of their contents.
+    pub fn from_arg_type(a: ArgType) -> Option<SignatureType> {
+        if a == ArgType::Variant { return Some(SignatureType::Variant) }
+        basic_type(a as u8).map(SignatureType::Basic)
+    }
+
+    /// Converts this type to a Signature.
+    ///
+    /// # Panics
+    ///
+    /// If the type is not valid, which can only happen for a type that was constructed by hand,
+    /// such as an empty `Struct`, a `Dict` with a key that is not a basic type, or a type that
+    /// is nested too deeply. Use `Signature::try_from` to check for this instead.
+    pub fn to_signature(&self) -> Signature<'static> {
+        Signature::new(self.to_string()).unwrap()
+    }
+
+    /// The ArgType of a value of this type, as returned from e g `Iter::arg_type`.
+    ///
+    /// Dicts are arrays of dict entries, so they have `ArgType::Array`.
+    pub fn arg_type(&self) -> ArgType {
+        match self {
+            SignatureType::Basic(a) => *a,
+            SignatureType::Array(_) | SignatureType::Dict(_, _) => ArgType::Array,
+            SignatureType::Struct(_) => ArgType::Struct,
+            SignatureType::Variant => ArgType::Variant,
+        }
+    }
+
+    /// Returns true for basic types, which are the types that can be used as dict keys.
+    pub fn is_basic(&self) -> bool {
+        matches!(self, SignatureType::Basic(_))
+    }
+
+    /// Returns true for containers, i e arrays, dicts, structs and variants.
+    pub fn is_container(&self) -> bool { !self.is_basic() }
+
+    /// The alignment, in bytes, of a value of this type in the D-Bus wire format.
+    pub fn alignment(&self) -> usize {
+        match self {
+            SignatureType::Basic(ArgType::Byte) | SignatureType::Basic(ArgType::Signature) | SignatureType::Variant => 1,
+            SignatureType::Basic(ArgType::Int16) | SignatureType::Basic(ArgType::UInt16) => 2,
+            SignatureType::Basic(ArgType::Int64) | SignatureType::Basic(ArgType::UInt64) |
+            SignatureType::Basic(ArgType::Double) | SignatureType::Struct(_) => 8,
+            _ => 4,
+        }
+    }
+
+    /// How deeply containers are nested in this type.
+    ///
+    /// Basic types have depth 0, an array of basic types has depth 1, and so on.
+    /// A dict counts as two containers, an array and a dict entry.
+    /// Variants have depth 1, since their contents are not known.
+    pub fn depth(&self) -> usize {
+        match self {
+            SignatureType::Basic(_) => 0,
+            SignatureType::Variant => 1,
+            SignatureType::Array(t) => t.depth() + 1,
+            SignatureType::Dict(k, v) => std::cmp::max(k.depth(), v.depth()) + 2,
+            SignatureType::Struct(f) => f.iter().map(|t| t.depth()).max().unwrap_or(0) + 1,
+        }
+    }
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureType::Basic(a) => write!(f, "{}", *a as u8 as char),
+            SignatureType::Variant => f.write_str("v"),
+            SignatureType::Array(t) => write!(f, "a{}", t),
+            SignatureType::Dict(k, v) => write!(f, "a{{{}{}}}", k, v),
+            SignatureType::Struct(v) => {
+                f.write_str("(")?;
+                for t in v { write!(f, "{}", t)?; }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl str::FromStr for SignatureType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> { SignatureType::parse(s) }
+}
+
+impl TryFrom<SignatureType> for Signature<'static> {
+    type Error = String;
+    fn try_from(t: SignatureType) -> Result<Self, String> { Signature::try_from(&t) }
+}
+
+impl<'a> TryFrom<&'a SignatureType> for Signature<'static> {
+    type Error = String;
+    fn try_from(t: &'a SignatureType) -> Result<Self, String> {
+        let s = t.to_string();
+        SignatureType::parse(&s)?;
+        Signature::new(s)
+    }
+}
+
+#[test]
+fn parse_print() {
+    use std::collections::HashMap;
+    for s in &["y", "v", "as", "a{sv}", "(ia{s(ayv)}ah)", "aa{oa{sa{sv}}}", "(((i)))", "g"] {
+        let t = SignatureType::parse(s).unwrap();
+        assert_eq!(&t.to_string(), s);
+        assert_eq!(&*t.to_signature(), *s);
+    }
+    assert_eq!(SignatureType::of::<HashMap<String, (u8, Vec<f64>)>>(),
+        SignatureType::Dict(Box::new(SignatureType::Basic(ArgType::String)),
+            Box::new(SignatureType::Struct(vec!(SignatureType::Basic(ArgType::Byte),
+                SignatureType::Array(Box::new(SignatureType::Basic(ArgType::Double))))))));
+    let b: Box<dyn RefArg> = Box::new(vec!(1i32));
+    assert_eq!(SignatureType::of_refarg(&*b).to_string(), "ai");
+    assert_eq!(SignatureType::from_arg_type(ArgType::UnixFd), Some(SignatureType::Basic(ArgType::UnixFd)));
+    assert_eq!(SignatureType::from_arg_type(ArgType::Struct), None);
+    assert_eq!(&*Signature::try_from(SignatureType::parse("a{sv}").unwrap()).unwrap(), "a{sv}");
+    assert!(Signature::try_from(SignatureType::Struct(vec!())).is_err());
+    assert!(Signature::try_from(SignatureType::Dict(Box::new(SignatureType::Variant), Box::new(SignatureType::Variant))).is_err());
+    assert!(Signature::try_from(SignatureType::Basic(ArgType::Array)).is_err());
+
+    for s in &["", "ii", "a", "()", "{sv}", "a{vs}", "a{s}", "a{sii}", "(i", "i)", "r", "ae", "z"] {
+        assert!(SignatureType::parse(s).is_err(), "{}", s);
+    }
+}
+
+#[test]
+fn iter_align_depth() {
+    let v: Vec<_> = SignatureType::iter("ia{sv}(yy)").map(|t| t.unwrap()).collect();
+    assert_eq!(v.iter().map(|t| t.to_string()).collect::<Vec<_>>(), vec!("i", "a{sv}", "(yy)"));
+    assert_eq!(v.iter().map(|t| t.alignment()).collect::<Vec<_>>(), vec!(4, 4, 8));
+    assert_eq!(v.iter().map(|t| t.depth()).collect::<Vec<_>>(), vec!(0, 3, 1));
+    assert_eq!(v.iter().map(|t| t.arg_type()).collect::<Vec<_>>(), vec!(ArgType::Int32, ArgType::Array, ArgType::Struct));
+    assert_eq!(SignatureType::iter("").count(), 0);
+    let v: Vec<_> = SignatureType::iter("sa{vs}i").collect();
+    assert_eq!(v.len(), 2);
+    assert!(v[1].is_err());
+
+    let deep = "a".repeat(32) + "i";
+    assert!(SignatureType::parse(&deep).is_ok());
+    assert!(SignatureType::parse(&("a".to_string() + &deep)).is_err());
+    let deep = "(".repeat(32) + "i" + &")".repeat(32);
+    assert!(SignatureType::parse(&deep).is_ok());
+    assert!(SignatureType::parse(&("a{s".to_string() + &deep + "}")).is_err());
+    assert!(SignatureType::parse(&"(i)".repeat(100)).is_err());
+}
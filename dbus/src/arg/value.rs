@@ -0,0 +1,825 @@
+use super::{Arg, Append, Get, RefArg, Iter, IterAppend, ArgType, OwnedFd, SignatureType};
+use super::{MAX_ARRAY_DEPTH, MAX_STRUCT_DEPTH, MAX_TOTAL_DEPTH};
+use crate::{Signature, Path};
+use std::{fmt, ops, any, str};
+use std::convert::TryFrom;
+use std::os::unix::io::AsRawFd;
+
+/// An owned D-Bus value of any type.
+///
+/// Unlike `Box<dyn RefArg>`, this can be inspected with a `match`, cloned and compared,
+/// and it keeps the types of empty arrays and dicts.
+///
+/// As an `Arg`, a `Value` is a variant, since its type is not known at compile time.
+/// This means that e g a `HashMap<String, Value>` is an `a{sv}`. To append or read a value
+/// as its actual type (e g, a message argument of type `s`), use `append_value`, `get_value`
+/// and `get_all`.
+///
+/// Values can be printed to, and parsed from, the GVariant text format, e g `<'hello'>`,
+/// `@as []` or `{'a': <1>}`.
+///
+/// # Example
+///
+/// ```
+/// use dbus::arg::Value;
+/// let v: Value = "{'Volume': <uint32 5>, 'Muted': <false>}".parse().unwrap();
+/// assert_eq!(&*v.signature(), "a{sv}");
+/// assert_eq!(v.to_string(), "{'Volume': <uint32 5>, 'Muted': <false>}");
+///
+/// let m = dbus::Message::new_signal("/", "com.example", "Sig").unwrap();
+/// let mut m = m.append1("hello");
+/// v.append_value(&mut dbus::arg::IterAppend::new(&mut m));
+/// let body = Value::get_all(&mut m.iter_init());
+/// assert_eq!(body, vec!(Value::from("hello"), v));
+/// ```
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    /// A D-Bus array (but not a dict).
+    Array(ValueArray),
+    /// A D-Bus struct. This must contain at least one field.
+    Struct(Vec<Value>),
+    /// A D-Bus variant.
+    Variant(Box<Value>),
+    /// A D-Bus dict, i e an array of dict entries.
+    Dict(ValueDict),
+    /// A D-Bus object path.
+    ObjectPath(Path<'static>),
+    /// A D-Bus signature.
+    Signature(Signature<'static>),
+    /// A D-Bus string.
+    Str(String),
+    /// A D-Bus boolean.
+    Bool(bool),
+    /// A D-Bus byte.
+    Byte(u8),
+    /// A D-Bus signed 16 bit integer.
+    Int16(i16),
+    /// A D-Bus signed 32 bit integer.
+    Int32(i32),
+    /// A D-Bus signed 64 bit integer.
+    Int64(i64),
+    /// A D-Bus unsigned 16 bit integer.
+    UInt16(u16),
+    /// A D-Bus unsigned 32 bit integer.
+    UInt32(u32),
+    /// A D-Bus unsigned 64 bit integer.
+    UInt64(u64),
+    /// A D-Bus double.
+    Double(f64),
+    /// A D-Bus file descriptor.
+    UnixFd(OwnedFd),
+}
+
+/// An array of values which all have the same type. The array may be empty.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct ValueArray {
+    t: SignatureType,
+    v: Vec<Value>,
+}
+
+impl ValueArray {
+    /// Creates a new array, where every element must have type `t`.
+    ///
+    /// Use `Value::Dict` for arrays of dict entries.
+    pub fn new(t: SignatureType, v: Vec<Value>) -> Result<ValueArray, String> {
+        for x in &v {
+            if x.value_type() != t { return Err(format!("Array element {} is not of type '{}'", x, t)) }
+        }
+        Ok(ValueArray { t, v })
+    }
+
+    /// The type of the elements.
+    pub fn element_type(&self) -> &SignatureType { &self.t }
+
+    /// Consumes the array, returning its elements.
+    pub fn into_vec(self) -> Vec<Value> { self.v }
+}
+
+impl ops::Deref for ValueArray {
+    type Target = [Value];
+    fn deref(&self) -> &Self::Target { &self.v }
+}
+
+/// A dict where all keys have the same (basic) type and all values have the same type. The dict may be empty.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct ValueDict {
+    k: SignatureType,
+    t: SignatureType,
+    v: Vec<(Value, Value)>,
+}
+
+impl ValueDict {
+    /// Creates a new dict, where every key must have type `k` and every value must have type `t`.
+    pub fn new(k: SignatureType, t: SignatureType, v: Vec<(Value, Value)>) -> Result<ValueDict, String> {
+        if !k.is_basic() { return Err(format!("Dict key type '{}' is not a basic type", k)) }
+        for (xk, xv) in &v {
+            if xk.value_type() != k { return Err(format!("Dict key {} is not of type '{}'", xk, k)) }
+            if xv.value_type() != t { return Err(format!("Dict value {} is not of type '{}'", xv, t)) }
+        }
+        Ok(ValueDict { k, t, v })
+    }
+
+    /// The type of the keys.
+    pub fn key_type(&self) -> &SignatureType { &self.k }
+
+    /// The type of the values.
+    pub fn value_type(&self) -> &SignatureType { &self.t }
+
+    /// Consumes the dict, returning its entries.
+    pub fn into_vec(self) -> Vec<(Value, Value)> { self.v }
+}
+
+impl ops::Deref for ValueDict {
+    type Target = [(Value, Value)];
+    fn deref(&self) -> &Self::Target { &self.v }
+}
+
+impl Value {
+    /// The type of this value.
+    pub fn value_type(&self) -> SignatureType {
+        match self {
+            Value::Array(a) => SignatureType::Array(Box::new(a.t.clone())),
+            Value::Dict(d) => SignatureType::Dict(Box::new(d.k.clone()), Box::new(d.t.clone())),
+            Value::Struct(v) => SignatureType::Struct(v.iter().map(|x| x.value_type()).collect()),
+            Value::Variant(_) => SignatureType::Variant,
+            _ => SignatureType::Basic(self.arg_type()),
+        }
+    }
+
+    /// The signature of this value.
+    ///
+    /// Note that this is the signature of the value itself, not "v", which is what
+    /// `Arg::signature` and `RefArg::signature` return.
+    pub fn signature(&self) -> Signature<'static> { self.value_type().to_signature() }
+
+    /// The ArgType of this value.
+    pub fn arg_type(&self) -> ArgType {
+        match self {
+            Value::Array(_) | Value::Dict(_) => ArgType::Array,
+            Value::Struct(_) => ArgType::Struct,
+            Value::Variant(_) => ArgType::Variant,
+            Value::ObjectPath(_) => ArgType::ObjectPath,
+            Value::Signature(_) => ArgType::Signature,
+            Value::Str(_) => ArgType::String,
+            Value::Bool(_) => ArgType::Boolean,
+            Value::Byte(_) => ArgType::Byte,
+            Value::Int16(_) => ArgType::Int16,
+            Value::Int32(_) => ArgType::Int32,
+            Value::Int64(_) => ArgType::Int64,
+            Value::UInt16(_) => ArgType::UInt16,
+            Value::UInt32(_) => ArgType::UInt32,
+            Value::UInt64(_) => ArgType::UInt64,
+            Value::Double(_) => ArgType::Double,
+            Value::UnixFd(_) => ArgType::UnixFd,
+        }
+    }
+
+    /// Appends this value as its actual type (i e, not wrapped in a variant).
+    pub fn append_value(&self, i: &mut IterAppend) {
+        match self {
+            Value::Array(a) => i.append_array(&a.t.to_signature(), |s| for x in &a.v { x.append_value(s) }),
+            Value::Dict(d) => i.append_dict(&d.k.to_signature(), &d.t.to_signature(), |s| for (k, v) in &d.v {
+                s.append_dict_entry(|e| { k.append_value(e); v.append_value(e) })
+            }),
+            Value::Struct(v) => i.append_struct(|s| for x in v { x.append_value(s) }),
+            Value::Variant(v) => i.append_variant(&Value::signature(v), |s| v.append_value(s)),
+            Value::ObjectPath(x) => x.append_by_ref(i),
+            Value::Signature(x) => x.append_by_ref(i),
+            Value::Str(x) => x.append_by_ref(i),
+            Value::Bool(x) => x.append_by_ref(i),
+            Value::Byte(x) => x.append_by_ref(i),
+            Value::Int16(x) => x.append_by_ref(i),
+            Value::Int32(x) => x.append_by_ref(i),
+            Value::Int64(x) => x.append_by_ref(i),
+            Value::UInt16(x) => x.append_by_ref(i),
+            Value::UInt32(x) => x.append_by_ref(i),
+            Value::UInt64(x) => x.append_by_ref(i),
+            Value::Double(x) => x.append_by_ref(i),
+            Value::UnixFd(x) => x.append_by_ref(i),
+        }
+    }
+
+    /// Reads the current argument of the iterator as its actual type, without advancing the iterator.
+    ///
+    /// Returns None if there are no more arguments.
+    pub fn get_value(i: &mut Iter) -> Option<Value> {
+        Some(match i.arg_type() {
+            ArgType::Array => {
+                let t = SignatureType::parse(&i.signature()).ok()?;
+                let mut s = i.recurse(ArgType::Array)?;
+                match t {
+                    SignatureType::Dict(k, t) => {
+                        let mut v = vec!();
+                        while let Some(mut e) = s.recurse(ArgType::DictEntry) {
+                            let k = Value::get_value(&mut e)?;
+                            e.next();
+                            v.push((k, Value::get_value(&mut e)?));
+                            s.next();
+                        }
+                        Value::Dict(ValueDict { k: *k, t: *t, v })
+                    }
+                    SignatureType::Array(t) => Value::Array(ValueArray { t: *t, v: Value::get_all(&mut s) }),
+                    _ => return None,
+                }
+            }
+            ArgType::Struct => Value::Struct(Value::get_all(&mut i.recurse(ArgType::Struct)?)),
+            ArgType::Variant => Value::Variant(Box::new(Value::get_value(&mut i.recurse(ArgType::Variant)?)?)),
+            ArgType::ObjectPath => Value::ObjectPath(i.get::<Path>()?.into_static()),
+            ArgType::Signature => Value::Signature(i.get::<Signature>()?.into_static()),
+            ArgType::String => Value::Str(i.get()?),
+            ArgType::Boolean => Value::Bool(i.get()?),
+            ArgType::Byte => Value::Byte(i.get()?),
+            ArgType::Int16 => Value::Int16(i.get()?),
+            ArgType::Int32 => Value::Int32(i.get()?),
+            ArgType::Int64 => Value::Int64(i.get()?),
+            ArgType::UInt16 => Value::UInt16(i.get()?),
+            ArgType::UInt32 => Value::UInt32(i.get()?),
+            ArgType::UInt64 => Value::UInt64(i.get()?),
+            ArgType::Double => Value::Double(i.get()?),
+            ArgType::UnixFd => Value::UnixFd(i.get()?),
+            ArgType::DictEntry | ArgType::Invalid => return None,
+        })
+    }
+
+    /// Reads all remaining arguments of the iterator, e g the entire body of a message.
+    pub fn get_all(i: &mut Iter) -> Vec<Value> {
+        let mut v = vec!();
+        while let Some(x) = Value::get_value(i) {
+            v.push(x);
+            i.next();
+        }
+        v
+    }
+
+    /// Parses a value from the GVariant text format.
+    ///
+    /// Integers without a type annotation are `i`, and numbers with a decimal point or
+    /// exponent are `d`. Empty arrays and dicts must have a type annotation, e g `@as []`.
+    pub fn parse(s: &str) -> Result<Value, String> {
+        let ast = TextParser::new(s).parse_all()?;
+        let t = ast.value_type()?;
+        ast.resolve(&t)
+    }
+
+    /// Parses a value of a known type from the GVariant text format.
+    ///
+    /// This makes type annotations unnecessary, e g `[]` can be parsed as an `as`,
+    /// and `5` as a `y`.
+    pub fn parse_with_type(s: &str, t: &SignatureType) -> Result<Value, String> {
+        TextParser::new(s).parse_all()?.resolve(t)
+    }
+
+    /// Looks through variants, returning the innermost value that is not a variant.
+    pub fn inner(&self) -> &Value {
+        match self {
+            Value::Variant(v) => v.inner(),
+            v => v,
+        }
+    }
+}
+
+impl Arg for Value {
+    const ARG_TYPE: ArgType = ArgType::Variant;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"v\0") } }
+}
+
+impl Append for Value {
+    /// Appends the value wrapped in a variant.
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        i.append_variant(&self.signature(), |s| self.append_value(s))
+    }
+}
+
+impl<'a> Get<'a> for Value {
+    /// Reads the contents of a variant.
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        Value::get_value(&mut i.recurse(ArgType::Variant)?)
+    }
+}
+
+impl RefArg for Value {
+    fn arg_type(&self) -> ArgType { ArgType::Variant }
+    fn signature(&self) -> Signature<'static> { <Value as Arg>::signature() }
+    fn append(&self, i: &mut IterAppend) { self.append_by_ref(i) }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_i64(&self) -> Option<i64> {
+        match self.inner() {
+            Value::Byte(x) => Some(*x as i64),
+            Value::Int16(x) => Some(*x as i64),
+            Value::UInt16(x) => Some(*x as i64),
+            Value::Int32(x) => Some(*x as i64),
+            Value::UInt32(x) => Some(*x as i64),
+            Value::Int64(x) => Some(*x),
+            _ => None,
+        }
+    }
+    fn as_u64(&self) -> Option<u64> {
+        match self.inner() {
+            Value::Byte(x) => Some(*x as u64),
+            Value::UInt16(x) => Some(*x as u64),
+            Value::UInt32(x) => Some(*x as u64),
+            Value::UInt64(x) => Some(*x),
+            _ => None,
+        }
+    }
+    fn as_f64(&self) -> Option<f64> {
+        match self.inner() {
+            Value::Double(x) => Some(*x),
+            Value::Int64(_) | Value::UInt64(_) => None,
+            v => v.as_i64().map(|x| x as f64),
+        }
+    }
+    fn as_str(&self) -> Option<&str> {
+        match self.inner() {
+            Value::Str(x) => Some(x),
+            Value::ObjectPath(x) => Some(x),
+            Value::Signature(x) => Some(x),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { Box::new(self.clone()) }
+}
+
+macro_rules! value_from {
+    ($t: ty, $v: ident) => {
+        impl From<$t> for Value { fn from(x: $t) -> Value { Value::$v(x) } }
+    }
+}
+
+value_from!(bool, Bool);
+value_from!(u8, Byte);
+value_from!(i16, Int16);
+value_from!(i32, Int32);
+value_from!(i64, Int64);
+value_from!(u16, UInt16);
+value_from!(u32, UInt32);
+value_from!(u64, UInt64);
+value_from!(f64, Double);
+value_from!(String, Str);
+value_from!(Path<'static>, ObjectPath);
+value_from!(Signature<'static>, Signature);
+value_from!(OwnedFd, UnixFd);
+impl<'a> From<&'a str> for Value { fn from(x: &'a str) -> Value { Value::Str(x.into()) } }
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    let q = if s.contains('\'') && !s.contains('"') { '"' } else { '\'' };
+    write!(f, "{}", q)?;
+    for c in s.chars() {
+        match c {
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            c if c == q => write!(f, "\\{}", c)?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "{}", q)
+}
+
+fn write_list<'a, I: Iterator<Item=&'a Value>>(f: &mut fmt::Formatter, i: I) -> fmt::Result {
+    for (idx, x) in i.enumerate() {
+        if idx > 0 { f.write_str(", ")? }
+        write!(f, "{}", x)?;
+    }
+    Ok(())
+}
+
+/// Prints the value in the GVariant text format.
+///
+/// Numbers other than `i` and `d` are printed with type annotations, and empty arrays and dicts
+/// with their types, so that the text can be parsed back into the same value.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Array(a) if a.v.is_empty() => write!(f, "@a{} []", a.t),
+            Value::Array(a) => { f.write_str("[")?; write_list(f, a.v.iter())?; f.write_str("]") },
+            Value::Dict(d) if d.v.is_empty() => write!(f, "@a{{{}{}}} {{}}", d.k, d.t),
+            Value::Dict(d) => {
+                f.write_str("{")?;
+                for (idx, (k, v)) in d.v.iter().enumerate() {
+                    if idx > 0 { f.write_str(", ")? }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                f.write_str("}")
+            }
+            Value::Struct(v) => {
+                f.write_str("(")?;
+                write_list(f, v.iter())?;
+                if v.len() == 1 { f.write_str(",")? }
+                f.write_str(")")
+            }
+            Value::Variant(v) => write!(f, "<{}>", v),
+            Value::ObjectPath(x) => { f.write_str("objectpath ")?; write_str(f, x) },
+            Value::Signature(x) => { f.write_str("signature ")?; write_str(f, x) },
+            Value::Str(x) => write_str(f, x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Byte(x) => write!(f, "byte 0x{:02x}", x),
+            Value::Int16(x) => write!(f, "int16 {}", x),
+            Value::Int32(x) => write!(f, "{}", x),
+            Value::Int64(x) => write!(f, "int64 {}", x),
+            Value::UInt16(x) => write!(f, "uint16 {}", x),
+            Value::UInt32(x) => write!(f, "uint32 {}", x),
+            Value::UInt64(x) => write!(f, "uint64 {}", x),
+            Value::Double(x) if x.is_nan() => f.write_str("nan"),
+            Value::Double(x) if x.is_infinite() => f.write_str(if *x > 0.0 { "inf" } else { "-inf" }),
+            Value::Double(x) => write!(f, "{:?}", x),
+            Value::UnixFd(x) => write!(f, "handle {}", x.as_raw_fd()),
+        }
+    }
+}
+
+impl str::FromStr for Value {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Value, String> { Value::parse(s) }
+}
+
+/// A value in the text format, before its type is known.
+#[derive(Debug)]
+enum Text {
+    Num(String),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+    Variant(Box<Text>),
+    Array(Vec<Text>),
+    Dict(Vec<(Text, Text)>),
+    Struct(Vec<Text>),
+    Typed(SignatureType, Box<Text>),
+}
+
+const TYPE_KEYWORDS: [(&str, ArgType); 13] = [
+    ("boolean", ArgType::Boolean), ("byte", ArgType::Byte), ("int16", ArgType::Int16), ("uint16", ArgType::UInt16),
+    ("int32", ArgType::Int32), ("uint32", ArgType::UInt32), ("int64", ArgType::Int64), ("uint64", ArgType::UInt64),
+    ("handle", ArgType::UnixFd), ("double", ArgType::Double), ("string", ArgType::String),
+    ("objectpath", ArgType::ObjectPath), ("signature", ArgType::Signature),
+];
+
+fn parse_int(s: &str) -> Option<i128> {
+    let (neg, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let v = if s.starts_with("0x") || s.starts_with("0X") { i128::from_str_radix(&s[2..], 16).ok()? }
+        else { s.parse::<i128>().ok()? };
+    Some(if neg { -v } else { v })
+}
+
+impl Text {
+    /// The type of this value, if it is given by the text itself.
+    fn known_type(&self) -> Option<SignatureType> {
+        match self {
+            Text::Num(_) => None,
+            Text::Bool(_) => Some(SignatureType::Basic(ArgType::Boolean)),
+            Text::Str(_) => Some(SignatureType::Basic(ArgType::String)),
+            Text::Bytes(_) => Some(SignatureType::Array(Box::new(SignatureType::Basic(ArgType::Byte)))),
+            Text::Variant(_) => Some(SignatureType::Variant),
+            Text::Typed(t, _) => Some(t.clone()),
+            Text::Array(v) => v.iter().find_map(|x| x.known_type()).map(|t| SignatureType::Array(Box::new(t))),
+            Text::Dict(v) => {
+                let k = v.iter().find_map(|x| x.0.known_type())?;
+                let t = v.iter().find_map(|x| x.1.known_type())?;
+                Some(SignatureType::Dict(Box::new(k), Box::new(t)))
+            }
+            Text::Struct(v) => v.iter().map(|x| x.known_type()).collect::<Option<_>>().map(SignatureType::Struct),
+        }
+    }
+
+    /// The type of this value, using defaults for numbers.
+    fn value_type(&self) -> Result<SignatureType, String> {
+        if let Some(t) = self.known_type() { return Ok(t) }
+        let first_type = |v: &mut dyn Iterator<Item=&Text>| -> Result<SignatureType, String> {
+            let v: Vec<_> = v.collect();
+            match v.iter().find_map(|x| x.known_type()) {
+                Some(t) => Ok(t),
+                None => v.first().ok_or_else(|| "Cannot determine the type of an empty container, add a type annotation (e g '@as []')".to_string())?.value_type(),
+            }
+        };
+        Ok(match self {
+            Text::Num(s) => {
+                if parse_int(s).is_some() { SignatureType::Basic(ArgType::Int32) }
+                else { SignatureType::Basic(ArgType::Double) }
+            }
+            Text::Array(v) => SignatureType::Array(Box::new(first_type(&mut v.iter())?)),
+            Text::Dict(v) => SignatureType::Dict(Box::new(first_type(&mut v.iter().map(|x| &x.0))?),
+                Box::new(first_type(&mut v.iter().map(|x| &x.1))?)),
+            Text::Struct(v) => SignatureType::Struct(v.iter().map(|x| x.value_type()).collect::<Result<_, _>>()?),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Converts this text to a value of type t.
+    fn resolve(self, t: &SignatureType) -> Result<Value, String> {
+        let mismatch = |x: &Text| format!("Expected a value of type '{}', found {:?}", t, x);
+        Ok(match (self, t) {
+            (Text::Typed(t2, x), _) => {
+                if t2 != *t { return Err(format!("Expected a value of type '{}', found type annotation '{}'", t, t2)) }
+                x.resolve(t)?
+            }
+            (Text::Num(s), SignatureType::Basic(a)) => {
+                let n = parse_int(&s);
+                let range = || format!("Number {} out of range for type '{}'", s, t);
+                match (a, n) {
+                    (ArgType::Double, _) => Value::Double(s.parse().map_err(|_| format!("Invalid number '{}'", s))?),
+                    (ArgType::UnixFd, _) => return Err("File descriptors cannot be parsed from text".into()),
+                    (_, None) => return Err(format!("Invalid integer '{}'", s)),
+                    (ArgType::Byte, Some(n)) => Value::Byte(u8::try_from(n).map_err(|_| range())?),
+                    (ArgType::Int16, Some(n)) => Value::Int16(i16::try_from(n).map_err(|_| range())?),
+                    (ArgType::UInt16, Some(n)) => Value::UInt16(u16::try_from(n).map_err(|_| range())?),
+                    (ArgType::Int32, Some(n)) => Value::Int32(i32::try_from(n).map_err(|_| range())?),
+                    (ArgType::UInt32, Some(n)) => Value::UInt32(u32::try_from(n).map_err(|_| range())?),
+                    (ArgType::Int64, Some(n)) => Value::Int64(i64::try_from(n).map_err(|_| range())?),
+                    (ArgType::UInt64, Some(n)) => Value::UInt64(u64::try_from(n).map_err(|_| range())?),
+                    _ => return Err(format!("Expected a value of type '{}', found number {}", t, s)),
+                }
+            }
+            (Text::Bool(b), SignatureType::Basic(ArgType::Boolean)) => Value::Bool(b),
+            (Text::Str(s), SignatureType::Basic(ArgType::String)) => Value::Str(s),
+            (Text::Str(s), SignatureType::Basic(ArgType::ObjectPath)) => Value::ObjectPath(Path::new(s)?),
+            (Text::Str(s), SignatureType::Basic(ArgType::Signature)) => Value::Signature(Signature::new(s)?),
+            (Text::Variant(x), SignatureType::Variant) => {
+                let t = x.value_type()?;
+                Value::Variant(Box::new(x.resolve(&t)?))
+            }
+            (Text::Bytes(b), SignatureType::Array(e)) if **e == SignatureType::Basic(ArgType::Byte) =>
+                Value::Array(ValueArray { t: (**e).clone(), v: b.into_iter().map(Value::Byte).collect() }),
+            (Text::Array(v), SignatureType::Array(e)) =>
+                Value::Array(ValueArray { t: (**e).clone(), v: v.into_iter().map(|x| x.resolve(e)).collect::<Result<_, _>>()? }),
+            (Text::Dict(v), SignatureType::Dict(k, e)) => {
+                let v = v.into_iter().map(|(xk, xv)| Ok((xk.resolve(k)?, xv.resolve(e)?))).collect::<Result<_, String>>()?;
+                Value::Dict(ValueDict { k: (**k).clone(), t: (**e).clone(), v })
+            }
+            // An empty dict can also be written as an empty array
+            (Text::Array(ref v), SignatureType::Dict(k, e)) if v.is_empty() =>
+                Value::Dict(ValueDict { k: (**k).clone(), t: (**e).clone(), v: vec!() }),
+            (Text::Struct(v), SignatureType::Struct(f)) => {
+                if v.len() != f.len() { return Err(format!("Expected a struct of type '{}', found {} fields", t, v.len())) }
+                Value::Struct(v.into_iter().zip(f).map(|(x, t)| x.resolve(t)).collect::<Result<_, _>>()?)
+            }
+            (x, _) => return Err(mismatch(&x)),
+        })
+    }
+}
+
+struct TextParser<'a> {
+    s: &'a str,
+    pos: usize,
+    arrays: usize,
+    structs: usize,
+    /// Nesting of all containers, variants and type annotations.
+    depth: usize,
+}
+
+impl<'a> TextParser<'a> {
+    fn new(s: &'a str) -> Self { TextParser { s, pos: 0, arrays: 0, structs: 0, depth: 0 } }
+
+    /// Runs f one level deeper, checking the nesting limits. Dicts count as both an array and a struct.
+    fn nested<T, F: FnOnce(&mut Self) -> Result<T, String>>(&mut self, arrays: usize, structs: usize, f: F) -> Result<T, String> {
+        if self.arrays + arrays > MAX_ARRAY_DEPTH { return self.err("Arrays nested too deeply") }
+        if self.structs + structs > MAX_STRUCT_DEPTH { return self.err("Structs nested too deeply") }
+        if self.depth >= MAX_TOTAL_DEPTH { return self.err("Value nested too deeply") }
+        self.arrays += arrays;
+        self.structs += structs;
+        self.depth += 1;
+        let r = f(self);
+        self.arrays -= arrays;
+        self.structs -= structs;
+        self.depth -= 1;
+        r
+    }
+
+    fn err<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("{} at position {} in '{}'", msg, self.pos, self.s))
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.s[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) { self.pos += c.len_utf8(); true } else { false }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) { Ok(()) } else { self.err(&format!("Expected '{}'", c)) }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let start = self.pos;
+        let rest = &self.s[start..];
+        let len = rest.char_indices().find(|&(_, c)| !f(c)).map(|(i, _)| i).unwrap_or(rest.len());
+        self.pos += len;
+        &self.s[start..start + len]
+    }
+
+    fn parse_all(mut self) -> Result<Text, String> {
+        let r = self.parse()?;
+        if self.peek().is_some() { return self.err("Unexpected text after value") }
+        Ok(r)
+    }
+
+    /// Parses a list of values separated by commas, until the end character.
+    fn parse_list(&mut self, end: char) -> Result<Vec<Text>, String> {
+        let mut v = vec!();
+        while !self.eat(end) {
+            if !v.is_empty() {
+                self.expect(',')?;
+                // Allow a trailing comma, as in "(1,)"
+                if self.eat(end) { break }
+            }
+            v.push(self.parse()?);
+        }
+        Ok(v)
+    }
+
+    fn parse_type(&mut self) -> Result<SignatureType, String> {
+        // Find the end of the first complete type, e g "a{sv}" in "a{sv} {}"
+        let start = self.pos;
+        let mut depth = 0;
+        let mut len = 0;
+        for (i, c) in self.s[start..].char_indices() {
+            match c {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                'a' => continue,
+                c if c.is_ascii_alphanumeric() => {},
+                _ => break,
+            }
+            if depth <= 0 { len = i + 1; break }
+        }
+        if len == 0 { return self.err("Expected a type") }
+        self.pos = start + len;
+        SignatureType::parse(&self.s[start..start + len])
+    }
+
+    fn parse_str(&mut self, q: char) -> Result<String, String> {
+        let mut r = String::new();
+        let mut chars = self.s[self.pos..].char_indices();
+        loop {
+            let (i, c) = match chars.next() { Some(x) => x, None => return self.err("Unterminated string") };
+            match c {
+                '\\' => {
+                    let (_, e) = match chars.next() { Some(x) => x, None => return self.err("Unterminated string") };
+                    match e {
+                        'n' => r.push('\n'),
+                        't' => r.push('\t'),
+                        'r' => r.push('\r'),
+                        'a' => r.push('\u{7}'),
+                        'b' => r.push('\u{8}'),
+                        'f' => r.push('\u{c}'),
+                        'v' => r.push('\u{b}'),
+                        'u' | 'U' => {
+                            let n = if e == 'u' { 4 } else { 8 };
+                            let hex: String = (0..n).filter_map(|_| chars.next().map(|x| x.1)).collect();
+                            let c = u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32);
+                            match c { Some(c) => r.push(c), None => return self.err("Invalid unicode escape") }
+                        }
+                        e => r.push(e),
+                    }
+                }
+                c if c == q => { self.pos += i + 1; return Ok(r) }
+                c => r.push(c),
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Result<Text, String> {
+        let c = match self.peek() { Some(c) => c, None => return self.err("Expected a value") };
+        match c {
+            '@' => {
+                self.pos += 1;
+                let t = self.parse_type()?;
+                Ok(Text::Typed(t, Box::new(self.nested(0, 0, |p| p.parse())?)))
+            }
+            '<' => {
+                self.pos += 1;
+                let v = self.nested(0, 0, |p| p.parse())?;
+                self.expect('>')?;
+                Ok(Text::Variant(Box::new(v)))
+            }
+            '[' => { self.pos += 1; Ok(Text::Array(self.nested(1, 0, |p| p.parse_list(']'))?)) },
+            '(' => {
+                self.pos += 1;
+                let v = self.nested(0, 1, |p| p.parse_list(')'))?;
+                if v.is_empty() { return self.err("Empty structs are not supported") }
+                Ok(Text::Struct(v))
+            }
+            '{' => {
+                self.pos += 1;
+                self.nested(1, 1, |p| {
+                    let mut v = vec!();
+                    while !p.eat('}') {
+                        if !v.is_empty() { p.expect(',')?; }
+                        let k = p.parse()?;
+                        p.expect(':')?;
+                        v.push((k, p.parse()?));
+                    }
+                    Ok(Text::Dict(v))
+                })
+            }
+            '\'' | '"' => { self.pos += 1; Ok(Text::Str(self.parse_str(c)?)) },
+            'b' if self.s[self.pos+1..].starts_with(['\'', '"']) => {
+                let q = self.s[self.pos+1..].chars().next().unwrap();
+                self.pos += 2;
+                Ok(Text::Bytes(self.parse_str(q)?.into_bytes()))
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' =>
+                Ok(Text::Num(self.take_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+').into())),
+            c if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                let w = self.take_while(|c| c.is_ascii_alphanumeric());
+                match w {
+                    "true" => Ok(Text::Bool(true)),
+                    "false" => Ok(Text::Bool(false)),
+                    "inf" | "nan" => Ok(Text::Num(w.into())),
+                    _ => match TYPE_KEYWORDS.iter().find(|k| k.0 == w) {
+                        Some(k) => Ok(Text::Typed(SignatureType::Basic(k.1), Box::new(self.nested(0, 0, |p| p.parse())?))),
+                        None => { self.pos = start; self.err(&format!("Unknown word '{}'", w)) },
+                    }
+                }
+            }
+            _ => self.err(&format!("Unexpected character '{}'", c)),
+        }
+    }
+}
+
+#[test]
+fn value_text() {
+    for s in &["'hello'", "<'hello'>", "@as []", "{'a': <1>}", "(byte 0x05, int16 -3, uint16 3, 7, uint32 8, int64 -9, uint64 10)",
+        "[1.5, 2.0, -inf]", "@a{sv} {}", "[@a{oas} {}, {objectpath '/a': ['x', \"it's\"]}]", "(signature 'a{sv}',)",
+        "<<true>>", "'tab\\tand\\\\ and \\u0001'", "[[1, 2], @ai []]"] {
+        let v = Value::parse(s).unwrap();
+        assert_eq!(&v.to_string(), s);
+    }
+    let v: Value = "  { 'x' : < ( 1 , 'y' ) > } ".parse().unwrap();
+    assert_eq!(&*v.signature(), "a{sv}");
+    assert_eq!(Value::parse("[int16 1, 2]").unwrap().to_string(), "[int16 1, int16 2]");
+    assert_eq!(Value::parse("[1, int64 2]").unwrap().to_string(), "[int64 1, int64 2]");
+    assert_eq!(Value::parse("b'ab'").unwrap().to_string(), "[byte 0x61, byte 0x62]");
+    assert_eq!(Value::parse("0x10").unwrap(), Value::Int32(16));
+    assert_eq!(Value::parse_with_type("[]", &"a{sv}".parse().unwrap()).unwrap().to_string(), "@a{sv} {}");
+    assert_eq!(Value::parse_with_type("[5]", &"ay".parse().unwrap()).unwrap().to_string(), "[byte 0x05]");
+
+    for s in &["[]", "{}", "()", "byte 256", "[1, 'a']", "'abc", "1 2", "@i 'a'", "handle 3", "foo"] {
+        assert!(Value::parse(s).is_err(), "{}", s);
+    }
+    #[cfg(not(feature = "no-string-validation"))]
+    assert!(Value::parse("objectpath 'a'").is_err());
+
+    let nest = |open: &str, close: &str, n: usize| open.repeat(n) + "1" + &close.repeat(n);
+    assert!(Value::parse(&nest("[", "]", 32)).is_ok());
+    assert!(Value::parse(&nest("[", "]", 33)).is_err());
+    assert!(Value::parse(&nest("(", ",)", 32)).is_ok());
+    assert!(Value::parse(&nest("(", ",)", 33)).is_err());
+    assert!(Value::parse(&nest("{1: ", "}", 33)).is_err());
+    assert!(Value::parse(&nest("<", ">", 64)).is_ok());
+    assert!(Value::parse(&nest("<", ">", 65)).is_err());
+    assert!(Value::parse(&nest("byte ", "", 100)).is_err());
+    assert!(Value::parse(&nest("<[", "]>", 100000)).is_err());
+}
+
+#[test]
+fn value_message() {
+    use crate::Message;
+    use std::collections::HashMap;
+    let items = vec!(
+        Value::parse("{'a': <uint32 1>, 'b': <[@ay [], b'x']>}").unwrap(),
+        Value::parse("@a(si) []").unwrap(),
+        Value::parse("(<<int16 5>>, @a{ss} {})").unwrap(),
+        Value::Double(0.5),
+    );
+    let mut m = Message::new_signal("/", "a.b", "C").unwrap();
+    {
+        let mut ia = IterAppend::new(&mut m);
+        for v in &items { v.append_value(&mut ia) }
+        items[2].append_by_ref(&mut ia);
+    }
+    let mut i = m.iter_init();
+    let mut all = Value::get_all(&mut i);
+    assert_eq!(all.pop(), Some(Value::Variant(Box::new(items[2].clone()))));
+    assert_eq!(all, items);
+    // Only the last argument is a variant
+    let mut i = m.iter_init();
+    for _ in 0..4 { i.next(); }
+    assert_eq!(i.get::<Value>(), Some(items[2].clone()));
+    assert!(m.iter_init().get::<Value>().is_none());
+
+    let h: HashMap<String, Value> = m.read1().unwrap();
+    assert_eq!(h["a"], Value::UInt32(1));
+    assert_eq!(RefArg::as_u64(&h["a"]), Some(1));
+    let m2 = Message::new_signal("/", "a.b", "C").unwrap().append1(&h);
+    assert_eq!(&*m2.iter_init().signature(), "a{sv}");
+    assert_eq!(m2.read1::<HashMap<String, Value>>().unwrap(), h);
+
+    assert!(ValueArray::new("s".parse().unwrap(), vec!(Value::Int32(1))).is_err());
+    assert!(ValueDict::new("v".parse().unwrap(), "s".parse().unwrap(), vec!()).is_err());
+}
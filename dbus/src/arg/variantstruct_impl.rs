@@ -123,6 +123,13 @@ impl<'a, $($t: Get<'a>),*> Get<'a> for ($($t,)*) {
     }
 }
 
+    }
+}
+
+// RefArg requires Debug, which is only implemented for tuples of up to 12 elements.
+macro_rules! struct_refarg_impl {
+    ( $($n: ident $t: ident,)+ ) => {
+
 impl<$($t: RefArg),*> RefArg for ($($t,)*) {
     fn arg_type(&self) -> ArgType { ArgType::Struct }
     fn signature(&self) -> Signature<'static> {
@@ -169,6 +176,23 @@ struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I,);
 struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J,);
 struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K,);
 struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L,);
+struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L, m M,);
+struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L, m M, n N,);
+struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L, m M, n N, o O,);
+struct_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L, m M, n N, o O, p P,);
+
+struct_refarg_impl!(a A,);
+struct_refarg_impl!(a A, b B,);
+struct_refarg_impl!(a A, b B, c C,);
+struct_refarg_impl!(a A, b B, c C, d D,);
+struct_refarg_impl!(a A, b B, c C, d D, e E,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G, h H,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K,);
+struct_refarg_impl!(a A, b B, c C, d D, e E, f F, g G, h H, i I, j J, k K, l L,);
 
 impl RefArg for Vec<Box<dyn RefArg>> {
     fn arg_type(&self) -> ArgType { ArgType::Struct }
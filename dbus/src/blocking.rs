@@ -1,15 +1,29 @@
 //! Connections and proxies that make blocking method calls.
 
 
-use crate::strings::{BusName, Path, Interface, Member};
+use crate::strings::{BusName, WellKnownName, Path, Interface, Member};
 use crate::arg::{AppendAll, ReadAll, IterAppend};
 use crate::{channel, Error, Message};
 use crate::message::{MatchRule, SignalArgs};
 use crate::channel::{Channel, BusType};
-use std::{cell::{RefCell, Cell}, time::Duration, sync::Mutex};
+use crate::util::Serial;
+use crate::namewatch::{self, Watch, OwnerChanged, NameEvent};
+use std::{cell::{RefCell, Cell}, rc::Rc, time::Duration, sync::{Arc, Mutex}, convert::TryFrom};
 
 pub mod stdintf;
 
+pub use crate::reconnect::{Reconnecting, Reconnectable, ReconnectEvent};
+
+mod propcache;
+pub use self::propcache::PropertyCache;
+
+mod objmanager;
+pub use self::objmanager::ObjectManagerClient;
+pub use crate::objmanager::ObjectEvent;
+
+mod nameowner;
+pub use self::nameowner::{NameOwner, NameState};
+
 struct Filter<F> {
    id: u32,
    rule: MatchRule<'static>,
@@ -45,6 +59,43 @@ pub struct SyncConnection {
 
 use crate::blocking::stdintf::org_freedesktop_dbus;
 
+impl From<Channel> for Connection {
+    fn from(x: Channel) -> Self {
+        Connection {
+            channel: x,
+            filters: Default::default(),
+            filter_nextid: Default::default(),
+        }
+    }
+}
+
+impl From<Channel> for SyncConnection {
+    fn from(x: Channel) -> Self {
+        SyncConnection {
+            channel: x,
+            filters: Default::default(),
+        }
+    }
+}
+
+impl crate::reconnect::private::Sealed for Connection {
+    fn channel(&self) -> &Channel { &self.channel }
+    fn replace_channel(&mut self, c: Channel) { self.channel = c }
+    fn match_strs(&self) -> Vec<String> { self.filters.borrow().iter().map(|f| f.rule.match_str()).collect() }
+    fn process_msg(&mut self, timeout: Duration) -> Result<bool, Error> { self.process(timeout) }
+}
+
+impl Reconnectable for Connection {}
+
+impl crate::reconnect::private::Sealed for SyncConnection {
+    fn channel(&self) -> &Channel { &self.channel }
+    fn replace_channel(&mut self, c: Channel) { self.channel = c }
+    fn match_strs(&self) -> Vec<String> { self.filters.lock().unwrap().1.iter().map(|f| f.rule.match_str()).collect() }
+    fn process_msg(&mut self, timeout: Duration) -> Result<bool, Error> { self.process(timeout) }
+}
+
+impl Reconnectable for SyncConnection {}
+
 impl Connection {
     /// Create a new connection to the session bus.
     pub fn new_session() -> Result<Self, Error> { Ok(Connection {
@@ -87,16 +138,43 @@ impl Connection {
     /// Request a name on the D-Bus.
     ///
     /// For detailed information on the flags and return values, see the libdbus documentation.
+    /// Returns an error if the name is a unique name, since those cannot be requested.
     pub fn request_name<'a, N: Into<BusName<'a>>>(&self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
     -> Result<org_freedesktop_dbus::RequestNameReply, Error> {
-        org_freedesktop_dbus::request_name(&self.channel, &name.into(), allow_replacement, replace_existing, do_not_queue)
+        let name = WellKnownName::try_from(name.into())?;
+        org_freedesktop_dbus::request_name(&self.channel, &name, allow_replacement, replace_existing, do_not_queue)
     }
 
     /// Release a previously requested name on the D-Bus.
+    ///
+    /// Returns an error if the name is a unique name.
     pub fn release_name<'a, N: Into<BusName<'a>>>(&self, name: N) -> Result<org_freedesktop_dbus::ReleaseNameReply, Error> {
-        org_freedesktop_dbus::release_name(&self.channel, &name.into())
+        let name = WellKnownName::try_from(name.into())?;
+        org_freedesktop_dbus::release_name(&self.channel, &name)
+    }
+
+    /// Watches a bus name, like `g_bus_watch_name` does.
+    ///
+    /// `on_appeared` is called with the unique name of the owner when the name gets an owner, and `on_vanished`
+    /// when it loses its owner. One of them is called before this function returns, with the current state.
+    /// A name that goes directly to a new owner is reported as vanished and then appeared again.
+    ///
+    /// Changes are reported when `process` dispatches the `NameOwnerChanged` signal. Since a signal
+    /// is only dispatched to the first matching callback, this returns an error if the name is already
+    /// watched on this connection.
+    ///
+    /// Returns an id that can be used with `unwatch_name`.
+    pub fn watch_name<'a, N, A, V>(&self, name: N, on_appeared: A, on_vanished: V) -> Result<u32, Error>
+    where N: Into<BusName<'a>>, A: FnMut(&str, &Connection) + 'static, V: FnMut(&Connection) + 'static {
+        let w = Rc::new(RefCell::new((Watch::default(), on_appeared, on_vanished)));
+        let w2 = w.clone();
+        let f = move |c: OwnerChanged, conn: &Connection| { report(&mut w2.borrow_mut(), |w| w.changed(c), conn); true };
+        watch_name(self, name.into(), f, |serial, owner| report(&mut w.borrow_mut(), |w| w.update(serial, owner), self))
     }
 
+    /// Stops watching a name watched with `watch_name`.
+    pub fn unwatch_name(&self, id: u32) -> Result<(), Error> { stdintf::proxy(self).match_stop(id, true) }
+
 }
 
 impl SyncConnection {
@@ -141,16 +219,74 @@ impl SyncConnection {
     /// Request a name on the D-Bus.
     ///
     /// For detailed information on the flags and return values, see the libdbus documentation.
+    /// Returns an error if the name is a unique name, since those cannot be requested.
     pub fn request_name<'a, N: Into<BusName<'a>>>(&self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
     -> Result<org_freedesktop_dbus::RequestNameReply, Error> {
-        org_freedesktop_dbus::request_name(&self.channel, &name.into(), allow_replacement, replace_existing, do_not_queue)
+        let name = WellKnownName::try_from(name.into())?;
+        org_freedesktop_dbus::request_name(&self.channel, &name, allow_replacement, replace_existing, do_not_queue)
     }
 
     /// Release a previously requested name on the D-Bus.
+    ///
+    /// Returns an error if the name is a unique name.
     pub fn release_name<'a, N: Into<BusName<'a>>>(&self, name: N) -> Result<org_freedesktop_dbus::ReleaseNameReply, Error> {
-        org_freedesktop_dbus::release_name(&self.channel, &name.into())
+        let name = WellKnownName::try_from(name.into())?;
+        org_freedesktop_dbus::release_name(&self.channel, &name)
+    }
+
+    /// Watches a bus name, like `g_bus_watch_name` does.
+    ///
+    /// `on_appeared` is called with the unique name of the owner when the name gets an owner, and `on_vanished`
+    /// when it loses its owner. One of them is called before this function returns, with the current state.
+    /// A name that goes directly to a new owner is reported as vanished and then appeared again.
+    ///
+    /// Changes are reported when `process` dispatches the `NameOwnerChanged` signal. Since a signal
+    /// is only dispatched to the first matching callback, this returns an error if the name is already
+    /// watched on this connection.
+    ///
+    /// Returns an id that can be used with `unwatch_name`.
+    pub fn watch_name<'a, N, A, V>(&self, name: N, on_appeared: A, on_vanished: V) -> Result<u32, Error>
+    where N: Into<BusName<'a>>, A: FnMut(&str, &SyncConnection) + Send + 'static, V: FnMut(&SyncConnection) + Send + 'static {
+        let w = Arc::new(Mutex::new((Watch::default(), on_appeared, on_vanished)));
+        let w2 = w.clone();
+        let f = move |c: OwnerChanged, conn: &SyncConnection| { report(&mut w2.lock().unwrap(), |w| w.changed(c), conn); true };
+        watch_name(self, name.into(), f, |serial, owner| report(&mut w.lock().unwrap(), |w| w.update(serial, owner), self))
     }
 
+    /// Stops watching a name watched with `watch_name`.
+    pub fn unwatch_name(&self, id: u32) -> Result<(), Error> { stdintf::proxy(self).match_stop(id, true) }
+
+}
+
+fn watch_name<T, F, G>(c: &T, name: BusName, f: F, initial: G) -> Result<u32, Error>
+where
+    T: BlockingSender + channel::MatchingReceiver + crate::reconnect::private::Sealed,
+    F: MakeSignal<<T as channel::MatchingReceiver>::F, OwnerChanged, T>,
+    G: FnOnce(u32, Option<String>),
+{
+    let mr = namewatch::match_rule(&name);
+    let mstr = mr.match_str();
+    namewatch::check_not_watched(&c.match_strs(), &mstr, &name)?;
+    let proxy = stdintf::proxy(c);
+    let id = proxy.match_start(mr, true, f.make(mstr))?;
+    let r: Result<Serial<(String,)>, Error> = proxy.method_call("org.freedesktop.DBus", "GetNameOwner", (&*name,));
+    let (serial, owner) = match r {
+        Ok(Serial(serial, (owner,))) => (serial, Some(owner)),
+        // The serial of an error reply is not available, so no signals are discarded as older than it.
+        Err(ref e) if e.name() == Some(namewatch::NAME_HAS_NO_OWNER) => (0, None),
+        Err(e) => { let _ = proxy.match_stop(id, true); return Err(e) },
+    };
+    initial(serial, owner);
+    Ok(id)
+}
+
+fn report<T, A: FnMut(&str, &T), V: FnMut(&T)>(w: &mut (Watch, A, V), f: impl FnOnce(&mut Watch) -> Vec<NameEvent>, c: &T) {
+    for e in f(&mut w.0) {
+        match e {
+            NameEvent::Appeared(owner) => (w.1)(&owner, c),
+            NameEvent::Vanished => (w.2)(c),
+        }
+    }
 }
 
 /// Abstraction over different connections
@@ -456,3 +592,36 @@ fn test_peer() {
     assert_eq!(s1, s2);
 
 }
+
+#[test]
+fn test_watch_name() {
+    let name = "com.example.dbusrs.watchname";
+    let c = SyncConnection::new_session().unwrap();
+    let events = Arc::new(Mutex::new(vec!()));
+    let (e1, e2) = (events.clone(), events.clone());
+    let id = c.watch_name(name, move |owner, _| e1.lock().unwrap().push(Some(owner.to_string())),
+        move |_| e2.lock().unwrap().push(None)).unwrap();
+    assert_eq!(*events.lock().unwrap(), vec!(None));
+    assert!(c.watch_name(name, |_, _| {}, |_| {}).is_err());
+
+    let process_until = |count| for _ in 0..50 {
+        if events.lock().unwrap().len() >= count { break }
+        c.process(Duration::from_millis(100)).unwrap();
+    };
+    // The owner restarting gets a new unique name.
+    let mut owners = vec!();
+    for _ in 0..2 {
+        let owner = Connection::new_session().unwrap();
+        owner.request_name(name, false, false, true).unwrap();
+        owners.push(owner.unique_name().to_string());
+        process_until(owners.len() * 2);
+    }
+    process_until(5);
+    assert_eq!(*events.lock().unwrap(), vec!(None, Some(owners[0].clone()), None, Some(owners[1].clone()), None));
+
+    c.unwatch_name(id).unwrap();
+    let owner = Connection::new_session().unwrap();
+    owner.request_name(name, false, false, true).unwrap();
+    while c.process(Duration::from_millis(100)).unwrap() {}
+    assert_eq!(events.lock().unwrap().len(), 5);
+}
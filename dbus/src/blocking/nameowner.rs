@@ -0,0 +1,243 @@
+use crate::Error;
+use crate::arg::{self, ReadAll, TypeMismatchError};
+use crate::channel::MatchingReceiver;
+use crate::message::SignalArgs;
+use crate::strings::WellKnownName;
+use crate::util::{Serial, Callbacks};
+use super::stdintf::{self, org_freedesktop_dbus::{self, RequestNameReply, ReleaseNameReply}};
+use super::{BlockingSender, MakeSignal};
+use std::sync::{Arc, Mutex, Weak};
+use std::ops::Deref;
+
+/// The state of a name requested with `NameOwner`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameState {
+    /// We are the primary owner of the name.
+    PrimaryOwner,
+    /// Another connection owns the name, and we are in its queue, so we get the name when it is released.
+    InQueue,
+    /// We neither own the name nor are in its queue, e g because another connection replaced us and we used `do_not_queue`.
+    Lost,
+}
+
+/// The NameAcquired signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct Acquired(u32);
+
+impl ReadAll for Acquired {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        let _name: String = i.read()?;
+        Ok(Acquired(i.message().get_serial()))
+    }
+}
+
+impl SignalArgs for Acquired {
+    const NAME: &'static str = "NameAcquired";
+    const INTERFACE: &'static str = "org.freedesktop.DBus";
+}
+
+/// The NameLost signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct Lost(u32);
+
+impl ReadAll for Lost {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        let _name: String = i.read()?;
+        Ok(Lost(i.message().get_serial()))
+    }
+}
+
+impl SignalArgs for Lost {
+    const NAME: &'static str = "NameLost";
+    const INTERFACE: &'static str = "org.freedesktop.DBus";
+}
+
+type AcquiredFn<T> = Box<dyn FnMut(Acquired, &T) -> bool + Send + Sync>;
+type LostFn<T> = Box<dyn FnMut(Lost, &T) -> bool + Send + Sync>;
+type Callback = Arc<Mutex<dyn FnMut(NameState) + Send>>;
+
+struct Inner {
+    // The serial of the message the state came from. The reply to RequestName and
+    // the NameAcquired and NameLost signals are all sent by the bus, so this orders them.
+    serial: u32,
+    state: NameState,
+    callbacks: Callbacks<Callback>,
+}
+
+/// Updates the state and calls the callbacks if it changed. Returns false if the owner has been dropped.
+///
+/// Callbacks are called without the lock held, so that they can read the state.
+fn update(inner: &Weak<Mutex<Inner>>, serial: u32, state: NameState) -> bool {
+    let inner = match inner.upgrade() { Some(i) => i, None => return false };
+    let callbacks: Vec<_> = {
+        let mut i = inner.lock().unwrap();
+        if serial < i.serial { return true }
+        i.serial = serial;
+        if i.state == state { return true }
+        i.state = state;
+        i.callbacks.iter().cloned().collect()
+    };
+    for f in callbacks { (f.lock().unwrap())(state) }
+    true
+}
+
+/// Owns a bus name for as long as it exists, like `g_bus_own_name` does.
+///
+/// The name is requested when the owner is created, and it then keeps track of whether we are the
+/// primary owner of the name, by listening to the `NameAcquired` and `NameLost` signals.
+/// This way a service that allows replacement can tell when it has been replaced, and a service
+/// that waits in the queue can tell when it gets the name.
+///
+/// Signals are dispatched by the connection, so you need to call `process` on it regularly
+/// for the state to stay up to date. The name is released when the owner is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::blocking::{SyncConnection, NameOwner, NameState};
+/// use std::time::Duration;
+///
+/// let conn = SyncConnection::new_session()?;
+/// let owner = NameOwner::new(&conn, "com.example.dbustest", true, false, false)?;
+/// owner.on_change(|state| println!("We are now {:?}", state));
+/// while owner.state() != NameState::Lost { conn.process(Duration::from_secs(1))?; }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct NameOwner<C> where C: Deref, C::Target: BlockingSender + MatchingReceiver + Sized {
+    connection: C,
+    name: WellKnownName<'static>,
+    ids: [u32; 2],
+    released: bool,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl<T, C> NameOwner<C>
+where
+    T: BlockingSender + MatchingReceiver,
+    C: Deref<Target=T>,
+    AcquiredFn<T>: MakeSignal<<T as MatchingReceiver>::F, Acquired, T>,
+    LostFn<T>: MakeSignal<<T as MatchingReceiver>::F, Lost, T>,
+{
+    /// Requests a name on the bus, and starts keeping track of its state.
+    ///
+    /// For detailed information on the flags, see `Connection::request_name`. If the name is taken and
+    /// `do_not_queue` is set, this succeeds but the state is `Lost`.
+    pub fn new<N: Into<WellKnownName<'static>>>(connection: C, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+    -> Result<Self, Error> {
+        let name = name.into();
+        let inner = Arc::new(Mutex::new(Inner { serial: 0, state: NameState::Lost, callbacks: Default::default() }));
+        let lost_state = if do_not_queue { NameState::Lost } else { NameState::InQueue };
+
+        // The signals are sent to us only, so there is no need to call AddMatch.
+        let proxy = stdintf::proxy(&*connection);
+        let weak = Arc::downgrade(&inner);
+        let f: AcquiredFn<T> = Box::new(move |a: Acquired, _: &T| update(&weak, a.0, NameState::PrimaryOwner));
+        let mut mr = Acquired::match_rule(None, None).static_clone();
+        mr.args.push((0, name.to_string()));
+        let mstr = mr.match_str();
+        let acquired_id = proxy.match_start(mr, false, f.make(mstr))?;
+        let weak = Arc::downgrade(&inner);
+        let f: LostFn<T> = Box::new(move |l: Lost, _: &T| update(&weak, l.0, lost_state));
+        let mut mr = Lost::match_rule(None, None).static_clone();
+        mr.args.push((0, name.to_string()));
+        let mstr = mr.match_str();
+        let lost_id = match proxy.match_start(mr, false, f.make(mstr)) {
+            Ok(id) => id,
+            Err(e) => { connection.stop_receive(acquired_id); return Err(e) },
+        };
+
+        let flags = org_freedesktop_dbus::request_name_flags(allow_replacement, replace_existing, do_not_queue);
+        let r: Result<Serial<(u32,)>, Error> = proxy.method_call("org.freedesktop.DBus", "RequestName", (&*name, flags));
+        let (serial, reply) = match r.and_then(|Serial(serial, (r,))| Ok((serial, org_freedesktop_dbus::request_name_reply(r)?))) {
+            Ok(r) => r,
+            Err(e) => {
+                connection.stop_receive(acquired_id);
+                connection.stop_receive(lost_id);
+                return Err(e);
+            }
+        };
+        let state = match reply {
+            RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => NameState::PrimaryOwner,
+            RequestNameReply::InQueue => NameState::InQueue,
+            RequestNameReply::Exists => NameState::Lost,
+        };
+        update(&Arc::downgrade(&inner), serial, state);
+        Ok(NameOwner { connection, name, ids: [acquired_id, lost_id], released: false, inner })
+    }
+}
+
+impl<C> NameOwner<C> where C: Deref, C::Target: BlockingSender + MatchingReceiver + Sized {
+    /// The current state of the name.
+    pub fn state(&self) -> NameState { self.inner.lock().unwrap().state }
+
+    /// The name that is owned.
+    pub fn name(&self) -> &WellKnownName<'static> { &self.name }
+
+    /// Calls `f` every time the state changes, with the new state.
+    ///
+    /// Returns an id that can be used to remove the callback.
+    pub fn on_change<F: FnMut(NameState) + Send + 'static>(&self, f: F) -> u32 {
+        self.inner.lock().unwrap().callbacks.add(Arc::new(Mutex::new(f)))
+    }
+
+    /// Removes a callback added with `on_change`. Returns false if there was no such callback.
+    pub fn remove_on_change(&self, id: u32) -> bool { self.inner.lock().unwrap().callbacks.remove(id) }
+
+    /// Releases the name, or leaves its queue, and returns the reply from the bus.
+    ///
+    /// Dropping the owner does the same, but ignores the reply.
+    pub fn release(mut self) -> Result<ReleaseNameReply, Error> {
+        self.released = true;
+        org_freedesktop_dbus::release_name(&*self.connection, &self.name)
+    }
+}
+
+impl<C> Drop for NameOwner<C> where C: Deref, C::Target: BlockingSender + MatchingReceiver + Sized {
+    fn drop(&mut self) {
+        for id in &self.ids { self.connection.stop_receive(*id); }
+        if !self.released {
+            let _ = org_freedesktop_dbus::release_name(&*self.connection, &self.name);
+        }
+    }
+}
+
+#[test]
+fn name_owner() {
+    use crate::blocking::SyncConnection;
+    use crate::testbus::TestBus;
+    use std::time::Duration;
+
+    let name = "com.example.dbusrs.nameowner";
+    let bus = TestBus::new().unwrap();
+    let (c1, c2) = (SyncConnection::from(bus.connect().unwrap()), SyncConnection::from(bus.connect().unwrap()));
+    let o1 = NameOwner::new(&c1, name, true, false, false).unwrap();
+    assert_eq!(o1.state(), NameState::PrimaryOwner);
+    let states = Arc::new(Mutex::new(vec!()));
+    let states2 = states.clone();
+    let id = o1.on_change(move |s| states2.lock().unwrap().push(s));
+
+    let process_until = |count| for _ in 0..50 {
+        if states.lock().unwrap().len() >= count { break }
+        c1.process(Duration::from_millis(100)).unwrap();
+    };
+    // Being replaced puts us in the queue, and we get the name back when the replacement releases it.
+    let o2 = NameOwner::new(&c2, name, false, true, true).unwrap();
+    assert_eq!(o2.state(), NameState::PrimaryOwner);
+    process_until(1);
+    assert_eq!(o1.state(), NameState::InQueue);
+    assert_eq!(o2.release().unwrap(), ReleaseNameReply::Released);
+    process_until(2);
+    assert_eq!(o1.state(), NameState::PrimaryOwner);
+
+    // Without replacing or queueing, this one does not get the name.
+    let o2 = NameOwner::new(&c2, name, false, false, true).unwrap();
+    assert_eq!(o2.state(), NameState::Lost);
+    drop(o2);
+    while c1.process(Duration::from_millis(100)).unwrap() {}
+    assert_eq!(*states.lock().unwrap(), vec!(NameState::InQueue, NameState::PrimaryOwner));
+    assert!(o1.remove_on_change(id));
+
+    drop(o1);
+    let o2 = NameOwner::new(&c2, name, false, false, true).unwrap();
+    assert_eq!(o2.state(), NameState::PrimaryOwner);
+}
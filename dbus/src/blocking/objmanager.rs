@@ -0,0 +1,131 @@
+use crate::Error;
+use crate::channel::MatchingReceiver;
+use crate::message::SignalArgs;
+use crate::strings::Path;
+use crate::util::Serial;
+use crate::objmanager::{self, Added, Removed, Interfaces, OBJECT_MANAGER};
+use super::{Proxy, BlockingSender, MakeSignal};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::ops::Deref;
+
+type AddedFn<T> = Box<dyn FnMut(Added, &T) -> bool + Send + Sync>;
+type RemovedFn<T> = Box<dyn FnMut(Removed, &T) -> bool + Send + Sync>;
+
+/// Keeps track of the objects below a remote object manager, for blocking connections.
+///
+/// Signals are dispatched by the connection, so you need to call `process` on it regularly
+/// for the client to stay up to date. Since the client keeps its proxy, and `Connection::process`
+/// takes `&mut self`, this means you will usually use a `SyncConnection`.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::blocking::{SyncConnection, ObjectManagerClient, ObjectEvent};
+/// use std::time::Duration;
+///
+/// let conn = SyncConnection::new_system()?;
+/// let proxy = conn.with_proxy("org.bluez", "/", Duration::from_secs(5));
+/// let bluez = ObjectManagerClient::new(proxy)?;
+/// println!("Devices: {:?}", bluez.with_interface("org.bluez.Device1"));
+/// bluez.on_event(|e| if let ObjectEvent::InterfacesAdded { path, new_object: true, .. } = e {
+///     println!("New object: {}", path);
+/// });
+/// loop { conn.process(Duration::from_secs(1))?; }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub type ObjectManagerClient<'a, C> = objmanager::ObjectManagerClient<Proxy<'a, C>>;
+
+impl<'a, T, C> ObjectManagerClient<'a, C>
+where
+    T: BlockingSender + MatchingReceiver,
+    C: Deref<Target=T>,
+    AddedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Added, T>,
+    RemovedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Removed, T>,
+{
+    /// Creates a client for the object manager at the proxy's destination and path.
+    ///
+    /// This starts matching the `InterfacesAdded` and `InterfacesRemoved` signals, and then fetches all objects.
+    pub fn new(proxy: Proxy<'a, C>) -> Result<Self, Error> {
+        let stop = |p: &Proxy<'a, C>, id| { let _ = p.match_stop(id, true); };
+        let mut r = ObjectManagerClient { proxy, objects: Default::default(), ids: vec!(), stop };
+
+        let weak = Arc::downgrade(&r.objects);
+        let f: AddedFn<T> = Box::new(move |a: Added, _: &T| objmanager::handle_added(&weak, a));
+        let mr = Added::match_rule(Some(&r.proxy.destination), Some(&r.proxy.path)).static_clone();
+        let mstr = mr.match_str();
+        r.ids.push(r.proxy.match_start(mr, true, f.make(mstr))?);
+
+        let weak = Arc::downgrade(&r.objects);
+        let f: RemovedFn<T> = Box::new(move |rm: Removed, _: &T| objmanager::handle_removed(&weak, rm));
+        let mr = Removed::match_rule(Some(&r.proxy.destination), Some(&r.proxy.path)).static_clone();
+        let mstr = mr.match_str();
+        r.ids.push(r.proxy.match_start(mr, true, f.make(mstr))?);
+
+        r.refresh()?;
+        Ok(r)
+    }
+
+    /// Fetches all objects again, calling the event callbacks for objects and interfaces that appeared or disappeared.
+    pub fn refresh(&self) -> Result<(), Error> {
+        let Serial(serial, (objects,)): Serial<(HashMap<Path<'static>, Interfaces>,)> =
+            self.proxy.method_call(OBJECT_MANAGER, "GetManagedObjects", ())?;
+        objmanager::reset(&self.objects, serial, objects);
+        Ok(())
+    }
+}
+
+#[test]
+fn object_manager_client() {
+    use crate::arg::Value;
+    use crate::blocking::{SyncConnection, ObjectEvent};
+    use std::sync::Mutex;
+    use crate::testbus::TestBus;
+    use std::time::Duration;
+
+    let bus = TestBus::new().unwrap();
+    let c = bus.connect().unwrap();
+    let dest = c.unique_name().unwrap().to_string();
+    let server = std::thread::spawn(move || objmanager::serve_test_objects(c));
+
+    let conn = SyncConnection::from(bus.connect().unwrap());
+    let proxy = conn.with_proxy(dest, "/", Duration::from_secs(5));
+    let client = ObjectManagerClient::new(proxy).unwrap();
+    assert_eq!(client.paths(), vec!(Path::from("/a")));
+    let a = client.get(&"/a".into()).unwrap();
+    assert_eq!(a["com.example.A"]["Name"], Value::Str("a".into()));
+    assert!(client.get(&"/b".into()).is_none());
+
+    let events = Arc::new(Mutex::new(vec!()));
+    let events2 = events.clone();
+    let id = client.on_event(move |e| events2.lock().unwrap().push(e.clone()));
+    let _: () = client.proxy().method_call("com.example.Test", "Change", ()).unwrap();
+    for _ in 0..50 {
+        if events.lock().unwrap().len() >= 4 { break }
+        conn.process(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(client.with_interface("com.example.B"), vec!(Path::from("/a")));
+    assert_eq!(client.with_interface("com.example.A"), vec!());
+
+    client.refresh().unwrap();
+    while conn.process(Duration::from_millis(100)).unwrap() {}
+    assert_eq!(client.paths(), vec!(Path::from("/a"), Path::from("/c")));
+    assert_eq!(client.with_interface("com.example.A"), vec!(Path::from("/a"), Path::from("/c")));
+
+    let (a, b, c) = (Path::from("/a"), Path::from("/b"), Path::from("/c"));
+    let strs = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(*events.lock().unwrap(), vec!(
+        ObjectEvent::InterfacesAdded { path: b.clone(), interfaces: strs(&["com.example.B"]), new_object: true },
+        ObjectEvent::InterfacesAdded { path: a.clone(), interfaces: strs(&["com.example.B"]), new_object: false },
+        ObjectEvent::InterfacesRemoved { path: a.clone(), interfaces: strs(&["com.example.A"]), object_removed: false },
+        ObjectEvent::InterfacesRemoved { path: b, interfaces: strs(&["com.example.B"]), object_removed: true },
+        // From the refresh
+        ObjectEvent::InterfacesAdded { path: a.clone(), interfaces: strs(&["com.example.A"]), new_object: false },
+        ObjectEvent::InterfacesAdded { path: c, interfaces: strs(&["com.example.A"]), new_object: true },
+        ObjectEvent::InterfacesRemoved { path: a, interfaces: strs(&["com.example.B"]), object_removed: false },
+    ));
+    assert!(client.remove_on_event(id));
+
+    let _: () = client.proxy().method_call("com.example.Test", "Quit", ()).unwrap();
+    server.join().unwrap();
+}
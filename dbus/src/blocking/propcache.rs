@@ -0,0 +1,131 @@
+use crate::Error;
+use crate::arg::{Arg, Get, Value};
+use crate::channel::MatchingReceiver;
+use crate::strings::Interface;
+use crate::propcache::{self, Cache, Changed, Lookup, PROPERTIES};
+use crate::util::Serial;
+use super::{Proxy, BlockingSender, MakeSignal};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::ops::Deref;
+
+type ChangedFn<T> = Box<dyn FnMut(Changed, &T) -> bool + Send + Sync>;
+
+/// A proxy that caches the properties of one interface of a remote object, for blocking connections.
+///
+/// Signals are dispatched by the connection, so you need to call `process` on it regularly
+/// for the cache to stay up to date. Since the cache keeps its proxy, and `Connection::process`
+/// takes `&mut self`, this means you will usually use a `SyncConnection`.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::blocking::{SyncConnection, PropertyCache};
+/// use std::time::Duration;
+///
+/// let conn = SyncConnection::new_system()?;
+/// let proxy = conn.with_proxy("org.bluez", "/org/bluez/hci0", Duration::from_secs(5));
+/// let adapter = PropertyCache::new(proxy, "org.bluez.Adapter1")?;
+/// adapter.on_change("Powered", |v| println!("Powered changed to {:?}", v));
+/// println!("Adapter address: {:?}", adapter.get::<String>("Address")?);
+/// loop { conn.process(Duration::from_secs(1))?; }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub type PropertyCache<'a, C> = propcache::PropertyCache<Proxy<'a, C>>;
+
+impl<'a, T, C> PropertyCache<'a, C>
+where
+    T: BlockingSender + MatchingReceiver,
+    C: Deref<Target=T>,
+    ChangedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Changed, T>,
+{
+    /// Creates a cache for the properties of an interface on the proxy's destination and path.
+    ///
+    /// This starts matching the `PropertiesChanged` signal, and then fetches all properties.
+    pub fn new<I: Into<Interface<'static>>>(proxy: Proxy<'a, C>, interface: I) -> Result<Self, Error> {
+        let interface = interface.into();
+        let cache: Arc<Mutex<Cache>> = Default::default();
+        let weak = Arc::downgrade(&cache);
+        let iface = interface.to_string();
+        let f: ChangedFn<T> = Box::new(move |c: Changed, _: &T| propcache::handle_changed(&weak, &iface, c));
+        let mr = propcache::match_rule(&proxy.destination, &proxy.path, &interface);
+        let mstr = mr.match_str();
+        let id = proxy.match_start(mr, true, f.make(mstr))?;
+        let stop = |p: &Proxy<'a, C>, id| { let _ = p.match_stop(id, true); };
+        let r = PropertyCache { proxy, interface, cache, id, stop };
+        r.refresh()?;
+        Ok(r)
+    }
+
+    /// Fetches all properties again, calling the change callbacks of those that changed.
+    pub fn refresh(&self) -> Result<(), Error> {
+        let Serial(serial, (props,)): Serial<(HashMap<String, Value>,)> =
+            self.proxy.method_call(PROPERTIES, "GetAll", (&*self.interface,))?;
+        propcache::update(&self.cache, serial, true, props.into_iter().map(|(k, v)| (k, Some(v))));
+        Ok(())
+    }
+
+    /// Returns a property, or None if the interface has no property with this name.
+    ///
+    /// If the property has been invalidated, it is fetched from the remote object.
+    pub fn get<R: Arg + for<'b> Get<'b>>(&self, name: &str) -> Result<Option<R>, Error> {
+        let l = self.cache.lock().unwrap().lookup(name);
+        match l {
+            Lookup::Cached(v) => propcache::convert(&v).map(Some),
+            Lookup::Unknown => Ok(None),
+            Lookup::Invalidated => {
+                let Serial(serial, (v,)): Serial<(Value,)> =
+                    self.proxy.method_call(PROPERTIES, "Get", (&*self.interface, name))?;
+                let r = propcache::convert(&v)?;
+                propcache::update(&self.cache, serial, false, Some((name.to_string(), Some(v))));
+                Ok(Some(r))
+            }
+        }
+    }
+}
+
+#[test]
+fn property_cache() {
+    use crate::blocking::SyncConnection;
+    use crate::testbus::TestBus;
+    use std::time::Duration;
+
+    let bus = TestBus::new().unwrap();
+    let server = bus.connect().unwrap();
+    let dest = server.unique_name().unwrap().to_string();
+    let server = std::thread::spawn(move || propcache::serve_test_properties(server));
+
+    let conn = SyncConnection::from(bus.connect().unwrap());
+    let proxy = conn.with_proxy(dest, "/test", Duration::from_secs(5));
+    let cache = PropertyCache::new(proxy, "com.example.Test").unwrap();
+    assert_eq!(cache.names(), vec!("Name", "Volume"));
+    assert_eq!(cache.get::<u32>("Volume").unwrap(), Some(5));
+    assert_eq!(cache.get::<u32>("Missing").unwrap(), None);
+    assert!(cache.get::<String>("Volume").is_err());
+
+    let changes = Arc::new(Mutex::new(vec!()));
+    let changes2 = changes.clone();
+    let id = cache.on_change("Volume", move |v| changes2.lock().unwrap().push(v.cloned()));
+    let _: () = cache.proxy().method_call("com.example.Test", "Bump", ()).unwrap();
+    for _ in 0..50 {
+        if !changes.lock().unwrap().is_empty() { break }
+        conn.process(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(cache.get_cached::<u32>("Volume").unwrap(), Some(6));
+    assert_eq!(cache.get_cached::<String>("Name").unwrap(), None);
+    assert_eq!(cache.get::<String>("Name").unwrap(), Some("b".into()));
+    assert_eq!(cache.get_cached::<String>("Name").unwrap(), Some("b".into()));
+
+    cache.refresh().unwrap();
+    while conn.process(Duration::from_millis(100)).unwrap() {}
+    assert_eq!(cache.get_cached::<u32>("Volume").unwrap(), Some(7));
+    assert_eq!(*changes.lock().unwrap(), vec!(Some(Value::UInt32(6)), Some(Value::UInt32(7))));
+    assert!(cache.remove_on_change(id));
+
+    let proxy = conn.with_proxy(cache.proxy().destination.clone(), "/test", Duration::from_secs(5));
+    let id = cache.id;
+    drop(cache);
+    assert!(conn.stop_receive(id).is_none());
+    let _: () = proxy.method_call("com.example.Test", "Quit", ()).unwrap();
+    server.join().unwrap();
+}
@@ -116,15 +116,22 @@ impl<'a, C: ::std::ops::Deref<Target=blocking::Connection>> Peer for blocking::P
 // Autogenerated code end
 
 
+pub (crate) fn request_name_flags(allow_replacement: bool, replace_existing: bool, do_not_queue: bool) -> u32 {
+    (if allow_replacement { 1 } else { 0 }) +
+    (if replace_existing { 2 } else { 0 }) +
+    (if do_not_queue { 4 } else { 0 })
+}
+
 pub (crate) fn request_name<S: blocking::BlockingSender>(s: &S, name: &str, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
     -> Result<RequestNameReply, dbus::Error> {
-    let flags: u32 = 
-        if allow_replacement { 1 } else { 0 } +
-        if replace_existing { 2 } else { 0 } +
-        if do_not_queue { 4 } else { 0 };
+    let flags = request_name_flags(allow_replacement, replace_existing, do_not_queue);
     let proxy = super::proxy(s);
     use super::org_freedesktop::DBus;
     let r = proxy.request_name(name, flags)?;
+    request_name_reply(r)
+}
+
+pub (crate) fn request_name_reply(r: u32) -> Result<RequestNameReply, dbus::Error> {
     use RequestNameReply::*;
     let all = [PrimaryOwner, InQueue, Exists, AlreadyOwner];
     all.iter().find(|x| **x as u32 == r).copied().ok_or_else(||
@@ -152,7 +159,7 @@ pub (crate) fn proxy<C>(c: C) -> crate::blocking::Proxy<'static, C> {
 }
 
 /// Not public yet, because of lack of named arguments
-pub (super) mod org_freedesktop {
+pub (crate) mod org_freedesktop {
 
 
 use crate as dbus;
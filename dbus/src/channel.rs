@@ -3,12 +3,31 @@
 //! Contains some helper structs and traits common to all Connection types.-
 
 use crate::{Error, Message, to_c_str, c_str_to_slice, MessageType};
-use std::{str, time::Duration, collections::HashMap};
-use std::sync::{Mutex, atomic::AtomicU8, atomic::Ordering};
+use crate::arg::ArgType;
+use std::{str, time::{Duration, Instant}, collections::HashMap};
+use std::sync::{Mutex, MutexGuard, Condvar, atomic::AtomicU8, atomic::Ordering};
 use std::ffi::CStr;
 use std::os::raw::{c_void, c_int};
 use crate::message::MatchRule;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::collections::VecDeque;
+
+mod server;
+pub use self::server::Server;
+
+mod address;
+pub use self::address::{Address, AddressEntry};
+
+mod monitor;
+pub use self::monitor::{Monitor, PcapWriter, LINKTYPE_DBUS};
+
+mod auth;
+pub use self::auth::{AuthMechanism, AuthResult, ClientAuth};
+
+mod transport;
+pub use self::transport::Transport;
+use self::transport::{ReadBuf, write_message, poll_read};
 
 #[derive(Debug)]
 struct ConnHandle(*mut ffi::DBusConnection, bool);
@@ -152,10 +171,105 @@ impl Drop for WatchMap {
 /// Blocking operations are clearly marked as such, although if you
 /// try to access the connection from several threads at the same time,
 /// blocking might occur due to an internal mutex inside the dbus library.
+///
+/// A channel created with `open_native` or `get_native` does not use libdbus for the connection
+/// itself, see `Transport`.
 #[derive(Debug)]
 pub struct Channel {
-    handle: ConnHandle,
-    watchmap: Option<Box<WatchMap>>,
+    backend: Backend,
+}
+
+#[derive(Debug)]
+enum Backend {
+    Ffi { handle: ConnHandle, watchmap: Option<Box<WatchMap>> },
+    Native { conn: Box<NativeConn>, unique_name: Option<String> },
+}
+
+/// The native backend. Writing and reading are locked separately, and only one thread at a time reads
+/// from the socket; other threads wait for it to put messages in the queue.
+#[derive(Debug)]
+struct NativeConn {
+    stream: UnixStream,
+    unix_fd: bool,
+    /// The next serial number. Also held while writing, so that messages are not interleaved.
+    writer: Mutex<u32>,
+    /// Held by the thread that currently reads from the socket.
+    reader: Mutex<ReadBuf>,
+    queue: Mutex<Queue>,
+    /// Notified when the reading thread is done.
+    read_done: Condvar,
+}
+
+#[derive(Debug)]
+struct Queue {
+    incoming: VecDeque<Message>,
+    connected: bool,
+}
+
+fn disconnected() -> Error { Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection is closed") }
+
+impl NativeConn {
+    fn send(&self, msg: &mut Message) -> Result<u32, Error> {
+        let mut serial = self.writer.lock().unwrap();
+        if msg.get_serial() == 0 {
+            crate::message::message_set_serial(msg, *serial);
+            *serial = serial.wrapping_add(1).max(1);
+        }
+        let r = write_message(&self.stream, msg, self.unix_fd);
+        drop(serial);
+        if r.is_err() { self.queue.lock().unwrap().connected = false }
+        r.map(|_| msg.get_serial())
+    }
+
+    /// Waits for the socket to be readable, then reads all messages that have arrived.
+    fn read(&self, rbuf: &mut ReadBuf, timeout: Option<Duration>) -> Result<Vec<Message>, Error> {
+        if poll_read(self.stream.as_raw_fd(), timeout)? {
+            while rbuf.fill(&self.stream)? {}
+        }
+        let mut v = vec!();
+        while let Some(m) = rbuf.parse()? { v.push(m) }
+        Ok(v)
+    }
+
+    /// Puts what was read into the queue and lets other threads read.
+    fn read_finished(&self, rbuf: MutexGuard<ReadBuf>, r: Result<Vec<Message>, Error>) -> Result<MutexGuard<'_, Queue>, Error> {
+        let mut q = self.queue.lock().unwrap();
+        // Waiting threads check whether they can read while holding the queue lock, so they cannot miss this
+        drop(rbuf);
+        self.read_done.notify_all();
+        match r {
+            Ok(v) => { q.incoming.extend(v); Ok(q) },
+            Err(e) => { q.connected = false; Err(e) },
+        }
+    }
+
+    /// Waits until f returns Some, or until the deadline has passed. f is called with the queue
+    /// each time messages have been read, by this or another thread.
+    fn wait_for<R, F: FnMut(&mut Queue) -> Option<R>>(&self, deadline: Option<Instant>, mut f: F) -> Result<Option<R>, Error> {
+        let mut q = self.queue.lock().unwrap();
+        loop {
+            if let Some(r) = f(&mut q) { return Ok(Some(r)) }
+            if !q.connected { return Err(disconnected()) }
+            let timeout = match deadline {
+                None => None,
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d { return Ok(None) }
+                    Some(d - now)
+                }
+            };
+            if let Ok(mut rbuf) = self.reader.try_lock() {
+                drop(q);
+                let r = self.read(&mut rbuf, timeout);
+                q = self.read_finished(rbuf, r)?;
+            } else {
+                q = match timeout {
+                    None => self.read_done.wait(q).unwrap(),
+                    Some(t) => self.read_done.wait_timeout(q, t).unwrap().0,
+                };
+            }
+        }
+    }
 }
 
 impl Drop for Channel {
@@ -167,7 +281,10 @@ impl Drop for Channel {
 impl Channel {
     #[inline(always)]
     pub (crate) fn conn(&self) -> *mut ffi::DBusConnection {
-        self.handle.0
+        match &self.backend {
+            Backend::Ffi { handle, .. } => handle.0,
+            Backend::Native { .. } => panic!("This channel does not use libdbus for the connection"),
+        }
     }
 
     fn conn_from_ptr(ptr: *mut ffi::DBusConnection) -> Result<Channel, Error> {
@@ -176,11 +293,46 @@ impl Channel {
         /* No, we don't want our app to suddenly quit if dbus goes down */
         unsafe { ffi::dbus_connection_set_exit_on_disconnect(ptr, 0) };
 
-        let c = Channel { handle, watchmap: None };
+        let c = Channel { backend: Backend::Ffi { handle, watchmap: None } };
+
+        Ok(c)
+    }
+
+    /// Creates a channel that uses a `Transport`, i e, does authentication and
+    /// message framing in Rust, instead of letting libdbus handle the connection.
+    ///
+    /// Unix file descriptors can only be sent or received over such a channel with the "native" feature,
+    /// see `Transport::new`.
+    pub fn from_transport(transport: Transport) -> Channel {
+        let conn = NativeConn {
+            stream: transport.stream,
+            unix_fd: transport.auth.unix_fd,
+            writer: Mutex::new(transport.next_serial),
+            reader: Mutex::new(transport.rbuf),
+            queue: Mutex::new(Queue { incoming: VecDeque::new(), connected: true }),
+            read_done: Condvar::new(),
+        };
+        Channel { backend: Backend::Native { conn: Box::new(conn), unique_name: None } }
+    }
 
+    /// Creates a new D-Bus connection, doing authentication and message framing in Rust.
+    ///
+    /// Blocking: until the connection is up and running.
+    pub fn get_native(bus: BusType) -> Result<Channel, Error> {
+        let mut c = Self::from_transport(Transport::connect_bus(bus, &ClientAuth::new())?);
+        c.register()?;
         Ok(c)
     }
 
+    /// Creates a new D-Bus connection to a remote address, doing authentication and message framing in Rust.
+    ///
+    /// Like with `open_private`, call `register` if the remote end is a message bus.
+    ///
+    /// Blocking: until the connection is established.
+    pub fn open_native(address: &str, auth: &ClientAuth) -> Result<Channel, Error> {
+        Ok(Self::from_transport(Transport::connect_address(address, auth)?))
+    }
+
 
     /// Creates a new D-Bus connection.
     ///
@@ -220,6 +372,13 @@ impl Channel {
     /// Blocking: until a "Hello" response is received from the server.
     pub fn register(&mut self) -> Result<(), Error> {
         // This function needs to take &mut self, because it changes unique_name and unique_name takes a &self
+        if let Backend::Native { .. } = self.backend {
+            let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "Hello")
+                .unwrap();
+            let name: String = self.send_with_reply_and_block(m, Duration::from_secs(25))?.read1()?;
+            if let Backend::Native { unique_name, .. } = &mut self.backend { *unique_name = Some(name) }
+            return Ok(())
+        }
         let mut e = Error::empty();
         if unsafe { ffi::dbus_bus_register(self.conn(), e.get_mut()) == 0 } {
             Err(e)
@@ -230,6 +389,7 @@ impl Channel {
 
     /// Gets whether the connection is currently open.
     pub fn is_connected(&self) -> bool {
+        if let Backend::Native { conn, .. } = &self.backend { return conn.queue.lock().unwrap().connected }
         unsafe { ffi::dbus_connection_get_is_connected(self.conn()) != 0 }
     }
 
@@ -237,6 +397,7 @@ impl Channel {
     ///
     /// It's usually something like ":1.54"
     pub fn unique_name(&self) -> Option<&str> {
+        if let Backend::Native { unique_name, .. } = &self.backend { return unique_name.as_deref() }
         let c = unsafe { ffi::dbus_bus_get_unique_name(self.conn()) };
         if c.is_null() { return None; }
         let s = unsafe { CStr::from_ptr(c) };
@@ -244,13 +405,49 @@ impl Channel {
     }
 
 
+    /// Returns true if arguments of type `t` can be sent over this connection.
+    ///
+    /// This is mostly useful for `ArgType::UnixFd`: file descriptors can only be passed
+    /// over unix sockets, and only if both sides have agreed to do so during authentication.
+    /// Until the connection is authenticated, this returns false for `ArgType::UnixFd`.
+    pub fn can_send_type(&self, t: ArgType) -> bool {
+        if let Backend::Native { conn, .. } = &self.backend { return t != ArgType::UnixFd || conn.unix_fd }
+        unsafe { ffi::dbus_connection_can_send_type(self.conn(), t as c_int) != 0 }
+    }
+
+    pub (crate) fn check_unix_fds(&self, msg: &Message) -> Result<(), Error> {
+        // Before authentication we don't know yet, in that case leave it to libdbus.
+        let authenticated = match self.backend {
+            Backend::Ffi { .. } => unsafe { ffi::dbus_connection_get_is_authenticated(self.conn()) != 0 },
+            Backend::Native { .. } => true,
+        };
+        if msg.contains_unix_fds() && authenticated && !self.can_send_type(ArgType::UnixFd) {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NotSupported",
+                "Message contains file descriptors, but this connection does not support passing them"));
+        }
+        Ok(())
+    }
+
     /// Puts a message into libdbus out queue. Use "flush" or "read_write" to make sure it is sent over the wire.
     ///
     /// Returns a serial number than can be used to match against a reply.
+    ///
+    /// Fails if the message contains file descriptors and the connection does not support passing them.
+    /// Use `try_send` to find out why sending failed.
     pub fn send(&self, msg: Message) -> Result<u32, ()> {
+        self.try_send(msg).map_err(|_| ())
+    }
+
+    /// Like `send`, but returns an error describing why the message could not be sent.
+    ///
+    /// In particular, returns an `org.freedesktop.DBus.Error.NotSupported` error if the message contains
+    /// file descriptors and the connection does not support passing them.
+    pub fn try_send(&self, msg: Message) -> Result<u32, Error> {
+        self.check_unix_fds(&msg)?;
+        if let Backend::Native { conn, .. } = &self.backend { return conn.send(&mut { msg }) }
         let mut serial = 0u32;
         let r = unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), &mut serial) };
-        if r == 0 { return Err(()); }
+        if r == 0 { return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Failed to queue message")) }
         Ok(serial)
     }
 
@@ -262,7 +459,14 @@ impl Channel {
     ///
     /// Note: In case pop_message and send_with_reply_and_block is called in parallel from different threads,
     /// they might race to retreive the reply message from the internal queue.
+    ///
+    /// Returns an `org.freedesktop.DBus.Error.NotSupported` error if the message contains file descriptors
+    /// and the connection does not support passing them.
     pub fn send_with_reply_and_block(&self, msg: Message, timeout: Duration) -> Result<Message, Error> {
+        self.check_unix_fds(&msg)?;
+        if let Backend::Native { conn, .. } = &self.backend {
+            return native_send_with_reply(conn, msg, timeout)
+        }
         let mut e = Error::empty();
         let response = unsafe {
             ffi::dbus_connection_send_with_reply_and_block(self.conn(), msg.ptr(),
@@ -277,7 +481,10 @@ impl Channel {
     /// Flush the queue of outgoing messages.
     /// 
     /// Blocking: until the outgoing queue is empty.
-    pub fn flush(&self) { unsafe { ffi::dbus_connection_flush(self.conn()) } }
+    pub fn flush(&self) {
+        // The native backend writes messages as they are sent
+        if let Backend::Ffi { .. } = self.backend { unsafe { ffi::dbus_connection_flush(self.conn()) } }
+    }
 
     /// Read and write to the connection.
     ///
@@ -286,6 +493,15 @@ impl Channel {
     /// Blocking: If there are no messages, for up to timeout, or forever if timeout is None.
     /// For non-blocking behaviour, set timeout to Some(0).
     pub fn read_write(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        if let Backend::Native { conn, .. } = &self.backend {
+            // Read what is already there, then wait for the queue to become non-empty
+            if let Ok(mut rbuf) = conn.reader.try_lock() {
+                let r = conn.read(&mut rbuf, Some(Duration::from_secs(0)));
+                drop(conn.read_finished(rbuf, r).map_err(|_| ())?);
+            }
+            let deadline = timeout.map(|t| Instant::now() + t);
+            return conn.wait_for(deadline, |q| if q.incoming.is_empty() { None } else { Some(()) }).map(|_| ()).map_err(|_| ())
+        }
         let t = timeout.map_or(-1, |t| t.as_millis() as c_int);
         if unsafe { ffi::dbus_connection_read_write(self.conn(), t) == 0 } {
             Err(())
@@ -300,6 +516,7 @@ impl Channel {
     /// For unhandled messages, please call MessageDispatcher::default_dispatch to return
     /// default replies for method calls.
     pub fn pop_message(&self) -> Option<Message> {
+        if let Backend::Native { conn, .. } = &self.backend { return conn.queue.lock().unwrap().incoming.pop_front() }
         let mptr = unsafe { ffi::dbus_connection_pop_message(self.conn()) };
         if mptr.is_null() {
             None
@@ -326,11 +543,15 @@ impl Channel {
     /// something else than one file descriptor,
     /// but this should be extremely unlikely to ever happen.)
     pub fn set_watch_enabled(&mut self, enable: bool) {
-        if enable == self.watchmap.is_some() { return }
+        let (handle, watchmap) = match &mut self.backend {
+            Backend::Ffi { handle, watchmap } => (handle, watchmap),
+            Backend::Native { .. } => return,
+        };
+        if enable == watchmap.is_some() { return }
         if enable {
-            self.watchmap = Some(WatchMap::new(ConnHandle(self.conn(), false)));
+            *watchmap = Some(WatchMap::new(ConnHandle(handle.0, false)));
         } else {
-            self.watchmap = None;
+            *watchmap = None;
         }
     }
 
@@ -342,7 +563,11 @@ impl Channel {
     /// something else than one file descriptor,
     /// but this should be extremely unlikely to ever happen.)
     pub fn watch(&self) -> Watch {
-        let wm = self.watchmap.as_ref().unwrap();
+        let wm = match &self.backend {
+            Backend::Ffi { watchmap, .. } => watchmap.as_ref().unwrap(),
+            // Messages are written as they are sent, so there is never anything to wait for but reading
+            Backend::Native { conn, .. } => return Watch { fd: conn.stream.as_raw_fd(), read: true, write: false },
+        };
         let rw = wm.current_rw.load(Ordering::Acquire);
         Watch {
             fd: wm.current_fd.unwrap(),
@@ -355,9 +580,11 @@ impl Channel {
     ///
     /// Obsolete - in practice, you can use watch and set_watch_enabled instead.
     pub fn watch_fds(&mut self) -> Result<Vec<Watch>, ()> {
-        let en = self.watchmap.is_some();
+        if let Backend::Native { .. } = self.backend { return Ok(vec!(self.watch())) }
+        let en = match &self.backend { Backend::Ffi { watchmap, .. } => watchmap.is_some(), _ => unreachable!() };
         self.set_watch_enabled(true);
-        let mut wlist: Vec<Watch> = self.watchmap.as_ref().unwrap().list.lock().unwrap().values()
+        let wm = match &self.backend { Backend::Ffi { watchmap, .. } => watchmap.as_ref().unwrap(), _ => unreachable!() };
+        let mut wlist: Vec<Watch> = wm.list.lock().unwrap().values()
             .map(|&(w, b)| Watch { fd: w.fd, read: b && w.read, write: b && w.write })
             .collect();
         self.set_watch_enabled(en);
@@ -375,6 +602,20 @@ impl Channel {
     }
 }
 
+/// Sends a method call over a native channel and waits until the reply arrives.
+///
+/// Other messages that arrive while waiting are kept in the incoming queue.
+fn native_send_with_reply(c: &NativeConn, mut msg: Message, timeout: Duration) -> Result<Message, Error> {
+    let serial = c.send(&mut msg)?;
+    let r = c.wait_for(Some(Instant::now() + timeout), |q| {
+        let i = q.incoming.iter().position(|m| m.get_reply_serial() == Some(serial))?;
+        q.incoming.remove(i)
+    })?;
+    let mut r = r.ok_or_else(|| Error::new_custom("org.freedesktop.DBus.Error.NoReply", "Did not receive a reply"))?;
+    r.as_result()?;
+    Ok(r)
+}
+
 /// Abstraction over different connections that send data
 pub trait Sender {
     /// Schedules a message for sending.
@@ -502,3 +743,74 @@ fn watchmap() {
     println!("{:?}", w);
     c.set_watch_enabled(true);
 }
+
+#[test]
+fn try_send_unix_fds() {
+    use crate::arg::OwnedFd;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    for &tcp in &[false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::listen(&if tcp { "tcp:host=localhost,port=0".to_string() }
+            else { format!("unix:path={}", dir.path().join("sock").display()) }).unwrap();
+        if tcp {
+            server.set_allow_anonymous(true);
+            server.set_auth_mechanisms(&["ANONYMOUS"]).unwrap();
+        }
+        let addr = server.address();
+
+        let client = std::thread::spawn(move || {
+            let c = Channel::open_private(&addr).unwrap();
+            // Fd passing is negotiated during authentication, so make sure that has happened first
+            let m = Message::new_method_call("com.example.peer", "/", "com.example.Peer", "Ping").unwrap();
+            c.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+            assert_eq!(c.can_send_type(ArgType::UnixFd), !tcp);
+
+            let (mut a, b) = UnixStream::pair().unwrap();
+            let m = Message::new_method_call("com.example.peer", "/", "com.example.Peer", "Fd").unwrap();
+            let r = c.send_with_reply_and_block(m.append1(OwnedFd::from(b)), Duration::from_secs(5));
+            if tcp {
+                assert_eq!(r.unwrap_err().name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+                let m = Message::new_method_call("com.example.peer", "/", "com.example.Peer", "Fd").unwrap();
+                let e = c.try_send(m.append1(OwnedFd::from(a))).unwrap_err();
+                assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+
+                // The nonblock proxy checks this before sending, too
+                use std::future::Future;
+                let c = crate::nonblock::LocalConnection::from(c);
+                let (a, _) = UnixStream::pair().unwrap();
+                let p = crate::nonblock::Proxy::new("com.example.peer", "/", &c);
+                let mut r = Box::pin(p.method_call::<(), _, _, _>("com.example.Peer", "Fd", (OwnedFd::from(a),)));
+                let waker = crate::nonblock::noop_waker();
+                match r.as_mut().poll(&mut std::task::Context::from_waker(&waker)) {
+                    std::task::Poll::Ready(Err(e)) => assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NotSupported")),
+                    _ => panic!("Message with file descriptors was sent"),
+                }
+            } else {
+                r.unwrap();
+                let mut s = String::new();
+                a.read_to_string(&mut s).unwrap();
+                assert_eq!(s, "hello");
+            }
+        });
+
+        let c = server.accept(Some(Duration::from_secs(5))).unwrap().unwrap();
+        let pop = || loop {
+            if let Some(m) = c.blocking_pop_message(Duration::from_secs(5)).unwrap() { break m }
+        };
+        let m = pop();
+        c.send(m.method_return()).unwrap();
+        c.flush();
+        if !tcp {
+            let m = pop();
+            assert!(m.contains_unix_fds());
+            let mut s: UnixStream = m.read1::<OwnedFd>().unwrap().into();
+            s.write_all(b"hello").unwrap();
+            drop(s);
+            c.send(m.method_return()).unwrap();
+            c.flush();
+        }
+        client.join().unwrap();
+    }
+}
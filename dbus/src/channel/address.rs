@@ -0,0 +1,276 @@
+use crate::Error;
+use super::BusType;
+use std::{env, fmt, str};
+
+const BAD_ADDRESS: &str = "org.freedesktop.DBus.Error.BadAddress";
+const DEFAULT_SYSTEM_BUS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+fn bad_address(s: String) -> Error { Error::new_custom(BAD_ADDRESS, &s) }
+
+/// Bytes that do not need to be escaped in address values, according to the D-Bus specification.
+fn is_optionally_escaped(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-_/.\\*".contains(&b)
+}
+
+fn escape(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for &b in s.as_bytes() {
+        if is_optionally_escaped(b) { write!(f, "{}", b as char)? } else { write!(f, "%{:02x}", b)? }
+    }
+    Ok(())
+}
+
+fn unescape(s: &str) -> Result<String, Error> {
+    let b = s.as_bytes();
+    let mut r = vec!();
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let h = s.get(i+1..i+3).filter(|x| x.bytes().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|x| u8::from_str_radix(x, 16).ok());
+            r.push(h.ok_or_else(|| bad_address(format!("Invalid escape sequence in '{}'", s)))?);
+            i += 3;
+        } else {
+            r.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(r).map_err(|_| bad_address(format!("Value '{}' is not valid UTF-8", s)))
+}
+
+/// One entry of a D-Bus address, i e, a transport name and its key-value pairs.
+///
+/// Keys and values are stored unescaped; escaping is done when the entry is printed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddressEntry {
+    transport: String,
+    params: Vec<(String, String)>,
+}
+
+impl AddressEntry {
+    /// Creates a new entry with the given transport name (e g "unix" or "tcp") and no parameters.
+    pub fn new(transport: &str) -> Self { AddressEntry { transport: transport.into(), params: vec!() } }
+
+    /// A unix socket at a path in the file system.
+    pub fn unix_path<P: AsRef<std::path::Path>>(path: P) -> Self {
+        Self::new("unix").with("path", &path.as_ref().to_string_lossy())
+    }
+
+    /// A unix socket in the abstract namespace (Linux only).
+    pub fn unix_abstract(name: &str) -> Self { Self::new("unix").with("abstract", name) }
+
+    /// A unix socket with a random name in a directory (only valid for listening).
+    pub fn unix_dir<P: AsRef<std::path::Path>>(dir: P) -> Self {
+        Self::new("unix").with("dir", &dir.as_ref().to_string_lossy())
+    }
+
+    /// A TCP socket.
+    pub fn tcp(host: &str, port: u16) -> Self {
+        Self::new("tcp").with("host", host).with("port", &port.to_string())
+    }
+
+    /// A TCP socket protected by a nonce file.
+    pub fn nonce_tcp<P: AsRef<std::path::Path>>(host: &str, port: u16, noncefile: P) -> Self {
+        Self::new("nonce-tcp").with("host", host).with("port", &port.to_string())
+            .with("noncefile", &noncefile.as_ref().to_string_lossy())
+    }
+
+    /// Looks up the bus address of the X11 session (or launches a bus if there is none).
+    pub fn autolaunch() -> Self { Self::new("autolaunch") }
+
+    /// Sets a parameter, replacing an existing one with the same key.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some(p) => p.1 = value.into(),
+            None => self.params.push((key.into(), value.into())),
+        }
+        self
+    }
+
+    /// The transport name, e g "unix".
+    pub fn transport(&self) -> &str { &self.transport }
+
+    /// Gets the value of a parameter.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| &**v)
+    }
+
+    /// All parameters, in the order they were specified.
+    pub fn params(&self) -> &[(String, String)] { &self.params }
+
+    /// The GUID of the server, if specified.
+    pub fn guid(&self) -> Option<&str> { self.get("guid") }
+
+    /// Checks that the parameters make sense for the transport.
+    ///
+    /// Unknown transports are accepted as-is.
+    pub fn validate(&self) -> Result<(), Error> {
+        let t = &*self.transport;
+        let err = |s: String| Err(bad_address(format!("{} (in '{}')", s, self)));
+        if t.is_empty() { return err("Missing transport name".into()) }
+        for (i, (k, _)) in self.params.iter().enumerate() {
+            if k.is_empty() { return err("Empty key".into()) }
+            if self.params[..i].iter().any(|(k2, _)| k2 == k) { return err(format!("Duplicate key '{}'", k)) }
+        }
+        if let Some(g) = self.guid() {
+            if g.len() != 32 || !g.bytes().all(|b| b.is_ascii_hexdigit()) { return err(format!("Invalid guid '{}'", g)) }
+        }
+        let allowed: &[&str] = match t {
+            "unix" => {
+                let kinds = ["path", "abstract", "dir", "tmpdir", "runtime"];
+                let n = kinds.iter().filter(|k| self.get(k).is_some()).count();
+                if n != 1 { return err("Exactly one of 'path', 'abstract', 'dir', 'tmpdir' or 'runtime' must be given".into()) }
+                if self.get("runtime").filter(|v| *v != "yes").is_some() { return err("'runtime' must be 'yes'".into()) }
+                &["path", "abstract", "dir", "tmpdir", "runtime", "guid"]
+            },
+            "tcp" | "nonce-tcp" => {
+                if let Some(p) = self.get("port") {
+                    if p.parse::<u16>().is_err() { return err(format!("Invalid port '{}'", p)) }
+                }
+                if let Some(f) = self.get("family") {
+                    if f != "ipv4" && f != "ipv6" { return err(format!("Invalid family '{}'", f)) }
+                }
+                if t == "tcp" { &["host", "bind", "port", "family", "guid"] }
+                else { &["host", "bind", "port", "family", "noncefile", "guid"] }
+            },
+            "autolaunch" => &["scope", "guid"],
+            _ => return Ok(()),
+        };
+        if let Some((k, _)) = self.params.iter().find(|(k, _)| !allowed.contains(&&**k)) {
+            return err(format!("Unknown key '{}' for transport '{}'", k, t))
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AddressEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.transport)?;
+        for (i, (k, v)) in self.params.iter().enumerate() {
+            if i > 0 { write!(f, ",")? }
+            write!(f, "{}=", k)?;
+            escape(v, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for AddressEntry {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let colon = s.find(':').ok_or_else(|| bad_address(format!("Address '{}' does not contain a colon", s)))?;
+        let mut r = AddressEntry::new(&s[..colon]);
+        for kv in s[colon+1..].split(',').filter(|kv| !kv.is_empty()) {
+            let eq = kv.find('=').ok_or_else(|| bad_address(format!("'{}' in address '{}' is not a key=value pair", kv, s)))?;
+            r.params.push((kv[..eq].into(), unescape(&kv[eq+1..])?));
+        }
+        r.validate()?;
+        Ok(r)
+    }
+}
+
+/// A D-Bus server address, i e, a list of entries to be tried in order.
+///
+/// # Example
+///
+/// ```
+/// use dbus::channel::{Address, AddressEntry};
+///
+/// let a: Address = "unix:path=/tmp/my%20socket;tcp:host=localhost,port=1234".parse()?;
+/// assert_eq!(a.entries()[0].get("path"), Some("/tmp/my socket"));
+/// assert_eq!(a.entries()[1], AddressEntry::tcp("localhost", 1234));
+/// assert_eq!(a.to_string(), "unix:path=/tmp/my%20socket;tcp:host=localhost,port=1234");
+///
+/// let e = "unix:path=/a,abstract=b".parse::<Address>().unwrap_err();
+/// assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.BadAddress"));
+/// # Ok::<(), dbus::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Address(Vec<AddressEntry>);
+
+impl Address {
+    /// Creates a new address from a list of entries.
+    pub fn new(entries: Vec<AddressEntry>) -> Self { Address(entries) }
+
+    /// Parses an address string, e g from a configuration file.
+    pub fn parse(s: &str) -> Result<Self, Error> { s.parse() }
+
+    /// The entries of the address.
+    pub fn entries(&self) -> &[AddressEntry] { &self.0 }
+
+    /// Adds an entry to try after the existing ones.
+    pub fn push(&mut self, entry: AddressEntry) { self.0.push(entry) }
+
+    /// Looks up the address of a bus the same way `Channel::get_private` does.
+    ///
+    /// This reads `DBUS_SESSION_BUS_ADDRESS`, `DBUS_SYSTEM_BUS_ADDRESS` or `DBUS_STARTER_ADDRESS`.
+    /// If the system bus address is not set, the well-known default is returned. If the session
+    /// bus address is not set, `$XDG_RUNTIME_DIR/bus` is used if it exists, otherwise "autolaunch:".
+    pub fn for_bus(bus: BusType) -> Result<Self, Error> {
+        let var = match bus {
+            BusType::Session => "DBUS_SESSION_BUS_ADDRESS",
+            BusType::System => "DBUS_SYSTEM_BUS_ADDRESS",
+            BusType::Starter => "DBUS_STARTER_ADDRESS",
+        };
+        if let Ok(s) = env::var(var) { return s.parse() }
+        match bus {
+            BusType::System => DEFAULT_SYSTEM_BUS.parse(),
+            BusType::Session => {
+                if let Some(d) = env::var_os("XDG_RUNTIME_DIR") {
+                    let p = std::path::Path::new(&d).join("bus");
+                    if p.exists() { return Ok(Address(vec!(AddressEntry::unix_path(p)))) }
+                }
+                Ok(Address(vec!(AddressEntry::autolaunch())))
+            },
+            BusType::Starter => Err(bad_address(format!("{} is not set", var))),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 { write!(f, ";")? }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for Address {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let v = s.split(';').filter(|e| !e.is_empty()).map(str::parse).collect::<Result<Vec<_>, _>>()?;
+        if v.is_empty() { return Err(bad_address("Empty address".into())) }
+        Ok(Address(v))
+    }
+}
+
+impl From<AddressEntry> for Address {
+    fn from(e: AddressEntry) -> Self { Address(vec!(e)) }
+}
+
+#[test]
+fn address_parse() {
+    let a: Address = "unix:abstract=/tmp/dbus-x,guid=0123456789abcdef0123456789ABCDEF;nonce-tcp:host=::1,port=80,family=ipv6,noncefile=/n;autolaunch:".parse().unwrap();
+    assert_eq!(a.entries().len(), 3);
+    assert_eq!(a.entries()[0].transport(), "unix");
+    assert_eq!(a.entries()[0].get("abstract"), Some("/tmp/dbus-x"));
+    assert_eq!(a.entries()[0].guid(), Some("0123456789abcdef0123456789ABCDEF"));
+    assert_eq!(a.entries()[1].get("host"), Some("::1"));
+    assert_eq!(a.entries()[2], AddressEntry::autolaunch());
+    assert_eq!(Address::parse(&a.to_string()).unwrap(), a);
+
+    let e = AddressEntry::unix_path("/tmp/a b,c;d=é");
+    assert_eq!(e.to_string(), "unix:path=/tmp/a%20b%2cc%3bd%3d%c3%a9");
+    assert_eq!(Address::parse(&e.to_string()).unwrap(), e.into());
+    assert_eq!(Address::parse("unix:dir=/tmp;;").unwrap(), AddressEntry::unix_dir("/tmp").into());
+    assert_eq!(Address::parse("foo:bar=baz").unwrap().entries()[0].get("bar"), Some("baz"));
+
+    for s in &["", ";", "unix", "unix:", "unix:path", "unix:path=/a,path=/b", "unix:path=/a,abstract=b",
+        "unix:path=/a,host=b", "unix:runtime=no", "tcp:port=70000", "tcp:family=ipx", "tcp:noncefile=/a",
+        "unix:path=%2", "unix:path=%zz", "unix:path=%ff", ":path=/a", "autolaunch:scope=x,guid=abc"] {
+        let e = Address::parse(s).unwrap_err();
+        assert_eq!(e.name(), Some(BAD_ADDRESS), "{}", s);
+    }
+    assert!(Address::parse("unix:path=/a,host=b").unwrap_err().message().unwrap().contains("Unknown key 'host'"));
+}
@@ -0,0 +1,341 @@
+use crate::Error;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::{env, fs};
+
+const MAX_LINE_LEN: usize = 16 * 1024;
+
+fn auth_failed(s: &str) -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.AuthFailed", s)
+}
+
+pub (crate) fn io_error(e: std::io::Error) -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.IOError", &e.to_string())
+}
+
+/// SASL mechanisms supported by the client side of the handshake.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AuthMechanism {
+    /// Authenticate with the credentials of the socket (i e, our uid).
+    External,
+    /// Prove that we can read a secret cookie from ~/.dbus-keyrings.
+    CookieSha1,
+    /// Do not authenticate at all. Most buses do not accept this.
+    Anonymous,
+}
+
+impl AuthMechanism {
+    /// The name of the mechanism, as used in the protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            AuthMechanism::External => "EXTERNAL",
+            AuthMechanism::CookieSha1 => "DBUS_COOKIE_SHA1",
+            AuthMechanism::Anonymous => "ANONYMOUS",
+        }
+    }
+}
+
+/// The outcome of a successful handshake.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthResult {
+    /// The mechanism the server accepted.
+    pub mechanism: AuthMechanism,
+    /// The GUID of the server.
+    pub guid: String,
+    /// Whether the server agreed to pass unix file descriptors.
+    pub unix_fd: bool,
+}
+
+/// Performs the client side of the D-Bus SASL handshake.
+///
+/// Mechanisms are tried in the order they were specified, skipping those the server
+/// has rejected.
+#[derive(Clone, Debug)]
+pub struct ClientAuth {
+    mechanisms: Vec<AuthMechanism>,
+    unix_fd: bool,
+    keyring_dir: Option<PathBuf>,
+    uid: u32,
+}
+
+impl Default for ClientAuth {
+    fn default() -> Self { ClientAuth::new() }
+}
+
+impl ClientAuth {
+    /// Tries EXTERNAL, then DBUS_COOKIE_SHA1, then ANONYMOUS, and asks for unix fd passing.
+    pub fn new() -> Self {
+        ClientAuth {
+            mechanisms: vec!(AuthMechanism::External, AuthMechanism::CookieSha1, AuthMechanism::Anonymous),
+            unix_fd: true,
+            keyring_dir: None,
+            uid: unsafe { libc::getuid() },
+        }
+    }
+
+    /// Sets the mechanisms to try, in order of preference.
+    pub fn mechanisms(mut self, m: &[AuthMechanism]) -> Self { self.mechanisms = m.into(); self }
+
+    /// Sets whether to negotiate passing of unix file descriptors.
+    pub fn negotiate_unix_fd(mut self, enable: bool) -> Self { self.unix_fd = enable; self }
+
+    /// Sets the directory to read DBUS_COOKIE_SHA1 cookies from (default: ~/.dbus-keyrings).
+    pub fn keyring_dir(mut self, dir: PathBuf) -> Self { self.keyring_dir = Some(dir); self }
+
+    /// Sets the uid to claim for the EXTERNAL and DBUS_COOKIE_SHA1 mechanisms (default: our uid).
+    pub fn uid(mut self, uid: u32) -> Self { self.uid = uid; self }
+
+    /// Runs the handshake over the stream, up to and including sending BEGIN.
+    ///
+    /// The stream is read one byte at a time, so that no message data following the handshake
+    /// is consumed.
+    pub fn authenticate<S: Read + Write>(&self, s: &mut S) -> Result<AuthResult, Error> {
+        s.write_all(b"\0").map_err(io_error)?;
+        let mut server_mechs: Option<Vec<String>> = None;
+        let mut last_error = String::from("No authentication mechanisms to try");
+        for &mech in &self.mechanisms {
+            if let Some(ref sm) = server_mechs {
+                if !sm.iter().any(|x| x == mech.name()) { continue }
+            }
+            match self.try_mechanism(s, mech)? {
+                Ok(guid) => {
+                    let unix_fd = self.unix_fd && {
+                        write_line(s, "NEGOTIATE_UNIX_FD")?;
+                        let l = read_line(s)?;
+                        l == "AGREE_UNIX_FD"
+                    };
+                    write_line(s, "BEGIN")?;
+                    return Ok(AuthResult { mechanism: mech, guid, unix_fd });
+                }
+                Err(rejected) => {
+                    last_error = format!("Server rejected {}", mech.name());
+                    server_mechs = Some(rejected);
+                }
+            }
+        }
+        Err(auth_failed(&last_error))
+    }
+
+    /// Returns Ok(Ok(guid)) on success, and Ok(Err(mechanisms)) if the mechanism was rejected.
+    fn try_mechanism<S: Read + Write>(&self, s: &mut S, mech: AuthMechanism) -> Result<Result<String, Vec<String>>, Error> {
+        let initial = match mech {
+            AuthMechanism::External => hex_encode(self.uid.to_string().as_bytes()),
+            AuthMechanism::CookieSha1 => hex_encode(user_name(self.uid)?.as_bytes()),
+            AuthMechanism::Anonymous => hex_encode(b"dbus-rs"),
+        };
+        write_line(s, &format!("AUTH {} {}", mech.name(), initial))?;
+        loop {
+            let line = read_line(s)?;
+            let (cmd, arg) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i+1..].trim()),
+                None => (&line[..], ""),
+            };
+            match cmd {
+                "OK" => return Ok(Ok(arg.into())),
+                "REJECTED" => return Ok(Err(arg.split(' ').filter(|x| !x.is_empty()).map(String::from).collect())),
+                "DATA" if mech == AuthMechanism::CookieSha1 => {
+                    let reply = match self.cookie_response(arg) {
+                        Ok(r) => format!("DATA {}", hex_encode(r.as_bytes())),
+                        Err(_) => "CANCEL".into(),
+                    };
+                    write_line(s, &reply)?;
+                }
+                "DATA" | "ERROR" => write_line(s, "CANCEL")?,
+                _ => write_line(s, "ERROR \"Unknown command\"")?,
+            }
+        }
+    }
+
+    fn cookie_response(&self, data: &str) -> Result<String, Error> {
+        let data = hex_decode(data).ok_or_else(|| auth_failed("Invalid hex data from server"))?;
+        let data = String::from_utf8(data).map_err(|_| auth_failed("Invalid cookie challenge"))?;
+        let mut parts = data.split(' ');
+        let (context, id, server_challenge) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(c), Some(i), Some(s), None) => (c, i, s),
+            _ => return Err(auth_failed("Invalid cookie challenge")),
+        };
+        if context.is_empty() || context.starts_with('.') || context.contains(|c: char| c == '/' || c == '\\' || c.is_whitespace()) {
+            return Err(auth_failed("Invalid cookie context"));
+        }
+        let dir = match self.keyring_dir {
+            Some(ref d) => d.clone(),
+            None => PathBuf::from(env::var_os("HOME").ok_or_else(|| auth_failed("HOME is not set"))?).join(".dbus-keyrings"),
+        };
+        let keyring = fs::read_to_string(dir.join(context)).map_err(io_error)?;
+        let cookie = keyring.lines().filter_map(|l| {
+            let mut p = l.split(' ');
+            match (p.next(), p.next(), p.next()) {
+                (Some(i), Some(_), Some(c)) if i == id => Some(c),
+                _ => None,
+            }
+        }).next().ok_or_else(|| auth_failed("Cookie not found in keyring"))?;
+        let client_challenge = hex_encode(&random_bytes(16)?);
+        let digest = sha1(format!("{}:{}:{}", server_challenge, client_challenge, cookie).as_bytes());
+        Ok(format!("{} {}", client_challenge, hex_encode(&digest)))
+    }
+}
+
+fn user_name(uid: u32) -> Result<String, Error> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let r = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() { return Err(auth_failed("Could not look up user name")) }
+    let n = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Ok(n.to_string_lossy().into_owned())
+}
+
+fn random_bytes(n: usize) -> Result<Vec<u8>, Error> {
+    let mut v = vec![0u8; n];
+    fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut v)).map_err(io_error)?;
+    Ok(v)
+}
+
+pub (crate) fn write_line<S: Write>(s: &mut S, line: &str) -> Result<(), Error> {
+    s.write_all(format!("{}\r\n", line).as_bytes()).and_then(|_| s.flush()).map_err(io_error)
+}
+
+pub (crate) fn read_line<S: Read>(s: &mut S) -> Result<String, Error> {
+    let mut v = vec!();
+    let mut b = [0u8];
+    while !v.ends_with(b"\r\n") {
+        if v.len() > MAX_LINE_LEN { return Err(auth_failed("Line too long")) }
+        if s.read(&mut b).map_err(io_error)? == 0 { return Err(auth_failed("Connection closed during authentication")) }
+        v.push(b[0]);
+    }
+    v.truncate(v.len() - 2);
+    String::from_utf8(v).map_err(|_| auth_failed("Invalid data during authentication"))
+}
+
+pub (crate) fn hex_encode(b: &[u8]) -> String {
+    b.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub (crate) fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() & 1 != 0 { return None }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i+2)?, 16).ok()).collect()
+}
+
+/// SHA-1, which DBUS_COOKIE_SHA1 needs. Not for any other use.
+pub (crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 { msg.push(0) }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([chunk[4*i], chunk[4*i+1], chunk[4*i+2], chunk[4*i+3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i-3] ^ w[i-8] ^ w[i-14] ^ w[i-16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+            e = d; d = c; c = b.rotate_left(30); b = a; a = t;
+        }
+        for (x, y) in h.iter_mut().zip(&[a, b, c, d, e]) { *x = x.wrapping_add(*y) }
+    }
+    let mut r = [0u8; 20];
+    for (i, x) in h.iter().enumerate() { r[4*i..4*i+4].copy_from_slice(&x.to_be_bytes()) }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    /// A scripted server: for every line received, answers with the next reply (if any).
+    fn fake_server(replies: Vec<&'static str>) -> (UnixStream, thread::JoinHandle<Vec<String>>) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let t = thread::spawn(move || {
+            let mut nul = [0u8];
+            server.read_exact(&mut nul).unwrap();
+            assert_eq!(nul[0], 0);
+            let mut received = vec!();
+            for r in replies {
+                received.push(read_line(&mut server).unwrap());
+                write_line(&mut server, r).unwrap();
+            }
+            received.push(read_line(&mut server).unwrap());
+            received
+        });
+        client.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        (client, t)
+    }
+
+    #[test]
+    fn sha1_vectors() {
+        assert_eq!(hex_encode(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex_encode(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex_encode(&sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+        assert_eq!(hex_decode("31303030"), Some(b"1000".to_vec()));
+        assert_eq!(hex_decode("3g"), None);
+    }
+
+    #[test]
+    fn external() {
+        let (mut c, t) = fake_server(vec!("OK 1234deadbeef", "AGREE_UNIX_FD"));
+        let r = ClientAuth::new().uid(1000).authenticate(&mut c).unwrap();
+        assert_eq!(r, AuthResult { mechanism: AuthMechanism::External, guid: "1234deadbeef".into(), unix_fd: true });
+        assert_eq!(t.join().unwrap(), vec!("AUTH EXTERNAL 31303030", "NEGOTIATE_UNIX_FD", "BEGIN"));
+    }
+
+    #[test]
+    fn fallback_and_no_fds() {
+        let (mut c, t) = fake_server(vec!("REJECTED DBUS_COOKIE_SHA1 ANONYMOUS", "OK abc", "ERROR"));
+        let r = ClientAuth::new().mechanisms(&[AuthMechanism::External, AuthMechanism::Anonymous])
+            .authenticate(&mut c).unwrap();
+        assert_eq!(r.mechanism, AuthMechanism::Anonymous);
+        assert!(!r.unix_fd);
+        let lines = t.join().unwrap();
+        assert!(lines[1].starts_with("AUTH ANONYMOUS "));
+        assert_eq!(lines[3], "BEGIN");
+    }
+
+    #[test]
+    fn all_rejected() {
+        let (mut c, t) = fake_server(vec!("REJECTED EXTERNAL"));
+        let e = ClientAuth::new().mechanisms(&[AuthMechanism::External, AuthMechanism::Anonymous])
+            .authenticate(&mut c).unwrap_err();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.AuthFailed"));
+        drop(c);
+        // The server never gets a BEGIN
+        assert!(t.join().is_err());
+    }
+
+    #[test]
+    fn cookie_sha1() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("org_freedesktop_general"), "7 1500000000 0123456789abcdef\n").unwrap();
+        let (mut c, mut s) = UnixStream::pair().unwrap();
+        let t = thread::spawn(move || {
+            let mut nul = [0u8];
+            s.read_exact(&mut nul).unwrap();
+            assert!(read_line(&mut s).unwrap().starts_with("AUTH DBUS_COOKIE_SHA1 "));
+            write_line(&mut s, &format!("DATA {}", hex_encode(b"org_freedesktop_general 7 serverchallenge"))).unwrap();
+            let l = read_line(&mut s).unwrap();
+            let data = String::from_utf8(hex_decode(&l["DATA ".len()..]).unwrap()).unwrap();
+            let mut p = data.split(' ');
+            let (cc, digest) = (p.next().unwrap(), p.next().unwrap());
+            let expected = sha1(format!("serverchallenge:{}:0123456789abcdef", cc).as_bytes());
+            if digest == hex_encode(&expected) { write_line(&mut s, "OK 42").unwrap() }
+            else { write_line(&mut s, "REJECTED").unwrap() }
+            assert_eq!(read_line(&mut s).unwrap(), "BEGIN");
+        });
+        let r = ClientAuth::new().mechanisms(&[AuthMechanism::CookieSha1]).negotiate_unix_fd(false)
+            .keyring_dir(dir.path().into()).authenticate(&mut c).unwrap();
+        assert_eq!(r.guid, "42");
+        t.join().unwrap();
+    }
+}
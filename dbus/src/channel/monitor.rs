@@ -0,0 +1,195 @@
+use crate::{Error, Message, MessageType};
+use crate::message::MatchRule;
+use super::Channel;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A connection that has become a bus monitor, i e, receives a copy of every message
+/// on the bus matching its rules.
+///
+/// A monitor cannot send messages or own names; the bus might disconnect it if it tries.
+/// This is the same thing `dbus-monitor` does, and requires the bus to allow it (the session
+/// bus typically does, the system bus typically only allows root).
+///
+/// # Example
+///
+/// ```
+/// use dbus::channel::{Monitor, PcapWriter};
+/// use dbus::message::MatchRule;
+/// use dbus::testbus::TestBus;
+///
+/// let bus = TestBus::new()?;
+/// let monitor = Monitor::new(bus.connect()?, &[MatchRule::new_signal("com.example.Test", "Hello")])?;
+/// let c = bus.connect()?;
+/// c.send(dbus::Message::new_signal("/", "com.example.Test", "Hello").unwrap()).unwrap();
+/// c.flush();
+///
+/// // Write everything observed to a pcap file, until our signal arrives
+/// let mut pcap = PcapWriter::new(vec!())?;
+/// for msg in monitor {
+///     pcap.write_message(&msg)?;
+///     if msg.interface().as_deref() == Some("com.example.Test") { break }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Monitor {
+    channel: Channel,
+}
+
+const LOCAL_INTERFACE: &str = "org.freedesktop.DBus.Local";
+
+impl Monitor {
+    /// Turns a registered channel into a monitor, by calling
+    /// `org.freedesktop.DBus.Monitoring.BecomeMonitor`.
+    ///
+    /// An empty list of rules means that every message is monitored.
+    ///
+    /// Blocking: until the bus has replied.
+    pub fn new(channel: Channel, rules: &[MatchRule]) -> Result<Monitor, Error> {
+        let rules: Vec<String> = rules.iter().map(|r| r.match_str()).collect();
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.Monitoring", "BecomeMonitor").unwrap().append2(rules, 0u32);
+        channel.send_with_reply_and_block(m, Duration::from_secs(25))?;
+        Ok(Monitor { channel })
+    }
+
+    /// The underlying channel, e g to get its file descriptor to wait on.
+    pub fn channel(&self) -> &Channel { &self.channel }
+
+    /// Gets whether the monitor is still connected to the bus.
+    pub fn is_connected(&self) -> bool { self.channel.is_connected() }
+
+    /// Gets the next observed message, if one is already waiting.
+    ///
+    /// Call `channel().read_write` to read more messages from the bus.
+    pub fn pop_message(&self) -> Option<Message> {
+        let m = self.channel.pop_message()?;
+        // The local "Disconnected" signal was not sent on the bus, so it is not a monitored message
+        if m.msg_type() == MessageType::Signal && m.interface().as_deref() == Some(LOCAL_INTERFACE) { return None }
+        Some(m)
+    }
+
+    /// Gets the next observed message.
+    ///
+    /// Blocking: until a message arrives, for up to timeout. Returns Ok(None) if the timeout
+    /// expired or the monitor was disconnected.
+    pub fn blocking_pop_message(&self, timeout: Duration) -> Result<Option<Message>, Error> {
+        let m = match self.channel.blocking_pop_message(timeout)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        if m.msg_type() == MessageType::Signal && m.interface().as_deref() == Some(LOCAL_INTERFACE) { return Ok(None) }
+        Ok(Some(m))
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = Message;
+
+    /// Blocks until the next message is observed. Returns None when the monitor is disconnected.
+    fn next(&mut self) -> Option<Message> {
+        loop {
+            if let Some(m) = self.pop_message() { return Some(m) }
+            if !self.is_connected() { return None }
+            match self.blocking_pop_message(Duration::from_secs(3600)) {
+                Ok(Some(m)) => return Some(m),
+                Ok(None) => {},
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// The pcap link type for D-Bus messages, see <https://www.tcpdump.org/linktypes.html>.
+pub const LINKTYPE_DBUS: u32 = 231;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 128 * 1024 * 1024; // Maximum message size of D-Bus
+
+/// Writes messages to a file in the pcap format, which can be read by e g Wireshark.
+///
+/// This is the same format as written by `dbus-monitor --pcap`.
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    w: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a new writer, and writes the pcap file header.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        let mut h = Vec::with_capacity(24);
+        h.extend_from_slice(&PCAP_MAGIC.to_ne_bytes());
+        h.extend_from_slice(&2u16.to_ne_bytes());
+        h.extend_from_slice(&4u16.to_ne_bytes());
+        h.extend_from_slice(&0i32.to_ne_bytes()); // Time zone correction
+        h.extend_from_slice(&0u32.to_ne_bytes()); // Accuracy of timestamps
+        h.extend_from_slice(&PCAP_SNAPLEN.to_ne_bytes());
+        h.extend_from_slice(&LINKTYPE_DBUS.to_ne_bytes());
+        w.write_all(&h)?;
+        Ok(PcapWriter { w })
+    }
+
+    /// Writes a message, timestamped with the current time.
+    pub fn write_message(&mut self, msg: &Message) -> io::Result<()> {
+        self.write_message_at(msg, SystemTime::now())
+    }
+
+    /// Writes a message with the given timestamp.
+    pub fn write_message_at(&mut self, msg: &Message, time: SystemTime) -> io::Result<()> {
+        let data = msg.marshal();
+        let t = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut h = Vec::with_capacity(16);
+        h.extend_from_slice(&(t.as_secs() as u32).to_ne_bytes());
+        h.extend_from_slice(&t.subsec_micros().to_ne_bytes());
+        h.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        h.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        self.w.write_all(&h)?;
+        self.w.write_all(&data)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> { self.w.flush() }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W { &self.w }
+
+    /// Unwraps this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W { self.w }
+}
+
+#[test]
+fn monitor_pcap() {
+    use crate::testbus::TestBus;
+    let bus = TestBus::new().unwrap();
+    let monitor = Monitor::new(bus.connect().unwrap(), &[]).unwrap();
+    let c = bus.connect().unwrap();
+    c.send(Message::new_signal("/a", "com.example.Test", "Sig").unwrap().append1("x")).unwrap();
+    c.flush();
+
+    let mut pcap = PcapWriter::new(vec!()).unwrap();
+    let mut seen = vec!();
+    while let Some(m) = monitor.blocking_pop_message(Duration::from_secs(5)).unwrap() {
+        pcap.write_message_at(&m, UNIX_EPOCH + Duration::new(5, 6000)).unwrap();
+        let done = m.member().as_deref() == Some("Sig");
+        seen.push(m);
+        if done { break }
+    }
+    // We should also have seen the second connection's Hello
+    assert!(seen.iter().any(|m| m.member().as_deref() == Some("Hello")));
+    let sig = seen.last().unwrap();
+    assert_eq!(&*sig.sender().unwrap(), c.unique_name().unwrap());
+
+    let v = pcap.into_inner();
+    assert_eq!(&v[..4], &PCAP_MAGIC.to_ne_bytes());
+    assert_eq!(&v[20..24], &LINKTYPE_DBUS.to_ne_bytes());
+    let mut pos = 24;
+    for m in &seen {
+        let len = u32::from_ne_bytes([v[pos+8], v[pos+9], v[pos+10], v[pos+11]]) as usize;
+        assert_eq!(&v[pos..pos+8], &[5u32.to_ne_bytes(), 6u32.to_ne_bytes()].concat()[..]);
+        let m2 = Message::demarshal(&v[pos+16..pos+16+len]).unwrap();
+        assert_eq!(m2.member(), m.member());
+        pos += 16 + len;
+    }
+    assert_eq!(pos, v.len());
+}
@@ -0,0 +1,314 @@
+use crate::{Error, to_c_str};
+use super::{Channel, ConnHandle, Watch, WatchHandle};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::os::raw::{c_void, c_int, c_uint, c_char};
+use std::{ptr, mem};
+
+#[derive(Debug)]
+struct ServerHandle(*mut ffi::DBusServer);
+
+unsafe impl Send for ServerHandle {}
+unsafe impl Sync for ServerHandle {}
+
+/// This struct must be boxed as it is called from D-Bus callbacks!
+#[derive(Debug, Default)]
+struct ServerData {
+    watches: Mutex<HashMap<WatchHandle, (Watch, bool)>>,
+    /// When each timeout expires next, or None if it is disabled
+    timeouts: Mutex<HashMap<TimeoutHandle, Option<Instant>>>,
+    incoming: Mutex<VecDeque<ConnHandle>>,
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+struct TimeoutHandle(*mut ffi::DBusTimeout);
+
+unsafe impl Send for TimeoutHandle {}
+unsafe impl Sync for TimeoutHandle {}
+
+fn next_expiry(t: *mut ffi::DBusTimeout) -> Option<Instant> {
+    if unsafe { ffi::dbus_timeout_get_enabled(t) } == 0 { return None }
+    let ms = unsafe { ffi::dbus_timeout_get_interval(t) };
+    Some(Instant::now() + Duration::from_millis(ms.max(0) as u64))
+}
+
+extern "C" fn add_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) -> u32 { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    sd.watches.lock().unwrap().insert(WatchHandle(watch), Watch::from_raw_enabled(watch));
+    1
+}}
+
+extern "C" fn remove_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    sd.watches.lock().unwrap().remove(&WatchHandle(watch));
+}}
+
+extern "C" fn toggled_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    if let Some((_, ref mut b)) = sd.watches.lock().unwrap().get_mut(&WatchHandle(watch)) {
+        *b = ffi::dbus_watch_get_enabled(watch) != 0;
+    }
+}}
+
+extern "C" fn add_timeout_cb(timeout: *mut ffi::DBusTimeout, data: *mut c_void) -> u32 { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    sd.timeouts.lock().unwrap().insert(TimeoutHandle(timeout), next_expiry(timeout));
+    1
+}}
+
+extern "C" fn remove_timeout_cb(timeout: *mut ffi::DBusTimeout, data: *mut c_void) { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    sd.timeouts.lock().unwrap().remove(&TimeoutHandle(timeout));
+}}
+
+extern "C" fn toggled_timeout_cb(timeout: *mut ffi::DBusTimeout, data: *mut c_void) { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    if let Some(t) = sd.timeouts.lock().unwrap().get_mut(&TimeoutHandle(timeout)) {
+        *t = next_expiry(timeout);
+    }
+}}
+
+extern "C" fn new_connection_cb(_: *mut ffi::DBusServer, conn: *mut ffi::DBusConnection, data: *mut c_void) { unsafe {
+    let sd: &ServerData = &*(data as *mut _);
+    ffi::dbus_connection_ref(conn);
+    sd.incoming.lock().unwrap().push_back(ConnHandle(conn, true));
+}}
+
+fn take_c_string(p: *mut c_char) -> String {
+    if p.is_null() { panic!("D-Bus error: out of memory") }
+    let s = unsafe { std::ffi::CStr::from_ptr(p) }.to_string_lossy().into_owned();
+    unsafe { ffi::dbus_free(p as *mut c_void) };
+    s
+}
+
+/// Listens for incoming peer-to-peer connections.
+///
+/// Each accepted client is returned as a `Channel`. Since there is no message bus involved,
+/// you should not call `register` on these channels, and messages do not need a destination.
+///
+/// # Example
+///
+/// ```
+/// use dbus::channel::{Channel, Server};
+/// use std::time::Duration;
+///
+/// let server = Server::listen("unix:tmpdir=/tmp")?;
+/// let address = server.address();
+/// let client = std::thread::spawn(move || {
+///     let c = Channel::open_private(&address).unwrap();
+///     let m = dbus::Message::new_method_call("com.example.peer", "/", "com.example.Peer", "Ping").unwrap();
+///     c.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+/// });
+/// let c = server.accept(Some(Duration::from_secs(5)))?.unwrap();
+/// let msg = loop {
+///     // The first read_write calls are spent on authentication
+///     if let Some(msg) = c.blocking_pop_message(Duration::from_secs(5))? { break msg }
+/// };
+/// assert_eq!(&*msg.member().unwrap(), "Ping");
+/// c.send(msg.method_return()).unwrap();
+/// c.flush();
+/// client.join().unwrap();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    handle: ServerHandle,
+    data: Box<ServerData>,
+    allow_anonymous: bool,
+}
+
+impl Server {
+    /// Starts listening on an address, e g "unix:path=/tmp/foo", "unix:abstract=foo",
+    /// "unix:tmpdir=/tmp" or "tcp:host=localhost,port=0".
+    ///
+    /// Several addresses can be separated by ';'.
+    pub fn listen(address: &str) -> Result<Server, Error> {
+        crate::init_dbus();
+        let mut e = Error::empty();
+        let p = unsafe { ffi::dbus_server_listen(to_c_str(address).as_ptr(), e.get_mut()) };
+        if p.is_null() { return Err(e) }
+        let s = Server { handle: ServerHandle(p), data: Default::default(), allow_anonymous: false };
+        let dptr: &ServerData = &s.data;
+        let dptr = dptr as *const _ as *mut c_void;
+        unsafe {
+            ffi::dbus_server_set_new_connection_function(p, Some(new_connection_cb), dptr, None);
+            if ffi::dbus_server_set_watch_functions(p, Some(add_watch_cb), Some(remove_watch_cb),
+                Some(toggled_watch_cb), dptr, None) == 0 {
+                return Err(Error::new_failed("Cannot enable watch tracking (OOM?)"))
+            }
+            if ffi::dbus_server_set_timeout_functions(p, Some(add_timeout_cb), Some(remove_timeout_cb),
+                Some(toggled_timeout_cb), dptr, None) == 0 {
+                return Err(Error::new_failed("Cannot enable timeout tracking (OOM?)"))
+            }
+        }
+        Ok(s)
+    }
+
+    /// The address clients can connect to, including the GUID of the server.
+    ///
+    /// If you listened on e g a "tmpdir" address, this contains the socket path actually used.
+    pub fn address(&self) -> String { take_c_string(unsafe { ffi::dbus_server_get_address(self.handle.0) }) }
+
+    /// The GUID of the server, which clients can compare against the GUID in the address.
+    pub fn guid(&self) -> String { take_c_string(unsafe { ffi::dbus_server_get_id(self.handle.0) }) }
+
+    /// Whether the server is still listening.
+    pub fn is_connected(&self) -> bool { unsafe { ffi::dbus_server_get_is_connected(self.handle.0) != 0 } }
+
+    /// Stops listening. Channels already accepted are not affected.
+    pub fn disconnect(&self) { unsafe { ffi::dbus_server_disconnect(self.handle.0) } }
+
+    /// Restricts the authentication mechanisms the server offers, e g `&["EXTERNAL"]`.
+    pub fn set_auth_mechanisms(&self, mechanisms: &[&str]) -> Result<(), Error> {
+        let c: Vec<_> = mechanisms.iter().map(|m| to_c_str(m)).collect();
+        let mut p: Vec<*const c_char> = c.iter().map(|m| m.as_ptr()).collect();
+        p.push(ptr::null());
+        if unsafe { ffi::dbus_server_set_auth_mechanisms(self.handle.0, p.as_mut_ptr()) } == 0 {
+            return Err(Error::new_failed("Setting auth mechanisms failed (OOM?)"))
+        }
+        Ok(())
+    }
+
+    /// Allows clients that authenticated with the ANONYMOUS mechanism on accepted channels.
+    pub fn set_allow_anonymous(&mut self, allow: bool) { self.allow_anonymous = allow }
+
+    /// The file descriptors to watch for incoming connections.
+    ///
+    /// When one of them is ready, call `accept` with a timeout of zero.
+    pub fn watch_fds(&self) -> Vec<Watch> {
+        self.data.watches.lock().unwrap().values()
+            .map(|&(w, b)| Watch { fd: w.fd, read: b && w.read, write: b && w.write })
+            .collect()
+    }
+
+    /// Calls dbus_timeout_handle for the timeouts that have expired.
+    fn handle_timeouts(&self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self.data.timeouts.lock().unwrap().iter()
+            .filter(|(_, t)| matches!(t, Some(t) if *t <= now)).map(|(h, _)| *h).collect();
+        for h in expired {
+            // Handling a timeout might remove others, and the lock must not be held while libdbus calls back
+            {
+                let mut timeouts = self.data.timeouts.lock().unwrap();
+                match timeouts.get_mut(&h) {
+                    Some(t) => *t = next_expiry(h.0),
+                    None => continue,
+                }
+            }
+            unsafe { ffi::dbus_timeout_handle(h.0) };
+        }
+    }
+
+    fn pop_incoming(&self) -> Option<Channel> {
+        let h = self.data.incoming.lock().unwrap().pop_front()?;
+        let p = h.0;
+        mem::forget(h);
+        if self.allow_anonymous { unsafe { ffi::dbus_connection_set_allow_anonymous(p, 1) } }
+        Channel::conn_from_ptr(p).ok()
+    }
+
+    /// Accepts a new client.
+    ///
+    /// The channel is returned before authentication has finished, that happens as soon as you
+    /// start reading from or writing to it.
+    ///
+    /// Blocking: until a client connects, for up to timeout, or forever if timeout is None.
+    /// Returns Ok(None) if the timeout expired. Polling is restarted if it is interrupted by a signal.
+    ///
+    /// Timeouts that libdbus registers on the server are handled while waiting here.
+    pub fn accept(&self, timeout: Option<Duration>) -> Result<Option<Channel>, Error> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            self.handle_timeouts();
+            if let Some(c) = self.pop_incoming() { return Ok(Some(c)) }
+            let watches: Vec<_> = self.data.watches.lock().unwrap().iter()
+                .filter(|(_, (_, b))| *b).map(|(h, (w, _))| (h.0, *w)).collect();
+            if watches.is_empty() { return Err(Error::new_failed("Server is not listening")) }
+            let mut fds: Vec<_> = watches.iter().map(|(_, w)| libc::pollfd {
+                fd: w.fd,
+                events: (if w.read { libc::POLLIN } else { 0 }) | (if w.write { libc::POLLOUT } else { 0 }),
+                revents: 0,
+            }).collect();
+            let next_timeout = self.data.timeouts.lock().unwrap().values().filter_map(|t| *t).min();
+            let wake = match (deadline, next_timeout) {
+                (Some(d), Some(t)) => Some(std::cmp::min(d, t)),
+                (d, t) => d.or(t),
+            };
+            let t = wake.map_or(-1, |d| {
+                let ms = d.saturating_duration_since(Instant::now()).as_millis();
+                std::cmp::min(ms, c_int::MAX as u128) as c_int
+            });
+            let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, t) };
+            if r < 0 {
+                let e = std::io::Error::last_os_error();
+                // Interrupted by a signal, try again with what is left of the timeout
+                if e.kind() == std::io::ErrorKind::Interrupted { continue }
+                return Err(Error::new_failed(&format!("poll failed: {}", e)))
+            }
+            if r == 0 {
+                // Either the deadline passed, or a libdbus timeout needs to be handled
+                match deadline {
+                    Some(d) if Instant::now() >= d => return Ok(None),
+                    _ => continue,
+                }
+            }
+            for ((wptr, _), pfd) in watches.iter().zip(&fds) {
+                if pfd.revents == 0 { continue }
+                // The watch might have been removed by handling a previous one
+                if !self.data.watches.lock().unwrap().contains_key(&WatchHandle(*wptr)) { continue }
+                let mut flags = 0;
+                if pfd.revents & libc::POLLIN != 0 { flags |= ffi::DBUS_WATCH_READABLE }
+                if pfd.revents & libc::POLLOUT != 0 { flags |= ffi::DBUS_WATCH_WRITABLE }
+                if pfd.revents & libc::POLLERR != 0 { flags |= ffi::DBUS_WATCH_ERROR }
+                if pfd.revents & libc::POLLHUP != 0 { flags |= ffi::DBUS_WATCH_HANGUP }
+                unsafe { ffi::dbus_watch_handle(*wptr, flags as c_uint) };
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let p = self.handle.0;
+        unsafe {
+            ffi::dbus_server_disconnect(p);
+            ffi::dbus_server_set_new_connection_function(p, None, ptr::null_mut(), None);
+            ffi::dbus_server_set_watch_functions(p, None, None, None, ptr::null_mut(), None);
+            ffi::dbus_server_set_timeout_functions(p, None, None, None, ptr::null_mut(), None);
+            ffi::dbus_server_unref(p);
+        }
+    }
+}
+
+#[test]
+fn server_peer_to_peer() {
+    use crate::Message;
+    let dir = tempfile::tempdir().unwrap();
+    let server = Server::listen(&format!("unix:path={}", dir.path().join("sock").display())).unwrap();
+    let addr = server.address();
+    assert!(addr.starts_with("unix:path="));
+    assert!(addr.ends_with(&format!("guid={}", server.guid())));
+    assert!(server.is_connected());
+    assert_eq!(server.watch_fds().len(), 1);
+    assert!(server.accept(Some(Duration::from_millis(0))).unwrap().is_none());
+
+    let client = std::thread::spawn(move || {
+        let c = Channel::open_private(&addr).unwrap();
+        let m = Message::new_method_call("com.example.peer", "/", "com.example.Peer", "Ping").unwrap();
+        let r = c.send_with_reply_and_block(m.append1("ping"), Duration::from_secs(5)).unwrap();
+        assert_eq!(r.read1::<&str>().unwrap(), "pong");
+    });
+
+    let c = server.accept(Some(Duration::from_secs(5))).unwrap().unwrap();
+    let m = loop {
+        if let Some(m) = c.blocking_pop_message(Duration::from_secs(5)).unwrap() { break m }
+    };
+    assert_eq!(m.read1::<&str>().unwrap(), "ping");
+    c.send(m.method_return().append1("pong")).unwrap();
+    c.flush();
+    client.join().unwrap();
+
+    server.disconnect();
+    assert!(!server.is_connected());
+}
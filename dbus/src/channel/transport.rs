@@ -0,0 +1,345 @@
+use crate::{Error, Message};
+use crate::arg::OwnedFd;
+use crate::message::message_set_serial;
+use super::{Address, BusType};
+use super::auth::{ClientAuth, AuthResult, io_error};
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+use std::mem;
+
+fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    if name.len() >= addr.sun_path.len() { return Err(io::Error::new(io::ErrorKind::InvalidInput, "Socket name too long")) }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (d, s) in addr.sun_path[1..].iter_mut().zip(name) { *d = *s as libc::c_char }
+    let len = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 { return Err(io::Error::last_os_error()) }
+        let s = UnixStream::from_raw_fd(fd);
+        if libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len as libc::socklen_t) < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(s)
+    }
+}
+
+/// Waits until the socket is readable. Returns false on timeout.
+pub (crate) fn poll_read(fd: RawFd, timeout: Option<Duration>) -> Result<bool, Error> {
+    let t = timeout.map_or(-1, |t| std::cmp::min(t.as_millis(), c_int::MAX as u128) as c_int);
+    let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    loop {
+        let r = unsafe { libc::poll(&mut pfd, 1, t) };
+        if r >= 0 { return Ok(r > 0) }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted { return Err(io_error(e)) }
+    }
+}
+
+/// The largest number of file descriptors that can be received at once, this is what the reference bus allows per message.
+const MAX_RECV_FDS: usize = 253;
+
+fn not_supported() -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.NotSupported", "Connection does not support passing unix file descriptors")
+}
+
+/// Writes all of data, the file descriptors are sent together with the first byte.
+fn write_with_fds(s: &UnixStream, mut data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let cspace = unsafe { libc::CMSG_SPACE(mem::size_of_val(fds) as u32) } as usize;
+    let mut cbuf = vec![0u64; cspace.div_ceil(8)];
+    let mut fds = fds;
+    while !data.is_empty() {
+        let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut _, iov_len: data.len() };
+        let mut mh: libc::msghdr = unsafe { mem::zeroed() };
+        mh.msg_iov = &mut iov;
+        mh.msg_iovlen = 1;
+        if !fds.is_empty() {
+            mh.msg_control = cbuf.as_mut_ptr() as *mut _;
+            mh.msg_controllen = cspace as _;
+            unsafe {
+                let c = libc::CMSG_FIRSTHDR(&mh);
+                (*c).cmsg_level = libc::SOL_SOCKET;
+                (*c).cmsg_type = libc::SCM_RIGHTS;
+                (*c).cmsg_len = libc::CMSG_LEN(mem::size_of_val(fds) as u32) as _;
+                std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(c) as *mut RawFd, fds.len());
+            }
+        }
+        let r = unsafe { libc::sendmsg(s.as_raw_fd(), &mh, libc::MSG_NOSIGNAL) };
+        if r < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted { continue }
+            return Err(e)
+        }
+        data = &data[r as usize..];
+        fds = &[];
+    }
+    Ok(())
+}
+
+/// Writes a message to the socket. The message must have a serial number.
+pub (crate) fn write_message(s: &UnixStream, msg: &Message, unix_fd: bool) -> Result<(), Error> {
+    if msg.contains_unix_fds() && !unix_fd { return Err(not_supported()) }
+    #[cfg(feature = "native")]
+    let fds: Vec<RawFd> = msg.unix_fds().iter().map(|f| f.as_raw_fd()).collect();
+    #[cfg(not(feature = "native"))]
+    let fds: Vec<RawFd> = vec!();
+    write_with_fds(s, &msg.marshal(), &fds).map_err(io_error)
+}
+
+/// Data that has been read from the socket, but does not make up a complete message yet.
+#[derive(Debug, Default)]
+pub (crate) struct ReadBuf {
+    data: Vec<u8>,
+    fds: Vec<OwnedFd>,
+}
+
+impl ReadBuf {
+    /// Reads what is available on the socket, without waiting. Returns false if nothing was.
+    pub fn fill(&mut self, s: &UnixStream) -> Result<bool, Error> {
+        let mut buf = [0u8; 4096];
+        let mut cbuf = [0u64; (MAX_RECV_FDS * 4 + 64) / 8];
+        let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut _, iov_len: buf.len() };
+        let mut mh: libc::msghdr = unsafe { mem::zeroed() };
+        mh.msg_iov = &mut iov;
+        mh.msg_iovlen = 1;
+        mh.msg_control = cbuf.as_mut_ptr() as *mut _;
+        mh.msg_controllen = mem::size_of_val(&cbuf) as _;
+        let r = loop {
+            let r = unsafe { libc::recvmsg(s.as_raw_fd(), &mut mh, libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC) };
+            if r >= 0 { break r as usize }
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock => return Ok(false),
+                _ => return Err(io_error(e)),
+            }
+        };
+        unsafe {
+            let mut c = libc::CMSG_FIRSTHDR(&mh);
+            while !c.is_null() {
+                if (*c).cmsg_level == libc::SOL_SOCKET && (*c).cmsg_type == libc::SCM_RIGHTS {
+                    let n = ((*c).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                    let p = libc::CMSG_DATA(c) as *const RawFd;
+                    for i in 0..n { self.fds.push(OwnedFd::new(std::ptr::read_unaligned(p.add(i)))) }
+                }
+                c = libc::CMSG_NXTHDR(&mh, c);
+            }
+        }
+        if mh.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.LimitsExceeded", "Too many file descriptors received"))
+        }
+        if r == 0 { return Err(io_error(io::ErrorKind::UnexpectedEof.into())) }
+        self.data.extend_from_slice(&buf[..r]);
+        Ok(true)
+    }
+
+    /// Returns the next message, if all of it has been read.
+    pub fn parse(&mut self) -> Result<Option<Message>, Error> {
+        let n = match Message::demarshal_bytes_needed(&self.data)? {
+            Some(n) if self.data.len() >= n => n,
+            _ => return Ok(None),
+        };
+        #[cfg(feature = "native")]
+        let m = Message::demarshal_with_fds(&self.data[..n], &mut self.fds);
+        #[cfg(not(feature = "native"))]
+        let m = Message::demarshal(&self.data[..n]);
+        self.data.drain(..n);
+        m.map(Some)
+    }
+}
+
+/// A connection that does authentication and message framing in Rust, without `dbus_connection_*`.
+///
+/// This only supports the unix socket transport. Unix file descriptors can be passed if the "native"
+/// feature is enabled and the server agrees to it during authentication; without that feature,
+/// passing them is never negotiated.
+///
+/// You probably want to use this through `Channel::open_native` or `Channel::get_native` instead.
+#[derive(Debug)]
+pub struct Transport {
+    pub (crate) stream: UnixStream,
+    pub (crate) auth: AuthResult,
+    pub (crate) rbuf: ReadBuf,
+    pub (crate) next_serial: u32,
+}
+
+impl Transport {
+    /// Authenticates over an already connected stream.
+    ///
+    /// Passing unix file descriptors is only negotiated with the "native" feature.
+    pub fn new(mut stream: UnixStream, auth: &ClientAuth) -> Result<Self, Error> {
+        let auth = if cfg!(feature = "native") { auth.clone() } else { auth.clone().negotiate_unix_fd(false) };
+        let r = auth.authenticate(&mut stream)?;
+        Ok(Transport { stream, auth: r, rbuf: ReadBuf::default(), next_serial: 1 })
+    }
+
+    /// Connects to a D-Bus address, such as "unix:path=/run/user/1000/bus".
+    ///
+    /// Entries of a ';' separated list are tried in order. Only "unix:path" and "unix:abstract" are supported.
+    pub fn connect_address(address: &str, auth: &ClientAuth) -> Result<Self, Error> {
+        Self::connect(&address.parse()?, auth)
+    }
+
+    /// Connects to the first entry of an address that works.
+    ///
+    /// Only "unix:path" and "unix:abstract" entries are supported, others are skipped.
+    pub fn connect(address: &Address, auth: &ClientAuth) -> Result<Self, Error> {
+        let mut last_err = Error::new_custom("org.freedesktop.DBus.Error.BadAddress", "No supported address found");
+        for entry in address.entries().iter().filter(|e| e.transport() == "unix") {
+            let stream = if let Some(p) = entry.get("path") { UnixStream::connect(p) }
+                else if let Some(a) = entry.get("abstract") { connect_abstract(a.as_bytes()) }
+                else { continue };
+            match stream {
+                Ok(s) => return Self::new(s, auth),
+                Err(e) => last_err = io_error(e),
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Connects to the session or system bus. This does not call Hello.
+    pub fn connect_bus(bus: BusType, auth: &ClientAuth) -> Result<Self, Error> {
+        Self::connect(&Address::for_bus(bus)?, auth)
+    }
+
+    /// The result of the authentication handshake.
+    pub fn auth_result(&self) -> &AuthResult { &self.auth }
+
+    /// Writes a message to the socket. A serial number is assigned if the message does not have one.
+    ///
+    /// Returns the serial number of the message.
+    pub fn send(&mut self, msg: &mut Message) -> Result<u32, Error> {
+        if msg.get_serial() == 0 {
+            message_set_serial(msg, self.next_serial);
+            self.next_serial = self.next_serial.wrapping_add(1).max(1);
+        }
+        write_message(&self.stream, msg, self.auth.unix_fd)?;
+        Ok(msg.get_serial())
+    }
+
+    /// Reads the next message, waiting for up to timeout, or forever if timeout is None.
+    ///
+    /// Returns Ok(None) if no complete message arrived in time.
+    pub fn read(&mut self, timeout: Option<Duration>) -> Result<Option<Message>, Error> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if let Some(m) = self.rbuf.parse()? { return Ok(Some(m)) }
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if !poll_read(self.stream.as_raw_fd(), remaining)? { return Ok(None) }
+            self.rbuf.fill(&self.stream)?;
+        }
+    }
+}
+
+impl AsRawFd for Transport {
+    fn as_raw_fd(&self) -> RawFd { self.stream.as_raw_fd() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::auth::AuthMechanism;
+
+    fn pair(unix_fd: bool) -> (Transport, Transport) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let res = AuthResult { mechanism: AuthMechanism::External, guid: "x".into(), unix_fd };
+        let t = |s| Transport { stream: s, auth: res.clone(), rbuf: ReadBuf::default(), next_serial: 1 };
+        (t(a), t(b))
+    }
+
+    #[test]
+    fn framing() {
+        let (mut a, mut b) = pair(false);
+        let mut m = Message::new_signal("/test", "org.example.Test", "Big").unwrap().append1(vec!(7u8; 10000));
+        assert_eq!(a.send(&mut m).unwrap(), 1);
+        assert_eq!(a.send(&mut Message::new_signal("/test", "org.example.Test", "Small").unwrap()).unwrap(), 2);
+
+        let m2 = b.read(Some(Duration::from_secs(5))).unwrap().unwrap();
+        assert_eq!(m2.get_serial(), 1);
+        assert_eq!(m2.read1::<Vec<u8>>().unwrap(), vec!(7u8; 10000));
+        let m2 = b.read(Some(Duration::from_secs(5))).unwrap().unwrap();
+        assert_eq!(&*m2.member().unwrap(), "Small");
+        assert!(b.read(Some(Duration::from_millis(0))).unwrap().is_none());
+        drop(a);
+        assert!(b.read(None).is_err());
+    }
+
+    #[test]
+    fn no_fd_support() {
+        let (mut a, _b) = pair(false);
+        let fd = crate::arg::OwnedFd::new(unsafe { libc::dup(0) });
+        let mut m = Message::new_signal("/test", "org.example.Test", "Fd").unwrap().append1(fd);
+        let e = a.send(&mut m).unwrap_err();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn fd_passing() {
+        use std::io::{Read, Write};
+        use crate::arg::OwnedFd;
+        let (mut a, mut b) = pair(true);
+        let (mut r, w) = UnixStream::pair().unwrap();
+        let mut m = Message::new_signal("/test", "org.example.Test", "Fd").unwrap()
+            .append3(OwnedFd::from(w), 5u8, vec!(OwnedFd::new(unsafe { libc::dup(0) })));
+        a.send(&mut m).unwrap();
+        a.send(&mut Message::new_signal("/test", "org.example.Test", "NoFd").unwrap()).unwrap();
+        drop(m);
+
+        let m2 = b.read(Some(Duration::from_secs(5))).unwrap().unwrap();
+        let (w2, x, v): (OwnedFd, u8, Vec<OwnedFd>) = m2.read3().unwrap();
+        assert_eq!((x, v.len()), (5, 1));
+        let mut w2 = UnixStream::from(w2);
+        w2.write_all(b"hi").unwrap();
+        drop((w2, m2));
+        let mut s = String::new();
+        r.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hi");
+        let m3 = b.read(Some(Duration::from_secs(5))).unwrap().unwrap();
+        assert!(!m3.contains_unix_fds());
+    }
+
+    #[test]
+    fn test_bus() {
+        use crate::blocking::Connection;
+        use crate::channel::Channel;
+        let bus = crate::testbus::TestBus::new().unwrap();
+        let mut c = Channel::open_native(bus.address(), &ClientAuth::new()).unwrap();
+        assert_eq!(c.unique_name(), None);
+        c.register().unwrap();
+        assert!(c.unique_name().unwrap().starts_with(':'));
+        assert_eq!(c.can_send_type(crate::arg::ArgType::UnixFd), cfg!(feature = "native"));
+
+        let c: Connection = c.into();
+        c.request_name("com.example.dbusrs.native", false, true, false).unwrap();
+        let proxy = c.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(5));
+        let (names,): (Vec<String>,) = proxy.method_call("org.freedesktop.DBus", "ListNames", ()).unwrap();
+        assert!(names.iter().any(|n| n == "com.example.dbusrs.native"));
+        assert!(names.iter().any(|n| **n == *c.unique_name()));
+        let e = proxy.method_call::<(), _, _, _>("org.freedesktop.DBus", "NoSuchMethod", ()).unwrap_err();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.UnknownMethod"));
+    }
+
+    #[test]
+    fn threads() {
+        use crate::channel::Channel;
+        use std::sync::Arc;
+        let bus = crate::testbus::TestBus::new().unwrap();
+        let mut c = Channel::open_native(bus.address(), &ClientAuth::new()).unwrap();
+        c.register().unwrap();
+        let c = Arc::new(c);
+        let c2 = c.clone();
+        // This thread waits for incoming messages, without keeping other threads from sending and receiving
+        let t = std::thread::spawn(move || c2.read_write(Some(Duration::from_secs(3))));
+        std::thread::sleep(Duration::from_millis(100));
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetId").unwrap();
+        let started = Instant::now();
+        let r = c.send_with_reply_and_block(m, Duration::from_secs(2)).unwrap();
+        assert!(r.read1::<&str>().is_ok());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        t.join().unwrap().unwrap();
+    }
+}
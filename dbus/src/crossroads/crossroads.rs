@@ -2,8 +2,12 @@ use std::collections::{BTreeMap, HashMap};
 use std::any::{TypeId, Any};
 use std::ffi::{CString, CStr};
 use std::fmt;
+use std::time::Duration;
 use crate::strings::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature};
-use crate::{Message, MessageType};
+use crate::{Message, MessageType, Error, blocking, nonblock};
+use crate::channel::{self, MatchingReceiver, Sender};
+use crate::message::MatchRule;
+use super::MethodErr;
 use super::info::{IfaceInfo, MethodInfo, PropInfo, IfaceInfoBuilder};
 use super::handlers::{Handlers, Par, ParInfo, Mut, MutCtx, MutMethods};
 use super::stdimpl::{DBusProperties, DBusIntrospectable};
@@ -199,6 +203,78 @@ impl Crossroads<Mut> {
     }
 }
 
+/// Internal helper trait, implemented for the connections a Crossroads can handle method calls from.
+pub trait MakeFilter<C: MatchingReceiver> {
+    /// Internal helper function that creates the callback.
+    fn make_filter(self) -> C::F;
+}
+
+impl<H: Handlers> MakeFilter<blocking::Connection> for Crossroads<H> where Self: 'static {
+    fn make_filter(mut self) -> <blocking::Connection as MatchingReceiver>::F { Box::new(move |msg, c| self.handle_message(msg, c)) }
+}
+
+impl<H: Handlers> MakeFilter<blocking::SyncConnection> for Crossroads<H> where Self: Send + Sync + 'static {
+    fn make_filter(mut self) -> <blocking::SyncConnection as MatchingReceiver>::F { Box::new(move |msg, c| self.handle_message(msg, c)) }
+}
+
+impl<H: Handlers> MakeFilter<nonblock::LocalConnection> for Crossroads<H> where Self: 'static {
+    fn make_filter(mut self) -> <nonblock::LocalConnection as MatchingReceiver>::F { Box::new(move |msg, c| self.handle_message(msg, c)) }
+}
+
+impl<H: Handlers> MakeFilter<nonblock::SyncConnection> for Crossroads<H> where Self: Send + 'static {
+    fn make_filter(mut self) -> <nonblock::SyncConnection as MatchingReceiver>::F { Box::new(move |msg, c| self.handle_message(msg, c)) }
+}
+
+impl<H: Handlers> Crossroads<H> {
+    /// Connects a connection with this Crossroads so that incoming method calls are handled.
+    ///
+    /// Method calls that no handler takes care of get an error reply, saying whether the object path,
+    /// the interface or the method is unknown. No replies are sent to method calls that have the
+    /// no_reply flag set, but their handlers are still called.
+    ///
+    /// This matches all method calls, and a message is only handed to the first matching callback
+    /// on the connection, so method calls matched by callbacks started before this one never reach
+    /// the Crossroads, and callbacks started after it never get any method calls.
+    pub fn start_receive<C: MatchingReceiver>(self, connection: &C) where Self: MakeFilter<C> {
+        let mut rule = MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        connection.start_receive(rule, self.make_filter());
+    }
+
+    /// Handles incoming method calls on a blocking connection, until an error occurs.
+    pub fn serve(self, connection: &mut blocking::Connection) -> Result<(), Error> where Self: MakeFilter<blocking::Connection> {
+        self.start_receive(&*connection);
+        loop { connection.process(Duration::from_secs(1))?; }
+    }
+
+    /// Handles incoming method calls on a blocking `SyncConnection`, until an error occurs.
+    pub fn serve_sync(self, connection: &blocking::SyncConnection) -> Result<(), Error> where Self: MakeFilter<blocking::SyncConnection> {
+        self.start_receive(connection);
+        loop { connection.process(Duration::from_secs(1))?; }
+    }
+
+    fn handle_message<S: Sender>(&mut self, msg: Message, c: &S) -> bool {
+        let replies = H::dispatch(self, &msg).unwrap_or_else(|| self.unhandled(&msg).into_iter().collect());
+        if msg.get_no_reply() { return true }
+        for r in replies { let _ = c.send(r); }
+        true
+    }
+
+    fn unhandled(&self, msg: &Message) -> Option<Message> {
+        let headers = match msg_headers(msg) {
+            Some(h) if &*h.i != "org.freedesktop.DBus.Peer" => h,
+            _ => return channel::default_reply(msg),
+        };
+        let e = match self.paths.get(headers.p.as_cstr()) {
+            None => MethodErr::no_path(&headers.p),
+            Some(data) => match self.reg.get(headers.i.as_cstr()) {
+                Some((typeid, _)) if data.contains_key(*typeid) => MethodErr::no_method(&headers.m),
+                _ => MethodErr::no_interface(&headers.i),
+            }
+        };
+        Some(e.to_message(msg))
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -304,4 +380,58 @@ mod test {
         let xml_data: &str = r[0].read1().unwrap();
         println!("{}", xml_data);
     }
+
+    #[test]
+    fn cr_start_receive() {
+        use std::sync::Arc;
+        let mut cr = Crossroads::new_par();
+        struct Score(u16);
+        cr.register::<Score,_>("com.example.dbusrs.crossroads.score")
+            .method("Hello", ("sender",), ("reply",), |score: &Score, _: &ParInfo, (sender,): (String,)| {
+                Ok((format!("Hello {}, my score is {}!", sender, score.0),))
+            });
+        let mut pdata = PathData::new();
+        pdata.insert_par(Score(7u16));
+        cr.insert("/score", pdata);
+        cr.insert("/empty", PathData::new());
+
+        let bus = crate::testbus::TestBus::new().unwrap();
+        let c = blocking::SyncConnection::from(bus.connect().unwrap());
+        cr.start_receive(&c);
+        let c_name = c.unique_name().into_static();
+        let done = Arc::new(());
+        let d2 = done.clone();
+        let (c2, c3) = (bus.connect().unwrap(), bus.connect().unwrap());
+        let client = std::thread::spawn(move || {
+            let _d2 = d2;
+            let c2 = blocking::Connection::from(c2);
+            let call = |path: &'static str, iface: &str, method: &str| {
+                let p = c2.with_proxy(c_name.clone(), path, Duration::from_secs(5));
+                p.method_call(iface, method, ("example",)).map(|(s,): (String,)| s).map_err(|e| e.name().unwrap().to_string())
+            };
+            let score = "com.example.dbusrs.crossroads.score";
+            assert_eq!(call("/score", score, "Hello"), Ok("Hello example, my score is 7!".into()));
+            assert_eq!(call("/missing", score, "Hello"), Err("org.freedesktop.DBus.Error.UnknownObject".into()));
+            assert_eq!(call("/empty", score, "Hello"), Err("org.freedesktop.DBus.Error.UnknownInterface".into()));
+            assert_eq!(call("/score", "com.example.Missing", "Hello"), Err("org.freedesktop.DBus.Error.UnknownInterface".into()));
+            assert_eq!(call("/score", score, "Missing"), Err("org.freedesktop.DBus.Error.UnknownMethod".into()));
+            let p = c2.with_proxy(c_name.clone(), "/missing", Duration::from_secs(5));
+            let _: () = p.method_call("org.freedesktop.DBus.Peer", "Ping", ()).unwrap();
+
+            // Neither handled nor unhandled calls get a reply if the caller does not want one.
+            for path in &["/score", "/missing"] {
+                let mut m = Message::new_method_call(c_name.clone(), *path, score, "Hello").unwrap().append1("example");
+                m.set_no_reply(true);
+                c3.send(m).unwrap();
+            }
+            let m = Message::new_method_call(c_name.clone(), "/score", score, "Hello").unwrap().append1("example");
+            c3.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+            while let Some(m) = c3.pop_message() { assert_eq!(m.get_reply_serial(), None) }
+        });
+        for _ in 0..50 {
+            if Arc::strong_count(&done) < 2 { break }
+            c.process(Duration::from_millis(100)).unwrap();
+        }
+        client.join().unwrap();
+    }
 }
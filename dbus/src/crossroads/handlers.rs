@@ -25,6 +25,8 @@ pub trait Handlers: Sized {
     fn make_method<IA: ReadAll, OA: AppendAll, F>(f: F) -> Self::Method
     where F: Fn(&Crossroads<Self>, &PathData<Self>, &Message, IA) -> Result<OA, MethodErr> + Send + Sync + 'static;
 
+    fn dispatch(cr: &mut Crossroads<Self>, msg: &Message) -> Option<Vec<Message>>;
+
     fn custom_method_helper(mutfn: Option<fn(&mut Crossroads<Self>, &Message) -> Result<Message, MethodErr>>) -> Self::Method { unimplemented!() }
     fn call_setprop_mut(handler: &mut Self::SetProp, pathdata: &mut PathData<Self>, iter: &mut arg::Iter, msg: &Message) 
         -> Result<bool, MethodErr> { unimplemented!() }
@@ -76,6 +78,7 @@ impl Handlers for Par {
     type SetProp = Box<dyn Fn(&(dyn Any + Send + Sync), &mut arg::Iter, &ParInfo) -> Result<(), MethodErr> + Send + Sync + 'static>;
     type Iface = Box<dyn Any + 'static + Send + Sync>;
 
+    fn dispatch(cr: &mut Crossroads<Self>, msg: &Message) -> Option<Vec<Message>> { cr.dispatch_par(msg) }
 
     fn make_method<IA: ReadAll, OA: AppendAll, F>(f: F) -> Self::Method
     where F: Fn(&Crossroads<Self>, &PathData<Self>, &Message, IA) -> Result<OA, MethodErr> + Send + Sync + 'static {
@@ -124,6 +127,8 @@ impl Handlers for Mut {
     type SetProp = Box<dyn FnMut(&mut PathData<Self>, &mut arg::Iter, &MutCtx) -> Result<bool, MethodErr> + 'static>;
     type Iface = Box<dyn Any>;
 
+    fn dispatch(cr: &mut Crossroads<Self>, msg: &Message) -> Option<Vec<Message>> { cr.dispatch_mut(msg) }
+
     fn make_method<IA: ReadAll, OA: AppendAll, F>(f: F) -> Self::Method
     where F: Fn(&Crossroads<Self>, &PathData<Self>, &Message, IA) -> Result<OA, MethodErr> + Send + Sync + 'static {
         MutMethod(MutMethods::AllRef(Box::new(move |cr, path, ctx| {
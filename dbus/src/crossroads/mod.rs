@@ -10,6 +10,6 @@ pub use crate::tree::MethodErr as MethodErr;
 
 pub use self::info::{IfaceInfo, MethodInfo, PropInfo};
 
-pub use self::crossroads::{Crossroads, PathData};
+pub use self::crossroads::{Crossroads, PathData, MakeFilter};
 
 pub use self::handlers::{Handlers, Par, ParInfo};
@@ -29,6 +29,16 @@ pub mod ffidisp;
 mod error;
 pub use error::Error;
 
+mod util;
+
+mod propcache;
+
+mod objmanager;
+
+mod namewatch;
+
+mod reconnect;
+
 pub mod channel;
 
 // Not ready for release yet
@@ -46,6 +56,11 @@ pub mod arg;
 
 pub mod tree;
 
+pub mod testbus;
+
+#[cfg(feature = "native")]
+mod native;
+
 static INITDBUS: std::sync::Once = std::sync::ONCE_INIT;
 
 use std::ffi::{CString, CStr};
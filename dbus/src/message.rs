@@ -1,6 +1,7 @@
 //! Contains structs and traits closely related to D-Bus messages.
 
 use std::{fmt, ptr};
+use std::cell::Cell;
 use super::{ffi, Error, libc, to_c_str, c_str_to_slice, init_dbus};
 use crate::strings::{BusName, Path, Interface, Member, ErrorName};
 use std::ffi::CStr;
@@ -30,7 +31,9 @@ pub use self::matchrule::MatchRule;
 /// A D-Bus message. A message contains headers - usually destination address, path, interface and member,
 /// and a list of arguments.
 pub struct Message {
-    msg: *mut ffi::DBusMessage,
+    msg: Cell<*mut ffi::DBusMessage>,
+    #[cfg(feature = "native")]
+    native: crate::native::State,
 }
 
 unsafe impl Send for Message {}
@@ -45,7 +48,7 @@ impl Message {
             ffi::dbus_message_new_method_call(d.as_ref().as_ptr(), p.as_ref().as_ptr(), i.as_ref().as_ptr(), m.as_ref().as_ptr())
         };
         if ptr.is_null() { Err("D-Bus error: dbus_message_new_method_call failed".into()) }
-        else { Ok(Message { msg: ptr}) }
+        else { Ok(Message::from_ptr(ptr, false)) }
     }
 
     /// Creates a new method call message.
@@ -56,7 +59,7 @@ impl Message {
                 iface.as_ref().as_ptr(), name.as_ref().as_ptr())
         };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_method_call failed") }
-        Message { msg: ptr}
+        Message::from_ptr(ptr, false)
     }
 
     /// Creates a new method call message.
@@ -81,7 +84,7 @@ impl Message {
             ffi::dbus_message_new_signal(p.as_ref().as_ptr(), i.as_ref().as_ptr(), m.as_ref().as_ptr())
         };
         if ptr.is_null() { Err("D-Bus error: dbus_message_new_signal failed".into()) }
-        else { Ok(Message { msg: ptr}) }
+        else { Ok(Message::from_ptr(ptr, false)) }
     }
 
     /// Creates a new signal message.
@@ -91,35 +94,35 @@ impl Message {
             ffi::dbus_message_new_signal(path.as_ref().as_ptr(), iface.as_ref().as_ptr(), name.as_ref().as_ptr())
         };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_signal failed") }
-        Message { msg: ptr}
+        Message::from_ptr(ptr, false)
     }
 
     /// Creates a method reply for this method call.
     pub fn new_method_return(m: &Message) -> Option<Message> {
-        let ptr = unsafe { ffi::dbus_message_new_method_return(m.msg) };
-        if ptr.is_null() { None } else { Some(Message { msg: ptr} ) }
+        let ptr = unsafe { ffi::dbus_message_new_method_return(m.msg.get()) };
+        if ptr.is_null() { None } else { Some(Message::from_ptr(ptr, false) ) }
     }
 
     /// Creates a method return (reply) for this method call.
     pub fn method_return(&self) -> Message {
-        let ptr = unsafe { ffi::dbus_message_new_method_return(self.msg) };
+        let ptr = unsafe { ffi::dbus_message_new_method_return(self.msg.get()) };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_method_return failed") }
-        Message {msg: ptr}
+        Message::from_ptr(ptr, false)
     }
 
     /// The old way to create a new error reply
     #[deprecated]
     pub fn new_error(m: &Message, error_name: &str, error_message: &str) -> Option<Message> {
         let (en, em) = (to_c_str(error_name), to_c_str(error_message));
-        let ptr = unsafe { ffi::dbus_message_new_error(m.msg, en.as_ptr(), em.as_ptr()) };
-        if ptr.is_null() { None } else { Some(Message { msg: ptr} ) }
+        let ptr = unsafe { ffi::dbus_message_new_error(m.msg.get(), en.as_ptr(), em.as_ptr()) };
+        if ptr.is_null() { None } else { Some(Message::from_ptr(ptr, false) ) }
     }
 
     /// Creates a new error reply
     pub fn error(&self, error_name: &ErrorName, error_message: &CStr) -> Message {
-        let ptr = unsafe { ffi::dbus_message_new_error(self.msg, error_name.as_ref().as_ptr(), error_message.as_ptr()) };
+        let ptr = unsafe { ffi::dbus_message_new_error(self.msg.get(), error_name.as_ref().as_ptr(), error_message.as_ptr()) };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_error failed") }
-        Message { msg: ptr}
+        Message::from_ptr(ptr, false)
     }
 
     /// Get the MessageItems that make up the message.
@@ -135,33 +138,104 @@ impl Message {
 
     /// Get the D-Bus serial of a message, if one was specified.
     pub fn get_serial(&self) -> u32 {
-        unsafe { ffi::dbus_message_get_serial(self.msg) }
+        unsafe { ffi::dbus_message_get_serial(self.msg.get()) }
     }
 
     /// Get the serial of the message this message is a reply to, if present.
     pub fn get_reply_serial(&self) -> Option<u32> {
-        let s = unsafe { ffi::dbus_message_get_reply_serial(self.msg) };
+        let s = unsafe { ffi::dbus_message_get_reply_serial(self.msg.get()) };
         if s == 0 { None } else { Some(s) }
     }
 
     /// Returns true if the message does not expect a reply.
-    pub fn get_no_reply(&self) -> bool { unsafe { ffi::dbus_message_get_no_reply(self.msg) != 0 } }
+    pub fn get_no_reply(&self) -> bool { unsafe { ffi::dbus_message_get_no_reply(self.msg.get()) != 0 } }
 
     /// Set whether or not the message expects a reply.
     ///
     /// Set to true if you send a method call and do not want a reply.
     pub fn set_no_reply(&mut self, v: bool) {
-        unsafe { ffi::dbus_message_set_no_reply(self.msg, if v { 1 } else { 0 }) }
+        unsafe { ffi::dbus_message_set_no_reply(self.msg.get(), if v { 1 } else { 0 }) }
     }
 
     /// Returns true if the message can cause a service to be auto-started.
-    pub fn get_auto_start(&self) -> bool { unsafe { ffi::dbus_message_get_auto_start(self.msg) != 0 } }
+    pub fn get_auto_start(&self) -> bool { unsafe { ffi::dbus_message_get_auto_start(self.msg.get()) != 0 } }
 
     /// Sets whether or not the message can cause a service to be auto-started.
     ///
     /// Defaults to true.
     pub fn set_auto_start(&mut self, v: bool) {
-        unsafe { ffi::dbus_message_set_auto_start(self.msg, if v { 1 } else { 0 }) }
+        unsafe { ffi::dbus_message_set_auto_start(self.msg.get(), if v { 1 } else { 0 }) }
+    }
+
+    /// Returns true if the message has unix file descriptors among its arguments.
+    ///
+    /// Such a message can only be sent over a connection that supports fd passing,
+    /// see `Channel::can_send_type`.
+    pub fn contains_unix_fds(&self) -> bool {
+        #[cfg(feature = "native")]
+        { if let Some(b) = self.native.loaded() { return !b.fds.is_empty() } }
+        unsafe { ffi::dbus_message_contains_unix_fds(self.msg.get()) != 0 }
+    }
+
+    /// Serializes the message into the D-Bus wire format.
+    ///
+    /// Unix file descriptors are not part of the wire format and are therefore lost.
+    #[cfg(feature = "native")]
+    pub fn marshal(&self) -> Vec<u8> { crate::native::marshal(self.msg.get(), self.native_body()) }
+
+    /// Serializes the message into the D-Bus wire format.
+    ///
+    /// Unix file descriptors are not part of the wire format and are therefore lost.
+    #[cfg(not(feature = "native"))]
+    pub fn marshal(&self) -> Vec<u8> {
+        let mut p = ptr::null_mut();
+        let mut len = 0;
+        if unsafe { ffi::dbus_message_marshal(self.msg.get(), &mut p, &mut len) } == 0 {
+            panic!("D-Bus error: dbus_message_marshal failed (OOM?)")
+        }
+        let v = unsafe { std::slice::from_raw_parts(p as *const u8, len as usize) }.to_vec();
+        unsafe { ffi::dbus_free(p as *mut libc::c_void) };
+        v
+    }
+
+    /// Deserializes a message from the D-Bus wire format, i e, the output of `marshal`.
+    ///
+    /// The buffer must contain exactly one complete message, use `demarshal_bytes_needed`
+    /// to find out how much of a stream that is.
+    #[cfg(feature = "native")]
+    pub fn demarshal(data: &[u8]) -> Result<Message, Error> { Message::demarshal_with_fds(data, &mut vec!()) }
+
+    /// Deserializes a message from the D-Bus wire format, i e, the output of `marshal`.
+    ///
+    /// The buffer must contain exactly one complete message, use `demarshal_bytes_needed`
+    /// to find out how much of a stream that is.
+    #[cfg(not(feature = "native"))]
+    pub fn demarshal(data: &[u8]) -> Result<Message, Error> {
+        init_dbus();
+        if data.len() > libc::c_int::MAX as usize { return Err(Error::new_failed("Message too large")) }
+        let mut e = Error::empty();
+        let p = unsafe { ffi::dbus_message_demarshal(data.as_ptr() as *const libc::c_char, data.len() as libc::c_int, e.get_mut()) };
+        if p.is_null() { Err(e) } else { Ok(Message::from_ptr(p, false)) }
+    }
+
+    /// Returns the total size of the message which starts at the beginning of data.
+    ///
+    /// Returns Ok(None) if data is too short to tell yet (at least 16 bytes are needed),
+    /// or an error if the header is invalid.
+    #[cfg(feature = "native")]
+    pub fn demarshal_bytes_needed(data: &[u8]) -> Result<Option<usize>, Error> { crate::native::bytes_needed(data) }
+
+    /// Returns the total size of the message which starts at the beginning of data.
+    ///
+    /// Returns Ok(None) if data is too short to tell yet (at least 16 bytes are needed),
+    /// or an error if the header is invalid.
+    #[cfg(not(feature = "native"))]
+    pub fn demarshal_bytes_needed(data: &[u8]) -> Result<Option<usize>, Error> {
+        let len = std::cmp::min(data.len(), libc::c_int::MAX as usize);
+        let r = unsafe { ffi::dbus_message_demarshal_bytes_needed(data.as_ptr() as *const libc::c_char, len as libc::c_int) };
+        if r < 0 { Err(Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", "Invalid message header")) }
+        else if r == 0 { Ok(None) }
+        else { Ok(Some(r as usize)) }
     }
 
     /// Add one or more MessageItems to this Message.
@@ -338,7 +412,7 @@ impl Message {
 
     /// Gets the MessageType of the Message.
     pub fn msg_type(&self) -> MessageType {
-        match unsafe { ffi::dbus_message_get_type(self.msg) } {
+        match unsafe { ffi::dbus_message_get_type(self.msg.get()) } {
             1 => MessageType::MethodCall,
             2 => MessageType::MethodReturn,
             3 => MessageType::Error,
@@ -354,15 +428,15 @@ impl Message {
 
     /// Gets the name of the connection that originated this message.
     pub fn sender(&self) -> Option<BusName> {
-        self.msg_internal_str(unsafe { ffi::dbus_message_get_sender(self.msg) })
+        self.msg_internal_str(unsafe { ffi::dbus_message_get_sender(self.msg.get()) })
             .map(|s| unsafe { BusName::from_slice_unchecked(s) })
     }
 
     /// Returns a tuple of (Message type, Path, Interface, Member) of the current message.
     pub fn headers(&self) -> (MessageType, Option<String>, Option<String>, Option<String>) {
-        let p = unsafe { ffi::dbus_message_get_path(self.msg) };
-        let i = unsafe { ffi::dbus_message_get_interface(self.msg) };
-        let m = unsafe { ffi::dbus_message_get_member(self.msg) };
+        let p = unsafe { ffi::dbus_message_get_path(self.msg.get()) };
+        let i = unsafe { ffi::dbus_message_get_interface(self.msg.get()) };
+        let m = unsafe { ffi::dbus_message_get_member(self.msg.get()) };
         (self.msg_type(),
          c_str_to_slice(&p).map(|s| s.to_string()),
          c_str_to_slice(&i).map(|s| s.to_string()),
@@ -371,13 +445,13 @@ impl Message {
 
     /// Gets the object path this Message is being sent to.
     pub fn path(&self) -> Option<Path> {
-        self.msg_internal_str(unsafe { ffi::dbus_message_get_path(self.msg) })
+        self.msg_internal_str(unsafe { ffi::dbus_message_get_path(self.msg.get()) })
             .map(|s| unsafe { Path::from_slice_unchecked(s) })
     }
 
     /// Gets the destination this Message is being sent to.
     pub fn destination(&self) -> Option<BusName> {
-        self.msg_internal_str(unsafe { ffi::dbus_message_get_destination(self.msg) })
+        self.msg_internal_str(unsafe { ffi::dbus_message_get_destination(self.msg.get()) })
             .map(|s| unsafe { BusName::from_slice_unchecked(s) })
     }
 
@@ -386,18 +460,18 @@ impl Message {
     /// If dest is none, that means broadcast to all relevant destinations.
     pub fn set_destination(&mut self, dest: Option<BusName>) {
         let c_dest = dest.as_ref().map(|d| d.as_cstr().as_ptr()).unwrap_or(ptr::null());
-        assert!(unsafe { ffi::dbus_message_set_destination(self.msg, c_dest) } != 0);
+        assert!(unsafe { ffi::dbus_message_set_destination(self.msg.get(), c_dest) } != 0);
     }
 
     /// Gets the interface this Message is being sent to.
     pub fn interface(&self) -> Option<Interface> {
-        self.msg_internal_str(unsafe { ffi::dbus_message_get_interface(self.msg) })
+        self.msg_internal_str(unsafe { ffi::dbus_message_get_interface(self.msg.get()) })
             .map(|s| unsafe { Interface::from_slice_unchecked(s) })
     }
 
     /// Gets the interface member being called.
     pub fn member(&self) -> Option<Member> {
-        self.msg_internal_str(unsafe { ffi::dbus_message_get_member(self.msg) })
+        self.msg_internal_str(unsafe { ffi::dbus_message_get_member(self.msg.get()) })
             .map(|s| unsafe { Member::from_slice_unchecked(s) })
     }
 
@@ -411,17 +485,46 @@ impl Message {
 
     pub (crate) fn set_error_from_msg(&self) -> Result<(), Error> {
         let mut e = Error::empty();
-        if unsafe { ffi::dbus_set_error_from_message(e.get_mut(), self.msg) } != 0 { Err(e) }
+        if unsafe { ffi::dbus_set_error_from_message(e.get_mut(), self.ptr()) } != 0 { Err(e) }
         else { Ok(()) }
     }
 
-    pub (crate) fn ptr(&self) -> *mut ffi::DBusMessage { self.msg }
+    pub (crate) fn ptr(&self) -> *mut ffi::DBusMessage {
+        #[cfg(feature = "native")]
+        self.native.sync(&self.msg);
+        self.msg.get()
+    }
 
     pub (crate) fn from_ptr(ptr: *mut ffi::DBusMessage, add_ref: bool) -> Message {
         if add_ref {
             unsafe { ffi::dbus_message_ref(ptr) };
         }
-        Message { msg: ptr }
+        Message {
+            msg: Cell::new(ptr),
+            #[cfg(feature = "native")]
+            native: Default::default(),
+        }
+    }
+
+    /// Deserializes a message. The file descriptors it refers to are taken from the front of fds.
+    #[cfg(feature = "native")]
+    pub (crate) fn demarshal_with_fds(data: &[u8], fds: &mut Vec<crate::arg::OwnedFd>) -> Result<Message, Error> {
+        init_dbus();
+        let (p, body) = crate::native::demarshal(data, fds)?;
+        Ok(Message { msg: Cell::new(p), native: crate::native::State::with_body(body) })
+    }
+
+    /// The file descriptors of the message, in the order of their indices in the wire format.
+    #[cfg(feature = "native")]
+    pub (crate) fn unix_fds(&self) -> &[crate::arg::OwnedFd] { &self.native_body().fds }
+
+    #[cfg(feature = "native")]
+    pub (crate) fn native_body(&self) -> &crate::native::Body { self.native.body(self.msg.get()) }
+
+    /// Changes the arguments; only used by IterAppend, which holds the message mutably borrowed.
+    #[cfg(feature = "native")]
+    pub (crate) fn with_native_body_mut<R, F: FnOnce(&mut crate::native::Body) -> R>(&self, f: F) -> R {
+        self.native.with_body_mut(self.msg.get(), f)
     }
 
 }
@@ -429,7 +532,7 @@ impl Message {
 impl Drop for Message {
     fn drop(&mut self) {
         unsafe {
-            ffi::dbus_message_unref(self.msg);
+            ffi::dbus_message_unref(self.msg.get());
         }
     }
 }
@@ -440,10 +543,9 @@ impl fmt::Debug for Message {
     }
 }
 
-// For purpose of testing the library only.
-#[cfg(test)]
+// Used by channel::Transport, and for testing the library.
 pub (crate) fn message_set_serial(m: &mut Message, s: u32) {
-    unsafe { ffi::dbus_message_set_serial(m.msg, s) };
+    unsafe { ffi::dbus_message_set_serial(m.msg.get(), s) };
 }
 
 #[cfg(test)]
@@ -461,4 +563,24 @@ mod test {
         m.set_no_reply(true);
         assert!(m.get_no_reply());
     }
+
+    #[test]
+    fn marshal_roundtrip() {
+        let mut m = Message::new_method_call("org.test.rust", "/a/b", "org.test.rust", "Test").unwrap()
+            .append3("Hello", 5u32, vec![1i16, 2, 3]);
+        super::message_set_serial(&mut m, 42);
+        let v = m.marshal();
+        assert_eq!(Message::demarshal_bytes_needed(&v[..10]).unwrap(), None);
+        assert_eq!(Message::demarshal_bytes_needed(&v[..20]).unwrap(), Some(v.len()));
+        assert!(Message::demarshal_bytes_needed(&[b'x'; 16]).is_err());
+        assert!(Message::demarshal(&v[..v.len()-1]).is_err());
+
+        let m2 = Message::demarshal(&v).unwrap();
+        assert_eq!(m2.get_serial(), 42);
+        assert_eq!(&*m2.path().unwrap(), "/a/b");
+        assert_eq!(&*m2.member().unwrap(), "Test");
+        let (s, u, a): (&str, u32, Vec<i16>) = m2.read3().unwrap();
+        assert_eq!((s, u, a), ("Hello", 5, vec![1, 2, 3]));
+        assert_eq!(m2.marshal(), v);
+    }
 }
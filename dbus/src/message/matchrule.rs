@@ -1,11 +1,13 @@
-use crate::{Message, MessageType};
-use crate::strings::{BusName, Path, Interface, Member};
+use crate::{Error, Message, MessageType};
+use crate::strings::{BusName, Path, Interface, Member, ValidationError};
+use crate::arg::Iter;
 
 #[derive(Clone, Debug, Default)]
-/// A "match rule", that can match Messages on its headers.
+/// A "match rule", that can match Messages on its headers and arguments.
 ///
 /// A field set to "None" means no filter for that header, 
-/// a field set to "Some(_)" must match exactly.
+/// a field set to "Some(_)" must match exactly (except for "path" when "path_is_namespace" is set).
+/// All argument matches given must match.
 pub struct MatchRule<'a> {
     /// Match on message type (you typically want to do this)
     pub msg_type: Option<MessageType>,
@@ -15,10 +17,22 @@ pub struct MatchRule<'a> {
     pub strict_sender: bool,
     /// Match on message object path
     pub path: Option<Path<'a>>,
+    /// If true, "path" is matched as a namespace, i e, it also matches all paths below it ("path_namespace")
+    pub path_is_namespace: bool,
     /// Match on message interface
     pub interface: Option<Interface<'a>>,
     /// Match on message member (signal or method name)
     pub member: Option<Member<'a>>,
+    /// Match on message destination
+    pub destination: Option<BusName<'a>>,
+    /// If true, also receive messages addressed to others, if the bus allows it. This does not affect `matches`.
+    pub eavesdrop: bool,
+    /// Match on string arguments: argument number (0 - 63) and the exact value ("argN")
+    pub args: Vec<(u8, String)>,
+    /// Match on string or object path arguments, using path semantics ("argNpath")
+    pub args_path: Vec<(u8, String)>,
+    /// Match on the first argument being a bus name or interface in this namespace ("arg0namespace")
+    pub arg0namespace: Option<String>,
     _more_fields_may_come: (),
 }
 
@@ -32,22 +46,81 @@ fn msg_type_str(m: MessageType) -> &'static str {
     }
 }
 
+/// Parses "argN" or "argNpath" into N and whether it was a path match.
+pub (crate) fn parse_arg_key(k: &str) -> Option<(u8, bool)> {
+    let n = k.strip_prefix("arg")?;
+    let (n, is_path) = match n.strip_suffix("path") { Some(n) => (n, true), None => (n, false) };
+    if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) || (n.len() > 1 && n.starts_with('0')) { return None }
+    n.parse::<u8>().ok().filter(|&n| n < 64).map(|n| (n, is_path))
+}
+
+/// Splits a match rule string into its keys and unquoted values.
+///
+/// Does not check whether the keys are known or unique.
+pub (crate) fn split_match_str(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut items = vec!();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.peek().filter(|c| c.is_whitespace()).is_some() { chars.next(); }
+        if chars.peek().is_none() { break }
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(c) => key.push(c),
+                None => return Err(format!("Missing '=' after key '{}'", key.trim())),
+            }
+        }
+        let key = key.trim().to_string();
+        if key.is_empty() { return Err("Missing key before '='".into()) }
+        let mut value = String::new();
+        let mut quoted = false;
+        loop {
+            match (chars.next(), quoted) {
+                (None, false) => break,
+                (None, true) => return Err(format!("Unterminated quote in value of '{}'", key)),
+                (Some('\''), q) => quoted = !q,
+                (Some('\\'), false) if chars.peek() == Some(&'\'') => { chars.next(); value.push('\'') },
+                (Some(','), false) => break,
+                (Some(c), _) => value.push(c),
+            }
+        }
+        items.push((key, value));
+    }
+    Ok(items)
+}
+
+/// Returns true if p is equal to, or below, the namespace ns.
+fn is_path_below(ns: &str, p: &str) -> bool {
+    ns == "/" || p == ns || (p.starts_with(ns) && p[ns.len()..].starts_with('/'))
+}
+
+/// Returns an iterator positioned at the nth argument, if there is one.
+fn arg_nth(msg: &Message, n: u8) -> Option<Iter<'_>> {
+    let mut i = msg.iter_init();
+    for _ in 0..n { if !i.next() { return None } }
+    if i.arg_type() == crate::arg::ArgType::Invalid { None } else { Some(i) }
+}
 
 impl<'a> MatchRule<'a> {
     /// Make a string which you can use in the call to "add_match".
-    ///
-    /// Panics: if msg_type is set to Some(MessageType::Invalid)
     pub fn match_str(&self) -> String {
-        let mut v = vec!();
-        if let Some(x) = self.msg_type { v.push(("type", msg_type_str(x))) };
-        if let Some(ref x) = self.sender { v.push(("sender", &x)) };
-        if let Some(ref x) = self.path { v.push(("path", &x)) };
-        if let Some(ref x) = self.interface { v.push(("interface", &x)) };
-        if let Some(ref x) = self.member { v.push(("member", &x)) };
-
-        // For now we don't need to worry about internal quotes in strings as those are not valid names. 
-        // If we start matching against arguments, we need to worry.
-        let v: Vec<_> = v.into_iter().map(|(k, v)| format!("{}='{}'", k, v)).collect();
+        let mut v: Vec<(String, &str)> = vec!();
+        if let Some(x) = self.msg_type { v.push(("type".into(), msg_type_str(x))) };
+        if let Some(ref x) = self.sender { v.push(("sender".into(), x)) };
+        if let Some(ref x) = self.path {
+            v.push((if self.path_is_namespace { "path_namespace" } else { "path" }.into(), x))
+        };
+        if let Some(ref x) = self.interface { v.push(("interface".into(), x)) };
+        if let Some(ref x) = self.member { v.push(("member".into(), x)) };
+        if let Some(ref x) = self.destination { v.push(("destination".into(), x)) };
+        if self.eavesdrop { v.push(("eavesdrop".into(), "true")) };
+        for (n, x) in &self.args { v.push((format!("arg{}", n), x)) };
+        for (n, x) in &self.args_path { v.push((format!("arg{}path", n), x)) };
+        if let Some(ref x) = self.arg0namespace { v.push(("arg0namespace".into(), x)) };
+
+        // Inside quotes, an apostrophe has to be written as '\'' (end quote, escaped apostrophe, start quote)
+        let v: Vec<_> = v.into_iter().map(|(k, v)| format!("{}='{}'", k, v.replace('\'', "'\\''"))).collect();
         v.join(",")
     }
 
@@ -57,16 +130,95 @@ impl<'a> MatchRule<'a> {
 
         if let Some(ref x) = self.sender {
             if let Some(s) = msg.sender() {
-                let check = self.strict_sender || (s.starts_with(":") == x.starts_with(":"));
+                let check = self.strict_sender || s.is_unique() == x.is_unique();
                 if check && s != *x { return false }
             } else if self.strict_sender { return false }
         };
-        if self.path.is_some() && msg.path() != self.path { return false };
+        if let Some(ref x) = self.path {
+            let p = match msg.path() { Some(p) => p, None => return false };
+            let ok = if self.path_is_namespace { is_path_below(x, &p) } else { *x == p };
+            if !ok { return false }
+        }
         if self.interface.is_some() && msg.interface() != self.interface { return false };
         if self.member.is_some() && msg.member() != self.member { return false };
+        if self.destination.is_some() && msg.destination() != self.destination { return false };
+        for (n, x) in &self.args {
+            if arg_nth(msg, *n).and_then(|mut i| i.get::<&str>().map(|s| s == x)) != Some(true) { return false }
+        }
+        for (n, x) in &self.args_path {
+            let s = arg_nth(msg, *n).and_then(|mut i| i.get::<&str>().map(String::from).or_else(|| i.get::<Path>().map(|p| p.to_string())));
+            if s.filter(|s| *s == *x || (x.ends_with('/') && s.starts_with(&**x)) || (s.ends_with('/') && x.starts_with(&**s))).is_none() { return false }
+        }
+        if let Some(ref x) = self.arg0namespace {
+            let s = arg_nth(msg, 0).and_then(|mut i| i.get::<&str>().map(String::from));
+            if s.filter(|s| *s == *x || (s.starts_with(&**x) && s[x.len()..].starts_with('.'))).is_none() { return false }
+        }
         true
     }
 
+    /// Parses a match rule string, as used in the call to "add_match".
+    ///
+    /// Values can be quoted with apostrophes; an apostrophe itself is written as `\'` outside quotes.
+    /// Unknown or duplicate keys, and invalid values, give an error with the name
+    /// "org.freedesktop.DBus.Error.MatchRuleInvalid".
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use dbus::message::MatchRule;
+    ///
+    /// let mr = MatchRule::parse("type='signal',interface='org.freedesktop.DBus',arg0='com.example.Foo'")?;
+    /// assert_eq!(mr.args, vec!((0, "com.example.Foo".to_string())));
+    /// assert_eq!(MatchRule::parse(&mr.match_str())?.match_str(), mr.match_str());
+    ///
+    /// let e = MatchRule::parse("type='signal',colour='blue'").unwrap_err();
+    /// assert_eq!(e.message(), Some("Unknown key 'colour' in match rule"));
+    /// # Ok::<(), dbus::Error>(())
+    /// ```
+    pub fn parse(s: &str) -> Result<MatchRule<'static>, Error> {
+        let err = |m: String| Error::new_custom("org.freedesktop.DBus.Error.MatchRuleInvalid", &m);
+        let invalid = |k: &str, v: &str, e: String| err(format!("Invalid value '{}' for '{}': {}", v, k, e));
+        let why = |e: ValidationError| format!("{} at position {}", e.reason(), e.position());
+        let mut mr = MatchRule::new();
+        let mut seen: Vec<String> = vec!();
+        for (k, v) in split_match_str(s).map_err(err)? {
+            if seen.contains(&k) { return Err(err(format!("Key '{}' given more than once in match rule", k))) }
+            match &*k {
+                "type" => mr.msg_type = Some(match &*v {
+                    "signal" => MessageType::Signal,
+                    "method_call" => MessageType::MethodCall,
+                    "method_return" => MessageType::MethodReturn,
+                    "error" => MessageType::Error,
+                    _ => return Err(invalid(&k, &v, "expected signal, method_call, method_return or error".into())),
+                }),
+                "sender" => mr.sender = Some(BusName::new_checked(&*v).map_err(|e| invalid(&k, &v, why(e)))?),
+                "path" | "path_namespace" => {
+                    if mr.path.is_some() { return Err(err("'path' and 'path_namespace' cannot both be given".into())) }
+                    mr.path = Some(Path::new_checked(&*v).map_err(|e| invalid(&k, &v, why(e)))?);
+                    mr.path_is_namespace = k == "path_namespace";
+                },
+                "interface" => mr.interface = Some(Interface::new_checked(&*v).map_err(|e| invalid(&k, &v, why(e)))?),
+                "member" => mr.member = Some(Member::new_checked(&*v).map_err(|e| invalid(&k, &v, why(e)))?),
+                "destination" => mr.destination = Some(BusName::new_checked(&*v).map_err(|e| invalid(&k, &v, why(e)))?),
+                "eavesdrop" => mr.eavesdrop = match &*v {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(invalid(&k, &v, "expected true or false".into())),
+                },
+                "arg0namespace" => mr.arg0namespace = Some(v),
+                _ => match parse_arg_key(&k) {
+                    Some((n, false)) => mr.args.push((n, v)),
+                    Some((n, true)) => mr.args_path.push((n, v)),
+                    None if k.starts_with("arg") && k.len() > 3 && k.as_bytes()[3].is_ascii_digit() =>
+                        return Err(err(format!("Invalid argument key '{}' in match rule, argument numbers must be 0 - 63", k))),
+                    None => return Err(err(format!("Unknown key '{}' in match rule", k))),
+                }
+            }
+            seen.push(k);
+        }
+        Ok(mr)
+    }
+
     /// Create a new struct which matches every message.
     pub fn new() -> Self { Default::default() }
 
@@ -87,9 +239,76 @@ impl<'a> MatchRule<'a> {
             sender: self.sender.as_ref().map(|x| x.clone().into_static()),
             strict_sender: self.strict_sender,
             path: self.path.as_ref().map(|x| x.clone().into_static()),
+            path_is_namespace: self.path_is_namespace,
             interface: self.interface.as_ref().map(|x| x.clone().into_static()),
             member: self.member.as_ref().map(|x| x.clone().into_static()),
+            destination: self.destination.as_ref().map(|x| x.clone().into_static()),
+            eavesdrop: self.eavesdrop,
+            args: self.args.clone(),
+            args_path: self.args_path.clone(),
+            arg0namespace: self.arg0namespace.clone(),
             _more_fields_may_come: (),
         }
     }
 } 
+impl std::str::FromStr for MatchRule<'static> {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> { MatchRule::parse(s) }
+}
+
+#[test]
+fn match_args() {
+    let mut mr = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+    mr.args.push((0, "com.example.It's".into()));
+    mr.args_path.push((2, "/a/".into()));
+    assert_eq!(mr.match_str(), "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='com.example.It'\\''s',arg2path='/a/'");
+
+    let m = |a: &str, b: &str, c: &str| Message::new_signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameOwnerChanged").unwrap().append3(a, b, c);
+    assert!(mr.matches(&m("com.example.It's", "", "/a/b")));
+    assert!(mr.matches(&m("com.example.It's", "", "/a/")));
+    assert!(mr.matches(&m("com.example.It's", "", "/")));
+    assert!(!mr.matches(&m("com.example.Its", "", "/a/b")));
+    assert!(!mr.matches(&m("com.example.It's", "", "/ab")));
+    assert!(!mr.matches(&Message::new_signal("/", "org.freedesktop.DBus", "NameOwnerChanged").unwrap().append1("com.example.It's")));
+
+    let mut mr = MatchRule::new();
+    mr.arg0namespace = Some("com.example".into());
+    mr.path = Some("/com/example".into());
+    mr.path_is_namespace = true;
+    mr.destination = Some(":1.5".into());
+    mr.eavesdrop = true;
+    assert_eq!(mr.match_str(), "path_namespace='/com/example',destination=':1.5',eavesdrop='true',arg0namespace='com.example'");
+    let mut m = Message::new_method_call(":1.5", "/com/example/a", "com.example", "Foo").unwrap().append1("com.example.Foo");
+    assert!(mr.matches(&m));
+    m.set_destination(Some(":1.6".into()));
+    assert!(!mr.matches(&m));
+    assert!(!mr.matches(&Message::new_method_call(":1.5", "/com/examples", "com.example", "Foo").unwrap().append1("com.example")));
+    assert!(!mr.matches(&Message::new_method_call(":1.5", "/com/example", "com.example", "Foo").unwrap().append1("com.examples")));
+    assert!(mr.matches(&Message::new_method_call(":1.5", "/com/example", "com.example", "Foo").unwrap().append1("com.example")));
+    assert_eq!(mr.static_clone().match_str(), mr.match_str());
+}
+
+#[test]
+fn match_parse() {
+    let mr: MatchRule = " type=signal, sender='org.freedesktop.DBus',path_namespace='/a',member=Foo,arg3='It'\\''s, ok',arg1path='/x/',eavesdrop='true'".parse().unwrap();
+    assert_eq!(mr.msg_type, Some(MessageType::Signal));
+    assert_eq!(&*mr.sender.clone().unwrap(), "org.freedesktop.DBus");
+    assert!(mr.path_is_namespace && mr.eavesdrop);
+    assert_eq!(mr.args, vec!((3, "It's, ok".to_string())));
+    assert_eq!(mr.args_path, vec!((1, "/x/".to_string())));
+    assert_eq!(MatchRule::parse(&mr.match_str()).unwrap().match_str(), mr.match_str());
+    assert_eq!(MatchRule::parse("").unwrap().match_str(), "");
+
+    let e = |s: &str| MatchRule::parse(s).unwrap_err().message().unwrap().to_string();
+    assert_eq!(e("type='foo'"), "Invalid value 'foo' for 'type': expected signal, method_call, method_return or error");
+    assert_eq!(e("member='a',member='b'"), "Key 'member' given more than once in match rule");
+    assert_eq!(e("member='a"), "Unterminated quote in value of 'member'");
+    assert_eq!(e("member"), "Missing '=' after key 'member'");
+    assert_eq!(e("arg64='a'"), "Invalid argument key 'arg64' in match rule, argument numbers must be 0 - 63");
+    assert_eq!(e("arg01='a'"), "Invalid argument key 'arg01' in match rule, argument numbers must be 0 - 63");
+    assert_eq!(e("path='/a',path_namespace='/b'"), "'path' and 'path_namespace' cannot both be given");
+    assert_eq!(e("argument='a'"), "Unknown key 'argument' in match rule");
+    #[cfg(not(feature = "no-string-validation"))]
+    assert!(e("path='a'").starts_with("Invalid value 'a' for 'path'"));
+    assert_eq!(MatchRule::parse("foo=bar").unwrap_err().name(), Some("org.freedesktop.DBus.Error.MatchRuleInvalid"));
+}
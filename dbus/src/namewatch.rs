@@ -0,0 +1,106 @@
+//! The connection independent parts of watching a bus name, like `g_bus_watch_name` does.
+
+use crate::Error;
+use crate::arg::{self, ReadAll, TypeMismatchError};
+use crate::message::{MatchRule, SignalArgs};
+use crate::strings::BusName;
+
+pub (crate) const NAME_HAS_NO_OWNER: &str = "org.freedesktop.DBus.Error.NameHasNoOwner";
+
+/// A change of owner of a watched bus name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameEvent {
+    /// The name got an owner, with this unique name.
+    Appeared(String),
+    /// The name lost its owner.
+    Vanished,
+}
+
+/// The NameOwnerChanged signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct OwnerChanged {
+    serial: u32,
+    new_owner: String,
+}
+
+impl ReadAll for OwnerChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        let serial = i.message().get_serial();
+        let (_name, _old_owner, new_owner): (String, String, String) = (i.read()?, i.read()?, i.read()?);
+        Ok(OwnerChanged { serial, new_owner })
+    }
+}
+
+impl SignalArgs for OwnerChanged {
+    const NAME: &'static str = "NameOwnerChanged";
+    const INTERFACE: &'static str = "org.freedesktop.DBus";
+}
+
+/// The match rule for NameOwnerChanged on a single name.
+pub (crate) fn match_rule(name: &BusName) -> MatchRule<'static> {
+    let mut mr = OwnerChanged::match_rule(Some(&"org.freedesktop.DBus".into()), Some(&"/org/freedesktop/DBus".into())).static_clone();
+    mr.args.push((0, name.to_string()));
+    mr
+}
+
+/// Fails if the match for a name is already started on the connection, since a signal is only
+/// dispatched to the first matching callback, so the second watch would never see any changes.
+pub (crate) fn check_not_watched(match_strs: &[String], mstr: &str, name: &BusName) -> Result<(), Error> {
+    if match_strs.iter().any(|s| s == mstr) {
+        return Err(Error::new_failed(&format!("The name {} is already watched on this connection", name)))
+    }
+    Ok(())
+}
+
+/// The owner of a watched name, as far as we know.
+#[derive(Debug, Default)]
+pub (crate) struct Watch {
+    // The serial of the message the owner came from. The reply to GetNameOwner and
+    // the NameOwnerChanged signals are all sent by the bus, so this orders them.
+    serial: u32,
+    known: bool,
+    owner: Option<String>,
+}
+
+impl Watch {
+    pub (crate) fn owner(&self) -> Option<&str> { self.owner.as_deref() }
+
+    /// Sets the owner from a message, and returns the events to report.
+    ///
+    /// The first call always reports the current state. A name that goes directly to a new owner
+    /// is reported as vanished and then appeared again.
+    pub (crate) fn update(&mut self, serial: u32, owner: Option<String>) -> Vec<NameEvent> {
+        if serial < self.serial { return vec!() }
+        self.serial = serial;
+        let first = !self.known;
+        self.known = true;
+        if !first && self.owner == owner { return vec!() }
+        let mut events = vec!();
+        if self.owner.is_some() || (first && owner.is_none()) { events.push(NameEvent::Vanished) }
+        if let Some(o) = &owner { events.push(NameEvent::Appeared(o.clone())) }
+        self.owner = owner;
+        events
+    }
+
+    /// Handles the NameOwnerChanged signal.
+    pub (crate) fn changed(&mut self, c: OwnerChanged) -> Vec<NameEvent> {
+        let owner = if c.new_owner.is_empty() { None } else { Some(c.new_owner) };
+        self.update(c.serial, owner)
+    }
+}
+
+#[test]
+fn watch_update() {
+    let a = || Some("a".to_string());
+    let mut w = Watch::default();
+    assert_eq!(w.update(5, None), vec!(NameEvent::Vanished));
+    assert_eq!(w.update(6, None), vec!());
+    // An older message must not undo a newer one.
+    assert_eq!(w.update(4, a()), vec!());
+    assert_eq!(w.update(7, a()), vec!(NameEvent::Appeared("a".into())));
+    assert_eq!(w.update(8, Some("b".into())), vec!(NameEvent::Vanished, NameEvent::Appeared("b".into())));
+    assert_eq!(w.owner(), Some("b"));
+
+    let mut w = Watch::default();
+    assert_eq!(w.update(0, a()), vec!(NameEvent::Appeared("a".into())));
+}
@@ -0,0 +1,14 @@
+//! Native (pure Rust) marshalling of message arguments.
+//!
+//! With the "native" feature, arguments are appended to and read from a buffer in the D-Bus
+//! wire format by Rust code, which avoids one FFI call per argument when building or parsing
+//! large messages. Messages received by `Message::demarshal` are also checked here.
+//!
+//! libdbus still stores message headers, and the body is handed over to it when a libdbus function
+//! needs the whole message, e g when it is sent over a libdbus connection.
+
+mod marshal;
+mod body;
+
+pub (crate) use self::body::{Body, Cursor, State, marshal, demarshal};
+pub (crate) use self::marshal::bytes_needed;
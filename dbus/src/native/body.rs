@@ -0,0 +1,544 @@
+//! Message bodies in marshalled form, and keeping them in sync with the libdbus message
+//! that holds the header.
+
+use super::marshal::{self, Buf, Header, align, alignment, fixed_size, single_len, read_u32, invalid};
+use crate::{ffi, Error};
+use crate::arg::{OwnedFd, MAX_SIGNATURE_LEN};
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::{mem, ptr};
+
+fn fail(what: &str) -> ! { panic!("D-Bus error: {}", what) }
+
+/// The arguments of a message, marshalled in native byte order.
+#[derive(Default)]
+pub (crate) struct Body {
+    pub data: Buf,
+    pub sig: Vec<u8>,
+    pub fds: Vec<OwnedFd>,
+    open: Vec<Container>,
+}
+
+/// A container which is being appended to.
+struct Container {
+    kind: u8,
+    /// Position of the length, for arrays
+    lenpos: usize,
+    /// Position of the first element, for arrays
+    start: usize,
+    /// The element type for arrays and variants, the types appended so far for structs and dict entries
+    sig: Vec<u8>,
+    count: usize,
+}
+
+impl Body {
+    fn added(&mut self, sig: &[u8]) {
+        match self.open.last_mut() {
+            None => {
+                self.sig.extend_from_slice(sig);
+                if self.sig.len() > MAX_SIGNATURE_LEN { fail("message signature is too long") }
+            }
+            Some(c) => {
+                c.count += 1;
+                match c.kind {
+                    b'a' | b'v' => if &*c.sig != sig { fail("value does not match the signature of its container") },
+                    _ => c.sig.extend_from_slice(sig),
+                }
+            }
+        }
+    }
+
+    /// Appends a value of a fixed size type, v is the value in native byte order.
+    pub fn append_fixed(&mut self, c: u8, v: &[u8]) {
+        debug_assert_eq!(fixed_size(c), Some(v.len()));
+        self.data.align(v.len());
+        self.data.extend_from_slice(v);
+        self.added(&[c]);
+    }
+
+    /// Appends a string, object path or signature.
+    pub fn append_str(&mut self, c: u8, s: &[u8]) {
+        if c == b'g' && s.len() > MAX_SIGNATURE_LEN { fail("signature is too long") }
+        self.data.push_str(s, c == b'g');
+        self.added(&[c]);
+    }
+
+    /// Appends a file descriptor, which is duplicated.
+    pub fn append_fd(&mut self, fd: RawFd) {
+        let x = unsafe { libc::dup(fd) };
+        if x == -1 { fail("duplicating file descriptor failed") }
+        self.fds.push(OwnedFd::new(x));
+        self.append_fixed(b'h', &((self.fds.len() - 1) as u32).to_ne_bytes());
+    }
+
+    /// Appends all elements of an array of a fixed size type, which must be open and empty.
+    pub fn append_fixed_array(&mut self, c: u8, v: &[u8]) {
+        match self.open.last() {
+            Some(a) if a.kind == b'a' && *a.sig == [c] && a.start == self.data.len() => {},
+            _ => fail("fixed array does not match the signature of its container"),
+        }
+        self.data.extend_from_slice(v);
+    }
+
+    /// Opens a container, sig is the element type for arrays and the inner type for variants.
+    pub fn open(&mut self, kind: u8, sig: &[u8]) {
+        let (mut lenpos, mut start) = (0, 0);
+        match kind {
+            b'a' => {
+                self.data.align(4);
+                lenpos = self.data.len();
+                self.data.extend_from_slice(&[0; 4]);
+                self.data.align(alignment(sig[0]));
+                start = self.data.len();
+            }
+            b'v' => self.append_sig_value(sig),
+            _ => self.data.align(8),
+        }
+        let sig = if kind == b'a' || kind == b'v' { sig.into() } else { vec!() };
+        self.open.push(Container { kind, lenpos, start, sig, count: 0 });
+    }
+
+    fn append_sig_value(&mut self, sig: &[u8]) {
+        if single_len(sig) != Some(sig.len()) { fail("variant signature is not a single complete type") }
+        self.data.push_str(sig, true);
+    }
+
+    /// Closes the innermost open container.
+    pub fn close(&mut self) {
+        let c = self.open.pop().unwrap();
+        let sig = match c.kind {
+            b'a' => {
+                let len = self.data.len() - c.start;
+                if len > marshal::MAX_ARRAY_LEN { fail("array is too long") }
+                self.data.as_mut_slice()[c.lenpos..c.lenpos+4].copy_from_slice(&(len as u32).to_ne_bytes());
+                [&b"a"[..], &c.sig].concat()
+            }
+            b'v' => {
+                if c.count != 1 { fail("a variant must contain exactly one value") }
+                b"v".to_vec()
+            }
+            b'(' => {
+                if c.count == 0 { fail("a struct must not be empty") }
+                [&b"("[..], &c.sig, b")"].concat()
+            }
+            _ => {
+                if c.count != 2 || single_len(&c.sig[..1]).is_none() || fixed_size(c.sig[0]).is_none() && !b"sog".contains(&c.sig[0]) {
+                    fail("a dict entry must contain a basic type key and a value")
+                }
+                [&b"{"[..], &c.sig, b"}"].concat()
+            }
+        };
+        self.added(&sig);
+    }
+}
+
+/// A position in a message body, for reading arguments.
+#[derive(Clone, Copy)]
+pub (crate) struct Cursor<'a> {
+    data: &'a [u8],
+    /// The types on this level; for arrays, the element type
+    sig: &'a [u8],
+    spos: usize,
+    pos: usize,
+    /// Where the elements end, for arrays
+    end: Option<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(b: &'a Body) -> Self {
+        Cursor { data: b.data.as_slice(), sig: &b.sig, spos: 0, pos: 0, end: None }
+    }
+
+    fn at_end(&self) -> bool {
+        match self.end {
+            Some(e) => self.pos >= e,
+            None => self.spos >= self.sig.len(),
+        }
+    }
+
+    /// The type code of the current value, as written in signatures, or 0 if there are no more values.
+    pub fn type_code(&self) -> u8 { if self.at_end() { 0 } else { self.sig[self.spos] } }
+
+    /// The signature of the current value, for arrays this is also known after the last element.
+    pub fn signature(&self) -> &'a [u8] {
+        if self.spos >= self.sig.len() { return &[] }
+        let l = single_len(&self.sig[self.spos..]).unwrap();
+        &self.sig[self.spos..self.spos+l]
+    }
+
+    /// Moves to the next value, returns false if there are no more values.
+    pub fn next(&mut self) -> bool {
+        if self.at_end() { return false }
+        let s = self.signature();
+        self.pos = marshal::skip(self.data, self.pos, s);
+        if self.end.is_none() { self.spos += s.len() }
+        !self.at_end()
+    }
+
+    /// The current value, in native byte order, if it is of fixed size type c.
+    pub fn fixed(&self, c: u8) -> Option<&'a [u8]> {
+        if self.type_code() != c { return None }
+        let size = fixed_size(c)?;
+        let p = align(self.pos, size);
+        Some(&self.data[p..p+size])
+    }
+
+    /// The current value, if it is a string, object path or signature, depending on c.
+    pub fn str(&self, c: u8) -> Option<&'a CStr> {
+        if self.type_code() != c || !b"sog".contains(&c) { return None }
+        let (start, len) = if c == b'g' { (self.pos + 1, self.data[self.pos] as usize) }
+            else { let p = align(self.pos, 4); (p + 4, read_u32(self.data, p) as usize) };
+        Some(unsafe { CStr::from_bytes_with_nul_unchecked(&self.data[start..start+len+1]) })
+    }
+
+    /// Returns a cursor for the contents of the current value, if it is a container of type c.
+    pub fn recurse(&self, c: u8) -> Option<Cursor<'a>> {
+        if self.type_code() != c { return None }
+        let sig = self.signature();
+        Some(match c {
+            b'a' => {
+                let p = align(self.pos, 4);
+                let start = align(p + 4, alignment(sig[1]));
+                let end = start + read_u32(self.data, p) as usize;
+                Cursor { data: self.data, sig: &sig[1..], spos: 0, pos: start, end: Some(end) }
+            }
+            b'v' => {
+                let l = self.data[self.pos] as usize;
+                Cursor { data: self.data, sig: &self.data[self.pos+1..self.pos+1+l], spos: 0, pos: self.pos + l + 2, end: None }
+            }
+            b'(' | b'{' => Cursor { data: self.data, sig: &sig[1..sig.len()-1], spos: 0, pos: align(self.pos, 8), end: None },
+            _ => return None,
+        })
+    }
+
+    /// The elements of the current value, if it is an array of the fixed size type c.
+    pub fn fixed_array(&self, c: u8) -> Option<&'a [u8]> {
+        if self.signature().get(1) != Some(&c) || fixed_size(c).is_none() { return None }
+        let s = self.recurse(b'a')?;
+        Some(&self.data[s.pos..s.end.unwrap()])
+    }
+}
+
+/// Positions of all unix fd indices, in the order they appear in the body.
+fn fd_positions(mut c: Cursor, v: &mut Vec<usize>) {
+    loop {
+        match c.type_code() {
+            0 => break,
+            b'h' => v.push(align(c.pos, 4)),
+            t @ b'a' | t @ b'(' | t @ b'{' if c.signature().contains(&b'h') => fd_positions(c.recurse(t).unwrap(), v),
+            b'v' => fd_positions(c.recurse(b'v').unwrap(), v),
+            _ => {},
+        }
+        c.next();
+    }
+}
+
+/// File descriptors of a libdbus message, in the order they appear in the body.
+unsafe fn ffi_fds(i: *mut ffi::DBusMessageIter, v: &mut Vec<OwnedFd>) {
+    loop {
+        match ffi::dbus_message_iter_get_arg_type(i) {
+            ffi::DBUS_TYPE_INVALID => break,
+            ffi::DBUS_TYPE_UNIX_FD => {
+                let mut fd: c_int = -1;
+                ffi::dbus_message_iter_get_basic(i, &mut fd as *mut _ as *mut c_void);
+                v.push(OwnedFd::new(fd));
+            }
+            ffi::DBUS_TYPE_ARRAY | ffi::DBUS_TYPE_VARIANT | ffi::DBUS_TYPE_STRUCT | ffi::DBUS_TYPE_DICT_ENTRY => {
+                let mut s = mem::zeroed();
+                ffi::dbus_message_iter_recurse(i, &mut s);
+                ffi_fds(&mut s, v);
+            }
+            _ => {},
+        }
+        ffi::dbus_message_iter_next(i);
+    }
+}
+
+/// Appends the values through libdbus, which is needed when there are file descriptors.
+unsafe fn append_ffi(mut c: Cursor, i: *mut ffi::DBusMessageIter, fds: &[OwnedFd]) {
+    fn check(r: u32) { if r == 0 { fail("appending to libdbus message failed") } }
+    loop {
+        let t = c.type_code();
+        if t == 0 { break }
+        if let Some(v) = c.fixed(t) {
+            let mut x = [0u64];
+            if t == b'h' { x[0] = fds[read_u32(v, 0) as usize].as_raw_fd() as u32 as u64 }
+            else { (&mut x as *mut _ as *mut u8).copy_from(v.as_ptr(), v.len()) }
+            check(ffi::dbus_message_iter_append_basic(i, t as c_int, &x as *const _ as *const c_void));
+        } else if let Some(s) = c.str(t) {
+            let p = s.as_ptr();
+            check(ffi::dbus_message_iter_append_basic(i, t as c_int, &p as *const _ as *const c_void));
+        } else {
+            let sub = c.recurse(t).unwrap();
+            let (ct, csig) = match t {
+                b'a' => (ffi::DBUS_TYPE_ARRAY, Some(sub.sig)),
+                b'v' => (ffi::DBUS_TYPE_VARIANT, Some(sub.sig)),
+                b'(' => (ffi::DBUS_TYPE_STRUCT, None),
+                _ => (ffi::DBUS_TYPE_DICT_ENTRY, None),
+            };
+            let csig = csig.map(|s| CString::new(s).unwrap());
+            let mut s = mem::zeroed();
+            check(ffi::dbus_message_iter_open_container(i, ct, csig.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()), &mut s));
+            append_ffi(sub, &mut s, fds);
+            check(ffi::dbus_message_iter_close_container(i, &mut s));
+        }
+        c.next();
+    }
+}
+
+fn marshal_ffi(p: *mut ffi::DBusMessage) -> Vec<u8> {
+    let mut d = ptr::null_mut();
+    let mut len = 0;
+    if unsafe { ffi::dbus_message_marshal(p, &mut d, &mut len) } == 0 {
+        fail("dbus_message_marshal failed (OOM?)")
+    }
+    let v = unsafe { std::slice::from_raw_parts(d as *const u8, len as usize) }.to_vec();
+    unsafe { ffi::dbus_free(d as *mut c_void) };
+    v
+}
+
+/// Creates a libdbus message from a header and a body without file descriptors.
+fn ffi_from_parts(mut h: Header, body: &[u8]) -> Result<*mut ffi::DBusMessage, Error> {
+    // libdbus does not accept a zero serial, so set it afterwards
+    let serial = h.serial;
+    if serial == 0 { h.serial = 1 }
+    let data = h.write(body);
+    let len = data.len();
+    if len > c_int::MAX as usize { return Err(invalid("Message is too long")) }
+    let mut e = Error::empty();
+    let p = unsafe { ffi::dbus_message_demarshal(data.as_slice().as_ptr() as *const c_char, len as c_int, e.get_mut()) };
+    if p.is_null() { return Err(e) }
+    if serial == 0 { unsafe { ffi::dbus_message_set_serial(p, 0) } }
+    Ok(p)
+}
+
+/// Reads the body of a libdbus message.
+fn load(p: *mut ffi::DBusMessage) -> Body {
+    let mut wire = marshal_ffi(p);
+    let (h, start, _) = Header::parse(&mut wire).unwrap_or_else(|e| panic!("Parsing libdbus message failed: {}", e));
+    let mut b = Body { data: Buf::from_slice(&wire[start..]), sig: h.signature, ..Default::default() };
+    if h.unix_fds > 0 {
+        // The indices refer to the file descriptors that libdbus holds. We can only get those
+        // in body order, so renumber the indices accordingly.
+        unsafe {
+            let mut i = mem::zeroed();
+            ffi::dbus_message_iter_init(p, &mut i);
+            ffi_fds(&mut i, &mut b.fds);
+        }
+        let mut pos = vec!();
+        fd_positions(Cursor::new(&b), &mut pos);
+        for (idx, p) in pos.into_iter().enumerate() {
+            b.data.as_mut_slice()[p..p+4].copy_from_slice(&(idx as u32).to_ne_bytes());
+        }
+    }
+    b
+}
+
+/// Creates a libdbus message with the header of p and the body b.
+fn sync(p: *mut ffi::DBusMessage, b: &Body) -> *mut ffi::DBusMessage {
+    let mut wire = marshal_ffi(p);
+    let (mut h, _, _) = Header::parse(&mut wire).unwrap_or_else(|e| panic!("Parsing libdbus message failed: {}", e));
+    h.signature = if b.fds.is_empty() { b.sig.clone() } else { vec!() };
+    h.unix_fds = 0;
+    let r = ffi_from_parts(h, if b.fds.is_empty() { b.data.as_slice() } else { &[] });
+    let q = r.unwrap_or_else(|e| panic!("Creating libdbus message failed: {}", e));
+    if !b.fds.is_empty() {
+        // libdbus only knows about file descriptors that are appended through it
+        unsafe {
+            let mut i = mem::zeroed();
+            ffi::dbus_message_iter_init_append(q, &mut i);
+            append_ffi(Cursor::new(b), &mut i, &b.fds);
+        }
+    }
+    q
+}
+
+/// Serializes a message with the header of p and the body b.
+pub (crate) fn marshal(p: *mut ffi::DBusMessage, b: &Body) -> Vec<u8> {
+    let mut wire = marshal_ffi(p);
+    let (mut h, _, _) = Header::parse(&mut wire).unwrap_or_else(|e| panic!("Parsing libdbus message failed: {}", e));
+    h.signature = b.sig.clone();
+    h.unix_fds = b.fds.len() as u32;
+    h.write(b.data.as_slice()).as_slice().into()
+}
+
+/// Parses a message, returning a libdbus message with its header and the body.
+///
+/// fds are file descriptors received from the same stream, the ones that belong to this message are
+/// taken from the front.
+pub (crate) fn demarshal(data: &[u8], fds: &mut Vec<OwnedFd>) -> Result<(*mut ffi::DBusMessage, Body), Error> {
+    let body_len = match marshal::bytes_needed(data)? {
+        Some(total) if total == data.len() => read_u32(data, 4),
+        _ => return Err(invalid("Message length does not match its header")),
+    };
+    let body_len = if data[0] == b'l' { u32::from_le(body_len) } else { u32::from_be(body_len) } as usize;
+    let body_start = data.len() - body_len;
+    let mut head = data[..body_start].to_vec();
+    let (mut h, _, swap) = Header::parse(&mut head)?;
+    if h.serial == 0 { return Err(invalid("Message serial must not be zero")) }
+    if h.unix_fds as usize > fds.len() { return Err(invalid("Message refers to missing file descriptors")) }
+    let fds = fds.drain(..h.unix_fds as usize).collect();
+    let mut b = Body { data: Buf::from_slice(&data[body_start..]), sig: mem::take(&mut h.signature), fds, open: vec!() };
+    marshal::check_body(b.data.as_mut_slice(), &b.sig, swap, b.fds.len())?;
+    h.unix_fds = 0;
+    let p = ffi_from_parts(h, &[])?;
+    Ok((p, b))
+}
+
+/// The native part of a `Message`.
+///
+/// libdbus keeps the header, and the body once it is read or appended to. Appending makes the body
+/// of the libdbus message outdated, so it is replaced the next time libdbus needs it.
+#[derive(Default)]
+pub (crate) struct State {
+    body: UnsafeCell<Option<Box<Body>>>,
+    outdated: Cell<bool>,
+    /// Replaced libdbus messages, which header strings we handed out might still point into.
+    retired: RefCell<Vec<*mut ffi::DBusMessage>>,
+}
+
+impl State {
+    /// A body read from the wire, the libdbus message does not have it yet.
+    pub fn with_body(b: Body) -> Self {
+        State { body: UnsafeCell::new(Some(Box::new(b))), outdated: Cell::new(true), retired: Default::default() }
+    }
+
+    /// The body of the message, p is the libdbus message it is read from the first time.
+    pub fn body(&self, p: *mut ffi::DBusMessage) -> &Body {
+        // The body is only replaced when it is None, and changed through body_mut.
+        let b = unsafe { &mut *self.body.get() };
+        if b.is_none() { *b = Some(Box::new(load(p))) }
+        b.as_ref().unwrap()
+    }
+
+    /// The body, if it has been read already.
+    pub fn loaded(&self) -> Option<&Body> {
+        unsafe { (*self.body.get()).as_deref() }
+    }
+
+    /// Changes the body, for appending.
+    ///
+    /// Callers must make sure that no references from `body` are alive, e g by holding
+    /// the `Message` mutably borrowed.
+    pub fn with_body_mut<R, F: FnOnce(&mut Body) -> R>(&self, p: *mut ffi::DBusMessage, f: F) -> R {
+        self.body(p);
+        self.outdated.set(true);
+        f(unsafe { (*self.body.get()).as_mut().unwrap() })
+    }
+
+    /// Makes sure the libdbus message in msg has the current body.
+    pub fn sync(&self, msg: &Cell<*mut ffi::DBusMessage>) {
+        if !self.outdated.get() { return }
+        let q = sync(msg.get(), self.body(msg.get()));
+        self.retired.borrow_mut().push(msg.replace(q));
+        self.outdated.set(false);
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        for p in self.retired.get_mut().drain(..) { unsafe { ffi::dbus_message_unref(p) } }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ffi, Message, Path, Signature};
+    use crate::arg::{Variant, OwnedFd};
+    use std::collections::HashMap;
+    use std::os::unix::io::AsRawFd;
+
+    fn args() -> Message {
+        let mut d = HashMap::new();
+        d.insert("a".to_string(), Variant(5u8));
+        d.insert("b".to_string(), Variant(7u8));
+        let mut m = Message::new_method_call("org.test.rust", "/a/b", "org.test.rust", "Test").unwrap()
+            .append3(true, -3i16, 300u16)
+            .append3(u64::max_value(), 1.5f64, "Hello")
+            .append3(Path::new("/x/y").unwrap(), Signature::new("a{sv}").unwrap(), Variant((1u8, "x")))
+            .append3(d, vec![vec![1i64, 2], vec!()], Vec::<String>::new());
+        crate::message::message_set_serial(&mut m, 3);
+        m
+    }
+
+    fn check_args(m: &Message) {
+        let mut i = m.iter_init();
+        assert_eq!(i.read::<bool>().unwrap(), true);
+        assert_eq!(i.read::<i16>().unwrap(), -3);
+        assert_eq!(i.read::<u16>().unwrap(), 300);
+        assert_eq!(i.read::<u64>().unwrap(), u64::max_value());
+        assert_eq!(i.read::<f64>().unwrap(), 1.5);
+        assert_eq!(i.read::<&str>().unwrap(), "Hello");
+        assert_eq!(&*i.read::<Path>().unwrap(), "/x/y");
+        assert_eq!(&*i.read::<Signature>().unwrap(), "a{sv}");
+        assert_eq!(i.read::<Variant<(u8, String)>>().unwrap().0, (1, "x".into()));
+        let d: HashMap<String, Variant<u8>> = i.read().unwrap();
+        assert_eq!(d["b"].0, 7);
+        assert_eq!(i.read::<Vec<Vec<i64>>>().unwrap(), vec![vec![1, 2], vec!()]);
+        assert_eq!(i.signature(), Signature::new("as").unwrap());
+        assert_eq!(i.read::<Vec<String>>().unwrap().len(), 0);
+        assert!(!i.next());
+    }
+
+    #[test]
+    fn libdbus_compat() {
+        let m = args();
+        check_args(&m);
+        let v = m.marshal();
+        // libdbus validates the whole message when demarshalling
+        let mut e = crate::Error::empty();
+        let p = unsafe { ffi::dbus_message_demarshal(v.as_ptr() as *const _, v.len() as _, e.get_mut()) };
+        assert!(!p.is_null());
+        let m2 = Message::from_ptr(p, false);
+        check_args(&m2);
+        assert_eq!(m2.marshal(), v);
+
+        // Hand the body over to libdbus, and read it back from there
+        let m3 = Message::from_ptr(m.ptr(), true);
+        check_args(&m3);
+        assert_eq!(m3.marshal(), v);
+    }
+
+    #[test]
+    fn invalid() {
+        let m = Message::new_signal("/a", "org.test.rust", "Test").unwrap().append3(true, "Hello", vec![1u32]);
+        let mut v = m.marshal();
+        assert!(Message::demarshal(&v).is_err()); // Zero serial
+        v[8] = 1;
+        assert!(Message::demarshal(&v).is_ok());
+        let body = v.len() - 24;
+        let mut w = v.clone();
+        w[body] = 2; // Boolean that is not 0 or 1
+        assert!(Message::demarshal(&w).is_err());
+        let mut w = v.clone();
+        w[body + 8] = 0xff; // Invalid UTF-8
+        assert!(Message::demarshal(&w).is_err());
+        let mut w = v.clone();
+        w[body + 13] = 1; // Missing NUL
+        assert!(Message::demarshal(&w).is_err());
+        let mut w = v.clone();
+        w[body + 14] = 1; // Padding that is not zero
+        assert!(Message::demarshal(&w).is_err());
+        let mut w = v.clone();
+        w[body + 16] = 8; // Array length beyond the end
+        assert!(Message::demarshal(&w).is_err());
+    }
+
+    #[test]
+    fn unix_fds() {
+        let f = std::fs::File::open("/dev/null").unwrap();
+        let mut m = Message::new_signal("/a", "org.test.rust", "Test").unwrap()
+            .append3(5u8, OwnedFd::from(f.try_clone().unwrap()), vec![OwnedFd::from(f)]);
+        assert!(m.contains_unix_fds());
+        crate::message::message_set_serial(&mut m, 3);
+        // The fds are appended through libdbus, which gives them its own indices
+        let m2 = Message::from_ptr(m.ptr(), true);
+        assert!(unsafe { ffi::dbus_message_contains_unix_fds(m2.ptr()) } != 0);
+        let (a, b, c): (u8, OwnedFd, Vec<OwnedFd>) = m2.read3().unwrap();
+        assert_eq!(a, 5);
+        assert!(b.as_raw_fd() >= 0 && c[0].as_raw_fd() >= 0);
+        assert_eq!(m2.marshal(), m.marshal());
+    }
+}
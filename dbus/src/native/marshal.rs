@@ -0,0 +1,403 @@
+//! The D-Bus wire format: alignment, message headers, and checking of untrusted message bodies.
+
+use crate::Error;
+use crate::arg::MAX_TOTAL_DEPTH;
+use crate::strings::validate;
+use std::{slice, str};
+
+pub (crate) const MAX_ARRAY_LEN: usize = 64 * 1024 * 1024;
+pub (crate) const MAX_MESSAGE_LEN: usize = 128 * 1024 * 1024;
+
+const PROTOCOL_VERSION: u8 = 1;
+const FIELD_SIGNATURE: u8 = 8;
+const FIELD_UNIX_FDS: u8 = 9;
+
+pub (crate) fn invalid(s: &str) -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", s)
+}
+
+fn native_endian() -> u8 { if cfg!(target_endian = "little") { b'l' } else { b'B' } }
+
+pub (crate) fn align(pos: usize, n: usize) -> usize { (pos + n - 1) & !(n - 1) }
+
+/// Alignment of a type on the wire.
+pub (crate) fn alignment(c: u8) -> usize {
+    match c {
+        b'n' | b'q' => 2,
+        b'b' | b'i' | b'u' | b's' | b'o' | b'a' | b'h' => 4,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 1,
+    }
+}
+
+/// Size of a fixed size type on the wire, or None for strings and containers.
+pub (crate) fn fixed_size(c: u8) -> Option<usize> {
+    match c {
+        b'y' => Some(1),
+        b'n' | b'q' => Some(2),
+        b'b' | b'i' | b'u' | b'h' => Some(4),
+        b'x' | b't' | b'd' => Some(8),
+        _ => None,
+    }
+}
+
+/// Length of the single complete type that sig starts with, or None if it does not start with one.
+pub (crate) fn single_len(sig: &[u8]) -> Option<usize> {
+    match *sig.first()? {
+        b'a' => single_len(&sig[1..]).map(|l| l + 1),
+        c @ b'(' | c @ b'{' => {
+            let close = if c == b'(' { b')' } else { b'}' };
+            let mut pos = 1;
+            while *sig.get(pos)? != close { pos += single_len(&sig[pos..])?; }
+            if pos == 1 { None } else { Some(pos + 1) }
+        }
+        b'v' | b's' | b'o' | b'g' => Some(1),
+        c => fixed_size(c).map(|_| 1),
+    }
+}
+
+/// Checks that sig is a valid signature of zero or more complete types.
+pub (crate) fn check_signature(sig: &[u8]) -> Result<(), Error> {
+    if sig.len() > crate::arg::MAX_SIGNATURE_LEN { return Err(invalid("Signature is too long")) }
+    let mut pos = 0;
+    while pos < sig.len() {
+        let l = single_len(&sig[pos..]).unwrap_or(0);
+        if l == 0 || validate::check_signature(&sig[pos..pos+l]).is_err() {
+            return Err(invalid(&format!("Invalid signature '{}'", String::from_utf8_lossy(sig))))
+        }
+        pos += l;
+    }
+    Ok(())
+}
+
+pub (crate) fn read_u32(data: &[u8], pos: usize) -> u32 {
+    let mut b = [0; 4];
+    b.copy_from_slice(&data[pos..pos+4]);
+    u32::from_ne_bytes(b)
+}
+
+/// A byte buffer which is 8 byte aligned in memory, so that arrays of fixed size types
+/// can be borrowed directly from it.
+#[derive(Clone, Default)]
+pub (crate) struct Buf {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Buf {
+    pub fn from_slice(b: &[u8]) -> Buf {
+        let mut r = Buf::default();
+        r.extend_from_slice(b);
+        r
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+
+    /// Grows or shrinks the buffer. New bytes are zero.
+    pub fn resize(&mut self, len: usize) {
+        let old = self.len;
+        self.words.resize(len.div_ceil(8), 0);
+        self.len = len;
+        if len > old { for b in &mut self.as_mut_slice()[old..] { *b = 0 } }
+    }
+
+    /// Pads with zeroes until the length is a multiple of n.
+    pub fn align(&mut self, n: usize) {
+        let l = align(self.len, n);
+        self.resize(l);
+    }
+
+    pub fn extend_from_slice(&mut self, b: &[u8]) {
+        let old = self.len;
+        self.resize(old + b.len());
+        self.as_mut_slice()[old..].copy_from_slice(b);
+    }
+
+    pub fn push_str(&mut self, s: &[u8], short_len: bool) {
+        if short_len {
+            self.extend_from_slice(&[s.len() as u8]);
+        } else {
+            self.align(4);
+            self.extend_from_slice(&(s.len() as u32).to_ne_bytes());
+        }
+        self.extend_from_slice(s);
+        self.extend_from_slice(&[0]);
+    }
+}
+
+/// The parts of a message header that the native marshaller needs to know about.
+///
+/// All other header fields are kept in marshalled form, in native byte order.
+#[derive(Debug)]
+pub (crate) struct Header {
+    pub msg_type: u8,
+    pub flags: u8,
+    pub serial: u32,
+    pub signature: Vec<u8>,
+    pub unix_fds: u32,
+    /// Each field is a (byte, variant) struct, starting at an 8 byte boundary.
+    fields: Vec<u8>,
+}
+
+/// Returns the total length of the message that starts with data, or None if more
+/// data is needed to find out.
+pub (crate) fn bytes_needed(data: &[u8]) -> Result<Option<usize>, Error> {
+    if data.len() < 16 { return Ok(None) }
+    let swap = match data[0] {
+        b'l' | b'B' => data[0] != native_endian(),
+        _ => return Err(invalid("Invalid byte order in message header")),
+    };
+    let u = |pos| { let x = read_u32(data, pos); if swap { x.swap_bytes() } else { x } };
+    let (body_len, fields_len) = (u(4) as usize, u(12) as usize);
+    if body_len > MAX_MESSAGE_LEN || fields_len > MAX_ARRAY_LEN { return Err(invalid("Message is too long")) }
+    let total = align(16 + fields_len, 8) + body_len;
+    if total > MAX_MESSAGE_LEN { return Err(invalid("Message is too long")) }
+    Ok(Some(total))
+}
+
+impl Header {
+    /// Parses the header at the start of data, converting it to native byte order.
+    /// The body, if any, is left alone.
+    ///
+    /// Returns the header, where the body starts, and whether the body needs to be byteswapped.
+    /// Apart from the signature and the number of unix fds, fields are not checked here:
+    /// libdbus checks them when the header is handed over.
+    pub fn parse(data: &mut [u8]) -> Result<(Header, usize, bool), Error> {
+        bytes_needed(data)?.ok_or_else(|| invalid("Message header is incomplete"))?;
+        let swap = data[0] != native_endian();
+        if !(1..=4).contains(&data[1]) { return Err(invalid("Invalid message type")) }
+        if data[3] != PROTOCOL_VERSION { return Err(invalid("Unsupported protocol version")) }
+        let mut c = Checker { data, swap, n_fds: 0 };
+        let fields_len = c.u32(12)? as usize;
+        c.u32(4)?;
+        let serial = c.u32(8)?;
+        let mut h = Header { msg_type: c.data[1], flags: c.data[2], serial, signature: vec!(), unix_fds: 0, fields: vec!() };
+        let end = 16 + fields_len;
+        let mut pos = 16;
+        while pos < end {
+            let start = c.pad(pos, 8)?;
+            let code = c.data[c.take(start, 1)? - 1];
+            let field_end = c.value(start + 1, b"v", 0)?;
+            if field_end > end { return Err(invalid("Header field is too long")) }
+            let sig = if c.data[start+1] == 1 { c.data[start+2] } else { 0 };
+            match code {
+                FIELD_SIGNATURE if sig == b'g' => {
+                    let l = c.data[start+4] as usize;
+                    h.signature = c.data[start+5..start+5+l].into();
+                },
+                FIELD_UNIX_FDS if sig == b'u' => h.unix_fds = read_u32(c.data, align(start + 4, 4)),
+                FIELD_SIGNATURE | FIELD_UNIX_FDS => return Err(invalid("Header field has the wrong type")),
+                _ => {
+                    h.fields.resize(align(h.fields.len(), 8), 0);
+                    h.fields.extend_from_slice(&c.data[start..field_end]);
+                }
+            }
+            pos = field_end;
+        }
+        if pos != end { return Err(invalid("Header field array length mismatch")) }
+        let body_start = c.pad(end, 8)?;
+        Ok((h, body_start, swap))
+    }
+
+    /// Returns the message, with the header in native byte order, followed by the body.
+    pub fn write(&self, body: &[u8]) -> Buf {
+        let mut out = Buf::default();
+        out.extend_from_slice(&[native_endian(), self.msg_type, self.flags, PROTOCOL_VERSION]);
+        out.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+        out.extend_from_slice(&self.serial.to_ne_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&self.fields);
+        if !self.signature.is_empty() {
+            out.align(8);
+            out.extend_from_slice(&[FIELD_SIGNATURE, 1, b'g', 0]);
+            out.push_str(&self.signature, true);
+        }
+        if self.unix_fds > 0 {
+            out.align(8);
+            out.extend_from_slice(&[FIELD_UNIX_FDS, 1, b'u', 0]);
+            out.extend_from_slice(&self.unix_fds.to_ne_bytes());
+        }
+        let fields_len = (out.len() - 16) as u32;
+        out.as_mut_slice()[12..16].copy_from_slice(&fields_len.to_ne_bytes());
+        out.align(8);
+        out.extend_from_slice(body);
+        out
+    }
+}
+
+/// Checks a message body that comes from an untrusted source, converting it to native byte order.
+pub (crate) fn check_body(data: &mut [u8], sig: &[u8], swap: bool, n_fds: usize) -> Result<(), Error> {
+    check_signature(sig)?;
+    let mut c = Checker { data, swap, n_fds };
+    let (mut pos, mut spos) = (0, 0);
+    while spos < sig.len() {
+        let l = single_len(&sig[spos..]).unwrap();
+        pos = c.value(pos, &sig[spos..spos+l], 0)?;
+        spos += l;
+    }
+    if pos != c.data.len() { return Err(invalid("Message body is longer than its signature")) }
+    Ok(())
+}
+
+struct Checker<'a> {
+    data: &'a mut [u8],
+    swap: bool,
+    n_fds: usize,
+}
+
+impl Checker<'_> {
+    fn take(&self, pos: usize, n: usize) -> Result<usize, Error> {
+        if self.data.len() < n || pos > self.data.len() - n { return Err(invalid("Unexpected end of message")) }
+        Ok(pos + n)
+    }
+
+    /// Skips padding, which must be zero.
+    fn pad(&self, pos: usize, n: usize) -> Result<usize, Error> {
+        let p = align(pos, n);
+        self.take(pos, p - pos)?;
+        if self.data[pos..p].iter().any(|&b| b != 0) { return Err(invalid("Non-zero padding")) }
+        Ok(p)
+    }
+
+    fn u32(&mut self, pos: usize) -> Result<u32, Error> {
+        self.take(pos, 4)?;
+        if self.swap { self.data[pos..pos+4].reverse() }
+        Ok(read_u32(self.data, pos))
+    }
+
+    fn fixed(&mut self, pos: usize, c: u8, size: usize) -> Result<(), Error> {
+        if self.swap { self.data[pos..pos+size].reverse() }
+        let ok = match c {
+            b'b' => read_u32(self.data, pos) <= 1,
+            b'h' => (read_u32(self.data, pos) as usize) < self.n_fds,
+            _ => true,
+        };
+        if ok { Ok(()) } else { Err(invalid(&format!("Invalid value of type '{}'", c as char))) }
+    }
+
+    fn string(&self, start: usize, len: usize) -> Result<usize, Error> {
+        let end = self.take(start, len + 1)?;
+        let s = &self.data[start..start+len];
+        if self.data[start+len] != 0 || s.contains(&0) { return Err(invalid("String is not null terminated")) }
+        str::from_utf8(s).map_err(|_| invalid("String is not valid UTF-8"))?;
+        Ok(end)
+    }
+
+    /// Checks a value of the single complete type sig, and returns the position after it.
+    fn value(&mut self, pos: usize, sig: &[u8], depth: usize) -> Result<usize, Error> {
+        if depth > MAX_TOTAL_DEPTH { return Err(invalid("Message is nested too deeply")) }
+        let c = sig[0];
+        if let Some(size) = fixed_size(c) {
+            let p = self.pad(pos, size)?;
+            let end = self.take(p, size)?;
+            self.fixed(p, c, size)?;
+            return Ok(end);
+        }
+        match c {
+            b's' | b'o' => {
+                let p = self.pad(pos, 4)?;
+                let len = self.u32(p)? as usize;
+                let end = self.string(p + 4, len)?;
+                if c == b'o' && validate::check_path(&self.data[p+4..p+4+len]).is_err() {
+                    return Err(invalid("Invalid object path"))
+                }
+                Ok(end)
+            }
+            b'g' => {
+                let len = self.data[self.take(pos, 1)? - 1] as usize;
+                let end = self.string(pos + 1, len)?;
+                check_signature(&self.data[pos+1..pos+1+len])?;
+                Ok(end)
+            }
+            b'v' => {
+                let end = self.value(pos, b"g", depth)?;
+                let l = self.data[pos] as usize;
+                let mut inner = [0u8; 256];
+                inner[..l].copy_from_slice(&self.data[pos+1..pos+1+l]);
+                if l == 0 || single_len(&inner[..l]) != Some(l) {
+                    return Err(invalid("Variant signature is not a single complete type"))
+                }
+                self.value(end, &inner[..l], depth + 1)
+            }
+            b'(' | b'{' => {
+                let mut p = self.pad(pos, 8)?;
+                let inner = &sig[1..sig.len()-1];
+                let mut spos = 0;
+                while spos < inner.len() {
+                    let l = single_len(&inner[spos..]).unwrap();
+                    p = self.value(p, &inner[spos..spos+l], depth + 1)?;
+                    spos += l;
+                }
+                Ok(p)
+            }
+            b'a' => {
+                let p = self.pad(pos, 4)?;
+                let len = self.u32(p)? as usize;
+                if len > MAX_ARRAY_LEN { return Err(invalid("Array is too long")) }
+                let esig = &sig[1..];
+                let start = self.pad(p + 4, alignment(esig[0]))?;
+                let end = self.take(start, len)?;
+                if let Some(size) = fixed_size(esig[0]) {
+                    if !len.is_multiple_of(size) { return Err(invalid("Array length mismatch")) }
+                    for q in (start..end).step_by(size) { self.fixed(q, esig[0], size)?; }
+                    return Ok(end);
+                }
+                let mut q = start;
+                while q < end { q = self.value(q, esig, depth + 1)?; }
+                if q != end { return Err(invalid("Array length mismatch")) }
+                Ok(end)
+            }
+            _ => Err(invalid(&format!("Invalid signature '{}'", String::from_utf8_lossy(sig)))),
+        }
+    }
+}
+
+/// Returns the position after the value of type sig at pos, in a body already known to be valid.
+pub (crate) fn skip(data: &[u8], pos: usize, sig: &[u8]) -> usize {
+    let c = sig[0];
+    if let Some(size) = fixed_size(c) { return align(pos, size) + size }
+    match c {
+        b's' | b'o' => { let p = align(pos, 4); p + 4 + read_u32(data, p) as usize + 1 },
+        b'g' => pos + data[pos] as usize + 2,
+        b'v' => {
+            let l = data[pos] as usize;
+            skip(data, pos + l + 2, &data[pos+1..pos+1+l])
+        }
+        b'(' | b'{' => {
+            let mut p = align(pos, 8);
+            let inner = &sig[1..sig.len()-1];
+            let mut spos = 0;
+            while spos < inner.len() {
+                let l = single_len(&inner[spos..]).unwrap();
+                p = skip(data, p, &inner[spos..spos+l]);
+                spos += l;
+            }
+            p
+        }
+        b'a' => {
+            let p = align(pos, 4);
+            align(p + 4, alignment(sig[1])) + read_u32(data, p) as usize
+        }
+        _ => panic!("Invalid signature '{}'", String::from_utf8_lossy(sig)),
+    }
+}
+
+#[test]
+fn signatures() {
+    assert_eq!(single_len(b"a{sv}ia(ii)v"), Some(5));
+    assert_eq!(single_len(b"(ii"), None);
+    assert_eq!(single_len(b"()"), None);
+    assert!(check_signature(b"a{sv}ia(ii)v").is_ok());
+    assert!(check_signature(b"").is_ok());
+    assert!(check_signature(b"a{vs}").is_err());
+    assert!(check_signature(b"{ss}").is_err());
+    assert!(check_signature(b"a").is_err());
+}
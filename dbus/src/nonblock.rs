@@ -9,15 +9,30 @@ use crate::{Error, Message};
 use crate::channel::{MatchingReceiver, Channel, Sender};
 use crate::strings::{BusName, Path, Interface, Member};
 use crate::arg::{AppendAll, ReadAll, IterAppend};
-use crate::message::MatchRule;
+use crate::message::{MatchRule, SignalArgs};
+use crate::reconnect::private::Sealed;
 
 use std::sync::{Arc, Mutex};
 use std::{future, task, pin, mem};
 use std::collections::{HashMap, BTreeMap};
 use std::cell::{Cell, RefCell};
+use std::time::Duration;
 
 pub mod stdintf;
 
+mod propcache;
+pub use self::propcache::PropertyCache;
+
+mod objmanager;
+pub use self::objmanager::ObjectManagerClient;
+pub use crate::objmanager::ObjectEvent;
+
+mod namewatch;
+pub use self::namewatch::NameWatch;
+pub use crate::namewatch::NameEvent;
+
+pub use crate::reconnect::{Reconnecting, Reconnectable, ReconnectEvent};
+
 /// Thread local + async Connection 
 pub struct LocalConnection {
     channel: Channel,
@@ -81,6 +96,10 @@ pub trait NonblockReply {
     fn cancel_reply(&self, id: u32) -> Option<Self::F>;
     /// Internal helper function that creates a callback.
     fn make_f<G: FnOnce(Message, &Self) + Send + 'static>(g: G) -> Self::F where Self: Sized;
+    /// Checks whether a message can be sent, before sending it.
+    ///
+    /// The default implementation accepts all messages.
+    fn check_send(&self, _msg: &Message) -> Result<(), Error> { Ok(()) }
 }
 
 impl NonblockReply for LocalConnection {
@@ -93,6 +112,7 @@ impl NonblockReply for LocalConnection {
     }
     fn cancel_reply(&self, id: u32) -> Option<Self::F> { self.replies.borrow_mut().remove(&id) }
     fn make_f<G: FnOnce(Message, &Self) + Send + 'static>(g: G) -> Self::F { Box::new(g) }
+    fn check_send(&self, msg: &Message) -> Result<(), Error> { self.channel.check_unix_fds(msg) }
 }
 
 impl MatchingReceiver for LocalConnection {
@@ -118,6 +138,7 @@ impl NonblockReply for SyncConnection {
     }
     fn cancel_reply(&self, id: u32) -> Option<Self::F> { self.replies.lock().unwrap().remove(&id) }
     fn make_f<G: FnOnce(Message, &Self) + Send + 'static>(g: G) -> Self::F { Box::new(g) }
+    fn check_send(&self, msg: &Message) -> Result<(), Error> { self.channel.check_unix_fds(msg) }
 }
 
 impl MatchingReceiver for SyncConnection {
@@ -202,6 +223,54 @@ impl Process for SyncConnection {
     }
 }
 
+/// The error reply given to method calls that were pending when the connection was replaced.
+fn disconnected_reply() -> Message {
+    let mut m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "Hello").unwrap();
+    // An error reply needs a serial to refer to
+    crate::message::message_set_serial(&mut m, 1);
+    m.error(&"org.freedesktop.DBus.Error.Disconnected".into(), &crate::to_c_str("Connection was lost before the reply arrived"))
+}
+
+/// Reads from the channel, waiting up to timeout, and dispatches everything that was read.
+fn process_msg<C: Process>(c: &C, timeout: Duration) -> Result<bool, Error> {
+    let ch: &Channel = c.as_ref();
+    ch.read_write(Some(timeout)).map_err(|_| Error::new_failed("Failed to read/write data, disconnected from D-Bus?"))?;
+    let mut handled = false;
+    while let Some(msg) = ch.pop_message() {
+        c.process_one(msg);
+        handled = true;
+    }
+    Ok(handled)
+}
+
+impl Sealed for LocalConnection {
+    fn channel(&self) -> &Channel { &self.channel }
+    fn replace_channel(&mut self, c: Channel) {
+        self.channel = c;
+        // Serials start over on the new connection, so pending replies would be mixed up
+        let replies: Vec<_> = self.replies.borrow_mut().drain().map(|x| x.1).collect();
+        for f in replies { f(disconnected_reply(), self) }
+    }
+    fn match_strs(&self) -> Vec<String> { self.filters.borrow().values().map(|f| f.0.match_str()).collect() }
+    fn process_msg(&mut self, timeout: Duration) -> Result<bool, Error> { process_msg(self, timeout) }
+}
+
+impl Reconnectable for LocalConnection {}
+
+impl Sealed for SyncConnection {
+    fn channel(&self) -> &Channel { &self.channel }
+    fn replace_channel(&mut self, c: Channel) {
+        self.channel = c;
+        // Serials start over on the new connection, so pending replies would be mixed up
+        let replies: Vec<_> = self.replies.lock().unwrap().drain().map(|x| x.1).collect();
+        for f in replies { f(disconnected_reply(), self) }
+    }
+    fn match_strs(&self) -> Vec<String> { self.filters.lock().unwrap().1.values().map(|f| f.0.match_str()).collect() }
+    fn process_msg(&mut self, timeout: Duration) -> Result<bool, Error> { process_msg(self, timeout) }
+}
+
+impl Reconnectable for SyncConnection {}
+
 /// A struct that wraps a connection, destination and path.
 ///
 /// A D-Bus "Proxy" is a client-side object that corresponds to a remote object on the server side. 
@@ -231,6 +300,9 @@ where
 {
 
     /// Make a method call using typed input argument, returns a future that resolves to the typed output arguments.
+    ///
+    /// The future resolves to an `org.freedesktop.DBus.Error.NotSupported` error if the arguments contain
+    /// file descriptors and the connection does not support passing them.
     pub fn method_call<'i, 'm, R: ReadAll + 'static, A: AppendAll, I: Into<Interface<'i>>, M: Into<Member<'m>>>(&self, i: I, m: M, args: A)
     -> MethodReply<R> {
         let mut msg = Message::method_call(&self.destination, &self.path, &i.into(), &m.into());
@@ -243,13 +315,93 @@ where
             let old = mem::replace(&mut *inner, MRInner::Ready(Ok(msg)));
             if let MRInner::Pending(waker) = old { waker.wake() }
         });
-        if let Err(_) = self.connection.send_with_reply(msg, f) {
-            *mr.lock().unwrap() = MRInner::Ready(Err(Error::new_failed("Failed to send message")));
-        }
+        let r = self.connection.check_send(&msg).and_then(|_| {
+            self.connection.send_with_reply(msg, f).map_err(|_| Error::new_failed("Failed to send message"))
+        });
+        if let Err(e) = r { *mr.lock().unwrap() = MRInner::Ready(Err(e)) }
         MethodReply(mr, Some(Box::new(|msg: Message| { msg.read_all() })))
     }
 }
 
+impl<'a, T, C> Proxy<'a, C>
+where
+    T: NonblockReply + MatchingReceiver,
+    C: std::ops::Deref<Target=T>
+{
+    /// Starts matching incoming messages on this destination and path.
+    ///
+    /// For matching signals, match_signal might be more convenient.
+    ///
+    /// The match rule will be modified to include this path and destination only.
+    /// The D-Bus server is notified that matching should start.
+    pub async fn match_start(&self, mut mr: MatchRule<'static>, f: <T as MatchingReceiver>::F) -> Result<u32, Error> {
+        mr.path = Some(self.path.clone().into_static());
+        mr.sender = Some(self.destination.clone().into_static());
+        let mstr = mr.match_str();
+        let id = self.connection.start_receive(mr, f);
+        let r: Result<(), Error> = dbus_proxy(&*self.connection).method_call("org.freedesktop.DBus", "AddMatch", (mstr,)).await;
+        if r.is_err() { self.connection.stop_receive(id); }
+        r.map(|_| id)
+    }
+
+    /// Stops matching a signal added with match_start or match_signal, and notifies the D-Bus server.
+    pub async fn match_stop(&self, id: u32) -> Result<(), Error> {
+        if let Some((mr, _)) = self.connection.stop_receive(id) {
+            let r: Result<(), Error> = dbus_proxy(&*self.connection).method_call("org.freedesktop.DBus", "RemoveMatch", (mr.match_str(),)).await;
+            r?;
+        }
+        Ok(())
+    }
+
+    /// Sets up an incoming signal match, that calls the supplied callback every time the signal is received.
+    ///
+    /// The returned value can be used to remove the match. The match is also removed if the callback
+    /// returns "false".
+    pub async fn match_signal<S: SignalArgs + ReadAll, F: MakeSignal<<T as MatchingReceiver>::F, S, T>>(&self, f: F) -> Result<u32, Error> {
+        let mr = S::match_rule(Some(&self.destination), Some(&self.path)).static_clone();
+        let ff = f.make(mr.match_str());
+        self.match_start(mr, ff).await
+    }
+}
+
+fn dbus_proxy<T>(c: &T) -> Proxy<'_, &T> { Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", c) }
+
+// Used when a signal callback returns false. Nobody waits for the reply.
+fn remove_match<T: Sender + ?Sized>(c: &T, mstr: &str) {
+    let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "RemoveMatch").unwrap();
+    let _ = c.send(m.append1(mstr));
+}
+
+/// Internal helper trait
+pub trait MakeSignal<G, S, T> {
+    /// Internal helper trait
+    fn make(self, mstr: String) -> G;
+}
+
+impl<S: ReadAll, F: FnMut(S, &LocalConnection) -> bool + 'static> MakeSignal<<LocalConnection as MatchingReceiver>::F, S, LocalConnection> for F {
+    fn make(mut self, mstr: String) -> <LocalConnection as MatchingReceiver>::F {
+        Box::new(move |msg: Message, conn: &LocalConnection| {
+            if let Ok(s) = S::read(&mut msg.iter_init()) {
+                if self(s, conn) { return true };
+                remove_match(conn, &mstr);
+                false
+            } else { true }
+        })
+    }
+}
+
+impl<S: ReadAll, F: FnMut(S, &SyncConnection) -> bool + Send + 'static> MakeSignal<<SyncConnection as MatchingReceiver>::F, S, SyncConnection> for F {
+    fn make(mut self, mstr: String) -> <SyncConnection as MatchingReceiver>::F {
+        Box::new(move |msg: Message, conn: &SyncConnection| {
+            if let Ok(s) = S::read(&mut msg.iter_init()) {
+                if self(s, conn) { return true };
+                remove_match(conn, &mstr);
+                false
+            } else { true }
+        })
+    }
+}
+
 enum MRInner {
     Ready(Result<Message, Error>),
     Pending(task::Waker),
@@ -288,6 +440,31 @@ impl<T: 'static> MethodReply<T> {
 }
 
 
+/// A waker that does nothing, for polling futures in tests.
+#[cfg(test)]
+pub (crate) fn noop_waker() -> task::Waker {
+    fn noop_raw() -> task::RawWaker {
+        const VTABLE: task::RawWakerVTable = task::RawWakerVTable::new(|_| noop_raw(), |_| {}, |_| {}, |_| {});
+        task::RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    unsafe { task::Waker::from_raw(noop_raw()) }
+}
+
+/// Runs a future to completion, processing the connection while waiting, for tests.
+#[cfg(test)]
+pub (crate) fn block_on<C: Process, F: future::Future>(c: &C, f: F) -> F::Output {
+    let waker = noop_waker();
+    let mut f = Box::pin(f);
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        if let task::Poll::Ready(r) = f.as_mut().poll(&mut task::Context::from_waker(&waker)) { return r }
+        assert!(std::time::Instant::now() < deadline, "Future did not complete in time");
+        let ch: &Channel = c.as_ref();
+        ch.read_write(Some(Duration::from_millis(10))).unwrap();
+        c.process_all();
+    }
+}
+
 #[test]
 fn test_conn_send_sync() {
     fn is_send<T: Send>(_: &T) {}
@@ -297,3 +474,58 @@ fn test_conn_send_sync() {
     is_sync(&c);
 }
 
+#[test]
+fn test_match_signal() {
+    use crate::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as PPC;
+    use crate::testbus::TestBus;
+
+    let bus = TestBus::new().unwrap();
+    let server = bus.connect().unwrap();
+    let path = Path::new("/test").unwrap();
+    let emit = |n: &str| {
+        let s = PPC { interface_name: n.into(), changed_properties: Default::default(), invalidated_properties: vec!() };
+        server.send(s.to_emit_message(&path)).unwrap();
+        server.flush();
+    };
+
+    let conn = SyncConnection::from(bus.connect().unwrap());
+    let process_until = |f: &dyn Fn() -> bool| {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !f() {
+            assert!(std::time::Instant::now() < deadline, "Signal was not received in time");
+            let ch: &Channel = conn.as_ref();
+            ch.read_write(Some(Duration::from_millis(50))).unwrap();
+            conn.process_all();
+        }
+    };
+    let proxy = Proxy::new(server.unique_name().unwrap().to_string(), "/test", &conn);
+    let names = Arc::new(Mutex::new(vec!()));
+    let names2 = names.clone();
+    let id = block_on(&conn, proxy.match_signal(move |s: PPC, _: &SyncConnection| {
+        names2.lock().unwrap().push(s.interface_name);
+        true
+    })).unwrap();
+    emit("com.example.A");
+    process_until(&|| !names.lock().unwrap().is_empty());
+    block_on(&conn, proxy.match_stop(id)).unwrap();
+
+    // Matches on the raw message, the rule is restricted to the proxy's destination and path.
+    let raw = Arc::new(Mutex::new(vec!()));
+    let raw2 = raw.clone();
+    let id = block_on(&conn, proxy.match_start(PPC::match_rule(None, None).static_clone(), Box::new(move |m, _| {
+        raw2.lock().unwrap().push(m.read1::<&str>().unwrap().to_string());
+        true
+    }))).unwrap();
+    emit("com.example.B");
+    process_until(&|| !raw.lock().unwrap().is_empty());
+    block_on(&conn, proxy.match_stop(id)).unwrap();
+
+    emit("com.example.C");
+    for _ in 0..5 {
+        let ch: &Channel = conn.as_ref();
+        ch.read_write(Some(Duration::from_millis(50))).unwrap();
+        conn.process_all();
+    }
+    assert_eq!(*names.lock().unwrap(), vec!("com.example.A".to_string()));
+    assert_eq!(*raw.lock().unwrap(), vec!("com.example.B".to_string()));
+}
@@ -0,0 +1,137 @@
+use crate::Error;
+use crate::channel::{MatchingReceiver, Sender};
+use crate::strings::BusName;
+use crate::util::Serial;
+use crate::namewatch::{self, Watch, OwnerChanged, NameEvent};
+use super::{NonblockReply, MakeSignal, Reconnectable, dbus_proxy, remove_match};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::ops::Deref;
+use std::task::{Context, Poll, Waker};
+
+type ChangedFn<T> = Box<dyn FnMut(OwnerChanged, &T) -> bool + Send + Sync>;
+
+#[derive(Default)]
+struct Queue {
+    watch: Watch,
+    events: VecDeque<NameEvent>,
+    waker: Option<Waker>,
+}
+
+impl Queue {
+    fn push(&mut self, events: Vec<NameEvent>) {
+        if events.is_empty() { return }
+        self.events.extend(events);
+        if let Some(w) = self.waker.take() { w.wake() }
+    }
+}
+
+/// Watches a bus name, like `g_bus_watch_name` does, as a stream of owner changes.
+///
+/// The first event is the state of the name when the watch was created. After that, there is an
+/// `Appeared` event with the unique name of the owner every time the name gets an owner, and a `Vanished` event
+/// every time it loses its owner. A name that goes directly to a new owner is reported as vanished and then appeared again.
+///
+/// Signals are dispatched by the connection's `process_all`, which is usually called by the reactor
+/// (e g dbus-tokio). Since a signal is only dispatched to the first matching callback, creating a watch fails
+/// if the name is already watched on the connection.
+///
+/// The signal match is removed when the watch is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::nonblock::{SyncConnection, NameWatch, NameEvent};
+/// use std::sync::Arc;
+///
+/// async fn follow_service(conn: Arc<SyncConnection>) -> Result<(), dbus::Error> {
+///     let mut watch = NameWatch::new(conn, "org.freedesktop.Notifications").await?;
+///     loop {
+///         match watch.next().await {
+///             NameEvent::Appeared(owner) => println!("Started as {}, subscribing again", owner),
+///             NameEvent::Vanished => println!("Stopped"),
+///         }
+///     }
+/// }
+/// ```
+pub struct NameWatch<C> where C: Deref, C::Target: MatchingReceiver + Sender {
+    connection: C,
+    name: BusName<'static>,
+    id: u32,
+    mstr: String,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl<T, C> NameWatch<C>
+where
+    T: NonblockReply + MatchingReceiver + Sender + Reconnectable,
+    C: Deref<Target=T>,
+    ChangedFn<T>: MakeSignal<<T as MatchingReceiver>::F, OwnerChanged, T>,
+{
+    /// Starts watching a name.
+    ///
+    /// This starts matching the `NameOwnerChanged` signal, and then asks the bus for the current owner.
+    pub async fn new<N: Into<BusName<'static>>>(connection: C, name: N) -> Result<Self, Error> {
+        let name = name.into();
+        let queue: Arc<Mutex<Queue>> = Default::default();
+        let q = queue.clone();
+        let f: ChangedFn<T> = Box::new(move |c: OwnerChanged, _: &T| {
+            let mut q = q.lock().unwrap();
+            let events = q.watch.changed(c);
+            q.push(events);
+            true
+        });
+        let mr = namewatch::match_rule(&name);
+        let mstr = mr.match_str();
+        namewatch::check_not_watched(&connection.match_strs(), &mstr, &name)?;
+        let proxy = dbus_proxy(&*connection);
+        let id = proxy.match_start(mr, f.make(mstr.clone())).await?;
+        let r: Result<Serial<(String,)>, Error> = proxy.method_call("org.freedesktop.DBus", "GetNameOwner", (&*name,)).await;
+        let (serial, owner) = match r {
+            Ok(Serial(serial, (owner,))) => (serial, Some(owner)),
+            // The serial of an error reply is not available, so no signals are discarded as older than it.
+            Err(ref e) if e.name() == Some(namewatch::NAME_HAS_NO_OWNER) => (0, None),
+            Err(e) => { let _ = proxy.match_stop(id).await; return Err(e) },
+        };
+        {
+            let mut q = queue.lock().unwrap();
+            let events = q.watch.update(serial, owner);
+            q.push(events);
+        }
+        Ok(NameWatch { connection, name, id, mstr, queue })
+    }
+}
+
+impl<C> NameWatch<C> where C: Deref, C::Target: MatchingReceiver + Sender {
+    /// Returns the next owner change, if there is one, or registers the current task for wakeup.
+    ///
+    /// This has the same signature as `Stream::poll_next` (except that the stream never ends),
+    /// so it is easy to wrap the watch in a stream.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<NameEvent> {
+        let mut q = self.queue.lock().unwrap();
+        if let Some(e) = q.events.pop_front() { return Poll::Ready(e) }
+        q.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Waits for the next owner change.
+    pub async fn next(&mut self) -> NameEvent {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    /// The unique name of the current owner, or None if the name has no owner.
+    ///
+    /// This includes changes that are not yet returned from `next`.
+    pub fn owner(&self) -> Option<String> { self.queue.lock().unwrap().watch.owner().map(|s| s.to_string()) }
+
+    /// The name being watched.
+    pub fn name(&self) -> &BusName<'static> { &self.name }
+}
+
+impl<C> Drop for NameWatch<C> where C: Deref, C::Target: MatchingReceiver + Sender {
+    fn drop(&mut self) {
+        if self.connection.stop_receive(self.id).is_some() {
+            remove_match(&*self.connection, &self.mstr);
+        }
+    }
+}
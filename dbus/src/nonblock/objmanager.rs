@@ -0,0 +1,127 @@
+use crate::Error;
+use crate::channel::{MatchingReceiver, Sender};
+use crate::message::SignalArgs;
+use crate::strings::Path;
+use crate::util::Serial;
+use crate::objmanager::{self, Added, Removed, Interfaces, OBJECT_MANAGER};
+use super::{Proxy, NonblockReply, MakeSignal, remove_match};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::ops::Deref;
+
+type AddedFn<T> = Box<dyn FnMut(Added, &T) -> bool + Send + Sync>;
+type RemovedFn<T> = Box<dyn FnMut(Removed, &T) -> bool + Send + Sync>;
+
+/// Keeps track of the objects below a remote object manager, for non-blocking connections.
+///
+/// Signals are dispatched by the connection's `process_all`, which is usually called by the reactor
+/// (e g dbus-tokio), so the client stays up to date as long as the connection is running.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::nonblock::{Proxy, SyncConnection, ObjectManagerClient, ObjectEvent};
+/// use std::sync::Arc;
+///
+/// async fn bluez_devices(conn: Arc<SyncConnection>) -> Result<Vec<dbus::Path<'static>>, dbus::Error> {
+///     let proxy = Proxy::new("org.bluez", "/", conn);
+///     let bluez = ObjectManagerClient::new(proxy).await?;
+///     bluez.on_event(|e| if let ObjectEvent::InterfacesAdded { path, new_object: true, .. } = e {
+///         println!("New object: {}", path);
+///     });
+///     Ok(bluez.with_interface("org.bluez.Device1"))
+/// }
+/// ```
+pub type ObjectManagerClient<'a, C> = objmanager::ObjectManagerClient<Proxy<'a, C>>;
+
+impl<'a, T, C> ObjectManagerClient<'a, C>
+where
+    T: NonblockReply + MatchingReceiver + Sender,
+    C: Deref<Target=T>,
+    AddedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Added, T>,
+    RemovedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Removed, T>,
+{
+    /// Creates a client for the object manager at the proxy's destination and path.
+    ///
+    /// This starts matching the `InterfacesAdded` and `InterfacesRemoved` signals, and then fetches all objects.
+    pub async fn new(proxy: Proxy<'a, C>) -> Result<Self, Error> {
+        // Nobody waits for the reply to RemoveMatch when the client is dropped.
+        let stop = |p: &Proxy<'a, C>, id| if let Some((mr, _)) = p.connection.stop_receive(id) {
+            remove_match(&*p.connection, &mr.match_str())
+        };
+        let mut r = ObjectManagerClient { proxy, objects: Default::default(), ids: vec!(), stop };
+
+        let weak = Arc::downgrade(&r.objects);
+        let f: AddedFn<T> = Box::new(move |a: Added, _: &T| objmanager::handle_added(&weak, a));
+        let mr = Added::match_rule(Some(&r.proxy.destination), Some(&r.proxy.path)).static_clone();
+        let mstr = mr.match_str();
+        let id = r.proxy.match_start(mr, f.make(mstr)).await?;
+        r.ids.push(id);
+
+        let weak = Arc::downgrade(&r.objects);
+        let f: RemovedFn<T> = Box::new(move |rm: Removed, _: &T| objmanager::handle_removed(&weak, rm));
+        let mr = Removed::match_rule(Some(&r.proxy.destination), Some(&r.proxy.path)).static_clone();
+        let mstr = mr.match_str();
+        let id = r.proxy.match_start(mr, f.make(mstr)).await?;
+        r.ids.push(id);
+
+        r.refresh().await?;
+        Ok(r)
+    }
+
+    /// Fetches all objects again, calling the event callbacks for objects and interfaces that appeared or disappeared.
+    pub async fn refresh(&self) -> Result<(), Error> {
+        let Serial(serial, (objects,)): Serial<(HashMap<Path<'static>, Interfaces>,)> =
+            self.proxy.method_call(OBJECT_MANAGER, "GetManagedObjects", ()).await?;
+        objmanager::reset(&self.objects, serial, objects);
+        Ok(())
+    }
+}
+
+#[test]
+fn object_manager_client() {
+    use crate::arg::Value;
+    use crate::channel::Channel;
+    use crate::nonblock::{SyncConnection, ObjectEvent, Process, block_on};
+    use crate::testbus::TestBus;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    let bus = TestBus::new().unwrap();
+    let c = bus.connect().unwrap();
+    let dest = c.unique_name().unwrap().to_string();
+    let server = std::thread::spawn(move || objmanager::serve_test_objects(c));
+
+    let conn = SyncConnection::from(bus.connect().unwrap());
+    let client = block_on(&conn, ObjectManagerClient::new(Proxy::new(dest, "/", &conn))).unwrap();
+    assert_eq!(client.paths(), vec!(Path::from("/a")));
+    let a = client.get(&"/a".into()).unwrap();
+    assert_eq!(a["com.example.A"]["Name"], Value::Str("a".into()));
+
+    let events = Arc::new(Mutex::new(vec!()));
+    let events2 = events.clone();
+    let id = client.on_event(move |e| events2.lock().unwrap().push(e.clone()));
+    let _: () = block_on(&conn, client.proxy().method_call("com.example.Test", "Change", ())).unwrap();
+    for _ in 0..50 {
+        if events.lock().unwrap().len() >= 4 { break }
+        let ch: &Channel = conn.as_ref();
+        ch.read_write(Some(Duration::from_millis(100))).unwrap();
+        conn.process_all();
+    }
+    assert_eq!(client.with_interface("com.example.B"), vec!(Path::from("/a")));
+    assert_eq!(events.lock().unwrap().len(), 4);
+
+    block_on(&conn, client.refresh()).unwrap();
+    assert_eq!(client.paths(), vec!(Path::from("/a"), Path::from("/c")));
+    // The signal about /d is dispatched before the reply, so it is reported, and then removed by the reply.
+    assert!(events.lock().unwrap()[4..].contains(&ObjectEvent::InterfacesAdded {
+        path: Path::from("/c"), interfaces: vec!("com.example.A".into()), new_object: true }));
+    assert!(client.remove_on_event(id));
+
+    let proxy = Proxy::new(client.proxy().destination.clone(), "/", &conn);
+    let ids = client.ids.clone();
+    drop(client);
+    assert!(ids.iter().all(|id| conn.stop_receive(*id).is_none()));
+    let _: () = block_on(&conn, proxy.method_call("com.example.Test", "Quit", ())).unwrap();
+    server.join().unwrap();
+}
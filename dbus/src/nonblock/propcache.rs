@@ -0,0 +1,131 @@
+use crate::Error;
+use crate::arg::{Arg, Get, Value};
+use crate::channel::{MatchingReceiver, Sender};
+use crate::strings::Interface;
+use crate::propcache::{self, Cache, Changed, Lookup, PROPERTIES};
+use crate::util::Serial;
+use super::{Proxy, NonblockReply, MakeSignal, remove_match};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::ops::Deref;
+
+type ChangedFn<T> = Box<dyn FnMut(Changed, &T) -> bool + Send + Sync>;
+
+/// A proxy that caches the properties of one interface of a remote object, for nonblocking connections.
+///
+/// Signals are dispatched by the connection's `process_all`, which is usually called by the reactor
+/// (e g dbus-tokio), so the cache stays up to date as long as the connection is running.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::nonblock::{Proxy, SyncConnection, PropertyCache};
+/// use std::sync::Arc;
+///
+/// async fn adapter_address(conn: Arc<SyncConnection>) -> Result<Option<String>, dbus::Error> {
+///     let proxy = Proxy::new("org.bluez", "/org/bluez/hci0", conn);
+///     let adapter = PropertyCache::new(proxy, "org.bluez.Adapter1").await?;
+///     adapter.on_change("Powered", |v| println!("Powered changed to {:?}", v));
+///     adapter.get::<String>("Address").await
+/// }
+/// ```
+pub type PropertyCache<'a, C> = propcache::PropertyCache<Proxy<'a, C>>;
+
+impl<'a, T, C> PropertyCache<'a, C>
+where
+    T: NonblockReply + MatchingReceiver + Sender,
+    C: Deref<Target=T>,
+    ChangedFn<T>: MakeSignal<<T as MatchingReceiver>::F, Changed, T>,
+{
+    /// Creates a cache for the properties of an interface on the proxy's destination and path.
+    ///
+    /// This starts matching the `PropertiesChanged` signal, and then fetches all properties.
+    pub async fn new<I: Into<Interface<'static>>>(proxy: Proxy<'a, C>, interface: I) -> Result<Self, Error> {
+        let interface = interface.into();
+        let cache: Arc<Mutex<Cache>> = Default::default();
+        let weak = Arc::downgrade(&cache);
+        let iface = interface.to_string();
+        let f: ChangedFn<T> = Box::new(move |c: Changed, _: &T| propcache::handle_changed(&weak, &iface, c));
+        let mr = propcache::match_rule(&proxy.destination, &proxy.path, &interface);
+        let mstr = mr.match_str();
+        let id = proxy.match_start(mr, f.make(mstr)).await?;
+        // Nobody waits for the reply to RemoveMatch when the cache is dropped.
+        let stop = |p: &Proxy<'a, C>, id| if let Some((mr, _)) = p.connection.stop_receive(id) {
+            remove_match(&*p.connection, &mr.match_str())
+        };
+        let r = PropertyCache { proxy, interface, cache, id, stop };
+        r.refresh().await?;
+        Ok(r)
+    }
+
+    /// Fetches all properties again, calling the change callbacks of those that changed.
+    pub async fn refresh(&self) -> Result<(), Error> {
+        let Serial(serial, (props,)): Serial<(HashMap<String, Value>,)> =
+            self.proxy.method_call(PROPERTIES, "GetAll", (&*self.interface,)).await?;
+        propcache::update(&self.cache, serial, true, props.into_iter().map(|(k, v)| (k, Some(v))));
+        Ok(())
+    }
+
+    /// Returns a property, or None if the interface has no property with this name.
+    ///
+    /// If the property has been invalidated, it is fetched from the remote object.
+    pub async fn get<R: Arg + for<'b> Get<'b>>(&self, name: &str) -> Result<Option<R>, Error> {
+        let l = self.cache.lock().unwrap().lookup(name);
+        match l {
+            Lookup::Cached(v) => propcache::convert(&v).map(Some),
+            Lookup::Unknown => Ok(None),
+            Lookup::Invalidated => {
+                let Serial(serial, (v,)): Serial<(Value,)> =
+                    self.proxy.method_call(PROPERTIES, "Get", (&*self.interface, name)).await?;
+                let r = propcache::convert(&v)?;
+                propcache::update(&self.cache, serial, false, Some((name.to_string(), Some(v))));
+                Ok(Some(r))
+            }
+        }
+    }
+}
+
+#[test]
+fn property_cache() {
+    use crate::nonblock::{SyncConnection, Process, block_on};
+    use crate::testbus::TestBus;
+    use std::time::Duration;
+
+    let bus = TestBus::new().unwrap();
+    let server = bus.connect().unwrap();
+    let dest = server.unique_name().unwrap().to_string();
+    let server = std::thread::spawn(move || propcache::serve_test_properties(server));
+
+    let conn = SyncConnection::from(bus.connect().unwrap());
+    let cache = block_on(&conn, PropertyCache::new(Proxy::new(dest, "/test", &conn), "com.example.Test")).unwrap();
+    assert_eq!(cache.names(), vec!("Name", "Volume"));
+    assert_eq!(block_on(&conn, cache.get::<u32>("Volume")).unwrap(), Some(5));
+    assert_eq!(block_on(&conn, cache.get::<u32>("Missing")).unwrap(), None);
+
+    let changes = Arc::new(Mutex::new(vec!()));
+    let changes2 = changes.clone();
+    let id = cache.on_change("Volume", move |v| changes2.lock().unwrap().push(v.cloned()));
+    let _: () = block_on(&conn, cache.proxy().method_call("com.example.Test", "Bump", ())).unwrap();
+    for _ in 0..50 {
+        if !changes.lock().unwrap().is_empty() { break }
+        let ch: &crate::channel::Channel = conn.as_ref();
+        ch.read_write(Some(Duration::from_millis(100))).unwrap();
+        conn.process_all();
+    }
+    assert_eq!(cache.get_cached::<u32>("Volume").unwrap(), Some(6));
+    assert_eq!(cache.get_cached::<String>("Name").unwrap(), None);
+    assert_eq!(block_on(&conn, cache.get::<String>("Name")).unwrap(), Some("b".into()));
+    assert_eq!(cache.get_cached::<String>("Name").unwrap(), Some("b".into()));
+
+    block_on(&conn, cache.refresh()).unwrap();
+    assert_eq!(cache.get_cached::<u32>("Volume").unwrap(), Some(7));
+    assert_eq!(*changes.lock().unwrap(), vec!(Some(Value::UInt32(6)), Some(Value::UInt32(7))));
+    assert!(cache.remove_on_change(id));
+
+    let proxy = Proxy::new(cache.proxy().destination.clone(), "/test", &conn);
+    let id = cache.id;
+    drop(cache);
+    assert!(conn.stop_receive(id).is_none());
+    let _: () = block_on(&conn, proxy.method_call("com.example.Test", "Quit", ())).unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,261 @@
+//! The connection independent parts of `blocking::ObjectManagerClient` and `nonblock::ObjectManagerClient`.
+
+use crate::arg::{self, ReadAll, TypeMismatchError, Value};
+use crate::message::SignalArgs;
+use crate::strings::Path;
+use crate::util::Callbacks;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, Weak};
+
+pub (crate) const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+
+/// The interfaces of an object, with their properties.
+pub (crate) type Interfaces = HashMap<String, HashMap<String, Value>>;
+
+/// A change to the objects tracked by an `ObjectManagerClient`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectEvent {
+    /// One or more interfaces were added to an object.
+    InterfacesAdded {
+        /// The path of the object.
+        path: Path<'static>,
+        /// The names of the interfaces that were added.
+        interfaces: Vec<String>,
+        /// True if the object did not exist before.
+        new_object: bool,
+    },
+    /// One or more interfaces were removed from an object.
+    InterfacesRemoved {
+        /// The path of the object.
+        path: Path<'static>,
+        /// The names of the interfaces that were removed.
+        interfaces: Vec<String>,
+        /// True if the object has no interfaces left, i e, it is gone.
+        object_removed: bool,
+    },
+}
+
+/// The InterfacesAdded signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct Added {
+    serial: u32,
+    path: Path<'static>,
+    interfaces: Interfaces,
+}
+
+impl ReadAll for Added {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        Ok(Added { serial: i.message().get_serial(), path: i.read()?, interfaces: i.read()? })
+    }
+}
+
+impl SignalArgs for Added {
+    const NAME: &'static str = "InterfacesAdded";
+    const INTERFACE: &'static str = OBJECT_MANAGER;
+}
+
+/// The InterfacesRemoved signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct Removed {
+    serial: u32,
+    path: Path<'static>,
+    interfaces: Vec<String>,
+}
+
+impl ReadAll for Removed {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        Ok(Removed { serial: i.message().get_serial(), path: i.read()?, interfaces: i.read()? })
+    }
+}
+
+impl SignalArgs for Removed {
+    const NAME: &'static str = "InterfacesRemoved";
+    const INTERFACE: &'static str = OBJECT_MANAGER;
+}
+
+type Callback = Arc<Mutex<dyn FnMut(&ObjectEvent) + Send>>;
+
+#[derive(Default)]
+pub (crate) struct Objects {
+    // The serial of the last GetManagedObjects reply. Signals sent before it are already part of the reply.
+    serial: u32,
+    objects: BTreeMap<Path<'static>, Interfaces>,
+    callbacks: Callbacks<Callback>,
+}
+
+impl Objects {
+    pub (crate) fn objects(&self) -> &BTreeMap<Path<'static>, Interfaces> { &self.objects }
+
+    pub (crate) fn with_interface(&self, interface: &str) -> Vec<Path<'static>> {
+        self.objects.iter().filter(|(_, i)| i.contains_key(interface)).map(|(p, _)| p.clone()).collect()
+    }
+
+    fn add(&mut self, path: Path<'static>, interfaces: Interfaces, events: &mut Vec<ObjectEvent>) {
+        if interfaces.is_empty() { return }
+        let new_object = !self.objects.contains_key(&path);
+        let obj = self.objects.entry(path.clone()).or_default();
+        let mut names: Vec<_> = interfaces.keys().filter(|n| !obj.contains_key(*n)).cloned().collect();
+        obj.extend(interfaces);
+        if names.is_empty() { return }
+        names.sort();
+        events.push(ObjectEvent::InterfacesAdded { path, interfaces: names, new_object });
+    }
+
+    fn remove(&mut self, path: Path<'static>, interfaces: &[String], events: &mut Vec<ObjectEvent>) {
+        let obj = match self.objects.get_mut(&path) { Some(o) => o, None => return };
+        let mut names: Vec<_> = interfaces.iter().filter(|n| obj.remove(*n).is_some()).cloned().collect();
+        if names.is_empty() { return }
+        names.sort();
+        let object_removed = obj.is_empty();
+        if object_removed { self.objects.remove(&path); }
+        events.push(ObjectEvent::InterfacesRemoved { path, interfaces: names, object_removed });
+    }
+}
+
+/// Calls the callbacks for each event, without the lock held, so that they can read the objects.
+fn dispatch(objects: &Mutex<Objects>, f: impl FnOnce(&mut Objects, &mut Vec<ObjectEvent>)) {
+    let mut events = vec!();
+    let callbacks: Vec<_> = {
+        let mut o = objects.lock().unwrap();
+        f(&mut o, &mut events);
+        if events.is_empty() { return }
+        o.callbacks.iter().cloned().collect()
+    };
+    for e in &events {
+        for f in &callbacks { (f.lock().unwrap())(e) }
+    }
+}
+
+/// Replaces all objects with the reply to GetManagedObjects, calling the callbacks for what changed.
+pub (crate) fn reset(objects: &Mutex<Objects>, serial: u32, new: HashMap<Path<'static>, Interfaces>) {
+    dispatch(objects, |o, events| {
+        o.serial = serial;
+        let gone: Vec<_> = o.objects.iter()
+            .map(|(p, i)| (p.clone(), i.keys().filter(|n| new.get(p).filter(|x| x.contains_key(*n)).is_none()).cloned().collect::<Vec<_>>()))
+            .filter(|(_, names)| !names.is_empty())
+            .collect();
+        // Add before removing, so that an object that only changes interfaces is not reported as gone.
+        let mut new: Vec<_> = new.into_iter().collect();
+        new.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, interfaces) in new { o.add(path, interfaces, events) }
+        for (path, names) in gone { o.remove(path, &names, events) }
+    })
+}
+
+/// Handles the InterfacesAdded signal. Returns false if the client has been dropped.
+pub (crate) fn handle_added(objects: &Weak<Mutex<Objects>>, a: Added) -> bool {
+    let objects = match objects.upgrade() { Some(o) => o, None => return false };
+    dispatch(&objects, |o, events| if a.serial > o.serial { o.add(a.path, a.interfaces, events) });
+    true
+}
+
+/// Handles the InterfacesRemoved signal. Returns false if the client has been dropped.
+pub (crate) fn handle_removed(objects: &Weak<Mutex<Objects>>, r: Removed) -> bool {
+    let objects = match objects.upgrade() { Some(o) => o, None => return false };
+    dispatch(&objects, |o, events| if r.serial > o.serial { o.remove(r.path, &r.interfaces, events) });
+    true
+}
+
+/// Keeps track of the objects below a remote `org.freedesktop.DBus.ObjectManager`, like GDBusObjectManagerClient does.
+///
+/// All objects, with their interfaces and properties, are fetched with a single `GetManagedObjects`
+/// call when the client is created, and are then kept up to date by listening to the `InterfacesAdded`
+/// and `InterfacesRemoved` signals. Property values are those the object had when the interface was
+/// added; use a `PropertyCache` on the object to follow property changes.
+/// If the destination is a well-known name that changes owner, call `refresh` to fetch all objects again.
+///
+/// The signal matches are removed when the client is dropped.
+///
+/// P is the proxy type; use this through `blocking::ObjectManagerClient` or `nonblock::ObjectManagerClient`.
+pub struct ObjectManagerClient<P> {
+    pub (crate) proxy: P,
+    pub (crate) objects: Arc<Mutex<Objects>>,
+    pub (crate) ids: Vec<u32>,
+    // Stops a match started by `new`.
+    pub (crate) stop: fn(&P, u32),
+}
+
+impl<P> ObjectManagerClient<P> {
+    /// Returns all objects, with their interfaces and properties.
+    pub fn objects(&self) -> BTreeMap<Path<'static>, HashMap<String, HashMap<String, Value>>> {
+        self.objects.lock().unwrap().objects().clone()
+    }
+
+    /// Returns the paths of all objects.
+    pub fn paths(&self) -> Vec<Path<'static>> { self.objects.lock().unwrap().objects().keys().cloned().collect() }
+
+    /// Returns the interfaces and properties of an object, or None if there is no such object.
+    pub fn get(&self, path: &Path) -> Option<HashMap<String, HashMap<String, Value>>> {
+        self.objects.lock().unwrap().objects().get(path).cloned()
+    }
+
+    /// Returns the paths of all objects that implement an interface.
+    pub fn with_interface(&self, interface: &str) -> Vec<Path<'static>> {
+        self.objects.lock().unwrap().with_interface(interface)
+    }
+
+    /// Calls `f` every time objects or interfaces appear or disappear.
+    ///
+    /// Returns an id that can be used to remove the callback.
+    pub fn on_event<F: FnMut(&ObjectEvent) + Send + 'static>(&self, f: F) -> u32 {
+        self.objects.lock().unwrap().callbacks.add(Arc::new(Mutex::new(f)))
+    }
+
+    /// Removes a callback added with `on_event`. Returns false if there was no such callback.
+    pub fn remove_on_event(&self, id: u32) -> bool { self.objects.lock().unwrap().callbacks.remove(id) }
+
+    /// The underlying proxy, e g for calling methods on the object manager.
+    pub fn proxy(&self) -> &P { &self.proxy }
+}
+
+impl<P> Drop for ObjectManagerClient<P> {
+    fn drop(&mut self) {
+        for id in &self.ids { (self.stop)(&self.proxy, *id) }
+    }
+}
+
+/// Serves an object manager on /, for testing the object manager clients. Returns when Quit is called.
+#[cfg(test)]
+pub (crate) fn serve_test_objects(c: crate::channel::Channel) {
+    use crate::{Message, MessageType, arg::PropMap};
+    use std::time::Duration;
+
+    let obj = |iface: &str, name: &str| {
+        let mut m = HashMap::new();
+        m.insert(iface.to_string(), PropMap::new().with("Name", name.to_string()));
+        m
+    };
+    let added = |path: &str, iface: &str, name: &str| {
+        Message::new_signal("/", OBJECT_MANAGER, "InterfacesAdded").unwrap().append2(Path::from(path), obj(iface, name))
+    };
+    let removed = |path: &str, ifaces: Vec<&str>| {
+        Message::new_signal("/", OBJECT_MANAGER, "InterfacesRemoved").unwrap().append2(Path::from(path), ifaces)
+    };
+    let mut count = 0;
+    while let Some(m) = c.blocking_pop_message(Duration::from_secs(5)).unwrap() {
+        if m.msg_type() != MessageType::MethodCall { continue }
+        let reply = m.method_return();
+        match &*m.member().unwrap() {
+            "GetManagedObjects" => {
+                let mut objects = HashMap::new();
+                objects.insert(Path::from("/a"), obj("com.example.A", "a"));
+                if count > 0 {
+                    // Signals about changes already in the reply must be ignored.
+                    c.send(added("/d", "com.example.A", "d")).unwrap();
+                    objects.insert(Path::from("/c"), obj("com.example.A", "c"));
+                }
+                count += 1;
+                c.send(reply.append1(objects)).unwrap();
+            }
+            "Change" => {
+                c.send(reply).unwrap();
+                c.send(added("/b", "com.example.B", "b")).unwrap();
+                c.send(added("/a", "com.example.B", "a")).unwrap();
+                c.send(removed("/a", vec!("com.example.A", "com.example.Missing"))).unwrap();
+                c.send(removed("/b", vec!("com.example.B"))).unwrap();
+            }
+            "Quit" => { c.send(reply).unwrap(); c.flush(); break },
+            _ => unreachable!(),
+        }
+    }
+}
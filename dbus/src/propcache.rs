@@ -0,0 +1,199 @@
+//! The connection independent parts of `blocking::PropertyCache` and `nonblock::PropertyCache`.
+
+use crate::Error;
+use crate::arg::{self, Arg, Get, ReadAll, TypeMismatchError, Value};
+use crate::util::Callbacks;
+use crate::message::{MatchRule, SignalArgs};
+use crate::strings::{BusName, Path, Interface};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+pub (crate) const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// The PropertiesChanged signal, along with the serial of the message it came in.
+#[derive(Debug)]
+pub struct Changed {
+    serial: u32,
+    interface: String,
+    changed: HashMap<String, Value>,
+    invalidated: Vec<String>,
+}
+
+impl ReadAll for Changed {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        Ok(Changed { serial: i.message().get_serial(), interface: i.read()?, changed: i.read()?, invalidated: i.read()? })
+    }
+}
+
+impl SignalArgs for Changed {
+    const NAME: &'static str = "PropertiesChanged";
+    const INTERFACE: &'static str = PROPERTIES;
+}
+
+/// The match rule for PropertiesChanged on a single interface.
+pub (crate) fn match_rule(dest: &BusName, path: &Path, interface: &str) -> MatchRule<'static> {
+    let mut mr = Changed::match_rule(Some(dest), Some(path)).static_clone();
+    mr.args.push((0, interface.into()));
+    mr
+}
+
+type Callback = Arc<Mutex<dyn FnMut(Option<&Value>) + Send>>;
+
+#[derive(Default)]
+pub (crate) struct Cache {
+    // Properties with the serial of the message that last set them. None means invalidated.
+    props: HashMap<String, (u32, Option<Value>)>,
+    callbacks: Callbacks<(String, Callback)>,
+}
+
+/// What the cache knows about a property.
+pub (crate) enum Lookup {
+    Cached(Value),
+    Invalidated,
+    Unknown,
+}
+
+impl Cache {
+    pub (crate) fn lookup(&self, name: &str) -> Lookup {
+        match self.props.get(name) {
+            Some((_, Some(v))) => Lookup::Cached(v.clone()),
+            Some((_, None)) => Lookup::Invalidated,
+            None => Lookup::Unknown,
+        }
+    }
+
+    pub (crate) fn names(&self) -> Vec<String> {
+        let mut v: Vec<_> = self.props.keys().cloned().collect();
+        v.sort();
+        v
+    }
+
+    // Does nothing if the cache already has a value from a newer message.
+    fn set(&mut self, serial: u32, name: String, v: Option<Value>, changed: &mut Vec<(String, Option<Value>)>) {
+        if let Some((old_serial, old)) = self.props.get(&name) {
+            if *old_serial > serial { return }
+            if *old == v { self.props.insert(name, (serial, v)); return }
+        }
+        changed.push((name.clone(), v.clone()));
+        self.props.insert(name, (serial, v));
+    }
+}
+
+/// Updates the cache and calls the callbacks of the properties that changed.
+///
+/// Callbacks are called without the lock held, so that they can read from the cache.
+pub (crate) fn update<I: IntoIterator<Item=(String, Option<Value>)>>(cache: &Mutex<Cache>, serial: u32, reset: bool, props: I) {
+    let mut changed = vec!();
+    let calls: Vec<_> = {
+        let mut c = cache.lock().unwrap();
+        if reset { for v in c.props.values_mut() { v.0 = 0 } }
+        for (name, v) in props { c.set(serial, name, v, &mut changed) }
+        changed.into_iter().flat_map(|(name, v)| {
+            c.callbacks.iter().filter(|x| x.0 == name).map(|x| (x.1.clone(), v.clone())).collect::<Vec<_>>()
+        }).collect()
+    };
+    for (f, v) in calls { (f.lock().unwrap())(v.as_ref()) }
+}
+
+/// Handles the PropertiesChanged signal. Returns false if the cache has been dropped.
+pub (crate) fn handle_changed(cache: &Weak<Mutex<Cache>>, interface: &str, c: Changed) -> bool {
+    let cache = match cache.upgrade() { Some(c) => c, None => return false };
+    if c.interface != interface { return true }
+    let props = c.changed.into_iter().map(|(k, v)| (k, Some(v)))
+        .chain(c.invalidated.into_iter().map(|k| (k, None)));
+    update(&cache, c.serial, false, props);
+    true
+}
+
+/// A proxy that caches the properties of one interface of a remote object, like GDBusProxy does.
+///
+/// All properties are fetched with a single `GetAll` call when the cache is created, and are then
+/// kept up to date by listening to the `PropertiesChanged` signal, so reading a property is usually
+/// just a lookup in a local map. Properties that the remote object invalidates (i e, signals as changed
+/// without sending the new value) are fetched again the next time they are read.
+/// If the destination is a well-known name that changes owner, call `refresh` to fetch all properties again.
+///
+/// The signal match is removed when the cache is dropped.
+///
+/// P is the proxy type; use this through `blocking::PropertyCache` or `nonblock::PropertyCache`.
+pub struct PropertyCache<P> {
+    pub (crate) proxy: P,
+    pub (crate) interface: Interface<'static>,
+    pub (crate) cache: Arc<Mutex<Cache>>,
+    pub (crate) id: u32,
+    // Stops the match started by `new`.
+    pub (crate) stop: fn(&P, u32),
+}
+
+impl<P> PropertyCache<P> {
+    /// Returns a property if its value is cached, without calling the remote object.
+    pub fn get_cached<R: Arg + for<'b> Get<'b>>(&self, name: &str) -> Result<Option<R>, Error> {
+        let l = self.cache.lock().unwrap().lookup(name);
+        if let Lookup::Cached(v) = l { convert(&v).map(Some) } else { Ok(None) }
+    }
+
+    /// Returns the names of all properties, including invalidated ones.
+    pub fn names(&self) -> Vec<String> { self.cache.lock().unwrap().names() }
+
+    /// Calls `f` every time the property changes, with the new value, or None if the property was invalidated.
+    ///
+    /// Returns an id that can be used to remove the callback.
+    pub fn on_change<F: FnMut(Option<&Value>) + Send + 'static>(&self, name: &str, f: F) -> u32 {
+        self.cache.lock().unwrap().callbacks.add((name.into(), Arc::new(Mutex::new(f))))
+    }
+
+    /// Removes a callback added with `on_change`. Returns false if there was no such callback.
+    pub fn remove_on_change(&self, id: u32) -> bool { self.cache.lock().unwrap().callbacks.remove(id) }
+
+    /// The interface whose properties are cached.
+    pub fn interface(&self) -> &Interface<'static> { &self.interface }
+
+    /// The underlying proxy, e g for calling methods on the same object.
+    pub fn proxy(&self) -> &P { &self.proxy }
+}
+
+impl<P> Drop for PropertyCache<P> {
+    fn drop(&mut self) { (self.stop)(&self.proxy, self.id) }
+}
+
+/// Converts a cached value to the requested type.
+pub (crate) fn convert<T: Arg + for<'b> Get<'b>>(v: &Value) -> Result<T, Error> {
+    Ok(arg::convert(&v.signature(), |i| v.append_value(i))?)
+}
+
+/// Serves the com.example.Test interface on /test, for testing the property caches. Returns when Quit is called.
+#[cfg(test)]
+pub (crate) fn serve_test_properties(c: crate::channel::Channel) {
+    use crate::{Message, MessageType, arg::PropMap};
+    use std::time::Duration;
+
+    let changed = |iface: &str, volume: u32, invalidated: Vec<&str>| {
+        Message::new_signal("/test", PROPERTIES, "PropertiesChanged").unwrap()
+            .append3(iface, PropMap::new().with("Volume", volume), invalidated)
+    };
+    let (mut volume, mut getall_count) = (5u32, 0);
+    while let Some(m) = c.blocking_pop_message(Duration::from_secs(5)).unwrap() {
+        if m.msg_type() != MessageType::MethodCall { continue }
+        let reply = m.method_return();
+        match &*m.member().unwrap() {
+            "GetAll" => {
+                // Signals about older values must not overwrite the newer values in the reply.
+                if getall_count > 0 {
+                    c.send(changed("com.example.Test", volume, vec!())).unwrap();
+                    volume += 1;
+                }
+                getall_count += 1;
+                c.send(reply.append1(PropMap::new().with("Volume", volume).with("Name", "a".to_string()))).unwrap();
+            }
+            "Get" => { c.send(reply.append1(crate::arg::Variant("b"))).unwrap(); },
+            "Bump" => {
+                volume = 6;
+                c.send(reply).unwrap();
+                c.send(changed("com.example.Other", 100, vec!())).unwrap();
+                c.send(changed("com.example.Test", volume, vec!("Name"))).unwrap();
+            }
+            "Quit" => { c.send(reply).unwrap(); c.flush(); break },
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! A connection wrapper that connects again when the connection to the bus is lost.
+
+use crate::{Error, strings::{BusName, WellKnownName}};
+use crate::channel::{Channel, BusType};
+use crate::blocking::stdintf::{self, org_freedesktop_dbus::{self, RequestNameReply, ReleaseNameReply}};
+use std::time::{Duration, Instant};
+use std::convert::TryFrom;
+use std::{cmp, fmt, ops, thread};
+
+pub (crate) mod private {
+    use crate::{channel::Channel, Error};
+    use std::time::Duration;
+
+    pub trait Sealed {
+        fn channel(&self) -> &Channel;
+        fn replace_channel(&mut self, c: Channel);
+        fn match_strs(&self) -> Vec<String>;
+        fn process_msg(&mut self, timeout: Duration) -> Result<bool, Error>;
+    }
+}
+
+/// Connection types that can be wrapped in `Reconnecting`.
+///
+/// These are `blocking::Connection`, `blocking::SyncConnection`, `nonblock::LocalConnection`
+/// and `nonblock::SyncConnection`.
+pub trait Reconnectable: private::Sealed + From<Channel> {}
+
+/// Something that happened to a `Reconnecting` connection.
+#[derive(Debug)]
+pub enum ReconnectEvent {
+    /// The connection to the bus was lost.
+    Disconnected,
+    /// An attempt to reconnect failed. The next attempt will be made after the given delay.
+    ReconnectFailed(Error, Duration),
+    /// A new connection was established, with the given unique name.
+    ///
+    /// This is reported after match rules have been added and names have been requested again.
+    Reconnected(String),
+    /// A match rule could not be added to the new connection.
+    AddMatchFailed(String, Error),
+    /// The result of requesting a name again on the new connection.
+    NameRequested(String, Result<RequestNameReply, Error>),
+}
+
+type ConnectFn = Box<dyn FnMut() -> Result<Channel, Error> + Send + 'static>;
+type EventFn = Box<dyn FnMut(ReconnectEvent) + Send + 'static>;
+
+#[derive(Debug, Clone)]
+struct OwnedName {
+    name: String,
+    allow_replacement: bool,
+    replace_existing: bool,
+    do_not_queue: bool,
+}
+
+/// A connection that connects again when the connection to the bus is lost,
+/// e g because the bus was restarted.
+///
+/// The reconnection happens inside `process`, with an exponential backoff between
+/// failed attempts. After reconnecting, `AddMatch` is called for the match rule of every
+/// callback added through `MatchingReceiver::start_receive` (this includes signal matches
+/// set up with `Proxy::match_signal`), and every name requested through `request_name` is
+/// requested again.
+///
+/// This derefs to the underlying connection. Note that method calls made
+/// while the connection is down fail, as does everything that depends on the unique name
+/// staying the same.
+///
+/// A wrapped `nonblock` connection is not driven by a reactor. Instead, call `process` with a zero
+/// timeout whenever the file descriptor of its channel (see `Channel::watch`) is readable, which
+/// dispatches replies and signals. This file descriptor changes when reconnecting, so look it up again
+/// on `ReconnectEvent::Reconnected`. Requesting and releasing names are blocking calls.
+///
+/// # Example
+///
+/// ```no_run
+/// use dbus::blocking::{Connection, Reconnecting, ReconnectEvent};
+/// use std::time::Duration;
+///
+/// let mut c: Reconnecting<Connection> = Reconnecting::new_system()?;
+/// c.set_event_handler(|e| if let ReconnectEvent::Disconnected = e { eprintln!("Lost connection to D-Bus") });
+/// c.request_name("com.example.service", false, true, false)?;
+/// loop { c.process(Duration::from_millis(1000))?; }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Reconnecting<C> {
+    conn: C,
+    connect: ConnectFn,
+    on_event: Option<EventFn>,
+    names: Vec<OwnedName>,
+    connected: bool,
+    backoff: (Duration, Duration),
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+impl<C: fmt::Debug> fmt::Debug for Reconnecting<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reconnecting").field("conn", &self.conn).field("names", &self.names)
+            .field("connected", &self.connected).field("delay", &self.delay).finish()
+    }
+}
+
+impl<C> ops::Deref for Reconnecting<C> {
+    type Target = C;
+    fn deref(&self) -> &C { &self.conn }
+}
+
+impl<C: Reconnectable> Reconnecting<C> {
+    /// Connects using the supplied function, which will also be called to reconnect.
+    ///
+    /// The function should return a registered channel, i e, one that has called `Hello`.
+    pub fn new<F: FnMut() -> Result<Channel, Error> + Send + 'static>(mut connect: F) -> Result<Self, Error> {
+        let mut c = connect()?;
+        // So that `Channel::watch` can be used to find out when to call `process`
+        c.set_watch_enabled(true);
+        let conn = C::from(c);
+        let backoff = (Duration::from_millis(100), Duration::from_secs(30));
+        Ok(Reconnecting {
+            conn, connect: Box::new(connect), on_event: None, names: vec!(), connected: true,
+            backoff, delay: backoff.0, next_attempt: Instant::now(),
+        })
+    }
+
+    /// Creates a new connection to the session bus.
+    pub fn new_session() -> Result<Self, Error> { Self::new(|| Channel::get_private(BusType::Session)) }
+
+    /// Creates a new connection to the system-wide bus.
+    pub fn new_system() -> Result<Self, Error> { Self::new(|| Channel::get_private(BusType::System)) }
+
+    /// Sets the delay before the first reconnection attempt, and the maximum delay between
+    /// attempts. The delay doubles after every failed attempt.
+    ///
+    /// Defaults to 100 ms and 30 seconds.
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+        self.backoff = (initial, cmp::max(initial, max));
+    }
+
+    /// Sets a callback that is called when the connection is lost or reestablished.
+    pub fn set_event_handler<F: FnMut(ReconnectEvent) + Send + 'static>(&mut self, f: F) {
+        self.on_event = Some(Box::new(f));
+    }
+
+    /// Returns false if the connection is currently lost.
+    pub fn is_connected(&self) -> bool { self.connected && self.conn.channel().is_connected() }
+
+    fn event(&mut self, e: ReconnectEvent) {
+        if let Some(f) = self.on_event.as_mut() { f(e) }
+    }
+
+    /// Request a name on the D-Bus, and remember to request it again after reconnecting.
+    ///
+    /// For detailed information on the flags and return values, see the libdbus documentation.
+    /// Returns an error if the name is a unique name, since those cannot be requested.
+    pub fn request_name<'a, N: Into<BusName<'a>>>(&mut self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+    -> Result<RequestNameReply, Error> {
+        let name = WellKnownName::try_from(name.into())?;
+        let r = org_freedesktop_dbus::request_name(self.conn.channel(), &name, allow_replacement, replace_existing, do_not_queue)?;
+        if r != RequestNameReply::Exists {
+            self.names.retain(|n| *n.name != *name);
+            self.names.push(OwnedName { name: name.to_string(), allow_replacement, replace_existing, do_not_queue });
+        }
+        Ok(r)
+    }
+
+    /// Release a previously requested name on the D-Bus, so that it is not requested again after reconnecting.
+    ///
+    /// Returns an error if the name is a unique name.
+    pub fn release_name<'a, N: Into<BusName<'a>>>(&mut self, name: N) -> Result<ReleaseNameReply, Error> {
+        let name = WellKnownName::try_from(name.into())?;
+        self.names.retain(|n| *n.name != *name);
+        org_freedesktop_dbus::release_name(self.conn.channel(), &name)
+    }
+
+    /// Tries to handle an incoming message if there is one. If there isn't one,
+    /// it will wait up to timeout.
+    ///
+    /// If the connection is lost, this tries to reconnect instead, waiting up to timeout
+    /// for the next attempt. Returns true if a message was handled.
+    pub fn process(&mut self, timeout: Duration) -> Result<bool, Error> {
+        if !self.connected { return self.reconnect(timeout).map(|_| false) }
+        let r = self.conn.process_msg(timeout);
+        if self.conn.channel().is_connected() { return r }
+        self.connected = false;
+        self.delay = self.backoff.0;
+        self.next_attempt = Instant::now() + self.delay;
+        self.event(ReconnectEvent::Disconnected);
+        Ok(r.unwrap_or(false))
+    }
+
+    fn reconnect(&mut self, timeout: Duration) -> Result<(), Error> {
+        let now = Instant::now();
+        if now < self.next_attempt {
+            thread::sleep(cmp::min(timeout, self.next_attempt - now));
+            if Instant::now() < self.next_attempt { return Ok(()) }
+        }
+        let mut c = match (self.connect)() {
+            Ok(c) => c,
+            Err(e) => {
+                let d = self.delay;
+                self.next_attempt = Instant::now() + d;
+                self.delay = cmp::min(d * 2, self.backoff.1);
+                self.event(ReconnectEvent::ReconnectFailed(e, d));
+                return Ok(())
+            }
+        };
+        c.set_watch_enabled(true);
+        self.conn.replace_channel(c);
+        self.connected = true;
+
+        let proxy = stdintf::proxy(self.conn.channel());
+        let mut events = vec!();
+        for m in self.conn.match_strs() {
+            use crate::blocking::stdintf::org_freedesktop::DBus;
+            if let Err(e) = proxy.add_match(&m) { events.push(ReconnectEvent::AddMatchFailed(m, e)) }
+        }
+        for n in &self.names {
+            let r = org_freedesktop_dbus::request_name(self.conn.channel(), &n.name,
+                n.allow_replacement, n.replace_existing, n.do_not_queue);
+            events.push(ReconnectEvent::NameRequested(n.name.clone(), r));
+        }
+        events.push(ReconnectEvent::Reconnected(self.conn.channel().unique_name().unwrap_or("").into()));
+        for e in events { self.event(e) }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn reconnect_testbus<C, F>(make_filter: F)
+where C: Reconnectable + crate::channel::MatchingReceiver, F: FnOnce(std::sync::Arc<std::sync::Mutex<u32>>) -> C::F {
+    use crate::testbus::TestBus;
+    use crate::message::MatchRule;
+    use std::sync::{Arc, Mutex};
+
+    let bus = TestBus::new().unwrap();
+    let addr = Arc::new(Mutex::new(bus.address().to_string()));
+    let addr2 = addr.clone();
+    let mut c: Reconnecting<C> = Reconnecting::new(move || {
+        let mut c = Channel::open_private(&addr2.lock().unwrap())?;
+        c.register()?;
+        Ok(c)
+    }).unwrap();
+    c.set_backoff(Duration::from_millis(10), Duration::from_millis(40));
+    let events = Arc::new(Mutex::new(vec!()));
+    let e2 = events.clone();
+    c.set_event_handler(move |e| e2.lock().unwrap().push(format!("{:?}", e)));
+
+    let signals = Arc::new(Mutex::new(0));
+    let s2 = signals.clone();
+    let rule = MatchRule::new_signal("com.example.Test", "Sig");
+    c.start_receive(rule.clone(), make_filter(s2));
+    assert_eq!(c.request_name("com.example.reconnect", false, false, true).unwrap(), RequestNameReply::PrimaryOwner);
+    assert!(c.request_name(":1.5", false, false, true).is_err());
+
+    // Kill the bus; reconnection attempts fail until a new one is up
+    drop(bus);
+    for _ in 0..20 {
+        c.process(Duration::from_millis(10)).unwrap();
+    }
+    assert!(!c.is_connected());
+    {
+        let ev = events.lock().unwrap();
+        assert_eq!(ev[0], "Disconnected");
+        assert!(ev[1].starts_with("ReconnectFailed"));
+    }
+
+    let bus = TestBus::new().unwrap();
+    *addr.lock().unwrap() = bus.address().to_string();
+    for _ in 0..20 {
+        c.process(Duration::from_millis(10)).unwrap();
+        if c.is_connected() { break }
+    }
+    assert!(c.is_connected());
+    let ev = events.lock().unwrap().clone();
+    assert!(ev.contains(&"NameRequested(\"com.example.reconnect\", Ok(PrimaryOwner))".to_string()));
+    assert!(ev.last().unwrap().starts_with("Reconnected(\":1."));
+
+    // The match rule was added to the new bus
+    let c2 = bus.connect().unwrap();
+    c2.send(crate::Message::new_signal("/", "com.example.Test", "Sig").unwrap()).unwrap();
+    c2.flush();
+    for _ in 0..50 {
+        c.process(Duration::from_millis(100)).unwrap();
+        if *signals.lock().unwrap() > 0 { break }
+    }
+    assert_eq!(*signals.lock().unwrap(), 1);
+    let p = crate::blocking::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(5), &c2);
+    let (owner,): (String,) = p.method_call("org.freedesktop.DBus", "GetNameOwner", ("com.example.reconnect",)).unwrap();
+    assert_eq!(&*owner, c.channel().unique_name().unwrap());
+}
+
+#[test]
+fn reconnect_blocking() {
+    reconnect_testbus::<crate::blocking::Connection, _>(|s| Box::new(move |_, _| { *s.lock().unwrap() += 1; true }));
+}
+
+#[test]
+fn reconnect_nonblock() {
+    reconnect_testbus::<crate::nonblock::LocalConnection, _>(|s| Box::new(move |_, _| { *s.lock().unwrap() += 1; true }));
+}
+
+#[test]
+fn reconnect_pending_reply() {
+    use crate::testbus::TestBus;
+    use crate::nonblock::{LocalConnection, Proxy};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::future::Future;
+
+    let bus = TestBus::new().unwrap();
+    let addr = Arc::new(Mutex::new(bus.address().to_string()));
+    let addr2 = addr.clone();
+    let mut c: Reconnecting<LocalConnection> = Reconnecting::new(move || {
+        let mut c = Channel::open_private(&addr2.lock().unwrap())?;
+        c.register()?;
+        Ok(c)
+    }).unwrap();
+    c.set_backoff(Duration::from_millis(10), Duration::from_millis(40));
+
+    // Nobody answers this call
+    let silent = bus.connect().unwrap();
+    let reply = Proxy::new(silent.unique_name().unwrap().to_string(), "/", &*c).method_call::<(), _, _, _>("com.example.Test", "Silent", ());
+    let mut reply = Box::pin(reply);
+    drop(bus);
+    let bus = TestBus::new().unwrap();
+    *addr.lock().unwrap() = bus.address().to_string();
+    for _ in 0..50 {
+        c.process(Duration::from_millis(10)).unwrap();
+        if c.is_connected() { break }
+    }
+    assert!(c.is_connected());
+    let waker = crate::nonblock::noop_waker();
+    match reply.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(Err(e)) => assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.Disconnected")),
+        _ => panic!("Pending reply was not failed on reconnect"),
+    }
+}
@@ -7,25 +7,142 @@
 use std::{str, fmt, ops, default, hash};
 use std::ffi::{CStr, CString};
 use std::borrow::{Borrow, Cow};
+use std::convert::TryFrom;
 use std::os::raw::c_char;
 
-#[cfg(not(feature = "no-string-validation"))]
+#[doc(hidden)]
+pub mod validate;
+
 use crate::Error;
 #[cfg(not(feature = "no-string-validation"))]
 use crate::ffi;
 
+/// What is wrong with a string that is not a valid D-Bus name, path or signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidReason {
+    /// The string is empty.
+    Empty,
+    /// The string is longer than 255 bytes.
+    TooLong,
+    /// The string contains a nul byte.
+    ContainsNul,
+    /// The string contains a character that is not allowed here.
+    InvalidCharacter,
+    /// An object path does not start with a slash.
+    MissingLeadingSlash,
+    /// An element of a path or name is empty, e g "/a//b", "/a/" or "org..example".
+    EmptyElement,
+    /// An element of a name starts with a digit.
+    LeadingDigit,
+    /// A name has fewer than two elements, e g "org".
+    TooFewElements,
+    /// A unique bus name was expected, but the name does not start with ':'.
+    NotUniqueName,
+    /// A well-known bus name was expected, but the name starts with ':'.
+    NotWellKnownName,
+    /// A signature ends in the middle of a type.
+    UnexpectedEnd,
+    /// A signature contains more than one complete type.
+    NotSingleType,
+    /// The key of a dict entry is not a basic type.
+    DictKeyNotBasic,
+    /// A dict entry is not inside an array, or does not have exactly one key and one value.
+    InvalidDictEntry,
+    /// A struct has no fields.
+    EmptyStruct,
+    /// Arrays, structs or dict entries are nested more than 32 levels deep.
+    TooDeep,
+    /// libdbus rejected the string for a reason not listed above.
+    Other,
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InvalidReason::*;
+        f.write_str(match self {
+            Empty => "it is empty",
+            TooLong => "it is longer than 255 bytes",
+            ContainsNul => "nul byte",
+            InvalidCharacter => "character not allowed",
+            MissingLeadingSlash => "it does not start with '/'",
+            EmptyElement => "empty element",
+            LeadingDigit => "element starts with a digit",
+            TooFewElements => "it needs at least two elements separated by '.'",
+            NotUniqueName => "unique names start with ':'",
+            NotWellKnownName => "well-known names cannot start with ':'",
+            UnexpectedEnd => "unexpected end",
+            NotSingleType => "more than one complete type",
+            DictKeyNotBasic => "dict entry key is not a basic type",
+            InvalidDictEntry => "invalid dict entry",
+            EmptyStruct => "empty struct",
+            TooDeep => "nested too deeply",
+            Other => "not valid",
+        })
+    }
+}
+
+/// The error returned from `Path::new_checked`, `Interface::new_checked` etc, for an invalid string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    kind: &'static str,
+    value: String,
+    position: usize,
+    reason: InvalidReason,
+}
+
+impl ValidationError {
+    fn new(kind: &'static str, value: &[u8], position: usize, reason: InvalidReason) -> Self {
+        ValidationError { kind, value: String::from_utf8_lossy(value).into_owned(), position, reason }
+    }
+
+    /// What kind of string was expected, e g "object path" or "interface name".
+    pub fn kind(&self) -> &'static str { self.kind }
+
+    /// The string that was rejected.
+    pub fn value(&self) -> &str { &self.value }
+
+    /// The byte offset in the string where the problem was found.
+    ///
+    /// If the string ended too early (e g "/a/" or "a{s"), this is the length of the string.
+    pub fn position(&self) -> usize { self.position }
+
+    /// What is wrong with the string.
+    pub fn reason(&self) -> InvalidReason { self.reason }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid {} '{}': {} at position {}", self.kind, self.value, self.reason, self.position)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for String {
+    fn from(e: ValidationError) -> String { e.to_string() }
+}
+
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Error { Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()) }
+}
+
 macro_rules! cstring_wrapper {
-    ($t: ident, $s: ident) => {
+    ($t: ident, $s: ident, $check: ident, $kind: expr) => {
+        cstring_wrapper!($t, $s, $check, $kind, |_: &[u8]| Ok(()));
+    };
+    ($t: ident, $s: ident, $check: ident, $kind: expr, $extra: expr) => {
 
 impl<'m> $t<'m> {
     #[cfg(feature = "no-string-validation")]
-    fn check_valid(_: *const c_char) -> Result<(), String> { Ok(()) }
+    fn check_valid(_: &CStr) -> Result<(), String> { Ok(()) }
 
     #[cfg(not(feature = "no-string-validation"))]
-    fn check_valid(c: *const c_char) -> Result<(), String> {
+    fn check_valid(c: &CStr) -> Result<(), String> {
         let mut e = Error::empty();
-        let b = unsafe { ffi::$s(c, e.get_mut()) };
-        if b != 0 { Ok(()) } else { Err(e.message().unwrap().into()) }
+        let b = unsafe { ffi::$s(c.as_ptr(), e.get_mut()) };
+        if b == 0 { return Err(e.message().unwrap().into()) }
+        let extra: fn(&[u8]) -> Result<(), String> = $extra;
+        extra(c.to_bytes())
     }
 
     /// Creates a new instance of this struct.
@@ -34,20 +151,36 @@ impl<'m> $t<'m> {
     /// will not be checked for conformance with the D-Bus specification.
     pub fn new<S: Into<Vec<u8>>>(s: S) -> Result<$t<'m>, String> {
         let c = CString::new(s).map_err(|e| e.to_string())?;
-        $t::check_valid(c.as_ptr()).map(|_| $t(Cow::Owned(c)))
+        $t::check_valid(&c).map(|_| $t(Cow::Owned(c)))
     }
 
     /// Creates a new instance of this struct. If you end it with \0,
     /// it can borrow the slice without extra allocation.
+    /// A slice with a \0 anywhere but at the end is rejected.
     ///
     /// Note: If the no-string-validation feature is activated, this string
     /// will not be checked for conformance with the D-Bus specification.
     pub fn from_slice(s: &'m [u8]) -> Result<$t<'m>, String> {
         if s.len() == 0 || s[s.len()-1] != 0 { return $t::new(s) };
-        $t::check_valid(s.as_ptr() as *const c_char).map(|_| {
-            let c = unsafe { CStr::from_ptr(s.as_ptr() as *const c_char) };
-            $t(Cow::Borrowed(c)) 
-        })
+        let c = CStr::from_bytes_with_nul(s).map_err(|e| e.to_string())?;
+        $t::check_valid(c).map(|_| $t(Cow::Borrowed(c)))
+    }
+
+    /// Like `new`, but on failure, returns an error that tells where and why the string is invalid.
+    ///
+    /// Note: If the no-string-validation feature is activated, this string
+    /// will not be checked for conformance with the D-Bus specification.
+    pub fn new_checked<S: Into<Vec<u8>>>(s: S) -> Result<$t<'m>, ValidationError> {
+        let c = CString::new(s).map_err(|e| {
+            let pos = e.nul_position();
+            ValidationError::new($kind, &e.into_vec(), pos, InvalidReason::ContainsNul)
+        })?;
+        // libdbus decides what is valid, validate::$check only describes the problem
+        $t::check_valid(&c).map_err(|_| {
+            let (pos, reason) = validate::$check(c.to_bytes()).err().unwrap_or((0, InvalidReason::Other));
+            ValidationError::new($kind, c.to_bytes(), pos, reason)
+        })?;
+        Ok($t(Cow::Owned(c)))
     }
 
     /// This function creates a new instance of this struct, without checking.
@@ -57,6 +190,12 @@ impl<'m> $t<'m> {
         $t(Cow::Borrowed(CStr::from_ptr(s.as_ptr() as *const c_char)))
     }
 
+    /// Used by the literal macros (`path!` etc), which have already validated s at compile time.
+    #[doc(hidden)]
+    pub const unsafe fn from_static_unchecked(s: &'static [u8]) -> $t<'static> {
+        $t(Cow::Borrowed(CStr::from_bytes_with_nul_unchecked(s)))
+    }
+
     /// View this struct as a CStr.
     pub fn as_cstr(&self) -> &CStr { &self.0 }
 
@@ -140,7 +279,7 @@ impl<'m> hash::Hash for $t<'m> {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Signature<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(Signature, dbus_signature_validate_single);
+cstring_wrapper!(Signature, dbus_signature_validate_single, check_signature, "signature");
 
 impl Signature<'static> {
     /// Makes a D-Bus signature that corresponds to A. 
@@ -152,40 +291,367 @@ impl Signature<'static> {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Path<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(Path, dbus_validate_path);
+cstring_wrapper!(Path, dbus_validate_path, check_path, "object path");
 
 // This is needed so one can make arrays of paths easily
 impl<'a> default::Default for Path<'a> {
     fn default() -> Path<'a> { Path(Cow::Borrowed(unsafe { CStr::from_ptr(b"/\0".as_ptr() as *const c_char)})) }
 }
 
+impl<'a> Path<'a> {
+    // The caller promises that s is a valid path.
+    fn from_string_unchecked(s: String) -> Path<'static> { Path(Cow::Owned(CString::new(s).unwrap())) }
+
+    /// Returns the parent of this path, or None if this is the root path.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// assert_eq!(Path::from("/org/example").parent(), Some(Path::from("/org")));
+    /// assert_eq!(Path::from("/org").parent(), Some(Path::from("/")));
+    /// assert_eq!(Path::from("/").parent(), None);
+    /// ```
+    pub fn parent(&self) -> Option<Path<'static>> {
+        let s: &str = self;
+        if s == "/" { return None }
+        let idx = s.rfind('/').unwrap();
+        Some(Path::from_string_unchecked(if idx == 0 { "/".into() } else { s[..idx].into() }))
+    }
+
+    /// Appends an element to this path.
+    ///
+    /// The element is appended as it is, so it must be a valid path element, i e, ASCII letters,
+    /// digits and `_`. Use `join_escaped` to make an element out of an arbitrary string.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// let p = Path::from("/org/example/devices");
+    /// assert_eq!(&*p.join("sda1").unwrap(), "/org/example/devices/sda1");
+    /// assert_eq!(&*Path::from("/").join("org").unwrap(), "/org");
+    /// assert!(p.join("a/b").is_err());
+    /// ```
+    pub fn join(&self, element: &str) -> Result<Path<'static>, String> {
+        if element.is_empty() || element.contains('/') {
+            return Err(format!("'{}' is not a single object path element", element))
+        }
+        let s: &str = self;
+        Path::new(if s == "/" { format!("/{}", element) } else { format!("{}/{}", s, element) })
+    }
+
+    /// Appends an element to this path, escaping it with `escape_element` first.
+    ///
+    /// Any string, including one containing slashes, becomes exactly one new element.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// let p = Path::from("/org/example/devices");
+    /// assert_eq!(&*p.join_escaped("my-disk"), "/org/example/devices/my_2ddisk");
+    /// assert_eq!(&*p.join_escaped("sda1"), "/org/example/devices/sda1");
+    /// ```
+    pub fn join_escaped<S: AsRef<[u8]>>(&self, element: S) -> Path<'static> {
+        let s: &str = self;
+        let e = Path::escape_element(element);
+        Path::from_string_unchecked(if s == "/" { format!("/{}", e) } else { format!("{}/{}", s, e) })
+    }
+
+    /// Iterates over the elements of this path. The root path has no elements.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// assert_eq!(Path::from("/org/example").components().collect::<Vec<_>>(), vec!["org", "example"]);
+    /// assert_eq!(Path::from("/").components().count(), 0);
+    /// ```
+    pub fn components(&self) -> impl DoubleEndedIterator<Item=&str> {
+        let s: &str = self;
+        s.split('/').filter(|e| !e.is_empty())
+    }
+
+    /// Returns true if this path is equal to `prefix`, or is a descendant of it.
+    ///
+    /// Unlike `str::starts_with`, this compares whole elements: "/org/example" has the path prefix "/org",
+    /// but not "/or".
+    pub fn has_path_prefix(&self, prefix: &str) -> bool {
+        self.strip_path_prefix(prefix).is_some()
+    }
+
+    /// Returns true if this path is a descendant of `ancestor`, i e, starts with it but is not equal to it.
+    pub fn is_descendant_of(&self, ancestor: &str) -> bool {
+        self.strip_path_prefix(ancestor).map(|r| !r.is_empty()).unwrap_or(false)
+    }
+
+    /// Returns the rest of this path relative to `prefix`, without a leading slash,
+    /// or None if this path does not have `prefix` as a path prefix (see `has_path_prefix`).
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// let p = Path::from("/org/example/a/b");
+    /// assert_eq!(p.strip_path_prefix("/org/example"), Some("a/b"));
+    /// assert_eq!(p.strip_path_prefix("/"), Some("org/example/a/b"));
+    /// assert_eq!(p.strip_path_prefix("/org/example/a/b"), Some(""));
+    /// assert_eq!(p.strip_path_prefix("/org/ex"), None);
+    /// ```
+    pub fn strip_path_prefix(&self, prefix: &str) -> Option<&str> {
+        let s: &str = self;
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() { return Some(&s[1..]) }
+        let rest = s.strip_prefix(prefix)?;
+        if rest.is_empty() { Some(rest) } else { rest.strip_prefix('/') }
+    }
+
+    /// Escapes an arbitrary string so that it can be used as a single path element.
+    ///
+    /// This is the same escaping as systemd's `sd_bus_path_encode` and (except for a leading digit)
+    /// GLib's `g_dbus_escape_object_path`: ASCII letters and digits are kept, all other bytes become
+    /// `_` followed by two lowercase hex digits. A leading digit is escaped as well, and
+    /// the empty string becomes `_`.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// assert_eq!(Path::escape_element("foo.service"), "foo_2eservice");
+    /// assert_eq!(Path::escape_element("1st"), "_31st");
+    /// assert_eq!(Path::escape_element(""), "_");
+    /// ```
+    pub fn escape_element<S: AsRef<[u8]>>(s: S) -> String {
+        let s = s.as_ref();
+        if s.is_empty() { return "_".into() }
+        let mut r = String::with_capacity(s.len());
+        for (i, &b) in s.iter().enumerate() {
+            if b.is_ascii_alphabetic() || (b.is_ascii_digit() && i > 0) { r.push(b as char) }
+            else { r.push_str(&format!("_{:02x}", b)) }
+        }
+        r
+    }
+
+    /// Reverses `escape_element`.
+    ///
+    /// Returns None if the element is not exactly what `escape_element` produces, e g if it
+    /// contains uppercase hex digits, starts with a digit, or has an escape sequence that is
+    /// malformed or not needed. The result is bytes, since the escaped string does not need to be valid UTF-8.
+    ///
+    /// ```
+    /// use dbus::Path;
+    /// assert_eq!(Path::unescape_element("foo_2eservice").unwrap(), b"foo.service");
+    /// assert_eq!(Path::unescape_element("_").unwrap(), b"");
+    /// assert_eq!(Path::unescape_element("foo_2"), None);
+    /// assert_eq!(Path::unescape_element("foo_2E"), None);
+    /// assert_eq!(Path::unescape_element("1st"), None);
+    /// ```
+    pub fn unescape_element(s: &str) -> Option<Vec<u8>> {
+        if s == "_" { return Some(vec!()) }
+        let b = s.as_bytes();
+        let mut r = Vec::with_capacity(b.len());
+        let mut i = 0;
+        while i < b.len() {
+            match b[i] {
+                b'_' => {
+                    let hex = s.get(i+1..i+3)?;
+                    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) { return None }
+                    r.push(u8::from_str_radix(hex, 16).ok()?);
+                    i += 3;
+                }
+                c if c.is_ascii_alphanumeric() => { r.push(c); i += 1; }
+                _ => return None,
+            }
+        }
+        // Rejects a leading digit and escapes of letters and digits
+        if Path::escape_element(&r) != s { return None }
+        Some(r)
+    }
+}
+
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus member, i e, a signal or method name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Member<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(Member, dbus_validate_member);
+cstring_wrapper!(Member, dbus_validate_member, check_member, "member name");
 
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus interface name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Interface<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(Interface, dbus_validate_interface);
+cstring_wrapper!(Interface, dbus_validate_interface, check_interface, "interface name");
 
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus bus name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct BusName<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(BusName, dbus_validate_bus_name);
+cstring_wrapper!(BusName, dbus_validate_bus_name, check_bus_name, "bus name");
 
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus bus name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ErrorName<'a>(Cow<'a, CStr>);
 
-cstring_wrapper!(ErrorName, dbus_validate_error_name);
+cstring_wrapper!(ErrorName, dbus_validate_error_name, check_error_name, "error name");
+
+impl<'a> BusName<'a> {
+    /// Returns true if this is a unique name, like ":1.54", which the bus assigns to each connection.
+    pub fn is_unique(&self) -> bool { self.as_bytes().first() == Some(&b':') }
+
+    /// Returns true if this is a well-known name, like "org.freedesktop.DBus", which connections request.
+    pub fn is_well_known(&self) -> bool { !self.is_unique() }
+}
+
+/// A wrapper around a string that is guaranteed to be
+/// a valid D-Bus unique bus name, e g ":1.54".
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct UniqueName<'a>(Cow<'a, CStr>);
+
+cstring_wrapper!(UniqueName, dbus_validate_bus_name, check_unique_name, "unique name", |s| {
+    if s.first() == Some(&b':') { Ok(()) } else { Err("Unique names must start with ':'".into()) }
+});
+
+/// A wrapper around a string that is guaranteed to be
+/// a valid D-Bus well-known bus name, e g "org.freedesktop.DBus".
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct WellKnownName<'a>(Cow<'a, CStr>);
+
+cstring_wrapper!(WellKnownName, dbus_validate_bus_name, check_well_known_name, "well-known name", |s| {
+    if s.first() != Some(&b':') { Ok(()) } else { Err("Well-known names cannot start with ':'".into()) }
+});
+
+impl<'a> From<UniqueName<'a>> for BusName<'a> {
+    fn from(n: UniqueName<'a>) -> BusName<'a> { BusName(n.0) }
+}
+
+impl<'a> From<WellKnownName<'a>> for BusName<'a> {
+    fn from(n: WellKnownName<'a>) -> BusName<'a> { BusName(n.0) }
+}
+
+impl<'a> TryFrom<BusName<'a>> for UniqueName<'a> {
+    type Error = ValidationError;
+    fn try_from(n: BusName<'a>) -> Result<UniqueName<'a>, ValidationError> {
+        if n.is_unique() { Ok(UniqueName(n.0)) }
+        else { Err(ValidationError::new("unique name", n.as_bytes(), 0, InvalidReason::NotUniqueName)) }
+    }
+}
+
+impl<'a> TryFrom<BusName<'a>> for WellKnownName<'a> {
+    type Error = ValidationError;
+    fn try_from(n: BusName<'a>) -> Result<WellKnownName<'a>, ValidationError> {
+        if n.is_well_known() { Ok(WellKnownName(n.0)) }
+        else { Err(ValidationError::new("well-known name", n.as_bytes(), 0, InvalidReason::NotWellKnownName)) }
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dbus_string_literal {
+    ($t: ident, $check: ident, $what: expr, $s: expr) => {{
+        const _: () = if $crate::strings::validate::$check($s.as_bytes()).is_err() {
+            panic!(concat!("invalid D-Bus ", $what, " literal"))
+        };
+        #[allow(unused_unsafe)]
+        unsafe { $crate::strings::$t::from_static_unchecked(concat!($s, "\0").as_bytes()) }
+    }}
+}
+
+/// Creates a `Path<'static>` from a string literal, which is checked at compile time.
+///
+/// No allocation or validation happens at runtime, and the macro can be used in constants.
+/// The literal is always checked, even if the no-string-validation feature is activated.
+///
+/// ```
+/// use dbus::{path, Path};
+/// const ROOT: Path<'static> = path!("/org/example");
+/// assert_eq!(&*ROOT, "/org/example");
+/// ```
+///
+/// ```compile_fail
+/// let p = dbus::path!("/org/example/");
+/// ```
+#[macro_export]
+macro_rules! path {
+    ($s: literal) => { $crate::__dbus_string_literal!(Path, check_path, "object path", $s) }
+}
+
+/// Creates an `Interface<'static>` from a string literal, which is checked at compile time.
+///
+/// See `path!` for details.
+///
+/// ```
+/// let i = dbus::interface!("org.freedesktop.DBus.Properties");
+/// assert_eq!(&*i, "org.freedesktop.DBus.Properties");
+/// ```
+///
+/// ```compile_fail
+/// let i = dbus::interface!("org.freedesktop.DBus.");
+/// ```
+#[macro_export]
+macro_rules! interface {
+    ($s: literal) => { $crate::__dbus_string_literal!(Interface, check_interface, "interface", $s) }
+}
+
+/// Creates a `Member<'static>` from a string literal, which is checked at compile time.
+///
+/// See `path!` for details.
+///
+/// ```
+/// let m = dbus::member!("GetAll");
+/// assert_eq!(&*m, "GetAll");
+/// ```
+///
+/// ```compile_fail
+/// let m = dbus::member!("Get.All");
+/// ```
+#[macro_export]
+macro_rules! member {
+    ($s: literal) => { $crate::__dbus_string_literal!(Member, check_member, "member", $s) }
+}
+
+/// Creates a `BusName<'static>` from a string literal, which is checked at compile time.
+///
+/// See `path!` for details.
+///
+/// ```
+/// let b = dbus::bus_name!("org.freedesktop.DBus");
+/// assert_eq!(&*b, "org.freedesktop.DBus");
+/// ```
+///
+/// ```compile_fail
+/// let b = dbus::bus_name!("freedesktop");
+/// ```
+#[macro_export]
+macro_rules! bus_name {
+    ($s: literal) => { $crate::__dbus_string_literal!(BusName, check_bus_name, "bus name", $s) }
+}
+
+/// Creates an `ErrorName<'static>` from a string literal, which is checked at compile time.
+///
+/// See `path!` for details.
+///
+/// ```
+/// let e = dbus::error_name!("org.freedesktop.DBus.Error.Failed");
+/// assert_eq!(&*e, "org.freedesktop.DBus.Error.Failed");
+/// ```
+///
+/// ```compile_fail
+/// let e = dbus::error_name!("org.freedesktop.DBus.Error.1Failed");
+/// ```
+#[macro_export]
+macro_rules! error_name {
+    ($s: literal) => { $crate::__dbus_string_literal!(ErrorName, check_error_name, "error name", $s) }
+}
+
+/// Creates a `Signature<'static>` from a string literal, which is checked at compile time.
+///
+/// The signature must be a single complete type, just like for `Signature::new`.
+/// See `path!` for details.
+///
+/// ```
+/// let s = dbus::signature!("a{sv}");
+/// assert_eq!(&*s, "a{sv}");
+/// ```
+///
+/// ```compile_fail
+/// let s = dbus::signature!("a{vs}");
+/// ```
+#[macro_export]
+macro_rules! signature {
+    ($s: literal) => { $crate::__dbus_string_literal!(Signature, check_signature, "signature", $s) }
+}
 
 #[test]
 fn some_path() {
@@ -194,7 +660,12 @@ fn some_path() {
     let p2 = Path::new("##invalid##");
     assert_eq!(p1, Path(Cow::Borrowed(unsafe { CStr::from_ptr(b"/valid\0".as_ptr() as *const c_char) })));
     #[cfg(not(feature = "no-string-validation"))]
-    assert_eq!(p2, Err("Object path was not valid: '##invalid##'".into()));
+    {
+        assert_eq!(p2, Err("Object path was not valid: '##invalid##'".into()));
+        let e = Path::new_checked("##invalid##").unwrap_err();
+        assert_eq!((e.reason(), e.position()), (InvalidReason::MissingLeadingSlash, 0));
+        assert_eq!(e.to_string(), "Invalid object path '##invalid##': it does not start with '/' at position 0");
+    }
     #[cfg(feature = "no-string-validation")]
     assert_eq!(p2, Ok(Path(Cow::Borrowed(unsafe { CStr::from_ptr(b"##invalid##\0".as_ptr() as *const c_char) }))));
 }
@@ -217,3 +688,68 @@ fn reborrow_path() {
 fn make_sig() {
     assert_eq!(&*Signature::make::<(&str, u8)>(), "(sy)");
 }
+
+#[test]
+fn path_manipulation() {
+    let p = Path::from("/org/example/a");
+    assert!(p.has_path_prefix("/org/example"));
+    assert!(p.has_path_prefix("/org/example/"));
+    assert!(p.has_path_prefix(&Path::from("/org/example/a")));
+    assert!(!p.has_path_prefix("/org/ex"));
+    assert!(p.starts_with("/org/ex"));
+    assert!(p.is_descendant_of("/"));
+    assert!(!p.is_descendant_of("/org/example/a"));
+    assert!(!Path::from("/").is_descendant_of("/"));
+    assert_eq!(p.components().rev().next(), Some("a"));
+
+    let mut q = Path::from("/org/example/a/b_c");
+    while let Some(pp) = q.parent() { q = pp; }
+    assert_eq!(q, Path::default());
+
+    for s in &["", "a", "0day", "foo-bar.service", "/dev/sda", "äö_", "abc123"] {
+        let e = Path::escape_element(s);
+        let j = p.join_escaped(s);
+        assert_eq!(j.components().last(), Some(&*e));
+        assert_eq!(j.parent().unwrap(), p);
+        assert_eq!(Path::unescape_element(&e).unwrap(), s.as_bytes());
+    }
+    assert_eq!(Path::unescape_element("a_zz"), None);
+    assert_eq!(Path::unescape_element("a-b"), None);
+    assert_eq!(Path::unescape_element("_3"), None);
+    assert_eq!(Path::unescape_element("a_2D"), None);
+    assert_eq!(Path::unescape_element("_61"), None);
+    assert_eq!(Path::unescape_element("0day"), None);
+    assert_eq!(&*p.join("b_c").unwrap(), "/org/example/a/b_c");
+    assert!(p.join("").is_err());
+    assert!(p.join("a/b").is_err());
+}
+
+#[test]
+fn string_literals() {
+    const P: Path<'static> = path!("/org/example");
+    assert_eq!(P, Path::new("/org/example").unwrap());
+    assert_eq!(signature!("(ia{s(ov)})"), Signature::new("(ia{s(ov)})").unwrap());
+    assert_eq!(bus_name!(":1.54"), BusName::new(":1.54").unwrap());
+    assert_eq!(&*member!("Get").as_cstr().to_bytes(), b"Get");
+}
+
+#[test]
+fn bus_name_kinds() {
+    let u = BusName::from(":1.54");
+    let w = BusName::from("org.example.Foo");
+    assert!(u.is_unique() && !u.is_well_known());
+    assert!(w.is_well_known() && !w.is_unique());
+    assert_eq!(UniqueName::try_from(u.clone()).unwrap(), UniqueName::from(":1.54"));
+    assert_eq!(UniqueName::try_from(w.clone()).unwrap_err().reason(), InvalidReason::NotUniqueName);
+    assert_eq!(BusName::from(WellKnownName::try_from(w.clone()).unwrap()), w);
+    assert!(WellKnownName::try_from(u).is_err());
+    assert!(Interface::from_slice(b"org.ex\0ample\0").is_err());
+    let e = Interface::new_checked("org.ex\0ample").unwrap_err();
+    assert_eq!((e.reason(), e.position()), (InvalidReason::ContainsNul, 6));
+    #[cfg(not(feature = "no-string-validation"))]
+    {
+        assert_eq!(WellKnownName::new_checked("org.1example").unwrap_err().position(), 4);
+        assert!(WellKnownName::new(":1.54").is_err());
+        assert_eq!(UniqueName::new_checked("org.example").unwrap_err().reason(), InvalidReason::NotUniqueName);
+    }
+}
@@ -0,0 +1,208 @@
+//! Validation of D-Bus strings as const fns, so that the `path!`, `interface!` etc macros
+//! can check their literals at compile time.
+//!
+//! These follow the rules in the D-Bus specification, i e, the same rules as libdbus uses.
+//! On failure, they return the byte position of the problem and what was wrong.
+//!
+//! At runtime, libdbus decides whether a string is valid; these are then only used by
+//! `new_checked` to describe the problem.
+
+use super::InvalidReason::{self, *};
+
+pub type Check = Result<(), (usize, InvalidReason)>;
+
+const MAX_NAME_LEN: usize = 255;
+const MAX_DEPTH: u32 = 32;
+
+const fn is_element_char(c: u8) -> bool { c.is_ascii_alphanumeric() || c == b'_' }
+
+const fn check_len(s: &[u8]) -> Check {
+    if s.is_empty() { Err((0, Empty)) }
+    else if s.len() > MAX_NAME_LEN { Err((MAX_NAME_LEN, TooLong)) }
+    else { Ok(()) }
+}
+
+/// Checks that s is a valid object path.
+pub const fn check_path(s: &[u8]) -> Check {
+    if s.is_empty() { return Err((0, Empty)) }
+    if s[0] != b'/' { return Err((0, MissingLeadingSlash)) }
+    if s.len() == 1 { return Ok(()) }
+    let mut i = 1;
+    while i < s.len() {
+        let c = s[i];
+        if c == b'/' {
+            if s[i - 1] == b'/' { return Err((i, EmptyElement)) }
+        }
+        else if !is_element_char(c) { return Err((i, InvalidCharacter)) }
+        i += 1;
+    }
+    if s[s.len() - 1] == b'/' { Err((s.len(), EmptyElement)) } else { Ok(()) }
+}
+
+// Interfaces, error names and well-known bus names are all two or more elements separated by dots.
+const fn check_dotted(s: &[u8], allow_dash: bool) -> Check {
+    if let Err(e) = check_len(s) { return Err(e) }
+    let mut i = 0;
+    let mut elements = 0;
+    let mut after_dot = true;
+    while i < s.len() {
+        let c = s[i];
+        if c == b'.' {
+            if after_dot { return Err((i, EmptyElement)) }
+            after_dot = true;
+        } else {
+            if !(is_element_char(c) || (allow_dash && c == b'-')) { return Err((i, InvalidCharacter)) }
+            if after_dot {
+                if c.is_ascii_digit() { return Err((i, LeadingDigit)) }
+                elements += 1;
+            }
+            after_dot = false;
+        }
+        i += 1;
+    }
+    if after_dot { Err((s.len(), EmptyElement)) }
+    else if elements < 2 { Err((s.len(), TooFewElements)) }
+    else { Ok(()) }
+}
+
+/// Checks that s is a valid interface name.
+pub const fn check_interface(s: &[u8]) -> Check { check_dotted(s, false) }
+
+/// Checks that s is a valid error name.
+pub const fn check_error_name(s: &[u8]) -> Check { check_dotted(s, false) }
+
+/// Checks that s is a valid member (method or signal) name.
+pub const fn check_member(s: &[u8]) -> Check {
+    if let Err(e) = check_len(s) { return Err(e) }
+    if s[0].is_ascii_digit() { return Err((0, LeadingDigit)) }
+    let mut i = 0;
+    while i < s.len() {
+        if !is_element_char(s[i]) { return Err((i, InvalidCharacter)) }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Checks that s is a valid unique bus name, e g ":1.54".
+pub const fn check_unique_name(s: &[u8]) -> Check {
+    if let Err(e) = check_len(s) { return Err(e) }
+    if s[0] != b':' { return Err((0, NotUniqueName)) }
+    // Like libdbus, only require that every dot is followed by a valid character.
+    let mut i = 1;
+    while i < s.len() {
+        let c = s[i];
+        if c == b'.' {
+            if i + 1 == s.len() || s[i + 1] == b'.' { return Err((i + 1, EmptyElement)) }
+        } else if !(is_element_char(c) || c == b'-') { return Err((i, InvalidCharacter)) }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Checks that s is a valid well-known bus name, e g "org.freedesktop.DBus".
+pub const fn check_well_known_name(s: &[u8]) -> Check {
+    if !s.is_empty() && s[0] == b':' { return Err((0, NotWellKnownName)) }
+    check_dotted(s, true)
+}
+
+/// Checks that s is a valid bus name, either unique or well-known.
+pub const fn check_bus_name(s: &[u8]) -> Check {
+    if !s.is_empty() && s[0] == b':' { check_unique_name(s) } else { check_well_known_name(s) }
+}
+
+/// Checks that s is a valid signature of a single complete type.
+pub const fn check_signature(s: &[u8]) -> Check {
+    if let Err(e) = check_len(s) { return Err(e) }
+    match single_type(s, 0, 0, 0, 0) {
+        Ok(n) if n == s.len() => Ok(()),
+        Ok(n) => Err((n, NotSingleType)),
+        Err(e) => Err(e),
+    }
+}
+
+const fn is_basic_type(c: u8) -> bool {
+    matches!(c, b'y' | b'b' | b'n' | b'q' | b'i' | b'u' | b'x' | b't' | b'd' | b'h' | b's' | b'o' | b'g')
+}
+
+// Returns the index after the complete type starting at index i.
+const fn single_type(s: &[u8], i: usize, arrays: u32, structs: u32, dicts: u32) -> Result<usize, (usize, InvalidReason)> {
+    if i >= s.len() { return Err((i, UnexpectedEnd)) }
+    match s[i] {
+        b'v' => Ok(i + 1),
+        c if is_basic_type(c) => Ok(i + 1),
+        b'a' => {
+            if arrays >= MAX_DEPTH { return Err((i, TooDeep)) }
+            if i + 1 >= s.len() || s[i + 1] != b'{' { return single_type(s, i + 1, arrays + 1, structs, dicts) }
+            if dicts >= MAX_DEPTH { return Err((i + 1, TooDeep)) }
+            if i + 2 >= s.len() { return Err((i + 2, UnexpectedEnd)) }
+            if s[i + 2] == b'}' { return Err((i + 2, InvalidDictEntry)) }
+            if !is_basic_type(s[i + 2]) { return Err((i + 2, DictKeyNotBasic)) }
+            if i + 3 < s.len() && s[i + 3] == b'}' { return Err((i + 3, InvalidDictEntry)) }
+            match single_type(s, i + 3, arrays + 1, structs, dicts + 1) {
+                Ok(n) if n >= s.len() => Err((n, UnexpectedEnd)),
+                Ok(n) if s[n] == b'}' => Ok(n + 1),
+                Ok(n) => Err((n, InvalidDictEntry)),
+                Err(e) => Err(e),
+            }
+        }
+        b'(' => {
+            if structs >= MAX_DEPTH { return Err((i, TooDeep)) }
+            let mut j = i + 1;
+            if j < s.len() && s[j] == b')' { return Err((j, EmptyStruct)) }
+            while j < s.len() && s[j] != b')' {
+                match single_type(s, j, arrays, structs + 1, dicts) {
+                    Ok(n) => j = n,
+                    Err(e) => return Err(e),
+                }
+            }
+            if j < s.len() { Ok(j + 1) } else { Err((j, UnexpectedEnd)) }
+        }
+        b'{' => Err((i, InvalidDictEntry)),
+        _ => Err((i, InvalidCharacter)),
+    }
+}
+
+#[test]
+fn same_as_libdbus() {
+    use std::ffi::CString;
+    use crate::{ffi, Error};
+    let samples = ["", "/", "//", "/a", "/a/", "/a//b", "/a/b_9/C", "/a-b", "a", "a.b", "a.b.", ".a.b", "a..b",
+        "a.9b", "a9.b", "a-b.c", ":1.54", ":1", ":", ":.a", ":a..b", ":a.", ":a-9.x", "org.freedesktop.DBus", "_a._b",
+        "Hello", "9ab", "a b", "y", "v", "ai", "a{sv}", "a{vs}", "{sv}", "a{s}", "a{}", "a{svs}", "()", "(i)", "(i",
+        "i)", "(ia{s(ov)})aai", "a{s(i)}", "ii", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaai", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaai",
+        "z", "ä.b"];
+    let long = format!("a.{}", "b".repeat(254));
+    let libdbus = |s: &str, f: unsafe extern "C" fn(*const std::os::raw::c_char, *mut ffi::DBusError) -> u32| {
+        let c = CString::new(s).unwrap();
+        let mut e = Error::empty();
+        unsafe { f(c.as_ptr(), e.get_mut()) != 0 }
+    };
+    for s in samples.iter().copied().chain(Some(&*long)) {
+        let b = s.as_bytes();
+        assert_eq!(check_path(b).is_ok(), libdbus(s, ffi::dbus_validate_path), "path {:?}", s);
+        assert_eq!(check_interface(b).is_ok(), libdbus(s, ffi::dbus_validate_interface), "interface {:?}", s);
+        assert_eq!(check_error_name(b).is_ok(), libdbus(s, ffi::dbus_validate_error_name), "error name {:?}", s);
+        assert_eq!(check_bus_name(b).is_ok(), libdbus(s, ffi::dbus_validate_bus_name), "bus name {:?}", s);
+        assert_eq!(check_member(b).is_ok(), libdbus(s, ffi::dbus_validate_member), "member {:?}", s);
+        assert_eq!(check_signature(b).is_ok(), libdbus(s, ffi::dbus_signature_validate_single), "signature {:?}", s);
+    }
+}
+
+#[test]
+fn reasons() {
+    assert_eq!(check_path(b"/a//b"), Err((3, EmptyElement)));
+    assert_eq!(check_path(b"/a/"), Err((3, EmptyElement)));
+    assert_eq!(check_interface(b"org.freedesktop"), Ok(()));
+    assert_eq!(check_interface(b"org"), Err((3, TooFewElements)));
+    assert_eq!(check_interface(b"org.9x"), Err((4, LeadingDigit)));
+    assert_eq!(check_interface(b"org.a-b"), Err((5, InvalidCharacter)));
+    assert_eq!(check_well_known_name(b"org.a-b"), Ok(()));
+    assert_eq!(check_well_known_name(b":1.5"), Err((0, NotWellKnownName)));
+    assert_eq!(check_unique_name(b"org.a"), Err((0, NotUniqueName)));
+    assert_eq!(check_member(b""), Err((0, Empty)));
+    assert_eq!(check_signature(b"a{vs}"), Err((2, DictKeyNotBasic)));
+    assert_eq!(check_signature(b"(ia{sv)"), Err((6, InvalidDictEntry)));
+    assert_eq!(check_signature(b"(i"), Err((2, UnexpectedEnd)));
+    assert_eq!(check_signature(b"ii"), Err((1, NotSingleType)));
+    assert_eq!(check_signature(b"()"), Err((1, EmptyStruct)));
+}
@@ -0,0 +1,569 @@
+//! A small in-process message bus, for tests.
+//!
+//! `TestBus` runs a minimal implementation of the message bus in a background thread,
+//! listening on a private socket. This lets tests run without a session bus being available.
+//!
+//! It implements routing of method calls, replies and signals, and the most common methods of
+//! org.freedesktop.DBus: `Hello`, `RequestName`, `ReleaseName`, `AddMatch`, `RemoveMatch`,
+//! `GetNameOwner`, `NameHasOwner`, `ListNames`, `ListQueuedOwners` and `GetId`. It also emits the
+//! `NameOwnerChanged`, `NameAcquired` and `NameLost` signals. Monitors can be set up through
+//! `org.freedesktop.DBus.Monitoring.BecomeMonitor`. There is no access control, no
+//! service activation and no eavesdropping.
+//!
+//! # Example
+//!
+//! ```
+//! use dbus::testbus::TestBus;
+//! use dbus::blocking::Connection;
+//! use std::time::Duration;
+//!
+//! let bus = TestBus::new()?;
+//! let c: Connection = bus.connect()?.into();
+//! c.request_name("com.example.test", false, true, false)?;
+//! let proxy = c.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(5));
+//! let (owner,): (String,) = proxy.method_call("org.freedesktop.DBus", "GetNameOwner", ("com.example.test",))?;
+//! assert_eq!(&*c.unique_name(), &*owner);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{ffi, Error, Message, MessageType, to_c_str};
+use crate::arg::{AppendAll, IterAppend};
+use crate::channel::{self, Channel, Server};
+use crate::strings::{BusName, ErrorName};
+use crate::message::MatchRule;
+use std::collections::BTreeMap;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+const FLAG_ALLOW_REPLACEMENT: u32 = 1;
+const FLAG_REPLACE_EXISTING: u32 = 2;
+const FLAG_DO_NOT_QUEUE: u32 = 4;
+
+/// A message bus running in a background thread.
+///
+/// The bus is shut down when this struct is dropped.
+#[derive(Debug)]
+pub struct TestBus {
+    address: String,
+    stop: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestBus {
+    /// Starts a new bus, listening on a private address.
+    pub fn new() -> Result<TestBus, Error> {
+        let server = Server::listen("unix:tmpdir=/tmp")?;
+        let address = server.address();
+        let (stop, wakeup) = UnixStream::pair().map_err(|e| Error::new_failed(&e.to_string()))?;
+        let bus = Bus { guid: server.guid(), server, peers: vec!(), names: BTreeMap::new(), next_id: 1, outbox: vec!() };
+        let thread = thread::spawn(move || bus.run(wakeup));
+        Ok(TestBus { address, stop: Some(stop), thread: Some(thread) })
+    }
+
+    /// The address of the bus, which can be passed to `Channel::open_private`.
+    pub fn address(&self) -> &str { &self.address }
+
+    /// Opens a new connection to the bus, and registers it (i e, calls Hello).
+    ///
+    /// The returned channel can be converted into the various Connection structs.
+    pub fn connect(&self) -> Result<Channel, Error> {
+        let mut c = Channel::open_private(&self.address)?;
+        c.register()?;
+        Ok(c)
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        // Closing our end of the socket wakes up the bus thread
+        self.stop.take();
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+/// A parsed match rule, along with a canonical form of it, so that rules can be compared.
+#[derive(Debug, Clone)]
+struct Rule {
+    rule: MatchRule<'static>,
+    canonical: String,
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool { self.canonical == other.canonical }
+}
+
+fn invalid_rule(e: Error) -> DriverError {
+    ("org.freedesktop.DBus.Error.MatchRuleInvalid", e.message().unwrap_or("Invalid match rule").into())
+}
+
+impl Rule {
+    fn parse(s: &str) -> Result<Rule, Error> {
+        let mut rule = MatchRule::parse(s)?;
+        rule.args.sort();
+        rule.args_path.sort();
+        // The bus knows who owns which name, so there is no need to guess, see `matches`
+        rule.strict_sender = true;
+        let canonical = rule.match_str();
+        Ok(Rule { rule, canonical })
+    }
+
+    fn matches(&self, m: &Message, names: &BTreeMap<String, Vec<Owner>>) -> bool {
+        match &self.rule.sender {
+            Some(s) if !s.is_unique() && &**s != BUS_NAME => {
+                let owner = names.get(&**s).and_then(|o| o.first()).map(|o| &*o.unique);
+                if owner.is_none() || m.sender().as_deref() != owner { return false }
+                let mut rule = self.rule.clone();
+                rule.sender = None;
+                rule.matches(m)
+            }
+            _ => self.rule.matches(m),
+        }
+    }
+}
+
+fn copy_with_sender(m: &Message, sender: &str) -> Message {
+    let p = unsafe { ffi::dbus_message_copy(m.ptr()) };
+    if p.is_null() { panic!("D-Bus error: dbus_message_copy failed") }
+    // The copy gets a zero serial, but replies must refer to the original one
+    unsafe { ffi::dbus_message_set_serial(p, m.get_serial()) };
+    let mut m = Message::from_ptr(p, false);
+    set_sender(&mut m, sender);
+    m
+}
+
+fn set_sender(m: &mut Message, sender: &str) {
+    let s = to_c_str(sender);
+    assert!(unsafe { ffi::dbus_message_set_sender(m.ptr(), s.as_ptr()) } != 0);
+}
+
+#[derive(Debug)]
+struct Owner {
+    unique: String,
+    flags: u32,
+}
+
+struct Peer {
+    channel: Channel,
+    name: Option<String>,
+    rules: Vec<Rule>,
+    /// Set if the peer is a monitor, to the rules it is monitoring
+    monitor: Option<Vec<Rule>>,
+}
+
+type DriverError = (&'static str, String);
+
+fn invalid_args(s: String) -> DriverError { ("org.freedesktop.DBus.Error.InvalidArgs", s) }
+
+struct Bus {
+    server: Server,
+    guid: String,
+    peers: Vec<Peer>,
+    names: BTreeMap<String, Vec<Owner>>,
+    next_id: u32,
+    outbox: Vec<Message>,
+}
+
+impl Bus {
+    fn run(mut self, wakeup: UnixStream) {
+        loop {
+            while let Ok(Some(mut c)) = self.server.accept(Some(Duration::from_millis(0))) {
+                c.set_watch_enabled(true);
+                self.peers.push(Peer { channel: c, name: None, rules: vec!(), monitor: None });
+            }
+            // Keep going until there is nothing more to read, so that no incoming
+            // message is left waiting in a queue while we're polling.
+            let mut busy = true;
+            while busy {
+                busy = false;
+                let mut i = 0;
+                while i < self.peers.len() {
+                    let ok = self.peers[i].channel.read_write(Some(Duration::from_millis(0))).is_ok();
+                    while let Some(m) = self.peers[i].channel.pop_message() {
+                        busy = true;
+                        self.handle(i, m);
+                    }
+                    if !ok || !self.peers[i].channel.is_connected() {
+                        busy = true;
+                        self.disconnect(i);
+                    } else { i += 1 }
+                }
+            }
+
+            let mut fds = vec!(libc::pollfd { fd: wakeup.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+            let watches = self.server.watch_fds().into_iter().chain(self.peers.iter().map(|p| p.channel.watch()));
+            fds.extend(watches.map(|w| libc::pollfd {
+                fd: w.fd,
+                events: (if w.read { libc::POLLIN } else { 0 }) | (if w.write { libc::POLLOUT } else { 0 }),
+                revents: 0,
+            }));
+            unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 1000) };
+            if fds[0].revents != 0 { return }
+        }
+    }
+
+    fn peer_index(&self, unique: &str) -> Option<usize> {
+        self.peers.iter().position(|p| p.name.as_deref() == Some(unique))
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        if name.starts_with(':') { return self.peer_index(name) }
+        let o = self.names.get(name)?.first()?;
+        self.peer_index(&o.unique)
+    }
+
+    /// Sends a copy of the message to every monitor that is interested in it.
+    fn observe(&self, m: &Message) {
+        for p in &self.peers {
+            let rules = match &p.monitor { Some(r) => r, None => continue };
+            // A monitor does not see the reply to its own BecomeMonitor call
+            if m.destination().is_some() && m.destination().as_deref() == p.name.as_deref() { continue }
+            if rules.is_empty() || rules.iter().any(|r| r.matches(m, &self.names)) {
+                let _ = p.channel.send(Message::from_ptr(m.ptr(), true));
+            }
+        }
+    }
+
+    fn route(&mut self, m: Message) {
+        self.observe(&m);
+        let dest = m.destination().map(|d| d.to_string());
+        if let Some(d) = dest {
+            if let Some(idx) = self.resolve(&d) {
+                let _ = self.peers[idx].channel.send(m);
+            } else if m.msg_type() == MessageType::MethodCall && !m.get_no_reply() {
+                let mut e = m.error(&"org.freedesktop.DBus.Error.ServiceUnknown".into(),
+                    &to_c_str(&format!("The name {} was not provided by any .service files", d)));
+                set_sender(&mut e, BUS_NAME);
+                self.route(e);
+            }
+            return;
+        }
+        for p in &self.peers {
+            if p.name.is_some() && p.monitor.is_none() && p.rules.iter().any(|r| r.matches(&m, &self.names)) {
+                let _ = p.channel.send(Message::from_ptr(m.ptr(), true));
+            }
+        }
+    }
+
+    fn flush_outbox(&mut self) {
+        for m in std::mem::take(&mut self.outbox) { self.route(m) }
+    }
+
+    fn signal<A: AppendAll>(&mut self, member: &'static str, dest: Option<&str>, args: A) {
+        let mut m = Message::signal(&BUS_PATH.into(), &BUS_NAME.into(), &member.into());
+        args.append(&mut IterAppend::new(&mut m));
+        set_sender(&mut m, BUS_NAME);
+        if let Some(d) = dest { m.set_destination(Some(BusName::from(d))) }
+        self.outbox.push(m);
+    }
+
+    fn handle(&mut self, from: usize, m: Message) {
+        let sender = match self.peers[from].name.clone() {
+            Some(s) => s,
+            None => {
+                let r = if m.member().as_deref() == Some("Hello") &&
+                    m.destination().as_deref() == Some(BUS_NAME) {
+                    let unique = format!(":1.{}", self.next_id);
+                    self.next_id += 1;
+                    self.peers[from].name = Some(unique.clone());
+                    self.observe(&copy_with_sender(&m, &unique));
+                    self.signal("NameOwnerChanged", None, (&*unique, "", &*unique));
+                    self.signal("NameAcquired", Some(&unique), (&*unique,));
+                    m.method_return().append1(&*unique)
+                } else {
+                    m.error(&"org.freedesktop.DBus.Error.AccessDenied".into(),
+                        &to_c_str("Client tried to send a message other than Hello without being registered"))
+                };
+                let mut r = r;
+                set_sender(&mut r, BUS_NAME);
+                self.observe(&r);
+                let _ = self.peers[from].channel.send(r);
+                self.flush_outbox();
+                return;
+            }
+        };
+        // Monitors are not allowed to send anything
+        if self.peers[from].monitor.is_some() { return }
+        let m = copy_with_sender(&m, &sender);
+        if m.destination().as_deref() != Some(BUS_NAME) { return self.route(m) }
+        self.observe(&m);
+
+        let reply = if m.msg_type() != MessageType::MethodCall { None } else {
+            match self.driver(from, &sender, &m) {
+                Ok(Some(r)) => Some(r),
+                Ok(None) => channel::default_reply(&m),
+                Err((name, text)) => Some(m.error(&ErrorName::from(name), &to_c_str(&text))),
+            }
+        };
+        if let Some(mut r) = reply {
+            if !m.get_no_reply() {
+                set_sender(&mut r, BUS_NAME);
+                self.route(r);
+            }
+        }
+        self.flush_outbox();
+    }
+
+    fn driver(&mut self, from: usize, sender: &str, m: &Message) -> Result<Option<Message>, DriverError> {
+        if m.interface().as_deref() == Some("org.freedesktop.DBus.Monitoring") && m.member().as_deref() == Some("BecomeMonitor") {
+            let (rules, _flags): (Vec<&str>, u32) = m.read2().map_err(|e| invalid_args(e.to_string()))?;
+            let rules = rules.into_iter().map(Rule::parse).collect::<Result<Vec<_>, _>>().map_err(invalid_rule)?;
+            let owned: Vec<String> = self.names.iter()
+                .filter(|(_, o)| o.iter().any(|o| o.unique == sender)).map(|(n, _)| n.clone()).collect();
+            for n in owned { self.release_name(sender, &n); }
+            self.peers[from].rules.clear();
+            self.peers[from].monitor = Some(rules);
+            return Ok(Some(m.method_return()));
+        }
+        if m.interface().filter(|i| &**i != BUS_NAME).is_some() { return Ok(None) }
+        let member = match m.member() { Some(x) => x.to_string(), None => return Ok(None) };
+        let arg0 = || m.read1::<&str>().map_err(|e| invalid_args(e.to_string()));
+        Ok(Some(match &*member {
+            "Hello" => return Err(("org.freedesktop.DBus.Error.Failed", "Already handled an Hello message".into())),
+            "RequestName" => {
+                let (name, flags): (&str, u32) = m.read2().map_err(|e| invalid_args(e.to_string()))?;
+                check_name(name)?;
+                m.method_return().append1(self.request_name(sender, name, flags))
+            }
+            "ReleaseName" => {
+                let name = arg0()?;
+                check_name(name)?;
+                m.method_return().append1(self.release_name(sender, name))
+            }
+            "AddMatch" => {
+                let rule = Rule::parse(arg0()?).map_err(invalid_rule)?;
+                self.peers[from].rules.push(rule);
+                m.method_return()
+            }
+            "RemoveMatch" => {
+                let rule = Rule::parse(arg0()?).map_err(invalid_rule)?;
+                let rules = &mut self.peers[from].rules;
+                let idx = rules.iter().position(|r| *r == rule).ok_or_else(||
+                    ("org.freedesktop.DBus.Error.MatchRuleNotFound", "The given match rule wasn't found and can't be removed".into()))?;
+                rules.remove(idx);
+                m.method_return()
+            }
+            "GetNameOwner" => {
+                let name = arg0()?;
+                let owner = if name == BUS_NAME { Some(BUS_NAME.to_string()) }
+                    else { self.resolve(name).and_then(|i| self.peers[i].name.clone()) };
+                let owner = owner.ok_or_else(|| ("org.freedesktop.DBus.Error.NameHasNoOwner",
+                    format!("Could not get owner of name '{}': no such name", name)))?;
+                m.method_return().append1(owner)
+            }
+            "NameHasOwner" => {
+                let name = arg0()?;
+                m.method_return().append1(name == BUS_NAME || self.resolve(name).is_some())
+            }
+            "ListNames" => {
+                let mut v = vec!(BUS_NAME.to_string());
+                v.extend(self.names.keys().cloned());
+                v.extend(self.peers.iter().filter_map(|p| p.name.clone()));
+                m.method_return().append1(v)
+            }
+            "ListQueuedOwners" => {
+                let name = arg0()?;
+                let v: Vec<String> = if name.starts_with(':') { self.peer_index(name).map(|_| name.to_string()).into_iter().collect() }
+                    else { self.names.get(name).map(|o| o.iter().map(|o| o.unique.clone()).collect()).unwrap_or_default() };
+                if v.is_empty() {
+                    return Err(("org.freedesktop.DBus.Error.NameHasNoOwner",
+                        format!("Could not get owners of name '{}': no such name", name)));
+                }
+                m.method_return().append1(v)
+            }
+            "GetId" => m.method_return().append1(&*self.guid),
+            _ => return Ok(None),
+        }))
+    }
+
+    fn request_name(&mut self, unique: &str, name: &str, flags: u32) -> u32 {
+        let owners = self.names.entry(name.into()).or_default();
+        if owners.is_empty() {
+            owners.push(Owner { unique: unique.into(), flags });
+            self.signal("NameOwnerChanged", None, (name, "", unique));
+            self.signal("NameAcquired", Some(unique), (name,));
+            return 1;
+        }
+        if owners[0].unique == unique {
+            owners[0].flags = flags;
+            return 4;
+        }
+        if (owners[0].flags & FLAG_ALLOW_REPLACEMENT != 0) && (flags & FLAG_REPLACE_EXISTING != 0) {
+            owners.retain(|o| o.unique != unique);
+            let old = owners.remove(0);
+            let old_unique = old.unique.clone();
+            owners.insert(0, Owner { unique: unique.into(), flags });
+            if old.flags & FLAG_DO_NOT_QUEUE == 0 { owners.insert(1, old) }
+            self.signal("NameLost", Some(&old_unique), (name,));
+            self.signal("NameOwnerChanged", None, (name, &*old_unique, unique));
+            self.signal("NameAcquired", Some(unique), (name,));
+            return 1;
+        }
+        if flags & FLAG_DO_NOT_QUEUE != 0 {
+            owners.retain(|o| o.unique != unique);
+            return 3;
+        }
+        match owners.iter_mut().find(|o| o.unique == unique) {
+            Some(o) => o.flags = flags,
+            None => owners.push(Owner { unique: unique.into(), flags }),
+        }
+        2
+    }
+
+    fn release_name(&mut self, unique: &str, name: &str) -> u32 {
+        let owners = match self.names.get_mut(name) {
+            Some(o) => o,
+            None => return 2,
+        };
+        let idx = match owners.iter().position(|o| o.unique == unique) {
+            Some(i) => i,
+            None => return 3,
+        };
+        owners.remove(idx);
+        if idx == 0 {
+            let new_owner = owners.first().map(|o| o.unique.clone());
+            if new_owner.is_none() { self.names.remove(name); }
+            self.signal("NameLost", Some(unique), (name,));
+            self.signal("NameOwnerChanged", None, (name, unique, new_owner.as_deref().unwrap_or("")));
+            if let Some(n) = new_owner { self.signal("NameAcquired", Some(&n), (name,)); }
+        }
+        1
+    }
+
+    fn disconnect(&mut self, idx: usize) {
+        let p = self.peers.remove(idx);
+        if let Some(unique) = p.name {
+            let owned: Vec<String> = self.names.iter()
+                .filter(|(_, o)| o.iter().any(|o| o.unique == unique)).map(|(n, _)| n.clone()).collect();
+            for n in owned { self.release_name(&unique, &n); }
+            self.signal("NameOwnerChanged", None, (&*unique, &*unique, ""));
+        }
+        self.flush_outbox();
+    }
+}
+
+fn check_name(name: &str) -> Result<(), DriverError> {
+    BusName::new(name).map_err(invalid_args)?;
+    if name.starts_with(':') || name == BUS_NAME {
+        return Err(invalid_args(format!("Cannot acquire a service named '{}'", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blocking::{Connection, BlockingSender, Proxy};
+    use crate::blocking::stdintf::org_freedesktop_dbus::{RequestNameReply, ReleaseNameReply};
+
+    fn pop_signal(c: &Channel, member: &str) -> Message {
+        loop {
+            let m = c.blocking_pop_message(Duration::from_secs(5)).unwrap().expect("Timeout waiting for signal");
+            if m.member().as_deref() == Some(member) { return m }
+        }
+    }
+
+    fn call<R: crate::arg::ReadAll, A: AppendAll, C: BlockingSender>(c: &C, m: &str, a: A) -> Result<R, Error> {
+        Proxy::new(BUS_NAME, BUS_PATH, Duration::from_secs(5), c).method_call(BUS_NAME, m, a)
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(Rule::parse("type='signal', member='A'").unwrap(), Rule::parse("member=A,type=signal").unwrap());
+        assert_eq!(Rule::parse("arg1=b,arg0=a").unwrap(), Rule::parse("arg0=a,arg1=b").unwrap());
+        assert_ne!(Rule::parse("arg0=a").unwrap(), Rule::parse("arg0path=a").unwrap());
+        assert_eq!(Rule::parse("arg0='It'\\''s'").unwrap().rule.args[0].1, "It's");
+        assert!(Rule::parse("type='foo'").is_err());
+        assert!(Rule::parse("unknown='x'").is_err());
+        assert!(Rule::parse("member='a',member='b'").is_err());
+        assert_eq!(Rule::parse("").unwrap().canonical, "");
+    }
+
+    #[test]
+    fn names() {
+        let bus = TestBus::new().unwrap();
+        let c1: Connection = bus.connect().unwrap().into();
+        let c2: Connection = bus.connect().unwrap().into();
+        assert_ne!(c1.unique_name(), c2.unique_name());
+
+        assert_eq!(c1.request_name("com.example.names", true, false, false).unwrap(), RequestNameReply::PrimaryOwner);
+        assert_eq!(c1.request_name("com.example.names", true, false, false).unwrap(), RequestNameReply::AlreadyOwner);
+        assert_eq!(c2.request_name("com.example.names", false, false, true).unwrap(), RequestNameReply::Exists);
+        assert_eq!(c2.request_name("com.example.names", false, false, false).unwrap(), RequestNameReply::InQueue);
+        let (q,): (Vec<String>,) = call(&c1, "ListQueuedOwners", ("com.example.names",)).unwrap();
+        assert_eq!(q, vec!(c1.unique_name().to_string(), c2.unique_name().to_string()));
+
+        let (o,): (String,) = call(&c2, "GetNameOwner", ("com.example.names",)).unwrap();
+        assert_eq!(&*o, &*c1.unique_name());
+        let (names,): (Vec<String>,) = call(&c2, "ListNames", ()).unwrap();
+        assert!(names.contains(&"com.example.names".into()));
+        assert!(names.contains(&c2.unique_name().to_string()));
+
+        assert_eq!(c2.release_name("com.example.other").unwrap(), ReleaseNameReply::NonExistent);
+        assert_eq!(c1.release_name("com.example.names").unwrap(), ReleaseNameReply::Released);
+        let (o,): (String,) = call(&c1, "GetNameOwner", ("com.example.names",)).unwrap();
+        assert_eq!(&*o, &*c2.unique_name());
+
+        // The bus notices the disconnection asynchronously
+        drop(c2);
+        let e = (0..50).filter_map(|_| {
+            let r = call::<(String,), _, _>(&c1, "GetNameOwner", ("com.example.names",)).err();
+            if r.is_none() { thread::sleep(Duration::from_millis(100)) }
+            r
+        }).next().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NameHasNoOwner"));
+        assert!(call::<(), _, _>(&c1, "RequestName", (":1.5", 0u32)).is_err());
+    }
+
+    #[test]
+    fn routing() {
+        let bus = TestBus::new().unwrap();
+        let c1 = bus.connect().unwrap();
+        let c2 = bus.connect().unwrap();
+        let name1 = c1.unique_name().unwrap().to_string();
+
+        // Unicast method call and reply
+        let t = thread::spawn(move || {
+            let p = Proxy::new(name1, "/", Duration::from_secs(5), &c2);
+            let (r,): (u32,) = p.method_call("com.example.Test", "Double", (21u32,)).unwrap();
+            assert_eq!(r, 42);
+            let e = Proxy::new("com.example.nobody", "/", Duration::from_secs(5), &c2)
+                .method_call::<(), _, _, _>("com.example.Test", "Double", (1u32,)).unwrap_err();
+            assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+            call::<(), _, _>(&c2, "AddMatch", ("type='signal',interface='com.example.Test',arg0='yes'",)).unwrap();
+            let e = call::<(), _, _>(&c2, "RemoveMatch", ("type='signal'",)).unwrap_err();
+            assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.MatchRuleNotFound"));
+            c2
+        });
+        let m = pop_signal(&c1, "Double");
+        let x: u32 = m.read1().unwrap();
+        c1.send(m.method_return().append1(x * 2)).unwrap();
+        let c2 = t.join().unwrap();
+
+        // Broadcast signals only reach matching peers
+        c1.send(Message::new_signal("/", "com.example.Test", "Sig").unwrap().append1("no")).unwrap();
+        c1.send(Message::new_signal("/", "com.example.Other", "Sig").unwrap().append1("yes")).unwrap();
+        c1.send(Message::new_signal("/", "com.example.Test", "Sig").unwrap().append1("yes")).unwrap();
+        c1.flush();
+        let s = pop_signal(&c2, "Sig");
+        assert_eq!(&*s.interface().unwrap(), "com.example.Test");
+        assert_eq!(s.read1::<&str>().unwrap(), "yes");
+        assert_eq!(&*s.sender().unwrap(), c1.unique_name().unwrap());
+
+        // NameOwnerChanged when a peer goes away
+        call::<(), _, _>(&c2, "AddMatch", ("type='signal',member='NameOwnerChanged'",)).unwrap();
+        let c3 = bus.connect().unwrap();
+        let c3_name = c3.unique_name().unwrap().to_string();
+        drop(c3);
+        loop {
+            let s = pop_signal(&c2, "NameOwnerChanged");
+            let (n, old, new): (&str, &str, &str) = s.read3().unwrap();
+            assert_eq!(&*s.sender().unwrap(), BUS_NAME);
+            if n == c3_name && new.is_empty() { assert_eq!(old, c3_name); break }
+        }
+    }
+}
@@ -28,6 +28,10 @@ impl MethodErr {
     pub fn failed<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
         ("org.freedesktop.DBus.Error.Failed", a.to_string()).into()
     }
+    /// Create a MethodErr that the Object path was unknown.
+    pub fn no_path<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
+        ("org.freedesktop.DBus.Error.UnknownObject", format!("Unknown object path {}", a)).into()
+    }
     /// Create a MethodErr that the Interface was unknown.
     pub fn no_interface<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
         ("org.freedesktop.DBus.Error.UnknownInterface", format!("Unknown interface {}", a)).into()
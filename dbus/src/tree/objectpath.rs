@@ -422,7 +422,8 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
             r.sort_by_key(|v| &**v.name);
             let mut prev: Option<&ObjectPath<M, D>> = None;
             r.retain(|v| {
-                let a = prev.map(|prev| !v.name.starts_with(&**prev.name)).unwrap_or(true);
+                // Compare whole elements, so that e g "/a/bc" is not taken as a child of "/a/b"
+                let a = prev.map(|prev| !v.name.has_path_prefix(&prev.name)).unwrap_or(true);
                 prev = Some(v);
                 a
             });
@@ -516,6 +517,21 @@ fn test_iter() {
     assert_eq!(paths.len(), 2);
 }
 
+#[test]
+fn test_children() {
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(())
+        .add(f.object_path("/echo", ()))
+        .add(f.object_path("/echo/a", ()))
+        .add(f.object_path("/echo/ab", ()))
+        .add(f.object_path("/echo/b", ()))
+        .add(f.object_path("/echo/b/c", ()));
+    let o = t.get(&"/echo".into()).unwrap();
+    let names: Vec<&str> = t.children(o, true).iter().map(|v| &**v.name).collect();
+    assert_eq!(names, vec!("/echo/a", "/echo/ab", "/echo/b"));
+    assert_eq!(t.children(o, false).len(), 4);
+}
+
 #[test]
 fn test_set_default_interface() {
     let iface_name: IfaceName<'_> = "com.example.echo".into();
@@ -0,0 +1,40 @@
+//! Helpers shared by the property caches, the object manager clients, the name watchers and `NameOwner`.
+
+use crate::arg::{self, ReadAll, TypeMismatchError};
+
+/// A method reply, along with its serial.
+pub (crate) struct Serial<T>(pub u32, pub T);
+
+impl<T: ReadAll> ReadAll for Serial<T> {
+    fn read(i: &mut arg::Iter) -> Result<Self, TypeMismatchError> {
+        Ok(Serial(i.message().get_serial(), T::read(i)?))
+    }
+}
+
+/// Callbacks added with e g `on_change`, along with the ids they can be removed with.
+pub (crate) struct Callbacks<T> {
+    list: Vec<(u32, T)>,
+    next_id: u32,
+}
+
+impl<T> Default for Callbacks<T> {
+    fn default() -> Self { Callbacks { list: vec!(), next_id: 0 } }
+}
+
+impl<T> Callbacks<T> {
+    /// Adds a callback and returns its id.
+    pub (crate) fn add(&mut self, t: T) -> u32 {
+        self.next_id += 1;
+        self.list.push((self.next_id, t));
+        self.next_id
+    }
+
+    /// Returns false if there was no callback with this id.
+    pub (crate) fn remove(&mut self, id: u32) -> bool {
+        let len = self.list.len();
+        self.list.retain(|x| x.0 != id);
+        len != self.list.len()
+    }
+
+    pub (crate) fn iter(&self) -> impl Iterator<Item=&T> { self.list.iter().map(|x| &x.1) }
+}
@@ -5,6 +5,7 @@ pub type DBusMessage = c_void;
 pub type DBusWatch = c_void;
 pub type DBusPendingCall = c_void;
 pub type DBusTimeout = c_void;
+pub type DBusServer = c_void;
 
 #[repr(C)]
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -145,6 +146,8 @@ pub type DBusPendingCallNotifyFunction = Option<extern fn(pending: *mut DBusPend
 
 pub type DBusFreeFunction = Option<extern fn(memory: *mut c_void)>;
 
+pub type DBusNewConnectionFunction = Option<extern fn(server: *mut DBusServer, conn: *mut DBusConnection, user_data: *mut c_void)>;
+
 #[repr(C)]
 pub struct DBusObjectPathVTable {
     pub unregister_function: Option<extern fn(conn: *mut DBusConnection, user_data: *mut c_void)>,
@@ -174,6 +177,10 @@ extern "C" {
     pub fn dbus_connection_open_private(address: *const c_char, error: *mut DBusError) -> *mut DBusConnection;
     pub fn dbus_connection_unref(conn: *mut DBusConnection);
     pub fn dbus_connection_get_is_connected(conn: *mut DBusConnection) -> u32;
+    pub fn dbus_connection_get_is_authenticated(conn: *mut DBusConnection) -> u32;
+    pub fn dbus_connection_get_server_id(conn: *mut DBusConnection) -> *mut c_char;
+    pub fn dbus_connection_set_allow_anonymous(conn: *mut DBusConnection, value: u32);
+    pub fn dbus_connection_ref(conn: *mut DBusConnection) -> *mut DBusConnection;
     pub fn dbus_connection_set_exit_on_disconnect(conn: *mut DBusConnection, enable: u32);
     pub fn dbus_connection_send_with_reply_and_block(conn: *mut DBusConnection,
         message: *mut DBusMessage, timeout_milliseconds: c_int, error: *mut DBusError) -> *mut DBusMessage;
@@ -181,6 +188,7 @@ extern "C" {
         message: *mut DBusMessage, pending_return: *mut *mut DBusPendingCall, timeout_milliseconds: c_int) -> u32;
     pub fn dbus_connection_send(conn: *mut DBusConnection,
         message: *mut DBusMessage, serial: *mut u32) -> u32;
+    pub fn dbus_connection_can_send_type(conn: *mut DBusConnection, t: c_int) -> u32;
     pub fn dbus_connection_read_write_dispatch(conn: *mut DBusConnection,
         timeout_milliseconds: c_int) -> u32;
     pub fn dbus_connection_read_write(conn: *mut DBusConnection, timeout_milliseconds: c_int) -> u32;
@@ -238,6 +246,26 @@ extern "C" {
     pub fn dbus_message_set_no_reply(message: *mut DBusMessage, no_reply: u32);
     pub fn dbus_message_get_auto_start(message: *mut DBusMessage) -> u32;
     pub fn dbus_message_set_auto_start(message: *mut DBusMessage, no_reply: u32);
+    pub fn dbus_message_set_sender(message: *mut DBusMessage, sender: *const c_char) -> u32;
+    pub fn dbus_message_contains_unix_fds(message: *mut DBusMessage) -> u32;
+    pub fn dbus_message_copy(message: *const DBusMessage) -> *mut DBusMessage;
+
+    pub fn dbus_server_listen(address: *const c_char, error: *mut DBusError) -> *mut DBusServer;
+    pub fn dbus_server_ref(server: *mut DBusServer) -> *mut DBusServer;
+    pub fn dbus_server_unref(server: *mut DBusServer);
+    pub fn dbus_server_disconnect(server: *mut DBusServer);
+    pub fn dbus_server_get_is_connected(server: *mut DBusServer) -> u32;
+    pub fn dbus_server_get_address(server: *mut DBusServer) -> *mut c_char;
+    pub fn dbus_server_get_id(server: *mut DBusServer) -> *mut c_char;
+    pub fn dbus_server_set_new_connection_function(server: *mut DBusServer, function: DBusNewConnectionFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction);
+    pub fn dbus_server_set_watch_functions(server: *mut DBusServer, add_function: DBusAddWatchFunction,
+        remove_function: DBusRemoveWatchFunction, toggled_function: DBusWatchToggledFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction) -> u32;
+    pub fn dbus_server_set_timeout_functions(server: *mut DBusServer, add_function: DBusAddTimeoutFunction,
+        remove_function: DBusRemoveTimeoutFunction, toggled_function: DBusTimeoutToggledFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction) -> u32;
+    pub fn dbus_server_set_auth_mechanisms(server: *mut DBusServer, mechanisms: *mut *const c_char) -> u32;
 
     pub fn dbus_message_iter_append_basic(iter: *mut DBusMessageIter, t: c_int, value: *const c_void) -> u32;
     pub fn dbus_message_iter_append_fixed_array(iter: *mut DBusMessageIter, element_type: c_int,